@@ -0,0 +1,177 @@
+/* Rebuilds the book from RustX's binary feed.
+ *
+ *      itch_book FILE [SYMBOL] [LEVELS]            <---- replays a recording, then prints the book.
+ *      itch_book --udp ADDRESS [SYMBOL] [LEVELS]   <---- listens to a live feed, printing each market as it changes.
+ *
+ * ADDRESS may be a multicast group, ex. 239.1.1.1:5000. Without a SYMBOL every market is printed.
+ **/
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::process;
+
+use itch::{Body, Book, BookError, MarketState, Message, decode_frames, price_to_f64};
+
+struct Options {
+    source: Source,
+    symbol: Option<String>,
+    levels: usize
+}
+
+enum Source {
+    File(String),
+    Udp(String)
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: itch_book FILE [SYMBOL] [LEVELS]\n       itch_book --udp ADDRESS [SYMBOL] [LEVELS]");
+            process::exit(1);
+        }
+    };
+    let result = match &options.source {
+        Source::File(path) => replay(path, &options),
+        Source::Udp(address) => listen(address, &options)
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut args = args.into_iter();
+    let source = match args.next().as_deref() {
+        Some("--udp") => Source::Udp(args.next().ok_or("Please provide an address to listen on.")?),
+        Some(path) => Source::File(path.to_string()),
+        None => return Err("Please provide a recording or --udp ADDRESS.".to_string())
+    };
+    let symbol = args.next().map(|symbol| symbol.to_uppercase());
+    let levels = match args.next() {
+        Some(levels) => levels.parse::<usize>().map_err(|_| "LEVELS must be a positive integer.")?,
+        None => 10
+    };
+    Ok(Options { source, symbol, levels })
+}
+
+/* Apply a whole recording, then print the book as it was left. */
+fn replay(path: &str, options: &Options) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!["Failed to read {}: {}", path, e])?;
+    let (messages, used) = decode_frames(&bytes).map_err(|e| format!["{} is not a recording: {}", path, e])?;
+    if used != bytes.len() {
+        eprintln!("Warning: the recording ends part way through a message.");
+    }
+
+    let mut book = Book::new();
+    for message in messages.iter() {
+        apply(&mut book, message);
+    }
+    println!("Replayed {} messages, up to sequence number {}{}.", messages.len(), book.last_seq, if book.ended { " (end of messages)" } else { "" });
+    for symbol in symbols(&book, options) {
+        print_market(&book, &symbol, options.levels);
+    }
+    Ok(())
+}
+
+/* Apply packets as they arrive, printing every market they changed. */
+fn listen(address: &str, options: &Options) -> Result<(), String> {
+    let address: SocketAddr = address.parse().map_err(|_| format!["{} is not an address, ex. 239.1.1.1:5000", address])?;
+    let socket = match address {
+        SocketAddr::V4(group) if group.ip().is_multicast() => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port())).map_err(|e| e.to_string())?;
+            socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED).map_err(|e| e.to_string())?;
+            socket
+        },
+        _ => UdpSocket::bind(address).map_err(|e| e.to_string())?
+    };
+    println!("Listening for the feed on {}", address);
+
+    let mut book = Book::new();
+    let mut packet = [0; 65536];
+    loop {
+        let (length, _) = socket.recv_from(&mut packet).map_err(|e| e.to_string())?;
+        let messages = match decode_frames(&packet[..length]) {
+            Ok((messages, _)) => messages,
+            Err(e) => {
+                eprintln!("Dropped a packet: {}", e);
+                continue;
+            }
+        };
+
+        let mut changed = BTreeSet::new();
+        for message in messages.iter() {
+            // Look the market up first, an execution may take the order off the book.
+            if let Some(symbol) = symbol_of(&book, message) {
+                changed.insert(symbol);
+            }
+            apply(&mut book, message);
+        }
+        for symbol in changed.iter().filter(|symbol| options.symbol.is_none() || options.symbol.as_ref() == Some(symbol)) {
+            print_market(&book, symbol, options.levels);
+        }
+        if book.ended {
+            println!("The exchange ended the feed at sequence number {}.", book.last_seq);
+            return Ok(());
+        }
+    }
+}
+
+fn apply(book: &mut Book, message: &Message) {
+    match book.apply(message) {
+        Ok(()) => (),
+        Err(BookError::Gap { expected, received }) => eprintln!("Gap: expected message {} but received {}, the book may be wrong.", expected, received),
+        Err(e) => eprintln!("Message {}: {}", message.seq, e)
+    }
+}
+
+// The market a message will change, if any.
+fn symbol_of(book: &Book, message: &Message) -> Option<String> {
+    match &message.body {
+        Body::MarketState { symbol, .. } | Body::AddOrder { symbol, .. } | Body::Trade { symbol, .. } => Some(symbol.clone()),
+        Body::OrderExecuted { order_id, .. } | Body::OrderCancelled { order_id, .. } => book.orders.get(order_id).map(|order| order.symbol.clone()),
+        Body::System { .. } => None
+    }
+}
+
+fn symbols(book: &Book, options: &Options) -> Vec<String> {
+    match &options.symbol {
+        Some(symbol) => vec![symbol.clone()],
+        None => {
+            let mut symbols: Vec<String> = book.markets.keys().cloned().collect();
+            symbols.sort();
+            symbols
+        }
+    }
+}
+
+fn print_market(book: &Book, symbol: &str, levels: usize) {
+    let market = match book.markets.get(symbol) {
+        Some(market) => market,
+        None => {
+            println!("\n${} has no orders or trades on the feed.", symbol);
+            return;
+        }
+    };
+    let last = match market.last_trade {
+        Some((price, shares)) => format!["last {} @ ${:.2}, volume {}", shares, price_to_f64(price), market.volume],
+        None => "no trades".to_string()
+    };
+    let state = match market.state {
+        Some(MarketState::Trading) => "trading",
+        Some(MarketState::Halted) => "halted",
+        None => "state unknown"
+    };
+    println!("\n${} ({}, {})", symbol, state, last);
+
+    let (bids, asks) = book.depth(symbol, levels);
+    println!("\t{:>12} {:>10} | {:<10} {:<12}", "Bid size", "Bid", "Ask", "Ask size");
+    for row in 0..bids.len().max(asks.len()) {
+        let bid = bids.get(row).map(|(price, shares)| (format!["{}", shares], format!["{:.2}", price_to_f64(*price)])).unwrap_or_default();
+        let ask = asks.get(row).map(|(price, shares)| (format!["{:.2}", price_to_f64(*price)], format!["{}", shares])).unwrap_or_default();
+        println!("\t{:>12} {:>10} | {:<10} {:<12}", bid.0, bid.1, ask.0, ask.1);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::message::{Body, MarketState, Message, Side, SystemEvent};
+
+// A price, and the shares resting at it.
+pub type Level = (u32, u64);
+
+// An order on the book, as far as the feed has told us.
+#[derive(Debug, Clone)]
+pub struct RestingOrder {
+    pub symbol: String,
+    pub side: Side,
+    pub shares: u32,
+    pub price: u32
+}
+
+// One market's book, aggregated by price.
+#[derive(Debug, Default)]
+pub struct MarketBook {
+    pub state: Option<MarketState>,
+    pub bids: BTreeMap<u32, u64>,       // price -> shares
+    pub asks: BTreeMap<u32, u64>,
+    pub last_trade: Option<(u32, u32)>, // (price, shares) of the last print
+    pub volume: u64                     // Shares traded since the feed started
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BookError {
+    Gap { expected: u64, received: u64 },   // Messages were lost, the book may be wrong from here on
+    UnknownOrder(u64),                      // Executed or cancelled an order we never saw added
+    DuplicateOrder(u64)
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BookError::Gap { expected, received } => write!(f, "expected message {} but received {}", expected, received),
+            BookError::UnknownOrder(order_id) => write!(f, "order {} is not on the book", order_id),
+            BookError::DuplicateOrder(order_id) => write!(f, "order {} was added twice", order_id)
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+/* Rebuilds every market's book from the feed.
+ * Messages must be applied in sequence; ones we've already seen are ignored.
+ **/
+#[derive(Debug, Default)]
+pub struct Book {
+    pub orders: HashMap<u64, RestingOrder>,
+    pub markets: HashMap<String, MarketBook>,
+    pub last_seq: u64,
+    pub ended: bool         // The exchange sent EndOfMessages
+}
+
+impl Book {
+    pub fn new() -> Self {
+        Book::default()
+    }
+
+    /* Apply the next message. A gap is reported, but the message is still applied,
+     * so the caller decides whether to carry on or start over.
+     **/
+    pub fn apply(&mut self, message: &Message) -> Result<(), BookError> {
+        if message.seq <= self.last_seq {
+            return Ok(());
+        }
+        let gap = if message.seq != self.last_seq + 1 {
+            Some(BookError::Gap { expected: self.last_seq + 1, received: message.seq })
+        } else {
+            None
+        };
+        self.last_seq = message.seq;
+
+        match &message.body {
+            Body::System { event } => {
+                match event {
+                    // A new feed, everything we knew is gone.
+                    SystemEvent::StartOfMessages => {
+                        self.orders.clear();
+                        self.markets.clear();
+                        self.ended = false;
+                    },
+                    SystemEvent::EndOfMessages => self.ended = true
+                }
+            },
+            Body::MarketState { symbol, state } => {
+                self.markets.entry(symbol.clone()).or_default().state = Some(*state);
+            },
+            Body::AddOrder { order_id, side, shares, symbol, price } => {
+                if self.orders.contains_key(order_id) {
+                    return Err(BookError::DuplicateOrder(*order_id));
+                }
+                let market = self.markets.entry(symbol.clone()).or_default();
+                *market.side_mut(*side).entry(*price).or_insert(0) += *shares as u64;
+                self.orders.insert(*order_id, RestingOrder { symbol: symbol.clone(), side: *side, shares: *shares, price: *price });
+            },
+            Body::OrderExecuted { order_id, shares, .. } | Body::OrderCancelled { order_id, shares } => {
+                self.reduce(*order_id, *shares)?;
+            },
+            Body::Trade { shares, price, symbol, .. } => {
+                let market = self.markets.entry(symbol.clone()).or_default();
+                market.last_trade = Some((*price, *shares));
+                market.volume += *shares as u64;
+            }
+        }
+
+        match gap {
+            Some(gap) => Err(gap),
+            None => Ok(())
+        }
+    }
+
+    // Take shares off a resting order, and off the book once none are left.
+    fn reduce(&mut self, order_id: u64, shares: u32) -> Result<(), BookError> {
+        let order = self.orders.get_mut(&order_id).ok_or(BookError::UnknownOrder(order_id))?;
+        let shares = shares.min(order.shares);
+        order.shares -= shares;
+
+        let (symbol, side, price, left) = (order.symbol.clone(), order.side, order.price, order.shares);
+        if left == 0 {
+            self.orders.remove(&order_id);
+        }
+        if let Some(market) = self.markets.get_mut(&symbol) {
+            let levels = market.side_mut(side);
+            if let Some(level) = levels.get_mut(&price) {
+                *level -= (shares as u64).min(*level);
+                if *level == 0 {
+                    levels.remove(&price);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* The best `levels` prices on each side of a market: bids from highest, asks from lowest. */
+    pub fn depth(&self, symbol: &str, levels: usize) -> (Vec<Level>, Vec<Level>) {
+        match self.markets.get(symbol) {
+            Some(market) => {
+                let bids = market.bids.iter().rev().take(levels).map(|(price, shares)| (*price, *shares)).collect();
+                let asks = market.asks.iter().take(levels).map(|(price, shares)| (*price, *shares)).collect();
+                (bids, asks)
+            },
+            None => (Vec::new(), Vec::new())
+        }
+    }
+}
+
+impl MarketBook {
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<u32, u64> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks
+        }
+    }
+}
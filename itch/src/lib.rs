@@ -0,0 +1,23 @@
+/* RustX's binary market data feed, in the style of NASDAQ's ITCH.
+ *
+ * Every message is big-endian, and starts with its type, sequence number and timestamp:
+ *      type: u8 | seq: u64 | timestamp: u64 (ns since the Unix epoch) | body
+ * Messages are framed with a u16 length prefix, both in UDP packets (one or more per packet)
+ * and in recordings, which are the framed messages one after another.
+ *
+ *      'S' System          event: u8 ('O' start of messages, 'C' end of messages)
+ *      'H' Market state    symbol: [u8; 10] | state: u8 ('T' trading, 'H' halted)
+ *      'A' Add order       order_id: u64 | side: u8 ('B'/'S') | shares: u32 | symbol: [u8; 10] | price: u32
+ *      'E' Order executed  order_id: u64 | shares: u32 | match_number: u64
+ *      'X' Order cancelled order_id: u64 | shares: u32
+ *      'P' Trade           side: u8 | shares: u32 | symbol: [u8; 10] | price: u32 | match_number: u64
+ *
+ * Prices are in ten-thousandths of a dollar, and symbols are padded with spaces.
+ * The feed starts with the state of every market and the orders already on the book, so it can be joined
+ * at its first message. A market's state is sent again when it opens, halts or resumes.
+ **/
+pub mod message;
+pub use crate::message::{Body, DecodeError, MarketState, Message, Side, SystemEvent, decode_frames, price_from_f64, price_to_f64};
+
+pub mod book;
+pub use crate::book::{Book, BookError, Level, MarketBook, RestingOrder};
@@ -0,0 +1,367 @@
+use std::convert::TryInto;
+use std::fmt;
+
+// Symbols are space padded to the longest symbol the exchange allows.
+pub const SYMBOL_LEN: usize = 10;
+// Prices are sent in ten-thousandths of a dollar, like ITCH.
+pub const PRICE_SCALE: f64 = 10000.0;
+
+// Every message starts with its type, sequence number and timestamp.
+const HEADER_LEN: usize = 1 + 8 + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemEvent {
+    StartOfMessages,    // The first message of the feed
+    EndOfMessages       // The exchange is shutting down, nothing follows
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketState {
+    Trading,
+    Halted
+}
+
+/* The messages of the feed. The book is described order by order:
+ * orders are added, then executed or cancelled until nothing of them is left.
+ **/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    // 'S'
+    System { event: SystemEvent },
+    // 'H', sent before the first order of a market, and whenever its state changes.
+    MarketState { symbol: String, state: MarketState },
+    // 'A', the unfilled part of a new order rests on the book.
+    AddOrder { order_id: u64, side: Side, shares: u32, symbol: String, price: u32 },
+    // 'E', a resting order traded, at its own price.
+    OrderExecuted { order_id: u64, shares: u32, match_number: u64 },
+    // 'X', shares of a resting order were cancelled.
+    OrderCancelled { order_id: u64, shares: u32 },
+    // 'P', the print of a trade, from the side of the incoming order.
+    // The book was already changed by the OrderExecuted with the same match number.
+    Trade { side: Side, shares: u32, symbol: String, price: u32, match_number: u64 }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub seq: u64,           // Counts up from 1 with no gaps, a gap means a message was lost
+    pub timestamp: u64,     // Nanoseconds since the Unix epoch
+    pub body: Body
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Truncated,              // Fewer bytes than the message type needs
+    UnknownType(u8),
+    InvalidField(&'static str)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "the message is truncated"),
+            DecodeError::UnknownType(kind) => write!(f, "unknown message type {:?}", *kind as char),
+            DecodeError::InvalidField(field) => write!(f, "invalid {}", field)
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Side {
+    fn code(&self) -> u8 {
+        match self {
+            Side::Buy => b'B',
+            Side::Sell => b'S'
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self, DecodeError> {
+        match code {
+            b'B' => Ok(Side::Buy),
+            b'S' => Ok(Side::Sell),
+            _ => Err(DecodeError::InvalidField("side"))
+        }
+    }
+}
+
+impl SystemEvent {
+    fn code(&self) -> u8 {
+        match self {
+            SystemEvent::StartOfMessages => b'O',
+            SystemEvent::EndOfMessages => b'C'
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self, DecodeError> {
+        match code {
+            b'O' => Ok(SystemEvent::StartOfMessages),
+            b'C' => Ok(SystemEvent::EndOfMessages),
+            _ => Err(DecodeError::InvalidField("system event"))
+        }
+    }
+}
+
+impl MarketState {
+    fn code(&self) -> u8 {
+        match self {
+            MarketState::Trading => b'T',
+            MarketState::Halted => b'H'
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self, DecodeError> {
+        match code {
+            b'T' => Ok(MarketState::Trading),
+            b'H' => Ok(MarketState::Halted),
+            _ => Err(DecodeError::InvalidField("market state"))
+        }
+    }
+}
+
+pub fn price_to_f64(price: u32) -> f64 {
+    price as f64 / PRICE_SCALE
+}
+
+pub fn price_from_f64(price: f64) -> u32 {
+    (price * PRICE_SCALE).round() as u32
+}
+
+impl Body {
+    fn kind(&self) -> u8 {
+        match self {
+            Body::System { .. } => b'S',
+            Body::MarketState { .. } => b'H',
+            Body::AddOrder { .. } => b'A',
+            Body::OrderExecuted { .. } => b'E',
+            Body::OrderCancelled { .. } => b'X',
+            Body::Trade { .. } => b'P'
+        }
+    }
+}
+
+impl Message {
+    pub fn new(seq: u64, timestamp: u64, body: Body) -> Self {
+        Message { seq, timestamp, body }
+    }
+
+    /* The message, big-endian, without its length prefix. */
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + 32);
+        bytes.push(self.body.kind());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        match &self.body {
+            Body::System { event } => bytes.push(event.code()),
+            Body::MarketState { symbol, state } => {
+                put_symbol(&mut bytes, symbol);
+                bytes.push(state.code());
+            },
+            Body::AddOrder { order_id, side, shares, symbol, price } => {
+                bytes.extend_from_slice(&order_id.to_be_bytes());
+                bytes.push(side.code());
+                bytes.extend_from_slice(&shares.to_be_bytes());
+                put_symbol(&mut bytes, symbol);
+                bytes.extend_from_slice(&price.to_be_bytes());
+            },
+            Body::OrderExecuted { order_id, shares, match_number } => {
+                bytes.extend_from_slice(&order_id.to_be_bytes());
+                bytes.extend_from_slice(&shares.to_be_bytes());
+                bytes.extend_from_slice(&match_number.to_be_bytes());
+            },
+            Body::OrderCancelled { order_id, shares } => {
+                bytes.extend_from_slice(&order_id.to_be_bytes());
+                bytes.extend_from_slice(&shares.to_be_bytes());
+            },
+            Body::Trade { side, shares, symbol, price, match_number } => {
+                bytes.push(side.code());
+                bytes.extend_from_slice(&shares.to_be_bytes());
+                put_symbol(&mut bytes, symbol);
+                bytes.extend_from_slice(&price.to_be_bytes());
+                bytes.extend_from_slice(&match_number.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /* The message with its u16 length prefix, as it is sent and recorded. */
+    pub fn encode_framed(&self) -> Vec<u8> {
+        let message = self.encode();
+        let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+        framed.extend(message);
+        framed
+    }
+
+    /* Decode one message (without its length prefix). */
+    pub fn decode(bytes: &[u8]) -> Result<Message, DecodeError> {
+        let mut reader = Reader { bytes, position: 0 };
+        let kind = reader.u8()?;
+        let seq = reader.u64()?;
+        let timestamp = reader.u64()?;
+        let body = match kind {
+            b'S' => Body::System { event: SystemEvent::from_code(reader.u8()?)? },
+            b'H' => Body::MarketState { symbol: reader.symbol()?, state: MarketState::from_code(reader.u8()?)? },
+            b'A' => Body::AddOrder {
+                order_id: reader.u64()?,
+                side: Side::from_code(reader.u8()?)?,
+                shares: reader.u32()?,
+                symbol: reader.symbol()?,
+                price: reader.u32()?
+            },
+            b'E' => Body::OrderExecuted { order_id: reader.u64()?, shares: reader.u32()?, match_number: reader.u64()? },
+            b'X' => Body::OrderCancelled { order_id: reader.u64()?, shares: reader.u32()? },
+            b'P' => Body::Trade {
+                side: Side::from_code(reader.u8()?)?,
+                shares: reader.u32()?,
+                symbol: reader.symbol()?,
+                price: reader.u32()?,
+                match_number: reader.u64()?
+            },
+            _ => return Err(DecodeError::UnknownType(kind))
+        };
+        Ok(Message { seq, timestamp, body })
+    }
+}
+
+/* Decode every length prefixed message in a buffer, ex. a UDP packet.
+ * Returns the messages, and how many bytes were used. Bytes left over are the start of a message.
+ **/
+pub fn decode_frames(bytes: &[u8]) -> Result<(Vec<Message>, usize), DecodeError> {
+    let mut messages = Vec::new();
+    let mut position = 0;
+    while position + 2 <= bytes.len() {
+        let length = u16::from_be_bytes([bytes[position], bytes[position + 1]]) as usize;
+        let end = position + 2 + length;
+        if end > bytes.len() {
+            break;
+        }
+        messages.push(Message::decode(&bytes[position + 2..end])?);
+        position = end;
+    }
+    Ok((messages, position))
+}
+
+fn put_symbol(bytes: &mut Vec<u8>, symbol: &str) {
+    let mut field = [b' '; SYMBOL_LEN];
+    for (byte, symbol_byte) in field.iter_mut().zip(symbol.bytes()) {
+        *byte = symbol_byte;
+    }
+    bytes.extend_from_slice(&field);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let field = self.bytes.get(self.position..self.position + length).ok_or(DecodeError::Truncated)?;
+        self.position += length;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn symbol(&mut self) -> Result<String, DecodeError> {
+        let field = self.take(SYMBOL_LEN)?;
+        match std::str::from_utf8(field) {
+            Ok(symbol) => Ok(symbol.trim_end().to_string()),
+            Err(_) => Err(DecodeError::InvalidField("symbol"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        let bodies = vec![
+            Body::System { event: SystemEvent::StartOfMessages },
+            Body::System { event: SystemEvent::EndOfMessages },
+            Body::MarketState { symbol: "GME".to_string(), state: MarketState::Trading },
+            Body::MarketState { symbol: "ABCDEFGHIJ".to_string(), state: MarketState::Halted },
+            Body::AddOrder { order_id: u64::MAX, side: Side::Buy, shares: 100, symbol: "GME".to_string(), price: price_from_f64(167.34) },
+            Body::AddOrder { order_id: 2, side: Side::Sell, shares: u32::MAX, symbol: "AAPL".to_string(), price: 1 },
+            Body::OrderExecuted { order_id: 1, shares: 40, match_number: 7 },
+            Body::OrderCancelled { order_id: 1, shares: 60 },
+            Body::Trade { side: Side::Sell, shares: 40, symbol: "GME".to_string(), price: price_from_f64(167.34), match_number: 7 }
+        ];
+        bodies.into_iter().enumerate().map(|(i, body)| Message::new(i as u64 + 1, 1_623_692_703_123_456_789, body)).collect()
+    }
+
+    #[test]
+    fn every_message_type_round_trips() {
+        for message in messages() {
+            assert_eq!(Message::decode(&message.encode()), Ok(message.clone()));
+        }
+    }
+
+    #[test]
+    fn framed_messages_round_trip() {
+        let messages = messages();
+        let bytes: Vec<u8> = messages.iter().flat_map(|message| message.encode_framed()).collect();
+        assert_eq!(decode_frames(&bytes), Ok((messages, bytes.len())));
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        for message in messages() {
+            let bytes = message.encode();
+            for length in 0..bytes.len() {
+                assert_eq!(Message::decode(&bytes[..length]), Err(DecodeError::Truncated), "{:?} cut to {} bytes", message.body, length);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_frames_are_left_for_the_next_read() {
+        let first = messages()[4].encode_framed();
+        let mut bytes = first.clone();
+        bytes.extend_from_slice(&messages()[6].encode_framed()[..5]);
+        let (decoded, used) = decode_frames(&bytes).unwrap();
+        assert_eq!(decoded, vec![messages()[4].clone()]);
+        assert_eq!(used, first.len());
+
+        // Not even the length of the next one.
+        assert_eq!(decode_frames(&bytes[..first.len() + 1]), Ok((vec![messages()[4].clone()], first.len())));
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        let mut bytes = messages()[4].encode();
+        bytes[0] = b'Z';
+        assert_eq!(Message::decode(&bytes), Err(DecodeError::UnknownType(b'Z')));
+
+        // The side follows the header and the order id.
+        let mut bytes = messages()[4].encode();
+        bytes[HEADER_LEN + 8] = b'?';
+        assert_eq!(Message::decode(&bytes), Err(DecodeError::InvalidField("side")));
+
+        let mut bytes = messages()[0].encode();
+        bytes[HEADER_LEN] = b'?';
+        assert_eq!(Message::decode(&bytes), Err(DecodeError::InvalidField("system event")));
+    }
+
+    #[test]
+    fn prices_are_in_ten_thousandths() {
+        assert_eq!(price_from_f64(167.34), 1_673_400);
+        assert_eq!(price_to_f64(1_673_400), 167.34);
+    }
+}
@@ -1,20 +1,91 @@
-use crate::exchange::requests::{Order, OrderStatus};
-use crate::exchange::filled::Trade;
+use crate::exchange::requests::{Order, OrderStatus, CancelOrder};
+use crate::exchange::filled::{Trade, TradeSides, Execution};
+use crate::exchange::stats::SecStat;
+use crate::exchange::errors::{ErrorCode, OrderError};
 
-use std::collections::HashMap;
+pub mod portfolio;
+pub use crate::account::portfolio::{Portfolio, Position, CostBasis};
 
-use postgres::Client;
-use crate::database;
-use chrono::{DateTime, Utc};
+pub mod margin;
+pub use crate::account::margin::{MarginAccount, MarginCall, MarginCallAction, MarginPolicy};
+
+pub mod password;
+
+pub mod session;
+pub use crate::account::session::Credentials;
+
+pub mod roles;
+pub use crate::account::roles::{Role, Permission};
+
+pub mod cache;
+pub use crate::account::cache::UserCache;
 
-use redis::{Commands, RedisError};
+use std::collections::{HashMap, HashSet};
+
+use crate::database::{Store, Cache};
+use chrono::{DateTime, Utc};
 
 use crate::buffer::BufferCollection;
 
+use std::fmt;
+
 // Error types for authentication
 pub enum AuthError<'a> {
-    NoUser(&'a String), // Username
+    NoUser(&'a str), // Username
     BadPassword(Option<String>), // optional error msg
+    BadSession,         // The session token is unknown or has expired
+    Forbidden(String, Permission), // Username, and the permission none of their roles grant
+    Suspended(String),  // Username, suspended accounts can't place orders
+    Closed(String),     // Username, closed accounts can't do anything
+}
+
+impl fmt::Display for AuthError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::NoUser(user) => write!(f, "Authentication failed! User ({}) not found.", user),
+            AuthError::BadPassword(Some(message)) => write!(f, "{}", message),
+            AuthError::BadPassword(None) => write!(f, "Authentication failed! Incorrect password!"),
+            AuthError::BadSession => write!(f, "Authentication failed! Your session is invalid or has expired, please login again."),
+            AuthError::Forbidden(user, permission) => write!(f, "Permission denied! None of the roles of ({}) allow {:?}.", user, permission),
+            AuthError::Suspended(user) => write!(f, "Request denied! The account ({}) is suspended, it can't place new orders.", user),
+            AuthError::Closed(user) => write!(f, "Authentication failed! The account ({}) has been closed.", user)
+        }
+    }
+}
+
+impl AuthError<'_> {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AuthError::Forbidden(_, _) | AuthError::Suspended(_) => ErrorCode::Forbidden,
+            _ => ErrorCode::Unauthorized
+        }
+    }
+}
+
+// Where an account is in its lifecycle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AccountStatus {
+    Active,
+    Suspended,  // Can log in, view the account and cancel orders, but can't place new orders
+    Closed      // Can't log in, the username stays taken
+}
+
+impl AccountStatus {
+    pub fn from(word: &str) -> Self {
+        match word {
+            "suspended" => AccountStatus::Suspended,
+            "closed" => AccountStatus::Closed,
+            _ => AccountStatus::Active
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::Closed => "closed"
+        }
+    }
 }
 
 /* This struct stores the pending orders of an account,
@@ -33,13 +104,19 @@ pub enum AuthError<'a> {
  **/
 #[derive(Debug, Clone)]
 pub struct AccountPendingOrders {
-    pub pending: HashMap<String, HashMap<i32, Order>>,   // Orders that have not been completely filled.
+    pub pending: HashMap<String, HashMap<i64, Order>>,   // Orders that have not been completely filled.
     pub is_complete: bool // a simple bool that represents if this account has a full picture of their orders.
 }
 
+impl Default for AccountPendingOrders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AccountPendingOrders {
     pub fn new() -> Self {
-        let pending: HashMap<String, HashMap<i32, Order>> = HashMap::new();
+        let pending: HashMap<String, HashMap<i64, Order>> = HashMap::new();
         AccountPendingOrders {
             pending,
             is_complete: false
@@ -47,13 +124,13 @@ impl AccountPendingOrders {
     }
 
     /* Returns a mutable reference to a market of pending orders in an account. */
-    pub fn get_mut_market(&mut self, symbol: &str) -> &mut HashMap<i32, Order> {
-        self.pending.entry(symbol.clone().to_string()).or_insert(HashMap::new())
+    pub fn get_mut_market(&mut self, symbol: &str) -> &mut HashMap<i64, Order> {
+        self.pending.entry(symbol.to_string()).or_default()
     }
 
     /* Insert an order into an accounts pending orders. */
     pub fn insert_order(&mut self, order: Order) {
-        let market = self.get_mut_market(&order.symbol.as_str());
+        let market = self.get_mut_market(order.symbol.as_str());
         market.insert(order.order_id, order);
     }
 
@@ -65,23 +142,28 @@ impl AccountPendingOrders {
         self.is_complete = true;
     }
 
-    pub fn view_market(&self, symbol: &str) -> Option<&HashMap<i32, Order>> {
+    /* The number of pending orders in every market. */
+    pub fn count(&self) -> usize {
+        self.pending.values().map(|market| market.len()).sum()
+    }
+
+    pub fn view_market(&self, symbol: &str) -> Option<&HashMap<i64, Order>> {
         self.pending.get(symbol)
     }
 
     /* Gives an immutable reference to an accounts pending order in a specific market. */
-    pub fn get_order_in_market(&self, symbol: &str, id: i32) -> Option<&Order> {
+    pub fn get_order_in_market(&self, symbol: &str, id: i64) -> Option<&Order> {
         if let Some(market) = self.view_market(symbol) {
             return market.get(&id);
         }
 
-        return None;
+        None
     }
 
     /* Removes a pending order from the Account.
      * The order IS in a market.
      **/
-    pub fn remove_order(&mut self, symbol: &str, id: i32) {
+    pub fn remove_order(&mut self, symbol: &str, id: i64) {
         let market = self.get_mut_market(symbol);
         market.remove(&id);
     }
@@ -91,8 +173,8 @@ impl AccountPendingOrders {
 #[derive(Debug, Clone)]
 pub struct UserAccount {
     pub username: String,
-    pub password: String,
-    pub id: Option<i32>,
+    pub password: String, // Salted hash of the password, except for accounts parsed from a request (see account::password).
+    pub id: Option<i64>,
     pub pending_orders: AccountPendingOrders,
     pub recent_trades: Vec<Trade>, // Trades that occured since the user was brought into cache
 
@@ -100,24 +182,41 @@ pub struct UserAccount {
     // If 2 orders were filled, and one new order was placed and is still pending (same market), the overall diff
     // is -1.
     pub recent_markets: HashMap<String, i32>,
-    pub modified: bool  // bool representing whether account has been modified since last batch write to DB
+    pub modified: bool, // bool representing whether account has been modified since last batch write to DB
+    pub flushed_in: u64, // The batch that carries this account's last modifications to the DB, see UserCache
+
+    pub margin: MarginAccount, // Short selling settings
+    pub roles: HashSet<Role>,  // What this account is allowed to do
+    pub status: AccountStatus,
+    // Net shares held in each market (average cost basis), None until we need them.
+    // Once loaded, we keep these up to date as the account trades.
+    pub positions: Option<HashMap<String, Position>>,
+    // Orders placed with a client order id since the account was cached, by client id.
+    // These may not be in the database yet, see find_client_order.
+    pub client_orders: HashMap<String, Order>
 }
 
 impl UserAccount {
-    pub fn from(username: &String, password: &String) -> Self {
+    pub fn from(username: &str, password: &str) -> Self {
         UserAccount {
-            username: username.clone(),
-            password: password.clone(),
+            username: username.to_string(),
+            password: password.to_string(),
             id: None, // We set this later
             pending_orders: AccountPendingOrders::new(),
             recent_trades: Vec::new(),
             recent_markets: HashMap::new(),
             modified: false,
+            flushed_in: 0,
+            margin: MarginAccount::default(),
+            roles: roles::default_roles(),
+            status: AccountStatus::Active,
+            positions: None,
+            client_orders: HashMap::new()
         }
     }
 
     /* Used when reading values from database.*/
-    pub fn direct(id: i32, username: &str, password: &str) -> Self {
+    pub fn direct(id: i64, username: &str, password: &str) -> Self {
         UserAccount {
             username: username.to_string().clone(),
              password: password.to_string().clone(),
@@ -126,13 +225,18 @@ impl UserAccount {
             recent_trades: Vec::new(),
             recent_markets: HashMap::new(),
             modified: false,
+            flushed_in: 0,
+            margin: MarginAccount::default(),
+            roles: roles::default_roles(),
+            status: AccountStatus::Active,
+            positions: None,
+            client_orders: HashMap::new()
         }
     }
 
-    // Sets this account's user id, and returns it.
-    fn set_id(&mut self, users: &Users) -> i32 {
-        self.id = Some(users.total + 1);
-        return self.id.unwrap();
+    /* Returns true if one of this account's roles grants the permission. */
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        roles::permits(&self.roles, permission)
     }
 
     /*
@@ -164,54 +268,51 @@ Well, you've done it again.
 You called validate_order on an account with in-complete pending order data.");
         }
 
-        match self.pending_orders.view_market(&order.symbol.as_str()) {
-            // We only care about the market that `order` is being submitted to.
-            Some(market) => {
-                let candidates = market.values().filter(|candidate| order.action.ne(&candidate.action));
-                match order.action.as_str() {
-                    "BUY" => {
-                        let result = candidates.min_by(|x, y| x.price.partial_cmp(&y.price).expect("Tried to compare NaN!"));
-                        if let Some(lowest_offer) = result {
-                            if lowest_offer.price <= order.price {
-                                return Some(lowest_offer.clone());
-                            }
+        // We only care about the market that `order` is being submitted to.
+        if let Some(market) = self.pending_orders.view_market(order.symbol.as_str()) {
+            let candidates = market.values().filter(|candidate| order.action.ne(&candidate.action));
+            match order.action.as_str() {
+                "BUY" => {
+                    let result = candidates.min_by(|x, y| x.price.partial_cmp(&y.price).expect("Tried to compare NaN!"));
+                    if let Some(lowest_offer) = result {
+                        if lowest_offer.price <= order.price {
+                            return Some(lowest_offer.clone());
                         }
-                    },
-                    "SELL" => {
-                        let result = candidates.max_by(|x, y| x.price.partial_cmp(&y.price).expect("Tried to compare Nan!"));
-                        if let Some(highest_bid) = result {
-                            if order.price <= highest_bid.price {
-                                return Some(highest_bid.clone());
-                            }
+                    }
+                },
+                "SELL" => {
+                    let result = candidates.max_by(|x, y| x.price.partial_cmp(&y.price).expect("Tried to compare Nan!"));
+                    if let Some(highest_bid) = result {
+                        if order.price <= highest_bid.price {
+                            return Some(highest_bid.clone());
                         }
-                    },
-                    _ => ()
-                }
-            },
-            None => ()
+                    }
+                },
+                _ => ()
+            }
         }
-        return None;
+        None
     }
 
     /* If the order is in the cache, we return its action (buy/sell), else None. */
-    fn check_pending_order_cache(&self, symbol: &String, id: i32) -> Option<String> {
+    fn check_pending_order_cache(&self, symbol: &str, id: i64) -> Option<String> {
         if !self.pending_orders.is_complete {
             panic!("Tried to check pending order cache but the account does not have up to date pending orders.");
         }
 
-        if let Some(order) = self.pending_orders.get_order_in_market(symbol.as_str(), id) {
+        if let Some(order) = self.pending_orders.get_order_in_market(symbol, id) {
             return Some(order.action.clone()); // buy or sell
         }
 
-        return None;
+        None
     }
 
     /* Check if the user with the given username owns a pending order with this id.
      * If they do, return the order's action.
      **/
-    pub fn user_placed_pending_order(&self, symbol: &String, id: i32, conn: &mut Client) -> Option<String> {
+    pub fn user_placed_pending_order(&self, symbol: &str, id: i64, store: &mut dyn Store) -> Option<String> {
         match self.check_pending_order_cache(symbol, id) {
-            Some(action) => return Some(action),
+            Some(action) => Some(action),
             /* TODO:
             * Recurrent issue: If it's not in the cache, but it IS in the db,
             *                  do we want to move it into the cache? Means we need
@@ -219,151 +320,279 @@ You called validate_order on an account with in-complete pending order data.");
             */
             None => {
                 // Doesn't update cache.
-                return database::read_match_pending_order(self.id.unwrap(), id, conn);
+                store.read_match_pending_order(self.id.unwrap(), id)
             }
         }
     }
 
     /* Removes a pending order from an account if it exists. */
-    pub fn remove_order_from_account(&mut self, symbol: &String, id: i32) {
-        self.pending_orders.remove_order(symbol.as_str(), id);
+    pub fn remove_order_from_account(&mut self, symbol: &str, id: i64) {
+        self.pending_orders.remove_order(symbol, id);
     }
 
-    /* Prints the account information of this user
-     * if their account view is up to date.
+    /* Find the order this account placed with the given client order id, in its current state.
+     *
+     * Orders placed since the account was cached may not have been written to the database yet,
+     * so we check the account's pending orders, then the order buffer, before asking the database.
      **/
-    pub fn print_user(&self) {
-        if !self.pending_orders.is_complete {
-            panic!("Tried to print_user who doesn't have complete pending order info!");
+    pub fn find_client_order(&self, client_id: &str, buffers: &BufferCollection, store: &mut dyn Store) -> Option<Order> {
+        let placed = match self.client_orders.get(client_id) {
+            Some(order) => order,
+            None => return store.read_client_order(self.id.unwrap(), client_id)
+        };
+
+        if let Some(pending) = self.pending_orders.get_order_in_market(&placed.symbol, placed.order_id) {
+            let mut order = pending.clone();
+            order.client_id = placed.client_id.clone();
+            return Some(order);
         }
 
-        println!("\nAccount information for user: {}", self.username);
+        if let Some(buffered) = buffers.buffered_orders.view_order(placed.order_id) {
+            let mut order = placed.clone();
+            if let Some(filled) = buffered.filled {
+                order.filled = filled;
+            }
+            if let Some(status) = buffered.status {
+                order.status = status;
+            }
+            return Some(order);
+        }
 
-        if !self.pending_orders.pending.is_empty() {
-            println!("\n\tOrders Awaiting Execution");
-            for (_, market) in self.pending_orders.pending.iter() {
-                for (_, order) in market.iter() {
-                    println!("\t\t{:?}", order);
-                }
+        // The buffer may have been sent to the database, but not written yet.
+        Some(store.read_client_order(self.id.unwrap(), client_id).unwrap_or_else(|| placed.clone()))
+    }
+
+    /* If a cancel used a client order id, find the exchange's id for the order. */
+    pub fn resolve_client_id(&self, cancel: &mut CancelOrder, buffers: &BufferCollection, store: &mut dyn Store) -> Result<(), String> {
+        if let Some(client_id) = &cancel.client_id {
+            match self.find_client_order(client_id, buffers, store) {
+                Some(order) => cancel.order_id = order.order_id,
+                None => return Err(format!["You have no order with client id {}!", client_id])
             }
-        } else {
-            println!("\n\tNo Orders awaiting Execution");
         }
+        Ok(())
+    }
+
+    /* Reads this account's executed trades to find its position in each market.
+     * After this, positions are kept up to date by record_position.
+     **/
+    pub fn load_positions(&mut self, cache: &mut dyn Cache) {
+        let portfolio = Portfolio::from_trades(&self.read_executed_trades(cache), CostBasis::AverageCost, &HashMap::new());
+        let positions = portfolio.positions.into_iter().map(|position| (position.symbol.clone(), position)).collect();
+        self.positions = Some(positions);
+    }
+
+    /* Apply a new trade to an account's positions (if they've been loaded).
+     * The trade's action must be the side the account was on.
+     *
+     * This takes the positions rather than &mut self, since we call it while
+     * the account's pending orders are borrowed.
+     **/
+    fn record_position(positions: &mut Option<HashMap<String, Position>>, trade: &Trade) {
+        if let Some(positions) = positions.as_mut() {
+            let signed = match trade.action.as_str() {
+                "BUY" => trade.exchanged,
+                _ => -trade.exchanged
+            };
+            let position = positions.entry(trade.symbol.clone()).or_insert_with(|| Position::new(&trade.symbol));
+            position.apply(signed, trade.price, CostBasis::AverageCost);
+        }
+    }
 
-        // TODO: Make a separate method/function that populates executed_trades, this is too many
-        // lines for this function IMO.
-        let client = redis::Client::open("redis://127.0.0.1/").expect("Failed to open redis");
-        let mut redis_conn = client.get_connection().expect("Failed to connect to redis");
+    /* Returns the number of shares held in this market, negative if short. */
+    pub fn position_in(&self, symbol: &str) -> i32 {
+        match self.positions.as_ref().and_then(|positions| positions.get(symbol)) {
+            Some(position) => position.quantity,
+            None => 0
+        }
+    }
 
+    /* Returns how many shares this account still wants to buy or sell in this market. */
+    pub fn pending_quantity(&self, symbol: &str, action: &str) -> i32 {
+        match self.pending_orders.view_market(symbol) {
+            Some(market) => market.values()
+                .filter(|order| order.action == action)
+                .map(|order| order.quantity - order.filled)
+                .sum(),
+            None => 0
+        }
+    }
+
+    /* Check that a sell order is allowed under the account's short selling settings.
+     *
+     * A sell is a short sale if, once it and every other pending sell in the market
+     * are filled, the account would hold fewer than 0 shares. Short sales need
+     *  1. short selling to be enabled on the account,
+     *  2. the short position to stay within the account's borrow limit,
+     *  3. enough equity to cover the initial margin on all short positions.
+     *
+     * When `order` replaces a pending order, pass that order as `replacing`
+     * so its remaining shares aren't counted as pending.
+     *
+     * Returns an error explaining why the order was rejected.
+     **/
+    pub fn validate_short_sale(&self, order: &Order, replacing: Option<&Order>, statistics: &HashMap<String, SecStat>, policy: &MarginPolicy) -> Result<(), OrderError> {
+        if order.action.as_str() != "SELL" {
+            return Ok(());
+        }
+
+        let positions = match self.positions.as_ref() {
+            Some(positions) => positions,
+            None => return Err(OrderError::PositionsUnavailable(self.username.clone()))
+        };
+
+        let held = self.position_in(&order.symbol);
+        let mut pending = self.pending_quantity(&order.symbol, "SELL");
+        if let Some(original) = replacing.filter(|original| original.action == "SELL" && original.symbol == order.symbol) {
+            pending -= original.quantity - original.filled;
+        }
+        let after = held - pending - order.quantity;
+        if after >= 0 {
+            return Ok(());
+        }
+
+        if !self.margin.short_enabled {
+            return Err(OrderError::ShortSaleDisabled { symbol: order.symbol.clone(), held });
+        }
+
+        if -after > self.margin.borrow_limit {
+            return Err(OrderError::BorrowLimit { symbol: order.symbol.clone(), short: -after, limit: self.margin.borrow_limit });
+        }
+
+        // Value the new short at whichever is higher, the order price or the last price.
+        let price = match statistics.get(&order.symbol).and_then(|stats| stats.last_price) {
+            Some(last_price) if last_price > order.price => last_price,
+            _ => order.price
+        };
+        let short_value = margin::short_market_value(positions, statistics, Some(&order.symbol)) + price * (-after) as f64;
+        let requirement = policy.initial * short_value;
+        let equity = margin::equity(&self.margin, positions, statistics);
+
+        if equity < requirement {
+            return Err(OrderError::InsufficientFunds { equity, requirement, short_value });
+        }
+        Ok(())
+    }
+
+    /* Reads every trade this account took part in.
+     * Trades that were flushed to the cache are read from the filler/filled lists,
+     * and any trades that occured since the user was cached are added on.
+     *
+     * The action of each trade is the side *this* account was on.
+     **/
+    pub fn read_executed_trades(&self, cache: &mut dyn Cache) -> Vec<Trade> {
         let mut executed_trades: Vec<Trade> = Vec::new();
 
-        let filler_list = format!["filler:{}", self.id.unwrap()];
-        let filled_list = format!["filled:{}", self.id.unwrap()];
         // We gather trades from both filled, and filler lists.
-        let lists = vec![filler_list, filled_list];
-        for list in lists {
-            let response: Result<Vec<String>, RedisError> = redis_conn.lrange(list, 0, -1);
-            match response {
-                Ok(trades) => {
-                    // Redis response is a string.
-                    // We parse it and extract the components.
-                    for trade in trades {
-                        let mut components = trade.split_whitespace();
-                        let symbol: &str    = components.next().unwrap();
-                        let action: &str    = components.next().unwrap();
-                        let price: f64      = components.next().unwrap().to_string().trim().parse::<f64>().unwrap();
-                        let filled_oid: i32 = components.next().unwrap().to_string().trim().parse::<i32>().unwrap();
-                        let filled_uid: i32 = components.next().unwrap().to_string().trim().parse::<i32>().unwrap();
-                        let filler_oid: i32 = components.next().unwrap().to_string().trim().parse::<i32>().unwrap();
-                        let filler_uid: i32 = components.next().unwrap().to_string().trim().parse::<i32>().unwrap();
-                        let exchanged: i32  = components.next().unwrap().to_string().trim().parse::<i32>().unwrap();
-                        let execution_time:
-                            DateTime<Utc>   = DateTime::parse_from_rfc3339(&components.next().unwrap().to_string().as_str()).unwrap().with_timezone(&Utc);
-
-                        // Build a Trade from the data and add it to the executed_trades.
-                        executed_trades.push(Trade::direct(symbol,
-                                                           action,
-                                                           price,
-                                                           filled_oid,
-                                                           filled_uid,
-                                                           filler_oid,
-                                                           filler_uid,
-                                                           exchanged,
-                                                           execution_time));
-                    }
-                },
-                Err(e) => {
-                    eprintln!("{}", e);
-                }
-            }
-
+        // Each is a string, we parse it and extract the components.
+        for trade in cache.read_trades(self.id.unwrap()) {
+            let mut components = trade.split_whitespace();
+            let symbol: &str    = components.next().unwrap();
+            let action: &str    = components.next().unwrap();
+            let price: f64      = components.next().unwrap().to_string().trim().parse::<f64>().unwrap();
+            let filled_oid: i64 = components.next().unwrap().to_string().trim().parse::<i64>().unwrap();
+            let filled_uid: i64 = components.next().unwrap().to_string().trim().parse::<i64>().unwrap();
+            let filler_oid: i64 = components.next().unwrap().to_string().trim().parse::<i64>().unwrap();
+            let filler_uid: i64 = components.next().unwrap().to_string().trim().parse::<i64>().unwrap();
+            let exchanged: i32  = components.next().unwrap().to_string().trim().parse::<i32>().unwrap();
+            let execution_time:
+                DateTime<Utc>   = DateTime::parse_from_rfc3339(components.next().unwrap().to_string().as_str()).unwrap().with_timezone(&Utc);
+
+            // Build a Trade from the data and add it to the executed_trades.
+            let sides = TradeSides { filled_oid, filled_uid, filler_oid, filler_uid };
+            executed_trades.push(Trade::direct(symbol,
+                                               action,
+                                               price,
+                                               sides,
+                                               exchanged,
+                                               execution_time));
         }
         // Get any trades that have occured since the user was cached.
-        if self.recent_trades.len() > 0 {
+        if !self.recent_trades.is_empty() {
             executed_trades.append(&mut (self.recent_trades.clone()));
         }
 
-        if executed_trades.len() > 0 {
-            println!("\n\tExecuted Trades");
-            for order in executed_trades.iter() {
-                println!("\t\t{:?}", order);
-            }
-        } else {
-            println!("\n\tNo Executed Trades to show");
-        }
-        println!("\n");
+        // Cached lists are newest first, show the trades in the order they happened.
+        executed_trades.sort_by_key(|trade| trade.execution_time);
+        executed_trades
     }
 
+    /* Builds this account's positions and P&L from its executed trades.
+     * Open positions are valued at the last traded price of each market.
+     **/
+    pub fn portfolio(&self, statistics: &HashMap<String, SecStat>, basis: CostBasis, cache: &mut dyn Cache) -> Portfolio {
+        let executed_trades = self.read_executed_trades(cache);
+        Portfolio::from_trades(&executed_trades, basis, statistics)
+    }
 
-    /* Update the redis cache active_markets:user_id.
-     * If we decrement a market to 0, then we remove it from the sorted set.
+    /* Shows the account information of this user
+     * if their account view is up to date.
      **/
-    fn redis_update_active_markets(&self, redis_conn: &mut redis::Connection) {
-        for (market, diff) in self.recent_markets.iter() {
-
-            let mut delete_required = false;
-            let response: Result<String, RedisError> = redis_conn.zincr(format!["active_markets:{}", self.id.unwrap()], market, *diff);
-
-            match response {
-                Ok(count) => {
-                    let count = count.trim().parse::<i32>().unwrap();
-                    if count == 0 {
-                        // Remove the value from the set.
-                        delete_required = true;
-                    } else if count < 0 {
-                        eprintln!("There is a bug in active_markets:{}. There are {} pending orders in our redis cache.", self.id.unwrap(), count);
-                        panic!("This is a bug, the programmer needs to find the bad logic!");
-                    }
-                },
-                Err(e) => {
-                    eprintln!("{}", e);
+    pub fn show_user(&self, statistics: &HashMap<String, SecStat>, basis: CostBasis, cache: &mut dyn Cache) -> String {
+        if !self.pending_orders.is_complete {
+            panic!("Tried to show_user who doesn't have complete pending order info!");
+        }
+        let mut lines: Vec<String> = Vec::new();
+
+        lines.push(format!["\nAccount information for user: {}", self.username]);
+        lines.push(format!["\tRoles: {}", roles::to_names(&self.roles).join(", ")]);
+        if self.status != AccountStatus::Active {
+            lines.push(format!["\tStatus: {}", self.status.as_str()]);
+        }
+
+        if !self.pending_orders.pending.is_empty() {
+            lines.push("\n\tOrders Awaiting Execution".to_string());
+            lines.push("\t\t| ID | Symbol | Action | Price \t| Quantity | Filled |".to_string());
+            lines.push("\t\t-------------------------------------------------------".to_string());
+            for (_, market) in self.pending_orders.pending.iter() {
+                for (_, order) in market.iter() {
+                    lines.push(format!["\t\t| {}\t{}\t {}\t  ${:.2}\t     {}\t  \t{}   |", order.order_id, order.symbol, order.action, order.price, order.quantity, order.filled]);
                 }
             }
+            lines.push("\t\t-------------------------------------------------------".to_string());
+        } else {
+            lines.push("\n\tNo Orders awaiting Execution".to_string());
+        }
+
+        let executed_trades = self.read_executed_trades(cache);
 
-            if delete_required {
-                let _: () = redis_conn.zrem(format!["active_markets:{}", self.id.unwrap()], market).unwrap();
+        lines.push(Portfolio::from_trades(&executed_trades, basis, statistics).show());
+
+        if !executed_trades.is_empty() {
+            lines.push("\n\tExecuted Trades".to_string());
+            lines.push("\t\t| Symbol | Action | Shares Exchanged | Price | Time |".to_string());
+            lines.push("\t\t------------------------------------------------------".to_string());
+            for trade in executed_trades.iter() {
+                lines.push(format!["\t\t| {}\t{}\t     {}\t  \t${:.2}\t{} |", trade.symbol, trade.action, trade.exchanged, trade.price, trade.execution_time.format("%Y-%m-%d %H:%M:%S")]);
             }
+            lines.push("\t\t------------------------------------------------------".to_string());
+        } else {
+            lines.push("\n\tNo Executed Trades to show".to_string());
         }
+        lines.push("\n".to_string());
+        lines.join("\n")
+    }
+
+
+    /* Write this account's recent_markets to active_markets:user_id right away, in a single batch,
+     * then start counting from 0 again. Markets left with no pending orders are removed from the set.
+     **/
+    pub fn sync_active_markets(&mut self, cache: &mut dyn Cache) {
+        cache.write_active_markets(self.id.unwrap(), &self.recent_markets);
+        self.recent_markets.clear();
     }
 
-    /* Flush the user's recent trades to Redis.
+    /* Flush the user's recent trades and recent markets to the cache.
      * We call this when users are evicted from cache,
      * including on program shutdown.
-     *
-     * TODO: Make 2 iterators, one for filled, one for filler,
-     *       then batch insert all trades into each list, rather
-     *       than do 1 request per trade.
      **/
-    fn flush_trades_to_redis(self, redis_conn: &mut redis::Connection) {
-
-        let filler_trades = self.recent_trades.iter().cloned().filter(|trade| trade.filler_uid == self.id.unwrap());
-        let filled_trades = self.recent_trades.iter().cloned().filter(|trade| trade.filled_uid == self.id.unwrap());
+    fn flush_to_cache(mut self, cache: &mut dyn Cache) {
+        self.sync_active_markets(cache);
 
-        // TODO: If we can figure out multiple item inserts, use these.
-        // let mut filler_args: Vec<String> = Vec::new();
-        // let mut filled_args: Vec<String> = Vec::new();
-
-        for trade in filler_trades {
+        let id = self.id.unwrap();
+        let mut filler_args: Vec<String> = Vec::new();
+        let mut filled_args: Vec<String> = Vec::new();
+        for trade in self.recent_trades.iter() {
             let args = format!["{} {} {} {} {} {} {} {} {}", trade.symbol,
                                                              trade.action,
                                                              trade.price,
@@ -373,31 +602,14 @@ You called validate_order on an account with in-complete pending order data.");
                                                              trade.filler_uid,
                                                              trade.exchanged,
                                                              trade.execution_time.to_rfc3339()];
-
-            let filler_response: Result<i32, RedisError> = redis_conn.lpush(&format!["filler:{}", self.id.unwrap()], args);
-            if let Err(e) =  filler_response {
-                eprintln!("{}", e);
+            if trade.filled_uid == id {
+                filled_args.push(args.clone());
             }
-            // filler_args.push(format!["{} {} {} {} {} {} {} {} {}", trade.symbol, trade.action, trade.price, trade.filled_oid, trade.filled_uid, trade.filler_oid, trade.filler_uid, trade.exchanged, time]);
-        }
-
-        for trade in filled_trades {
-            let args = format!["{} {} {} {} {} {} {} {} {}", trade.symbol,
-                                                             trade.action,
-                                                             trade.price,
-                                                             trade.filled_oid,
-                                                             trade.filled_uid,
-                                                             trade.filler_oid,
-                                                             trade.filler_uid,
-                                                             trade.exchanged,
-                                                             trade.execution_time.to_rfc3339()];
-
-            let filled_response: Result<i32, RedisError> = redis_conn.lpush(&format!["filled:{}", self.id.unwrap()], args);
-            if let Err(e) = filled_response {
-                eprintln!("{}", e);
+            if trade.filler_uid == id {
+                filler_args.push(args);
             }
-            // filled_args.push(format!["{} {} {} {} {} {} {} {} {}", trade.symbol, trade.action, trade.price, trade.filled_oid, trade.filled_uid, trade.filler_oid, trade.filler_uid, trade.exchanged, time]);
         }
+        cache.write_trades(id, &filler_args, &filled_args);
     }
 }
 
@@ -416,42 +628,47 @@ You called validate_order on an account with in-complete pending order data.");
 //          that data, we'll need to change our data structures.
 // ------------------------------------------------------------------------------------------------------
 pub struct Users {
-    users: HashMap<String, UserAccount>,
-    // TODO: This should be an LRU cache eventually
-    id_map: HashMap<i32, String>,   // maps user_id to username
-    pub redis_conn: redis::Connection,
-    total: i32,
+    users: UserCache,
+    id_map: HashMap<i64, String>,   // maps user_id to username, for the users in the cache
+    pub cache: Box<dyn Cache>,      // Accounts, sessions and trades that outlive the user cache
+    // Symbol -> usernames of accounts that are short in that market.
+    // Used to find the accounts to check when a market's price changes.
+    short_interest: HashMap<String, HashSet<String>>,
+    pub executions: Vec<Execution>  // Trades since the drop copy last published
 }
 
 impl Users {
 
-    pub fn new() -> Self {
-        // TODO: How do we want to decide what the max # users is?
-        let max_users = 1000;
-        let users: HashMap<String, UserAccount> = HashMap::with_capacity(max_users);
-        let id_map: HashMap<i32, String> = HashMap::with_capacity(max_users);
+    /* max_users is the capacity of the user cache. */
+    pub fn new(max_users: usize, cache: Box<dyn Cache>) -> Self {
+        let users = UserCache::new(max_users);
+        let id_map: HashMap<i64, String> = HashMap::with_capacity(max_users);
 
-        let client = redis::Client::open("redis://127.0.0.1/").expect("Failed to open redis");
-        let redis_conn = client.get_connection().expect("Failed to connect to redis");
 
         Users {
             users,
             id_map,
-            redis_conn,
-            total: 0
+            cache,
+            short_interest: HashMap::new(),
+            executions: Vec::new()
         }
     }
 
-    /* Update the total user count. */
-    pub fn direct_update_total(&mut self, conn: &mut Client) {
-        self.total = database::read_total_accounts(conn);
+    /* Set all UserAccount's modified field to false, once their modifications are sent in the given batch.
+     * They stay cached until the writer thread has written that batch, see confirm_writes.
+     **/
+    pub fn reset_users_modified(&mut self, batch: u64) {
+        for entry in self.users.values_mut() {
+            if entry.modified {
+                entry.modified = false;
+                entry.flushed_in = batch;
+            }
+        }
     }
 
-    /* Set all UserAccount's modified field to false. */
-    pub fn reset_users_modified(&mut self) {
-        for (_key, entry) in self.users.iter_mut() {
-            entry.modified = false;
-        }
+    /* Let the user cache know how many batches the writer thread has written. */
+    pub fn confirm_writes(&mut self, batches: u64) {
+        self.users.confirm_writes(batches);
     }
 
     /* TODO: Some later PR, create a new thread to make new accounts.
@@ -459,149 +676,275 @@ impl Users {
      * If an account with this username exists, do nothing, otherwise
      * add the account to the database and return it's ID.
      */
-    pub fn new_account(&mut self, account: UserAccount, conn: &mut Client) -> Option<i32> {
+    pub fn new_account(&mut self, account: UserAccount, store: &mut dyn Store) -> Option<i64> {
         // User is cached already
         if self.users.contains_key(&account.username) {
-            return None;
+            None
         } else {
 
             // Check if the user exists.
-            if let true = database::read_account_exists(&account.username, conn) {
+            if store.read_account_exists(&account.username) {
                 return None;
             }
 
             // User doesn't exist, so create a new one.
+            // We never store the password itself, only its hash.
             let mut account = account;
-            self.total = account.set_id(&self);
+            account.password = password::hash(&account.password);
+            let id = store.next_account_id();
+            account.id = Some(id);
+
+            // Someone has to be able to grant roles, so the first account becomes the admin.
+            if !store.read_admin_exists() {
+                account.roles.insert(Role::Admin);
+            }
 
             // Insert to db
-            match database::write_insert_new_account(&account, conn) {
+            match store.write_insert_new_account(&account) {
                 Ok(()) => {
-                    return Some(self.total);
+                    Some(id)
                 },
-                Err(()) => panic!("Something went wrong while inserting a new user!")
+                Err(e) => {
+                    eprintln!("{}", e);
+                    panic!("Something went wrong while inserting a new user!");
+                }
             }
         }
     }
 
-    pub fn print_auth_error(err: AuthError) {
-        match err {
-            AuthError::NoUser(user) => println!("Authentication failed! User ({}) not found.", user),
-            AuthError::BadPassword(message) => if let Some(msg) = message {
-                eprintln!("{}", msg);
-            } else {
-                eprintln!("Authentication failed! Incorrect password!")
-            }
+    /* Update the short selling settings of an account.
+     * Returns false if the account doesn't exist.
+     *
+     * The cached copy of the account is dropped rather than updated,
+     * it gets re-cached from the database the next time the user authenticates.
+     **/
+    pub fn set_margin_account(&mut self, username: &str, margin: MarginAccount, store: &mut dyn Store) -> bool {
+        if !store.write_update_margin_account(username, &margin) {
+            return false;
+        }
+
+        self.cache.delete_cached_account(username);
+        if let Some(account) = self.users.peek_mut(username) {
+            account.margin = margin;
         }
+        true
     }
 
-    /* Stores a user in the programs cache.
-     * If a user is successfully added to the cache, we return true, otherwise, return false.
+    /* Grant (or revoke) a role. Returns the account's roles afterwards,
+     * or None if the account doesn't exist.
+     *
+     * Like set_margin_account, the cached copy of the account is dropped rather than updated.
      **/
-    fn cache_user(&mut self, account: UserAccount) {
-        // Evict a user if we don't have space.
-        let capacity: f64 = self.users.capacity() as f64;
-        let count: f64 = self.users.len() as f64;
-        if capacity * 0.9 <= count {
-            // If no one good eviction candidates, force evictions.
-            if !self.evict_user(false) {
-                self.evict_user(true);
-            }
-        }
+    pub fn set_role(&mut self, username: &str, role: Role, grant: bool, store: &mut dyn Store) -> Option<HashSet<Role>> {
+        let names = store.write_update_role(username, role.as_str(), grant)?;
+        let updated = roles::from_names(&names);
 
-        self.id_map.insert(account.id.unwrap(), account.username.clone());
-        self.users.insert(account.username.clone(), account);
+        self.cache.delete_cached_account(username);
+        if let Some(account) = self.users.peek_mut(username) {
+            account.roles = updated.clone();
+        }
+        Some(updated)
     }
 
-    /* Evict a user from the cache.
-     * If a user was evicted successfully, return true, else return false.
+    /* Suspend, reactivate or close an account. Returns false if the account doesn't exist.
+     * Closing an account also ends its sessions.
      *
-     * We can only evict users who's modified fields are set to false.
-     * This is the only constraint on our cache eviction policy.
-     *
-     * We can have extremely simple cache eviction, ex, random or
-     * evict first candidate found.
-     *
-     * We could have extremely complicated cache eviction, ex.
-     *      - keep a ranking of users by likelihood they will be
-     *        modified again. Track things like:
-     *          - likelihood of an order being filled (track all orders in all markets).
-     *          - likelihood of *placing an order* again soon
-     *          - likelihood of cancelling an order soon
+     * Like set_margin_account, the cached copy of the account is dropped rather than updated.
+     **/
+    pub fn set_status(&mut self, username: &str, status: AccountStatus, store: &mut dyn Store) -> bool {
+        if !store.write_update_status(username, status.as_str()) {
+            return false;
+        }
+
+        self.cache.delete_cached_account(username);
+        if let Some(account) = self.users.peek_mut(username) {
+            account.status = status;
+        }
+        if status == AccountStatus::Closed {
+            self.revoke_sessions(username);
+        }
+        true
+    }
+
+    /* Change the password of an authenticated (so cached) account.
+     * Every session of the account ends, so they have to log in with the new password.
+     **/
+    pub fn change_password(&mut self, username: &str, new_password: &str, store: &mut dyn Store) {
+        let account = self.users.peek_mut(username).expect("Tried to change the password of an account that isn't cached!");
+        account.password = password::hash(new_password);
+        store.write_update_password(account.id.unwrap(), &account.password);
+
+        self.cache.delete_cached_account(username);
+        self.revoke_sessions(username);
+    }
+
+    /* Change the username of an authenticated (so cached) account.
      *
-     *  It remains to be seen if a basic cache eviction policy is good enough.
+     * Everything the exchange stores is keyed by user ID, except for the caches,
+     * so we move the account in both caches, and end its sessions.
+     **/
+    pub fn change_username(&mut self, username: &str, new_username: &str, store: &mut dyn Store) -> Result<(), String> {
+        if self.users.contains_key(new_username) || store.read_account_exists(new_username) {
+            return Err(format!["Sorry, the username {} is already taken!", new_username]);
+        }
+
+        let mut account = self.users.remove(username).expect("Tried to rename an account that isn't cached!");
+        let id = account.id.unwrap();
+        store.write_update_username(id, new_username);
+
+        account.username = new_username.to_string();
+        self.id_map.insert(id, new_username.to_string());
+        self.cache_user(account);
+        for accounts in self.short_interest.values_mut() {
+            if accounts.remove(username) {
+                accounts.insert(new_username.to_string());
+            }
+        }
+
+        self.cache.delete_cached_account(username);
+        self.cache.write_username(id, new_username);
+        self.revoke_sessions(username);
+        Ok(())
+    }
+
+    /* Returns the account with this username, caching it if it isn't already.
      *
-     * On cache eviction, write all recent_trades of the evicted user to Redis!
+     * This does NOT check a password! Only use it for accounts the exchange
+     * itself controls, like simulated traders, since password hashing is deliberately slow.
      **/
-    fn evict_user(&mut self, force_evict: bool) -> bool {
-        // POLICY: Delete first candidate
-        //     Itereate over all the entries, once we find one that's not modified, stop
-        //     iterating, make note of the key, then delete the entry.
+    pub fn get_mut_unauthenticated(&mut self, username: &str, store: &mut dyn Store) -> &mut UserAccount {
+        self._get_mut(username, store)
+    }
 
-        let mut key_to_evict: Option<i32> = None;
+    /* Keep the short interest index in line with an account's positions. */
+    fn track_short_interest(short_interest: &mut HashMap<String, HashSet<String>>, account: &UserAccount) {
+        if let Some(positions) = account.positions.as_ref() {
+            for (symbol, position) in positions.iter() {
+                if position.quantity < 0 {
+                    short_interest.entry(symbol.clone()).or_default().insert(account.username.clone());
+                } else if let Some(accounts) = short_interest.get_mut(symbol) {
+                    accounts.remove(&account.username);
+                }
+            }
+        }
+    }
 
-        for (_name, entry) in self.users.iter() {
-            if (!entry.pending_orders.is_complete) || force_evict {
-                key_to_evict = entry.id;
-                break;
+    /* Load the positions of a cached account if we don't have them yet. */
+    pub fn load_positions(&mut self, username: &str) {
+        if let Some(account) = self.users.peek_mut(username) {
+            if account.positions.is_none() {
+                account.load_positions(&mut *self.cache);
+                Users::track_short_interest(&mut self.short_interest, account);
             }
         }
+    }
 
-        // If we found a user we can evict
-        if let Some(key) = key_to_evict {
-            let username = self.id_map.remove(&key).unwrap(); // returns the value (username)
-            let evicted = self.users.remove(&username).unwrap();
+    /* Returns the usernames of every account we know to be short in this market. */
+    pub fn accounts_short_in(&self, symbol: &str) -> Vec<String> {
+        match self.short_interest.get(symbol) {
+            Some(accounts) => accounts.iter().cloned().collect(),
+            None => Vec::new()
+        }
+    }
 
-            // Write the cached data to redis
-            evicted.redis_update_active_markets(&mut self.redis_conn);
-            evicted.flush_trades_to_redis(&mut self.redis_conn);
-            return true;
+    /* Check that an account short in `symbol` still meets the maintenance margin.
+     * Returns Some(MarginCall) if the account's equity has fallen below the requirement.
+     *
+     * The account may have been evicted since it went short, so we re-cache it if needed.
+     **/
+    pub fn check_maintenance_margin(&mut self, username: &str, symbol: &str, statistics: &HashMap<String, SecStat>, policy: &MarginPolicy, store: &mut dyn Store) -> Option<MarginCall> {
+        self._get_mut(username, store);
+        let account = self.users.peek_mut(username).unwrap();
+        if account.positions.is_none() {
+            account.load_positions(&mut *self.cache);
         }
-        // Failed to evict a user.
-        return false;
+
+        let positions = account.positions.as_ref().unwrap();
+        let call = policy.maintenance_call(username, symbol, &account.margin, positions, statistics);
+
+        let account = self.users.peek(username).unwrap();
+        Users::track_short_interest(&mut self.short_interest, account);
+        call
     }
 
-    /* On shutdown, we flush all recent_trades and recent_markets to Redis. */
+    pub fn print_auth_error(err: AuthError) {
+        match err {
+            AuthError::NoUser(_) => println!("{}", err),
+            _ => eprintln!("{}", err)
+        }
+    }
+
+    /* Stores a user in the programs cache.
+     * Caching a user can evict others (see UserCache), on eviction we write
+     * all recent_trades and recent_markets of the evicted users to the cache!
+     **/
+    fn cache_user(&mut self, account: UserAccount) {
+        self.id_map.insert(account.id.unwrap(), account.username.clone());
+        for evicted in self.users.insert(account) {
+            self.id_map.remove(&evicted.id.unwrap());
+            evicted.flush_to_cache(&mut *self.cache);
+        }
+    }
+
+    /* Show the user cache's size, hit rate and evictions. */
+    pub fn show_cache_stats(&self) -> String {
+        self.users.show_stats()
+    }
+
+    /* On shutdown, we flush all recent_trades and recent_markets to the cache. */
     pub fn flush_user_cache(&mut self) {
         for user in self.users.values().cloned() {
-            user.redis_update_active_markets(&mut self.redis_conn);
-            user.flush_trades_to_redis(&mut self.redis_conn);
+            user.flush_to_cache(&mut *self.cache);
         }
     }
 
-    /* Check the redis cache for the user, on success we return Some(user),
+    /* Check the shared cache for the user, on success we return Some(user),
      * on failure we return None.
      **/
-    fn check_redis_user_cache(&mut self, username: &str) -> Result<Option<UserAccount>, RedisError> {
-        let response: Result<HashMap<String, String>, RedisError> = self.redis_conn.hgetall(format!["user:{}", username]);
-        match response {
-            Ok(map) => {
-                let id: i32;
-                let mut password = String::new();
-
-                if let Some(val) =  map.get("id") {
-                    id = val.trim().parse::<i32>().unwrap();
-                    password.push_str(map.get("password").unwrap());
-                    return Ok(Some(UserAccount::direct(id, username, &password)));
-                }
-                return Ok(None);
-            },
-            // Otherwise we got an err
-            Err(e) => return Err(e)
+    fn check_cached_account(&mut self, username: &str) -> Option<UserAccount> {
+        let map = self.cache.read_cached_account(username);
+        let id: i64;
+        let mut password = String::new();
+
+        if let Some(val) =  map.get("id") {
+            id = val.trim().parse::<i64>().unwrap();
+            password.push_str(map.get("password").unwrap());
+
+            // Users cached before we hashed passwords hold a plaintext password,
+            // and users cached before roles or account statuses existed don't have them.
+            // Drop them, so they get re-cached from the database.
+            if !password::is_hashed(&password) || !map.contains_key("roles") || !map.contains_key("status") {
+                self.cache.delete_cached_account(username);
+                return None;
+            }
+
+            let mut account = UserAccount::direct(id, username, &password);
+            let names: Vec<&str> = map.get("roles").unwrap().split(',').collect();
+            account.roles = roles::from_names(&names);
+            account.status = AccountStatus::from(map.get("status").unwrap());
+
+            // Accounts cached before short selling existed won't have these fields.
+            if let (Some(enabled), Some(limit), Some(collateral)) = (map.get("short_enabled"), map.get("borrow_limit"), map.get("collateral")) {
+                account.margin = MarginAccount::direct(enabled == "1",
+                                                       limit.trim().parse::<i32>().unwrap(),
+                                                       collateral.trim().parse::<f64>().unwrap());
+            }
+            return Some(account);
         }
+        None
     }
 
 
     /* Checks the user cache*/
-    fn auth_check_cache<'a>(&self, username: &'a String, password: & String) -> Result<(), AuthError<'a>> {
+    fn auth_check_cache<'a>(&mut self, username: &'a str, password: &str) -> Result<(), AuthError<'a>> {
         if let Some(account) = self.users.get(username) {
             // Found user in cache
-            if *password == account.password {
+            if password::verify(password, &account.password) {
                 return Ok(());
             }
             return Err(AuthError::BadPassword(None));
         }
-        return Err(AuthError::NoUser(username));
+        Err(AuthError::NoUser(username))
     }
 
     /* If the username exists and the password is correct,
@@ -614,14 +957,14 @@ impl Users {
      *       for the frontend to hold on to?
      *
      */
-    pub fn authenticate<'a>(&mut self, username: &'a String, password: &String, conn: &mut Client) -> Result<&mut UserAccount, AuthError<'a>> {
+    pub fn authenticate<'a>(&mut self, username: &'a str, password: &str, store: &mut dyn Store) -> Result<&mut UserAccount, AuthError<'a>> {
         // First, we check our in-memory cache
         let mut cache_miss = true;
-        let mut redis_miss = true;
+        let mut shared_miss = true;
         match self.auth_check_cache(username, password) {
             Ok(()) => {
                 cache_miss = false;
-                redis_miss = false;
+                shared_miss = false;
             }
             Err(e) => {
                 if let AuthError::BadPassword(_) = e {
@@ -630,48 +973,49 @@ impl Users {
             }
         }
 
-        // On cache miss, check redis.
+        // On cache miss, check the shared cache.
         if cache_miss {
-            match self.check_redis_user_cache(username.as_str()) {
-                Ok(acc) => {
-                    if let Some(account) = acc {
-                        // TODO: I don't like that we read the password into the program.
-                        // I would rather have it be checked in Redis like postgres does,
-                        // since they may do security better. But then again, I've heard
-                        // redis security isn't great.
-                        if &account.password == password {
-                            // Cache the user we found
-                            self.cache_user(account.clone());
-                            redis_miss = false;
-                        } else {
-                            return Err(AuthError::BadPassword(None));
-                        }
-                    }
-                },
-                Err(e) => {
-                    eprintln!("{}", e);
-                    panic!("Something went wrong with redis.");
+            if let Some(account) = self.check_cached_account(username) {
+                // The shared cache only holds the password hash, so we verify it here.
+                if password::verify(password, &account.password) {
+                    // Cache the user we found
+                    self.cache_user(account.clone());
+                    shared_miss = false;
+                } else {
+                    return Err(AuthError::BadPassword(None));
                 }
             }
         }
-        // On redis cache miss, check the database.
-        if redis_miss {
-            match database::read_auth_user(username, password, conn) {
+        // On shared cache miss, check the database.
+        if shared_miss {
+            match store.read_auth_user(username, password) {
                 // We got an account, move it into the cache.
                 Ok(account) => {
 
                     // Copy of the id
                     let id = account.id.unwrap();
+                    let margin = account.margin.clone();
+                    let password_hash = account.password.clone();
+                    let role_names = roles::to_names(&account.roles).join(",");
+                    let status = account.status.as_str();
 
                     // If we fail to cache the user, flush the buffers so we can evict users.
                     self.cache_user(account.clone());
 
-                    // Finally, cache the user in redis
+                    // Finally, cache the user in the shared cache
                     let id = id.to_string();
-                    let v = vec![   ("id", id.as_str()),
-                                    ("username", username),
-                                    ("password", password)];
-                    let _: () = self.redis_conn.hset_multiple(format!["user:{}", username], &v[..]).unwrap();
+                    let short_enabled = if margin.short_enabled { "1" } else { "0" };
+                    let borrow_limit = margin.borrow_limit.to_string();
+                    let collateral = margin.collateral.to_string();
+                    let v = [   ("id", id.as_str()),
+                                ("username", username),
+                                ("password", password_hash.as_str()),
+                                ("short_enabled", short_enabled),
+                                ("borrow_limit", borrow_limit.as_str()),
+                                ("collateral", collateral.as_str()),
+                                ("roles", role_names.as_str()),
+                                ("status", status)];
+                    self.cache.write_cached_account(username, &v[..]);
                 },
                 Err(e) => return Err(e)
             }
@@ -684,7 +1028,69 @@ impl Users {
         //  I believe this can be fixed by storing + accessing only 1 hashmap for a cache.
         //  Rather than taking &mut self, we can just take &mut HashMap.
         //  This will be fixed once I switch to userIDs instead of usernames.
-        return Ok(self.users.get_mut(username).unwrap());
+        let account = self.users.peek_mut(username).unwrap();
+        if account.status == AccountStatus::Closed {
+            return Err(AuthError::Closed(username.to_string()));
+        }
+        Ok(account)
+    }
+
+    /* Authenticate with a session token rather than a password.
+     * Every successful use pushes the session's expiry back.
+     **/
+    pub fn authenticate_session<'a>(&mut self, token: &str, store: &mut dyn Store) -> Result<&mut UserAccount, AuthError<'a>> {
+        let username = match self.cache.read_session(token) {
+            Some(username) => username,
+            None => return Err(AuthError::BadSession)
+        };
+
+        self.cache.touch_session(token, &username, session::SESSION_TTL);
+        let account = self._get_mut(&username, store);
+        if account.status == AccountStatus::Closed {
+            return Err(AuthError::Closed(username));
+        }
+        Ok(account)
+    }
+
+    /* Authenticate a request, whichever credentials it was sent with. */
+    pub fn authenticate_credentials<'a>(&mut self, credentials: &'a Credentials, store: &mut dyn Store) -> Result<&mut UserAccount, AuthError<'a>> {
+        match credentials {
+            Credentials::Password(username, password) => self.authenticate(username, password, store),
+            Credentials::Session(token) => self.authenticate_session(token, store)
+        }
+    }
+
+    /* Authenticate the sender of a request, then make sure one of their roles
+     * grants the permission the request needs.
+     **/
+    pub fn authorize<'a>(&mut self, credentials: &'a Credentials, permission: Permission, store: &mut dyn Store) -> Result<&mut UserAccount, AuthError<'a>> {
+        let account = self.authenticate_credentials(credentials, store)?;
+        if !account.has_permission(permission) {
+            return Err(AuthError::Forbidden(account.username.clone(), permission));
+        }
+        if account.status == AccountStatus::Suspended && permission == Permission::PlaceOrder {
+            return Err(AuthError::Suspended(account.username.clone()));
+        }
+        Ok(account)
+    }
+
+    /* Start a new session for the user, returning the session token. */
+    pub fn login<'a>(&mut self, username: &'a str, password: &str, store: &mut dyn Store) -> Result<String, AuthError<'a>> {
+        self.authenticate(username, password, store)?;
+
+        let token = session::generate_token();
+        self.cache.write_session(&token, username, session::SESSION_TTL);
+        Ok(token)
+    }
+
+    /* End every session of an account, ex. when its password changes. */
+    pub fn revoke_sessions(&mut self, username: &str) {
+        self.cache.delete_sessions(username);
+    }
+
+    /* End a session. Returns false if the session didn't exist (or already expired). */
+    pub fn logout(&mut self, token: &str) -> bool {
+        self.cache.delete_session(token)
     }
 
     /* Returns a reference to a user account if the user has been authenticated.
@@ -693,9 +1099,9 @@ impl Users {
      * Note: We don't do any database lookups here. The authentication function
      * is always called right before, and that cache's the user!
      */
-    pub fn get<'a>(&mut self, username: &'a String, authenticated: bool) -> Result<&UserAccount, AuthError<'a>> {
+    pub fn get<'a>(&mut self, username: &'a str, authenticated: bool) -> Result<&UserAccount, AuthError<'a>> {
         if authenticated {
-            match self.users.get(username) {
+            match self.users.peek(username) {
                 // Cached
                 Some(account) => return Ok(account),
                 // In database
@@ -705,7 +1111,7 @@ Be sure to call authenticate() before trying to get a reference to a user!")
             }
         }
         let err_msg = format!["Must authenticate before accessing account belonging to: ({})", username];
-        return Err(AuthError::BadPassword(Some(err_msg)));
+        Err(AuthError::BadPassword(Some(err_msg)))
     }
 
     /* Returns a reference to a user account if the user has been authenticated.
@@ -714,9 +1120,9 @@ Be sure to call authenticate() before trying to get a reference to a user!")
      * Note: We don't do any database lookups here. The authentication function
      * is always called right before, and that cache's the user!
      */
-    pub fn get_mut<'a>(&mut self, username: &'a String, authenticated: bool) -> Result<&mut UserAccount, AuthError<'a>> {
+    pub fn get_mut<'a>(&mut self, username: &'a str, authenticated: bool) -> Result<&mut UserAccount, AuthError<'a>> {
         if authenticated {
-            match self.users.get_mut(username) {
+            match self.users.peek_mut(username) {
                 Some(account) => return Ok(account),
                 None => panic!("\
 Attempted to get user that was not already cached.
@@ -724,7 +1130,7 @@ Be sure to call authenticate() before trying to get a reference to a user!")
             }
         }
         let err_msg = format!["Must authenticate before accessing account belonging to: ({})", username];
-        return Err(AuthError::BadPassword(Some(err_msg)));
+        Err(AuthError::BadPassword(Some(err_msg)))
     }
 
     /* For internal use only.
@@ -733,52 +1139,32 @@ Be sure to call authenticate() before trying to get a reference to a user!")
      * If the account is in the database, we construct a user, cache them, get the pending orders,
      * then return the UserAccount to the calling function.
      */
-    fn _get_mut(&mut self, username: &String, conn: &mut Client) -> &mut UserAccount {
+    fn _get_mut(&mut self, username: &str, store: &mut dyn Store) -> &mut UserAccount {
         match self.users.get_mut(username) {
             Some(_) => (),
             None => {
-                // TODO: First check redis, then check DB if redis fails.
-                let account: UserAccount;
-                let redis_response = match self.check_redis_user_cache(username.as_str()) {
-                    Ok(response) => response,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        panic!("Something went wrong while trying to get a user from Redis!")
+                // First check the shared cache, then check DB if it misses.
+                let account = match self.check_cached_account(username) {
+                    Some(account) => account,
+                    // If we didn't find the user in the shared cache, check DB.
+                    None => match store.read_account(username) {
+                        Some(acc) => acc,
+                        None => panic!("Something went wrong while trying to get a user from the database!")
                     }
                 };
-                // If we didn't find the user in Redis, check DB.
-                if let None = redis_response {
-                    account = match database::read_account(username, conn) {
-                        Ok(acc) => acc,
-                        Err(_) => panic!("Something went wrong while trying to get a user from the database!")
-                    };
-                } else {
-                    account = redis_response.unwrap();
-                }
 
                 self.cache_user(account.clone());
             }
         }
-        return self.users.get_mut(username).unwrap();
-    }
-
-    /* Returns a username if one is found. */
-    fn redis_get_id_map(&mut self, id: i32) -> Option<String> {
-        let response: Result<Option<String>, RedisError> = self.redis_conn.hget(format!["id:{}", id], "username");
-
-        if let Ok(potential_name) = response {
-            if let Some(name) = potential_name {
-                return Some(String::from(name));
-            }
-        }
-
-        return None;
+        self.users.peek_mut(username).unwrap()
     }
 
     /* Update this users pending_orders, and the Orders table.
      * We have 2 cases to consider, as explained in update_account_orders().
+     *
+     * Returns the user's username.
      **/
-    fn update_single_user(&mut self, buffers: &mut BufferCollection, id: i32, modified_orders: &Vec<Order>, trades: &Vec<Trade>, is_filler: bool, conn: &mut Client) {
+    fn update_single_user(&mut self, buffers: &mut BufferCollection, id: i64, modified_orders: &[Order], trades: &[Trade], is_filler: bool, store: &mut dyn Store) -> String {
         // TODO:
         //  At some point, we want to get the username by calling some helper access function.
         //  This new function will
@@ -789,40 +1175,40 @@ Be sure to call authenticate() before trying to get a reference to a user!")
         let username: String = match self.id_map.get(&id) {
             Some(name) => name.clone(),
             None => {
-                // Check redis for the user id -> username map
-                let response = self.redis_get_id_map(id);
-                // wasn't in redis, check the database.
-                if let None = response {
-                    let result = database::read_user_by_id(id, conn);
-                    if let Err(_) = result {
-                        panic!("Query to get user by id failed!");
-                    };
-
-                    // Store this in redis now.
-                    let _: () = self.redis_conn.hset(format!["id:{}", id], "username", result.as_ref().unwrap()).unwrap();
-                    result.unwrap()
-                } else {
-                    // name found in redis
-                    response.unwrap()
+                // Check the shared cache for the user id -> username map
+                match self.cache.read_username(id) {
+                    // name found in the shared cache
+                    Some(username) => username,
+                    // wasn't cached, check the database.
+                    None => {
+                        let username = match store.read_user_by_id(id) {
+                            Some(username) => username,
+                            None => panic!("Query to get user by id failed!")
+                        };
+
+                        // Cache this now.
+                        self.cache.write_username(id, &username);
+                        username
+                    }
                 }
             }
         };
 
         // Gives a mutable reference to cache.
-        let account = self._get_mut(&username, conn);
+        let account = self._get_mut(&username, store);
 
         // PER-6 set account modified to true because we're modifying their orders.
         account.modified = true;
 
         // Since we can't remove entries while iterating, store the key's here.
         // We know we won't need more than trade.len() entries.
-        let mut entries_to_remove: Vec<i32> = Vec::with_capacity(trades.len());
+        let mut entries_to_remove: Vec<i64> = Vec::with_capacity(trades.len());
 
         // constant strings
         const BUY: &str = "BUY";
         const SELL: &str = "SELL";
 
-        let account_market = account.pending_orders.get_mut_market(&trades[0].symbol.as_str());
+        let account_market = account.pending_orders.get_mut_market(trades[0].symbol.as_str());
 
         // Iterate over the trades, storing them + modifying orders in the users
         // respective accounts and the buffers.
@@ -841,11 +1227,13 @@ Be sure to call authenticate() before trying to get a reference to a user!")
                 }
 
                 // Since this account is the filler, we know every trade belongs to them
+                UserAccount::record_position(&mut account.positions, &update_trade);
                 account.recent_trades.push(update_trade);
             } else {
                 // If this user placed the order that was filled,
                 // add the trade to their account.
                 if update_trade.filled_uid == account.id.unwrap() {
+                    UserAccount::record_position(&mut account.positions, &update_trade);
                     account.recent_trades.push(update_trade);
                 }
             }
@@ -858,8 +1246,8 @@ Be sure to call authenticate() before trying to get a reference to a user!")
                     if !is_filler && (trade.exchanged == (order.quantity - order.filled)) {
                         // Add/update this completed order in the database buffer.
                         order.status = OrderStatus::COMPLETE;
-                        order.filled = order.quantity;
-                        buffers.buffered_orders.add_or_update_entry_in_order_buffer(&order, true); // PER-5 update
+                        order.record_fill(trade.exchanged, trade.price);
+                        buffers.buffered_orders.add_or_update_entry_in_order_buffer(order, true); // PER-5 update
 
                         entries_to_remove.push(order.order_id);
                         // Get the entry in the recent_markets map, we want to decrement it by 1.
@@ -868,10 +1256,10 @@ Be sure to call authenticate() before trying to get a reference to a user!")
                     } else if !is_filler {
                         // Don't update the filler's filled count,
                         // new orders are added to accounts in submit_order_to_market.
-                        order.filled += trade.exchanged;
+                        order.record_fill(trade.exchanged, trade.price);
 
                         // Add/update this pre-existing pending order to the database buffer.
-                        buffers.buffered_orders.add_or_update_entry_in_order_buffer(&order, true); // PER-5 update
+                        buffers.buffered_orders.add_or_update_entry_in_order_buffer(order, true); // PER-5 update
                     }
                 },
                 // Order not found in users in-mem account, this is because
@@ -886,7 +1274,7 @@ Be sure to call authenticate() before trying to get a reference to a user!")
                             } else {
                                 *market_diff -= 1;
                             }
-                            buffers.buffered_orders.add_or_update_entry_in_order_buffer(&order, true);
+                            buffers.buffered_orders.add_or_update_entry_in_order_buffer(order, true);
                             break;
                         }
                     }
@@ -898,12 +1286,16 @@ Be sure to call authenticate() before trying to get a reference to a user!")
         for i in &entries_to_remove {
             account_market.remove(i);
         }
+
+        let account = self.users.peek(&username).unwrap();
+        Users::track_short_interest(&mut self.short_interest, account);
+        username
     }
 
     /* Given a vector of Trades, update all the accounts
      * that had orders filled.
      */
-    pub fn update_account_orders(&mut self, modified_orders: &mut Vec<Order>, trades: &mut Vec<Trade>, buffers: &mut BufferCollection, conn: &mut Client) {
+    pub fn update_account_orders(&mut self, modified_orders: &mut [Order], trades: &mut Vec<Trade>, buffers: &mut BufferCollection, store: &mut dyn Store) {
 
         /* All orders in the vector were filled by 1 new order,
          * so we have to handle 2 cases.
@@ -912,7 +1304,7 @@ Be sure to call authenticate() before trying to get a reference to a user!")
          **/
 
         // Map of {users: freshly executed trades}
-        let mut update_map: HashMap<i32, Vec<Trade>> = HashMap::new();
+        let mut update_map: HashMap<i64, Vec<Trade>> = HashMap::new();
 
         // Fill update_map
         for trade in trades.iter() {
@@ -922,11 +1314,22 @@ Be sure to call authenticate() before trying to get a reference to a user!")
 
         // Case 1
         // TODO: This is a good candidate for multithreading.
+        let mut usernames: HashMap<i64, String> = HashMap::with_capacity(update_map.len());
         for (user_id, new_trades) in update_map.iter() {
-            self.update_single_user(buffers, *user_id, modified_orders, new_trades, false, conn);
+            let username = self.update_single_user(buffers, *user_id, modified_orders, new_trades, false, store);
+            usernames.insert(*user_id, username);
         }
         // Case 2: update account who placed order that filled others.
-        self.update_single_user(buffers, trades[0].filler_uid, modified_orders, trades, true, conn);
+        let filler_username = self.update_single_user(buffers, trades[0].filler_uid, modified_orders, trades, true, store);
+
+        // Copy the trades to the drop copy.
+        for trade in trades.iter() {
+            self.executions.push(Execution {
+                trade: trade.clone(),
+                filled_username: usernames[&trade.filled_uid].clone(),
+                filler_username: filler_username.clone()
+            });
+        }
 
         // Add this trade to the trades database buffer.
         buffers.buffered_trades.add_trades_to_buffer(trades); // PER-5 update
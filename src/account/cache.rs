@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::account::UserAccount;
+
+// An account in the cache, and when it was last used.
+#[derive(Debug)]
+struct CacheEntry {
+    account: UserAccount,
+    last_used: u64
+}
+
+/* An LRU cache of user accounts, keyed by username.
+ *
+ * Every use of an account moves it to the back of the recency list.
+ * When the cache is over capacity, we evict the least recently used accounts first,
+ * but accounts with modifications that haven't been written to the database yet are never evicted.
+ * That includes accounts whose modifications were sent to the writer thread in a batch it hasn't finished,
+ * otherwise reloading one from the database could miss orders it just placed.
+ * If every account is modified, the cache grows past its capacity until the buffers are flushed.
+ *
+ * Lookups through get/get_mut count as hits or misses, peek/peek_mut don't
+ * (they're for internal bookkeeping on accounts we already know are cached).
+ **/
+#[derive(Debug)]
+pub struct UserCache {
+    capacity: usize,
+    entries: HashMap<String, CacheEntry>,
+    recency: BTreeMap<u64, String>,   // last_used -> username, least recently used first
+    clock: u64,                         // Incremented on every use, so last_used values are unique
+    written: u64,                       // Batches the writer thread has finished, see UserAccount.flushed_in
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64
+}
+
+impl UserCache {
+    pub fn new(capacity: usize) -> Self {
+        UserCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            recency: BTreeMap::new(),
+            clock: 0,
+            written: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn contains_key(&self, username: &str) -> bool {
+        self.entries.contains_key(username)
+    }
+
+    /* Mark an account as the most recently used. */
+    fn touch(&mut self, username: &str) {
+        if let Some(entry) = self.entries.get_mut(username) {
+            self.recency.remove(&entry.last_used);
+            self.clock += 1;
+            entry.last_used = self.clock;
+            self.recency.insert(self.clock, username.to_string());
+        }
+    }
+
+    /* Look up an account, counting a hit or a miss. */
+    fn lookup(&mut self, username: &str) -> Option<&mut UserAccount> {
+        if self.entries.contains_key(username) {
+            self.hits += 1;
+            self.touch(username);
+            return self.entries.get_mut(username).map(|entry| &mut entry.account);
+        }
+        self.misses += 1;
+        None
+    }
+
+    pub fn get(&mut self, username: &str) -> Option<&UserAccount> {
+        self.lookup(username).map(|account| &*account)
+    }
+
+    pub fn get_mut(&mut self, username: &str) -> Option<&mut UserAccount> {
+        self.lookup(username)
+    }
+
+    pub fn peek(&self, username: &str) -> Option<&UserAccount> {
+        self.entries.get(username).map(|entry| &entry.account)
+    }
+
+    pub fn peek_mut(&mut self, username: &str) -> Option<&mut UserAccount> {
+        self.entries.get_mut(username).map(|entry| &mut entry.account)
+    }
+
+    /* Cache an account as the most recently used,
+     * returning the accounts that were evicted to make room for it.
+     **/
+    pub fn insert(&mut self, account: UserAccount) -> Vec<UserAccount> {
+        let username = account.username.clone();
+        self.remove(&username);
+
+        self.clock += 1;
+        self.recency.insert(self.clock, username.clone());
+        self.entries.insert(username.clone(), CacheEntry { account, last_used: self.clock });
+        self.evict(&username)
+    }
+
+    pub fn remove(&mut self, username: &str) -> Option<UserAccount> {
+        let entry = self.entries.remove(username)?;
+        self.recency.remove(&entry.last_used);
+        Some(entry.account)
+    }
+
+    /* The writer thread has written this many batches, accounts flushed in them can be evicted. */
+    pub fn confirm_writes(&mut self, batches: u64) {
+        self.written = batches;
+    }
+
+    // True if the account has modifications that aren't in the database yet.
+    fn is_unwritten(&self, account: &UserAccount) -> bool {
+        account.modified || self.written < account.flushed_in
+    }
+
+    /* Evict the least recently used unmodified accounts until we're within capacity.
+     * The account we just inserted is kept, the caller is about to use it.
+     **/
+    fn evict(&mut self, keep: &str) -> Vec<UserAccount> {
+        let mut evicted = Vec::new();
+        if self.entries.len() <= self.capacity {
+            return evicted;
+        }
+
+        let over = self.entries.len() - self.capacity;
+        let candidates: Vec<String> = self.recency.values()
+            .filter(|username| username.as_str() != keep && !self.is_unwritten(&self.entries[*username].account))
+            .take(over)
+            .cloned()
+            .collect();
+
+        for username in candidates.iter() {
+            if let Some(account) = self.remove(username) {
+                self.evictions += 1;
+                evicted.push(account);
+            }
+        }
+        evicted
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &UserAccount> {
+        self.entries.values().map(|entry| &entry.account)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut UserAccount> {
+        self.entries.values_mut().map(|entry| &mut entry.account)
+    }
+
+    pub fn show_stats(&self) -> String {
+        let lookups = self.hits + self.misses;
+        let hit_rate = if lookups == 0 { 0.0 } else { 100.0 * self.hits as f64 / lookups as f64 };
+        let mut lines: Vec<String> = Vec::new();
+        lines.push(format!["\nUser cache: {} / {} account(s)", self.entries.len(), self.capacity]);
+        lines.push(format!["\tHits: {}\tMisses: {}\tHit rate: {:.1}%", self.hits, self.misses, hit_rate]);
+        lines.push(format!["\tEvictions: {}", self.evictions]);
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(username: &str, modified: bool) -> UserAccount {
+        let mut account = UserAccount::from(username, "password");
+        account.modified = modified;
+        account
+    }
+
+    fn usernames(accounts: &[UserAccount]) -> Vec<&str> {
+        accounts.iter().map(|account| account.username.as_str()).collect()
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut cache = UserCache::new(2);
+        assert!(cache.insert(account("alice", false)).is_empty());
+        assert!(cache.insert(account("bob", false)).is_empty());
+
+        // Using alice makes bob the least recently used.
+        assert!(cache.get("alice").is_some());
+        assert_eq!(usernames(&cache.insert(account("carol", false))), vec!["bob"]);
+        assert!(cache.contains_key("alice") && cache.contains_key("carol"));
+
+        // Peeking doesn't count as a use.
+        assert!(cache.peek("alice").is_some());
+        assert_eq!(usernames(&cache.insert(account("dave", false))), vec!["alice"]);
+
+        assert!(cache.get("alice").is_none());
+        assert_eq!((cache.hits, cache.misses, cache.evictions), (1, 1, 2));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn reinserting_an_account_replaces_it() {
+        let mut cache = UserCache::new(2);
+        cache.insert(account("alice", false));
+        cache.insert(account("bob", false));
+        assert!(cache.insert(account("alice", true)).is_empty());
+        assert!(cache.peek("alice").unwrap().modified);
+
+        // And makes it the most recently used.
+        assert_eq!(usernames(&cache.insert(account("carol", false))), vec!["bob"]);
+    }
+
+    #[test]
+    fn modified_accounts_are_never_evicted() {
+        let mut cache = UserCache::new(2);
+        cache.insert(account("alice", true));
+        cache.insert(account("bob", true));
+
+        // There's nothing we can evict, so the cache grows past its capacity.
+        assert!(cache.insert(account("carol", false)).is_empty());
+        assert_eq!(cache.len(), 3);
+
+        // The account just inserted isn't evicted, though it's unmodified and the cache is still over.
+        assert_eq!(usernames(&cache.insert(account("dave", false))), vec!["carol"]);
+        assert_eq!(cache.len(), 3);
+
+        // Once bob is written to the database he can go, alice never does.
+        cache.peek_mut("bob").unwrap().modified = false;
+        assert_eq!(usernames(&cache.insert(account("erin", false))), vec!["bob", "dave"]);
+        assert!(cache.contains_key("alice") && cache.contains_key("erin"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn accounts_are_kept_until_their_batch_is_written() {
+        let mut cache = UserCache::new(1);
+        let mut alice = account("alice", false);
+        alice.flushed_in = 1;
+        cache.insert(alice);
+
+        // alice's orders were sent in batch 1, which the writer hasn't finished.
+        assert!(cache.insert(account("bob", false)).is_empty());
+        assert_eq!(cache.len(), 2);
+
+        cache.confirm_writes(1);
+        assert_eq!(usernames(&cache.insert(account("carol", false))), vec!["alice", "bob"]);
+        assert!(cache.contains_key("carol"));
+    }
+}
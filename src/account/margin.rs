@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use crate::account::portfolio::Position;
+use crate::exchange::stats::SecStat;
+
+/* The short selling settings of a single account.
+ * These live in the Account table, and are cached alongside the account.
+ *
+ * An account that does not have short selling enabled can only sell
+ * shares that it holds (minus any shares it is already trying to sell).
+ **/
+#[derive(Debug, Clone, Default)]
+pub struct MarginAccount {
+    pub short_enabled: bool,
+    pub borrow_limit: i32,  // The most shares this account may be short in a single market.
+    pub collateral: f64,    // Cash posted by the account to cover its margin requirement.
+}
+
+impl MarginAccount {
+    pub fn direct(short_enabled: bool, borrow_limit: i32, collateral: f64) -> Self {
+        MarginAccount {
+            short_enabled,
+            borrow_limit,
+            collateral
+        }
+    }
+}
+
+// What the exchange does when an account falls below its maintenance margin.
+#[derive(Debug, Copy, Clone)]
+pub enum MarginCallAction {
+    Flag,       // Report the margin call and let the account fix it.
+    Liquidate   // Buy back the short position on the account's behalf.
+}
+
+impl MarginCallAction {
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "flag" => Some(MarginCallAction::Flag),
+            "liquidate" => Some(MarginCallAction::Liquidate),
+            _ => None
+        }
+    }
+}
+
+/* Exchange wide margin rules.
+ *  - initial: a short sale is only accepted if equity covers this fraction of
+ *             the short market value after the sale.
+ *  - maintenance: once short, equity must stay above this fraction of the short
+ *                 market value, otherwise the account gets a margin call.
+ **/
+#[derive(Debug, Clone)]
+pub struct MarginPolicy {
+    pub initial: f64,
+    pub maintenance: f64,
+    pub action: MarginCallAction
+}
+
+impl MarginPolicy {
+    pub fn new(initial: f64, maintenance: f64, action: MarginCallAction) -> Self {
+        MarginPolicy {
+            initial,
+            maintenance,
+            action
+        }
+    }
+
+    /* The margin call of an account whose equity is below the maintenance margin on its short positions, if it is.
+     * `symbol` is the market whose price update we're checking for.
+     **/
+    pub fn maintenance_call(&self, username: &str, symbol: &str, margin: &MarginAccount, positions: &HashMap<String, Position>, statistics: &HashMap<String, SecStat>) -> Option<MarginCall> {
+        let short_value = short_market_value(positions, statistics, None);
+        let equity = equity(margin, positions, statistics);
+        let requirement = self.maintenance * short_value;
+
+        if short_value > 0.0 && equity < requirement {
+            Some(MarginCall {
+                username: username.to_string(),
+                symbol: symbol.to_string(),
+                equity,
+                requirement,
+                short_value
+            })
+        } else {
+            None
+        }
+    }
+}
+
+// An account that has dropped below its maintenance margin.
+#[derive(Debug, Clone)]
+pub struct MarginCall {
+    pub username: String,
+    pub symbol: String,     // The market whose price update triggered the call.
+    pub equity: f64,
+    pub requirement: f64,
+    pub short_value: f64
+}
+
+/* Value a position at the last traded price of its market.
+ * If the market has never traded, we fall back to what the account paid.
+ **/
+fn mark(position: &Position, statistics: &HashMap<String, SecStat>) -> f64 {
+    match statistics.get(&position.symbol).and_then(|stats| stats.last_price) {
+        Some(price) => price,
+        None => position.average_cost
+    }
+}
+
+/* The equity of an account is its collateral, plus everything it has
+ * made or lost on its positions so far.
+ **/
+pub fn equity(margin: &MarginAccount, positions: &HashMap<String, Position>, statistics: &HashMap<String, SecStat>) -> f64 {
+    let mut equity = margin.collateral;
+    for position in positions.values() {
+        let price = mark(position, statistics);
+        equity += position.realised + (price - position.average_cost) * position.quantity as f64;
+    }
+    equity
+}
+
+/* The market value of all the short positions of an account, as a positive number.
+ * If `skip` is Some(symbol), that market is left out.
+ **/
+pub fn short_market_value(positions: &HashMap<String, Position>, statistics: &HashMap<String, SecStat>, skip: Option<&str>) -> f64 {
+    positions.values()
+        .filter(|position| position.quantity < 0 && Some(position.symbol.as_str()) != skip)
+        .map(|position| mark(position, statistics) * (-position.quantity) as f64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::portfolio::CostBasis;
+
+    fn positions(trades: &[(&str, i32, f64)]) -> HashMap<String, Position> {
+        let mut positions: HashMap<String, Position> = HashMap::new();
+        for (symbol, signed, price) in trades.iter() {
+            positions.entry(symbol.to_string()).or_insert_with(|| Position::new(symbol)).apply(*signed, *price, CostBasis::Fifo);
+        }
+        positions
+    }
+
+    fn prices(prices: &[(&str, f64)]) -> HashMap<String, SecStat> {
+        prices.iter().map(|(symbol, price)| (symbol.to_string(), SecStat::direct(symbol, 0, 0, 0, 0, Some(*price)))).collect()
+    }
+
+    fn call(positions: &HashMap<String, Position>, statistics: &HashMap<String, SecStat>) -> Option<MarginCall> {
+        let policy = MarginPolicy::new(0.5, 0.25, MarginCallAction::Flag);
+        policy.maintenance_call("bob", "GME", &MarginAccount::direct(true, 100, 1000.0), positions, statistics)
+    }
+
+    #[test]
+    fn equity_includes_realised_and_unrealised_pnl() {
+        let positions = positions(&[("GME", 10, 100.0), ("GME", -5, 110.0), ("AAPL", -10, 50.0)]);
+        let statistics = prices(&[("GME", 120.0), ("AAPL", 60.0)]);
+        // 1000 collateral, 50 realised on GME, 100 unrealised on GME and -100 on AAPL.
+        assert_eq!(equity(&MarginAccount::direct(true, 100, 1000.0), &positions, &statistics), 1050.0);
+        assert_eq!(short_market_value(&positions, &statistics, None), 600.0);
+        assert_eq!(short_market_value(&positions, &statistics, Some("AAPL")), 0.0);
+    }
+
+    #[test]
+    fn unpriced_positions_are_valued_at_cost() {
+        let positions = positions(&[("GME", -10, 100.0)]);
+        assert_eq!(short_market_value(&positions, &HashMap::new(), None), 1000.0);
+        assert_eq!(equity(&MarginAccount::direct(true, 100, 1000.0), &positions, &HashMap::new()), 1000.0);
+    }
+
+    #[test]
+    fn accounts_at_the_maintenance_margin_are_not_called() {
+        // Short 10 from 100: at 160 equity is 1000 - 600 = 400, and the requirement 25% of 1600.
+        let positions = positions(&[("GME", -10, 100.0)]);
+        assert!(call(&positions, &prices(&[("GME", 150.0)])).is_none());
+        assert!(call(&positions, &prices(&[("GME", 160.0)])).is_none());
+
+        let call = call(&positions, &prices(&[("GME", 160.5)])).unwrap();
+        assert_eq!((call.username.as_str(), call.symbol.as_str()), ("bob", "GME"));
+        assert_eq!(call.equity, 395.0);
+        assert_eq!(call.requirement, 401.25);
+        assert_eq!(call.short_value, 1605.0);
+    }
+
+    #[test]
+    fn accounts_without_short_positions_are_not_called() {
+        // However much the long position has lost.
+        let positions = positions(&[("GME", 100, 100.0), ("AAPL", -10, 50.0), ("AAPL", 10, 40.0)]);
+        assert!(call(&positions, &prices(&[("GME", 1.0), ("AAPL", 500.0)])).is_none());
+    }
+}
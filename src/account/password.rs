@@ -0,0 +1,96 @@
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::password_hash::rand_core::OsRng;
+
+/* Passwords are stored as salted Argon2id hashes in PHC string format, ex.
+ *      $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+ * The salt and parameters live in the string, so verifying only needs the stored value.
+ *
+ * Accounts created before we hashed passwords stored them in plaintext.
+ * These get hashed on startup (see database::write_hash_plaintext_passwords),
+ * but until then we still let them log in.
+ **/
+
+/* Hash a password with a fresh random salt. */
+pub fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => hash.to_string(),
+        Err(e) => {
+            eprintln!("{}", e);
+            panic!("Failed to hash a password!");
+        }
+    }
+}
+
+/* Returns true if the stored value is a password hash rather than a plaintext password. */
+pub fn is_hashed(stored: &str) -> bool {
+    stored.starts_with("$argon2")
+}
+
+/* Check a password against the stored value in constant time. */
+pub fn verify(password: &str, stored: &str) -> bool {
+    if !is_hashed(stored) {
+        return constant_time_eq(password.as_bytes(), stored.as_bytes());
+    }
+
+    match PasswordHash::new(stored) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(e) => {
+            eprintln!("Stored password hash is malformed: {}", e);
+            false
+        }
+    }
+}
+
+/* Only used for legacy plaintext passwords.
+ * Every byte is compared, so the time taken doesn't depend on where the first mismatch is.
+ **/
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::UserAccount;
+    use crate::database::{AccountStore, MemoryStore};
+
+    #[test]
+    fn hashes_verify_their_password() {
+        let stored = hash("hunter2");
+        assert!(is_hashed(&stored) && stored.starts_with("$argon2id$"));
+        assert!(verify("hunter2", &stored));
+        assert!(!verify("hunter3", &stored));
+
+        // Every hash gets its own salt.
+        assert_ne!(hash("hunter2"), stored);
+        assert!(!verify("hunter2", "$argon2id$not-a-hash"));
+    }
+
+    #[test]
+    fn plaintext_passwords_still_verify() {
+        assert!(!is_hashed("hunter2"));
+        assert!(verify("hunter2", "hunter2"));
+        assert!(!verify("hunter", "hunter2") && !verify("hunter3", "hunter2"));
+    }
+
+    #[test]
+    fn plaintext_passwords_are_hashed_on_login() {
+        let mut store = MemoryStore::new();
+        let id = store.next_account_id();
+        store.write_insert_new_account(&UserAccount::direct(id, "bob", "hunter2")).unwrap();
+
+        // A failed login leaves the password alone.
+        assert!(store.read_auth_user("bob", "hunter3").is_err());
+        assert_eq!(store.read_account("bob").unwrap().password, "hunter2");
+
+        assert!(store.read_auth_user("bob", "hunter2").is_ok());
+        let stored = store.read_account("bob").unwrap().password;
+        assert!(is_hashed(&stored) && verify("hunter2", &stored));
+        assert!(store.read_auth_user("bob", "hunter2").is_ok());
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::exchange::filled::Trade;
+use crate::exchange::stats::SecStat;
+
+/* How we decide the cost of the shares that a trade closes out.
+ *  - Fifo: the oldest open lot is closed first.
+ *  - AverageCost: all open shares share one weighted average cost.
+ **/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CostBasis {
+    Fifo,
+    AverageCost
+}
+
+impl CostBasis {
+    pub fn from(word: &str) -> Option<Self> {
+        match word {
+            "fifo" => Some(CostBasis::Fifo),
+            "avg" | "average" => Some(CostBasis::AverageCost),
+            _ => None
+        }
+    }
+}
+
+/* A lot is a group of shares that were opened at the same price.
+ * Quantity is negative for short lots.
+ **/
+#[derive(Debug, Clone)]
+struct Lot {
+    quantity: i32,
+    price: f64
+}
+
+// An account's holdings in a single market.
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub symbol: String,
+    pub quantity: i32,          // Shares held, negative if the account has sold more than it bought.
+    pub average_cost: f64,      // Average cost of the shares still held.
+    pub realised: f64,          // Profit/loss locked in by trades that closed shares.
+    pub unrealised: f64,        // Profit/loss of the open shares, marked to the last traded price.
+    pub last_price: Option<f64>,
+    lots: VecDeque<Lot>         // Open lots, oldest first.
+}
+
+impl Position {
+    pub fn new(symbol: &str) -> Self {
+        Position {
+            symbol: symbol.to_string(),
+            quantity: 0,
+            average_cost: 0.0,
+            realised: 0.0,
+            unrealised: 0.0,
+            last_price: None,
+            lots: VecDeque::new()
+        }
+    }
+
+    /* Apply a trade to this position.
+     * `signed` is the number of shares this account received (negative when selling).
+     *
+     * Shares that move the position away from 0 open a new lot,
+     * shares that move it towards 0 close existing lots and realise P&L.
+     **/
+    pub fn apply(&mut self, signed: i32, price: f64, basis: CostBasis) {
+        let mut remaining = signed;
+
+        // Close lots while the trade is on the opposite side of the position.
+        while remaining != 0 && self.quantity != 0 && (self.quantity > 0) != (remaining > 0) {
+            let lot = self.lots.front_mut().unwrap();
+            let closed = std::cmp::min(lot.quantity.abs(), remaining.abs());
+            let direction = lot.quantity.signum();
+
+            // Long lots profit when we sell higher, short lots profit when we buy lower.
+            self.realised += (price - lot.price) * (closed * direction) as f64;
+
+            lot.quantity -= closed * direction;
+            self.quantity -= closed * direction;
+            remaining += closed * direction;
+
+            if lot.quantity == 0 {
+                self.lots.pop_front();
+            }
+        }
+
+        // Whatever is left opens (or adds to) the position.
+        if remaining != 0 {
+            match (basis, self.lots.back_mut()) {
+                (CostBasis::AverageCost, Some(lot)) => {
+                    let total = lot.quantity + remaining;
+                    lot.price = (lot.price * lot.quantity as f64 + price * remaining as f64) / total as f64;
+                    lot.quantity = total;
+                },
+                _ => self.lots.push_back(Lot { quantity: remaining, price })
+            }
+            self.quantity += remaining;
+        }
+
+        self.update_average_cost();
+    }
+
+    fn update_average_cost(&mut self) {
+        if self.quantity == 0 {
+            self.average_cost = 0.0;
+            return;
+        }
+        let cost: f64 = self.lots.iter().map(|lot| lot.price * lot.quantity as f64).sum();
+        self.average_cost = cost / self.quantity as f64;
+    }
+
+    /* Set the unrealised P&L of the open shares using the latest price.
+     * If the market has never traded we can't value the shares, so it stays at 0.
+     **/
+    fn mark_to_market(&mut self, last_price: Option<f64>) {
+        self.last_price = last_price;
+        self.unrealised = match last_price {
+            Some(price) => (price - self.average_cost) * self.quantity as f64,
+            None => 0.0
+        };
+    }
+
+    pub fn market_value(&self) -> Option<f64> {
+        self.last_price.map(|price| price * self.quantity as f64)
+    }
+}
+
+/* A summary of an account's positions, built from its executed trades.
+ * The trades must be from the account's point of view, that is,
+ * trade.action is the side this account was on.
+ **/
+#[derive(Debug, Clone)]
+pub struct Portfolio {
+    pub basis: CostBasis,
+    pub positions: Vec<Position> // Sorted by symbol
+}
+
+impl Portfolio {
+    pub fn from_trades(trades: &[Trade], basis: CostBasis, statistics: &HashMap<String, SecStat>) -> Self {
+        // Lots have to be opened and closed in the order the trades happened.
+        let mut ordered: Vec<&Trade> = trades.iter().collect();
+        ordered.sort_by_key(|trade| trade.execution_time);
+
+        let mut positions: HashMap<String, Position> = HashMap::new();
+        for trade in ordered {
+            let signed = match trade.action.as_str() {
+                "BUY" => trade.exchanged,
+                "SELL" => -trade.exchanged,
+                _ => continue
+            };
+
+            let position = positions.entry(trade.symbol.clone()).or_insert_with(|| Position::new(&trade.symbol));
+            position.apply(signed, trade.price, basis);
+        }
+
+        let mut positions: Vec<Position> = positions.into_values().collect();
+        for position in positions.iter_mut() {
+            let last_price = statistics.get(&position.symbol).and_then(|stats| stats.last_price);
+            position.mark_to_market(last_price);
+        }
+        positions.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        Portfolio {
+            basis,
+            positions
+        }
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Position> {
+        self.positions.iter().find(|position| position.symbol == symbol)
+    }
+
+    pub fn total_realised(&self) -> f64 {
+        self.positions.iter().map(|position| position.realised).sum()
+    }
+
+    pub fn total_unrealised(&self) -> f64 {
+        self.positions.iter().map(|position| position.unrealised).sum()
+    }
+
+    pub fn show(&self) -> String {
+        if self.positions.is_empty() {
+            return "\n\tNo Positions to show".to_string();
+        }
+        let mut lines: Vec<String> = Vec::new();
+
+        let basis = match self.basis {
+            CostBasis::Fifo => "FIFO",
+            CostBasis::AverageCost => "average cost"
+        };
+
+        lines.push(format!["\n\tPortfolio ({})", basis]);
+        lines.push("\t\t| Symbol | Position | Avg Cost | Last Price | Realised P&L | Unrealised P&L |".to_string());
+        lines.push("\t\t---------------------------------------------------------------------------".to_string());
+        for position in self.positions.iter() {
+            let last_price = match position.last_price {
+                Some(price) => format!["${:.2}", price],
+                None => "-".to_string()
+            };
+            lines.push(format!["\t\t| {:<6} | {:>8} | {:>8.2} | {:>10} | {:>12.2} | {:>14.2} |",
+                     position.symbol,
+                     position.quantity,
+                     position.average_cost,
+                     last_price,
+                     position.realised,
+                     position.unrealised]);
+        }
+        lines.push("\t\t---------------------------------------------------------------------------".to_string());
+        lines.push(format!["\t\tTotal Realised P&L: ${:.2}\tTotal Unrealised P&L: ${:.2}", self.total_realised(), self.total_unrealised()]);
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(trades: &[(i32, f64)], basis: CostBasis) -> Position {
+        let mut position = Position::new("GME");
+        for (signed, price) in trades.iter() {
+            position.apply(*signed, *price, basis);
+        }
+        position
+    }
+
+    #[test]
+    fn fifo_closes_the_oldest_lots_first() {
+        let position = position(&[(10, 100.0), (10, 110.0), (-15, 120.0)], CostBasis::Fifo);
+        // 10 shares bought at 100 and 5 at 110 were sold at 120.
+        assert_eq!(position.realised, 250.0);
+        assert_eq!(position.quantity, 5);
+        assert_eq!(position.average_cost, 110.0);
+    }
+
+    #[test]
+    fn average_cost_closes_at_the_average() {
+        let position = position(&[(10, 100.0), (10, 110.0), (-15, 120.0)], CostBasis::AverageCost);
+        assert_eq!(position.realised, 225.0);
+        assert_eq!(position.quantity, 5);
+        assert_eq!(position.average_cost, 105.0);
+    }
+
+    #[test]
+    fn selling_more_than_is_held_goes_short() {
+        for basis in [CostBasis::Fifo, CostBasis::AverageCost].iter() {
+            // The sale closes the long position at a loss, and opens a short one at 90.
+            let mut position = position(&[(10, 100.0), (-15, 90.0)], *basis);
+            assert_eq!(position.realised, -100.0);
+            assert_eq!(position.quantity, -5);
+            assert_eq!(position.average_cost, 90.0);
+
+            position.mark_to_market(Some(95.0));
+            assert_eq!(position.unrealised, -25.0);
+            assert_eq!(position.market_value(), Some(-475.0));
+
+            // Buying back lower is a profit on the short.
+            position.apply(5, 80.0, *basis);
+            assert_eq!(position.realised, -50.0);
+            assert_eq!(position.quantity, 0);
+            assert_eq!(position.average_cost, 0.0);
+        }
+    }
+
+    #[test]
+    fn unpriced_positions_have_no_unrealised_pnl() {
+        let mut position = position(&[(10, 100.0)], CostBasis::Fifo);
+        position.mark_to_market(None);
+        assert_eq!(position.unrealised, 0.0);
+        assert_eq!(position.market_value(), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+
+/* Roles decide which requests an account may make.
+ * An account can hold several roles, and may do anything any of its roles permits.
+ *
+ * Roles are stored in the Account table, and cached along with the account.
+ **/
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Role {
+    ReadOnly,       // Can only look at its own account
+    Trader,         // Places and cancels its own orders, the default for new accounts
+    MarketMaker,    // Trades like a trader, quoting both sides of a market
+    Operator,       // Runs the exchange day to day, ex. short selling settings
+    Admin           // Can do anything, including granting roles
+}
+
+// Everything a request can need permission to do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Permission {
+    Public,         // No account needed, ex. market info, creating an account
+    Console,        // Only from the exchange's own console, ex. simulate, exit
+    ViewAccount,
+    EditAccount,    // Change its own password or username
+    PlaceOrder,
+    CancelOrder,
+    ManageMargin,   // Change short selling settings, see margin calls
+    ManageLimits,   // Change rate limits, see accounts' usage
+    ManageAccounts, // Suspend, reactivate and close accounts
+    ManageMarkets,  // Halt and resume trading in a market
+    DropCopy,       // Receive a copy of other accounts' executions
+    UpgradeDb,
+    ManageRoles
+}
+
+impl Role {
+    pub fn from(word: &str) -> Option<Self> {
+        match word {
+            "read_only" | "readonly"        => Some(Role::ReadOnly),
+            "trader"                        => Some(Role::Trader),
+            "market_maker" | "marketmaker"  => Some(Role::MarketMaker),
+            "operator"                      => Some(Role::Operator),
+            "admin"                         => Some(Role::Admin),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::ReadOnly      => "read_only",
+            Role::Trader        => "trader",
+            Role::MarketMaker   => "market_maker",
+            Role::Operator      => "operator",
+            Role::Admin         => "admin"
+        }
+    }
+
+    /* The permission table. Public and Console requests never reach this,
+     * as they don't come from an account.
+     **/
+    pub fn permits(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Operator => matches!(permission, Permission::ViewAccount | Permission::EditAccount | Permission::ManageMargin | Permission::ManageLimits | Permission::ManageAccounts | Permission::ManageMarkets | Permission::DropCopy),
+            Role::Trader | Role::MarketMaker => matches!(permission, Permission::ViewAccount | Permission::EditAccount | Permission::PlaceOrder | Permission::CancelOrder),
+            Role::ReadOnly => matches!(permission, Permission::ViewAccount | Permission::EditAccount)
+        }
+    }
+}
+
+/* Returns true if any of the roles grants the permission. */
+pub fn permits(roles: &HashSet<Role>, permission: Permission) -> bool {
+    roles.iter().any(|role| role.permits(permission))
+}
+
+/* Roles are stored as a list of names, ex. {trader,operator} in Postgres, "trader,operator" in Redis.
+ * Unknown names are skipped.
+ **/
+pub fn from_names<S: AsRef<str>>(names: &[S]) -> HashSet<Role> {
+    names.iter().filter_map(|name| Role::from(name.as_ref())).collect()
+}
+
+/* The names of the roles, sorted so they always print in the same order. */
+pub fn to_names(roles: &HashSet<Role>) -> Vec<String> {
+    let mut names: Vec<String> = roles.iter().map(|role| role.as_str().to_string()).collect();
+    names.sort();
+    names
+}
+
+/* The roles a new account starts with. */
+pub fn default_roles() -> HashSet<Role> {
+    let mut roles = HashSet::new();
+    roles.insert(Role::Trader);
+    roles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles(names: &[&str]) -> HashSet<Role> {
+        from_names(names)
+    }
+
+    #[test]
+    fn admins_may_do_anything() {
+        let admin = roles(&["admin"]);
+        assert!(permits(&admin, Permission::ManageRoles));
+        assert!(permits(&admin, Permission::UpgradeDb));
+        assert!(permits(&admin, Permission::PlaceOrder));
+    }
+
+    #[test]
+    fn traders_may_only_trade() {
+        let trader = default_roles();
+        assert!(permits(&trader, Permission::PlaceOrder));
+        assert!(permits(&trader, Permission::CancelOrder));
+        assert!(!permits(&trader, Permission::ManageMargin));
+        assert!(!permits(&trader, Permission::ManageRoles));
+    }
+
+    #[test]
+    fn viewers_may_only_look() {
+        let viewer = roles(&["read_only"]);
+        assert!(permits(&viewer, Permission::ViewAccount));
+        assert!(!permits(&viewer, Permission::PlaceOrder));
+        assert!(!permits(&viewer, Permission::CancelOrder));
+
+        // Any one of an account's roles is enough.
+        let both = roles(&["read_only", "operator"]);
+        assert!(permits(&both, Permission::ManageMarkets) && !permits(&both, Permission::PlaceOrder));
+    }
+
+    #[test]
+    fn role_names_round_trip() {
+        let names = to_names(&roles(&["operator", "trader", "unknown", "marketmaker"]));
+        assert_eq!(names, vec!["market_maker", "operator", "trader"]);
+        assert_eq!(from_names(&names), roles(&["trader", "operator", "market_maker"]));
+    }
+}
@@ -0,0 +1,105 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+/* Sessions let a user log in once, then place orders with a token
+ * instead of typing their username and password into every request.
+ *
+ * Sessions live in Redis, so they survive restarts and can be used by script files:
+ *      session:{token}     => username, expires after SESSION_TTL seconds of inactivity.
+ *      sessions:{username} => set of the user's tokens, so they can all be revoked at once.
+ **/
+
+pub const SESSION_TTL: usize = 30 * 60;
+const TOKEN_PREFIX: &str = "sess_";
+const TOKEN_BYTES: usize = 16;
+
+// How a request proves who sent it.
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    Password(String, String),   // username, password
+    Session(String)             // token returned by a login request
+}
+
+/* Generate a new random session token, ex. sess_3f9a0c...
+ * Tokens are lowercase since the parser lowercases every word.
+ **/
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+
+    let mut token = String::from(TOKEN_PREFIX);
+    for byte in bytes.iter() {
+        token.push_str(&format!["{:02x}", byte]);
+    }
+    token
+}
+
+/* Returns true if the word has the shape of a session token.
+ * The parser uses this to tell a token apart from a username.
+ **/
+pub fn is_token(word: &str) -> bool {
+    match word.strip_prefix(TOKEN_PREFIX) {
+        Some(hex) => hex.len() == TOKEN_BYTES * 2 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Users, UserAccount, AuthError};
+    use crate::database::{Store, MemoryStore, CacheBackend, MemoryCache};
+
+    // Users on an in-memory cache, with the account bob.
+    fn users() -> (Users, Box<dyn Store>) {
+        let mut store: Box<dyn Store> = Box::new(MemoryStore::new());
+        let mut users = Users::new(10, CacheBackend::Memory(MemoryCache::new()).connect());
+        assert!(users.new_account(UserAccount::from("bob", "password"), &mut *store).is_some());
+        (users, store)
+    }
+
+    fn session_user(users: &mut Users, token: &str, store: &mut dyn Store) -> Option<String> {
+        users.authenticate_session(token, store).ok().map(|account| account.username.clone())
+    }
+
+    #[test]
+    fn logging_in_creates_a_session() {
+        let (mut users, mut store) = users();
+        let token = users.login("bob", "password", &mut *store).ok().unwrap();
+        assert!(is_token(&token));
+        assert_eq!(session_user(&mut users, &token, &mut *store), Some("bob".to_string()));
+
+        // A wrong password doesn't get a session.
+        assert!(matches!(users.login("bob", "wrong", &mut *store), Err(AuthError::BadPassword(_))));
+        assert!(!is_token("bob") && !is_token(&generate_token()[1..]));
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected() {
+        let (mut users, mut store) = users();
+        let token = users.login("bob", "password", &mut *store).ok().unwrap();
+        users.cache.write_session(&token, "bob", 0);
+        assert!(matches!(users.authenticate_session(&token, &mut *store), Err(AuthError::BadSession)));
+        // It's gone, so there's nothing to log out of.
+        assert!(!users.logout(&token));
+    }
+
+    #[test]
+    fn sessions_can_be_revoked() {
+        let (mut users, mut store) = users();
+        let first = users.login("bob", "password", &mut *store).ok().unwrap();
+        let second = users.login("bob", "password", &mut *store).ok().unwrap();
+        assert_ne!(first, second);
+
+        // Logging out ends one session.
+        assert!(users.logout(&first));
+        assert!(!users.logout(&first));
+        assert_eq!(session_user(&mut users, &first, &mut *store), None);
+        assert_eq!(session_user(&mut users, &second, &mut *store), Some("bob".to_string()));
+
+        // Revoking ends all of them.
+        let third = users.login("bob", "password", &mut *store).ok().unwrap();
+        users.revoke_sessions("bob");
+        assert_eq!(session_user(&mut users, &second, &mut *store), None);
+        assert_eq!(session_user(&mut users, &third, &mut *store), None);
+    }
+}
@@ -0,0 +1,647 @@
+use std::io::{self, Read};
+use std::sync::mpsc;
+use std::thread;
+
+use serde_json::{json, Value};
+use tiny_http::{Server, Method, Header};
+
+use crate::exchange::{Exchange, Order, ExecutionReport, ErrorCode, OrderStatus, Request, CancelOrder, AmendOrder, CancelAll, PriceError, BufferCollection};
+use crate::exchange::requests;
+use crate::exchange::filled::Trade;
+use crate::account::{Users, Credentials, Permission, session};
+use crate::parser::{self, Response, Status};
+use crate::server::Incoming;
+use crate::database::{Store, Cache};
+
+// How many price levels of the book we show, unless ?depth= says otherwise.
+const DEFAULT_DEPTH: usize = 10;
+// How many past trades we show, unless ?limit= says otherwise.
+const DEFAULT_HISTORY: usize = 100;
+// The largest request body we read, anything bigger is answered with 413.
+const MAX_BODY: u64 = 64 * 1024;
+
+/* An HTTP request, translated into something the exchange understands.
+ *
+ * Anything that changes the exchange is an existing Request, serviced exactly like
+ * the console's. The rest read the state the console only prints as tables.
+ **/
+pub enum ApiCall {
+    Service(Request, bool),     // The request, and whether success means something was created (201).
+    Login(String, String),      // username, password
+    Orders(Credentials),        // The account's pending orders
+    Trades(Credentials),        // The account's executed trades
+    Price(String),
+    Book(String, usize),        // symbol, price levels per side
+    History(String, usize)      // symbol, most recent trades
+}
+
+pub struct ApiRequest {
+    pub call: ApiCall,
+    pub reply: mpsc::Sender<ApiResponse>
+}
+
+pub struct ApiResponse {
+    pub code: u16,
+    pub body: Value
+}
+
+impl ApiResponse {
+    pub fn ok(body: Value) -> Self {
+        ApiResponse { code: 200, body }
+    }
+
+    pub fn error(code: u16, message: &str) -> Self {
+        let status = match code {
+            400 => Status::BadRequest,
+            401 => Status::Unauthorized,
+            403 => Status::Forbidden,
+            404 => Status::NotFound,
+            409 => Status::Conflict,
+            413 => Status::TooLarge,
+            429 => Status::Throttled,
+            _   => Status::Error
+        };
+        let mut response = ApiResponse::from(Response::error(status, message));
+        response.code = code;
+        response
+    }
+}
+
+impl From<Response> for ApiResponse {
+    fn from(response: Response) -> Self {
+        let mut body = json!({
+            "status": response.status.name(),
+            "message": response.message
+        });
+        if let Some(order) = &response.order {
+            body["order"] = order_json(order);
+        }
+        if let Some(code) = response.code {
+            body["code"] = json!(code.as_str());
+        }
+        if !response.reports.is_empty() {
+            body["reports"] = json!(response.reports.iter().map(report_json).collect::<Vec<Value>>());
+        }
+        ApiResponse { code: response.status.code(), body }
+    }
+}
+
+/* Serve the REST API on the given address. Every call is sent to the main thread,
+ * and serviced in line with the requests of the console and the line protocol.
+ *
+ * Returns the handle of the accepting thread, or an error if we can't listen on the address.
+ **/
+pub fn listen(address: &str, requests: mpsc::Sender<Incoming>) -> io::Result<thread::JoinHandle<()>> {
+    let server = match Server::http(address) {
+        Ok(server) => server,
+        Err(e) => return Err(io::Error::other(e.to_string()))
+    };
+    dark_green!("Serving the HTTP API on {}\n", address);
+
+    let handle = thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let requests = requests.clone();
+            // Reading the body can be slow, so don't make everyone else wait on it.
+            thread::spawn(move || handle_http(request, requests));
+        }
+    });
+    Ok(handle)
+}
+
+fn handle_http(mut request: tiny_http::Request, requests: mpsc::Sender<Incoming>) {
+    let response = match route(&mut request) {
+        Ok(call) => {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            match requests.send(Incoming::Api(ApiRequest { call, reply: reply_tx })) {
+                Ok(()) => reply_rx.recv().unwrap_or_else(|_| ApiResponse::error(503, "The exchange is shutting down.")),
+                Err(_) => ApiResponse::error(503, "The exchange is shutting down.")
+            }
+        },
+        Err(response) => response
+    };
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let http_response = tiny_http::Response::from_string(response.body.to_string())
+        .with_status_code(response.code)
+        .with_header(content_type);
+    if let Err(e) = request.respond(http_response) {
+        eprintln!("Failed to respond to an HTTP request: {}", e);
+    }
+}
+
+/* Turn the method, path and body of a request into an ApiCall.
+ *
+ *      POST   /accounts                        {"username", "password"}
+ *      POST   /sessions                        {"username", "password"}, returns a token
+ *      DELETE /sessions                        ends the session of the bearer token
+ *      GET    /orders                          the account's pending orders
+ *      POST   /orders                          {"symbol", "side", "quantity", "price", "client_id"?}
+ *      DELETE /orders?symbol=&side=            cancel all (or some) of the account's orders
+ *      PUT    /orders/SYMBOL/ID                {"quantity", "price", "client_id"?}, amends the order
+ *      DELETE /orders/SYMBOL/ID                cancels the order
+ *      GET    /trades                          the account's executed trades
+ *      GET    /markets/SYMBOL/price
+ *      GET    /markets/SYMBOL/book?depth=10
+ *      GET    /markets/SYMBOL/trades?limit=100
+ *
+ * Order ids can be the exchange's id, or clid=client_order_id like on the console.
+ * Requests on behalf of an account need an `Authorization: Bearer sess_...` header.
+ **/
+fn route(request: &mut tiny_http::Request) -> Result<ApiCall, ApiResponse> {
+    let url = request.url().to_string();
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (url.as_str(), "")
+    };
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let method = request.method().clone();
+
+    match (&method, &segments[..]) {
+        (Method::Post, ["accounts"]) => {
+            let body = read_body(request)?;
+            let (username, password) = (lowercase_field(&body, "username")?, lowercase_field(&body, "password")?);
+            let credentials = Credentials::Password(username, password);
+            Ok(ApiCall::Service(Request::UserReq(credentials, "create".to_string(), None), true))
+        },
+        (Method::Post, ["sessions"]) => {
+            let body = read_body(request)?;
+            Ok(ApiCall::Login(lowercase_field(&body, "username")?, lowercase_field(&body, "password")?))
+        },
+        (Method::Delete, ["sessions"]) => {
+            match bearer(request)? {
+                Credentials::Session(token) => Ok(ApiCall::Service(Request::LogoutReq(token), false)),
+                Credentials::Password(_, _) => Err(ApiResponse::error(401, "Please log in first."))
+            }
+        },
+        (Method::Get, ["orders"]) => Ok(ApiCall::Orders(bearer(request)?)),
+        (Method::Get, ["trades"]) => Ok(ApiCall::Trades(bearer(request)?)),
+        (Method::Post, ["orders"]) => {
+            let credentials = bearer(request)?;
+            let body = read_body(request)?;
+            let action = match lowercase_field(&body, "side")?.as_str() {
+                "buy"  => "BUY",
+                "sell" => "SELL",
+                _ => return Err(ApiResponse::error(400, "The side of an order must be buy or sell."))
+            };
+            let (quantity, price) = (quantity_field(&body)?, price_field(&body)?);
+            let mut order = Order::from(action.to_string(), field(&body, "symbol")?.to_uppercase(), quantity, price, OrderStatus::PENDING, None);
+            order.client_id = client_id_field(&body)?;
+            Ok(ApiCall::Service(Request::OrderReq(order, credentials), true))
+        },
+        (Method::Delete, ["orders"]) => {
+            let credentials = bearer(request)?;
+            let symbol = query_param(query, "symbol").map(|symbol| symbol.to_uppercase());
+            let action = match query_param(query, "side").map(|side| side.to_lowercase()) {
+                Some(side) if side == "buy"  => Some("BUY".to_string()),
+                Some(side) if side == "sell" => Some("SELL".to_string()),
+                Some(_) => return Err(ApiResponse::error(400, "The side to cancel must be buy or sell.")),
+                None => None
+            };
+            Ok(ApiCall::Service(Request::CancelAllReq(CancelAll { symbol, action, username: None }, credentials), false))
+        },
+        (Method::Put, ["orders", symbol, order_ref]) => {
+            let credentials = bearer(request)?;
+            let body = read_body(request)?;
+            let cancel = cancel_order(symbol, order_ref, &credentials)?;
+            let amend = AmendOrder {
+                cancel,
+                quantity: quantity_field(&body)?,
+                price: price_field(&body)?,
+                client_id: client_id_field(&body)?
+            };
+            Ok(ApiCall::Service(Request::AmendReq(amend, credentials), false))
+        },
+        (Method::Delete, ["orders", symbol, order_ref]) => {
+            let credentials = bearer(request)?;
+            let cancel = cancel_order(symbol, order_ref, &credentials)?;
+            Ok(ApiCall::Service(Request::CancelReq(cancel, credentials), false))
+        },
+        (Method::Get, ["markets", symbol, "price"]) => Ok(ApiCall::Price(symbol.to_uppercase())),
+        (Method::Get, ["markets", symbol, "book"]) => {
+            Ok(ApiCall::Book(symbol.to_uppercase(), count_param(query, "depth", DEFAULT_DEPTH)?))
+        },
+        (Method::Get, ["markets", symbol, "trades"]) => {
+            Ok(ApiCall::History(symbol.to_uppercase(), count_param(query, "limit", DEFAULT_HISTORY)?))
+        },
+        (_, ["accounts"]) | (_, ["sessions"]) | (_, ["orders"]) | (_, ["trades"]) | (_, ["orders", _, _]) | (_, ["markets", _, _]) => {
+            Err(ApiResponse::error(405, &format!["{} is not supported on {}.", method, path]))
+        },
+        _ => Err(ApiResponse::error(404, &format!["There is nothing at {}.", path]))
+    }
+}
+
+/* Service an ApiCall on the main thread. */
+pub fn service_call(call: ApiCall, exchange: &mut Exchange, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) -> ApiResponse {
+    match call {
+        ApiCall::Service(request, creates) => {
+            let response = parser::service_request(request, exchange, users, buffers, store, cache);
+            let mut api_response = ApiResponse::from(response);
+            if creates && api_response.code == 200 {
+                api_response.code = 201;
+            }
+            api_response
+        },
+        ApiCall::Login(username, password) => {
+            match users.login(&username, &password, store) {
+                Ok(token) => ApiResponse::ok(json!({
+                    "status": Status::Ok.name(),
+                    "token": token,
+                    "expires_in": session::SESSION_TTL
+                })),
+                Err(e) => ApiResponse::from(Response::from(e))
+            }
+        },
+        ApiCall::Orders(credentials) => {
+            let account = match users.authorize(&credentials, Permission::ViewAccount, store) {
+                Ok(account) => account,
+                Err(e) => return ApiResponse::from(Response::from(e))
+            };
+            if !account.pending_orders.is_complete {
+                exchange.fetch_account_pending_orders(account, cache);
+            }
+            let mut orders: Vec<&Order> = account.pending_orders.pending.values().flat_map(|market| market.values()).collect();
+            orders.sort_by_key(|order| order.order_id);
+            ApiResponse::ok(json!({
+                "username": account.username,
+                "orders": orders.into_iter().map(order_json).collect::<Vec<Value>>()
+            }))
+        },
+        ApiCall::Trades(credentials) => {
+            let account = match users.authorize(&credentials, Permission::ViewAccount, store) {
+                Ok(account) => account,
+                Err(e) => return ApiResponse::from(Response::from(e))
+            };
+            let trades = account.read_executed_trades(cache);
+            ApiResponse::ok(json!({
+                "username": account.username,
+                "trades": trades.iter().map(trade_json).collect::<Vec<Value>>()
+            }))
+        },
+        ApiCall::Price(symbol) => {
+            match exchange.get_price(&symbol) {
+                Ok(price) => ApiResponse::ok(json!({ "symbol": symbol, "price": price })),
+                Err(PriceError::NoMarket) => ApiResponse::from(Response::error(Status::NotFound, &format!["There is no market for ${}.", symbol]).with_code(ErrorCode::UnknownSymbol)),
+                Err(PriceError::NoTrades) => ApiResponse::error(404, &format!["${} has not had any trades yet, so there is no price.", symbol])
+            }
+        },
+        ApiCall::Book(symbol, levels) => {
+            if !exchange.statistics.contains_key(&symbol) {
+                return ApiResponse::from(Response::error(Status::NotFound, &format!["${} is not a market.", symbol]).with_code(ErrorCode::UnknownSymbol));
+            }
+            let (bids, asks) = match exchange.live_orders.get(&symbol) {
+                Some(market) => market.depth(levels),
+                None => (Vec::new(), Vec::new())
+            };
+            let level_json = |(price, quantity): &(f64, i32)| json!({ "price": price, "quantity": quantity });
+            ApiResponse::ok(json!({
+                "symbol": symbol,
+                "bids": bids.iter().map(level_json).collect::<Vec<Value>>(),
+                "asks": asks.iter().map(level_json).collect::<Vec<Value>>()
+            }))
+        },
+        ApiCall::History(symbol, limit) => {
+            if !exchange.statistics.contains_key(&symbol) {
+                return ApiResponse::from(Response::error(Status::NotFound, &format!["${} is not a market.", symbol]).with_code(ErrorCode::UnknownSymbol));
+            }
+            // Trades still in the buffer haven't reached the database yet.
+            let mut trades = store.read_trades(&symbol).unwrap_or_default();
+            trades.extend(buffers.buffered_trades.view_trades(&symbol).cloned());
+            trades.sort_by_key(|trade| trade.execution_time);
+            let start = trades.len().saturating_sub(limit);
+            ApiResponse::ok(json!({
+                "symbol": symbol,
+                "trades": trades[start..].iter().map(trade_json).collect::<Vec<Value>>()
+            }))
+        }
+    }
+}
+
+pub fn order_json(order: &Order) -> Value {
+    json!({
+        "order_id": order.order_id,
+        "client_id": order.client_id,
+        "symbol": order.symbol,
+        "side": order.action,
+        "quantity": order.quantity,
+        "filled": order.filled,
+        "price": order.price,
+        "status": format!["{:?}", order.status]
+    })
+}
+
+pub fn report_json(report: &ExecutionReport) -> Value {
+    json!({
+        "exec_type": report.exec_type.name(),
+        "order_id": report.order_id,
+        "client_id": report.client_id,
+        "symbol": report.symbol,
+        "side": report.action,
+        "quantity": report.quantity,
+        "price": report.price,
+        "last_quantity": report.last_quantity,
+        "last_price": report.last_price,
+        "cum_quantity": report.cum_quantity,
+        "leaves_quantity": report.leaves_quantity,
+        "avg_price": report.avg_price,
+        "replaces": report.replaces,
+        "reason": report.reason
+    })
+}
+
+pub fn trade_json(trade: &Trade) -> Value {
+    json!({
+        "symbol": trade.symbol,
+        "side": trade.action,
+        "price": trade.price,
+        "quantity": trade.exchanged,
+        "filled_order_id": trade.filled_oid,
+        "filler_order_id": trade.filler_oid,
+        "time": trade.execution_time.to_rfc3339()
+    })
+}
+
+// The credentials of the request, from its `Authorization: Bearer TOKEN` header.
+fn bearer(request: &tiny_http::Request) -> Result<Credentials, ApiResponse> {
+    let header = request.headers().iter().find(|header| header.field.equiv("Authorization"));
+    if let Some(header) = header {
+        if let Some(token) = header.value.as_str().strip_prefix("Bearer ") {
+            // Tokens are lowercase, like everything the console reads.
+            let token = token.trim().to_lowercase();
+            if session::is_token(&token) {
+                return Ok(Credentials::Session(token));
+            }
+            return Err(ApiResponse::error(401, "That is not a session token."));
+        }
+    }
+    Err(ApiResponse::error(401, "Please send an Authorization: Bearer header with the token from POST /sessions."))
+}
+
+fn read_body(request: &mut tiny_http::Request) -> Result<Value, ApiResponse> {
+    let too_large = || ApiResponse::error(413, &format!["The request body can be at most {} bytes.", MAX_BODY]);
+    if request.body_length().is_some_and(|length| length as u64 > MAX_BODY) {
+        return Err(too_large());
+    }
+    // The client may not have sent a Content-Length, so read one byte past the limit to tell.
+    let mut body = String::new();
+    if request.as_reader().take(MAX_BODY + 1).read_to_string(&mut body).is_err() {
+        return Err(ApiResponse::error(400, "Failed to read the request body."));
+    }
+    if body.len() as u64 > MAX_BODY {
+        return Err(too_large());
+    }
+    match serde_json::from_str::<Value>(&body) {
+        Ok(value) if value.is_object() => Ok(value),
+        _ => Err(ApiResponse::error(400, "The request body must be a JSON object."))
+    }
+}
+
+fn field<'a>(body: &'a Value, name: &str) -> Result<&'a str, ApiResponse> {
+    match body.get(name).and_then(Value::as_str) {
+        Some(value) if !value.trim().is_empty() && !value.contains(char::is_whitespace) => Ok(value),
+        _ => Err(ApiResponse::error(400, &format!["\"{}\" must be a string without spaces.", name]))
+    }
+}
+
+// Usernames, passwords and client ids are case insensitive on the console, so they are here too.
+fn lowercase_field(body: &Value, name: &str) -> Result<String, ApiResponse> {
+    field(body, name).map(|value| value.to_lowercase())
+}
+
+fn quantity_field(body: &Value) -> Result<i32, ApiResponse> {
+    match body.get("quantity").and_then(Value::as_i64) {
+        Some(quantity) if quantity > 0 && quantity <= i32::MAX as i64 => Ok(quantity as i32),
+        _ => Err(ApiResponse::from(Response::failed(ErrorCode::InvalidQuantity, "\"quantity\" must be a whole number of shares greater than 0.")))
+    }
+}
+
+fn price_field(body: &Value) -> Result<f64, ApiResponse> {
+    match body.get("price").and_then(Value::as_f64) {
+        Some(price) if price > 0.0 => Ok(price),
+        _ => Err(ApiResponse::from(Response::failed(ErrorCode::InvalidPrice, "\"price\" must be a number greater than 0.")))
+    }
+}
+
+fn client_id_field(body: &Value) -> Result<Option<String>, ApiResponse> {
+    if let None | Some(Value::Null) = body.get("client_id") {
+        return Ok(None);
+    }
+    let client_id = lowercase_field(body, "client_id")?;
+    if !requests::is_valid_client_id(&client_id) {
+        return Err(ApiResponse::error(400, &format!["Client order ids must be 1 to {} letters, numbers, dashes or underscores.", requests::MAX_CLIENT_ID_LEN]));
+    }
+    Ok(Some(client_id))
+}
+
+// The order in /orders/SYMBOL/ID, where ID is the exchange's id or clid=client_order_id.
+fn cancel_order(symbol: &str, order_ref: &str, credentials: &Credentials) -> Result<CancelOrder, ApiResponse> {
+    let order_ref = order_ref.to_lowercase();
+    let (order_id, client_id) = match order_ref.strip_prefix("clid=") {
+        Some(client_id) if requests::is_valid_client_id(client_id) => (0, Some(client_id.to_string())),
+        Some(_) => return Err(ApiResponse::error(400, "That is not a valid client order id.")),
+        None => match order_ref.parse::<i64>() {
+            Ok(id) => (id, None),
+            Err(_) => return Err(ApiResponse::error(400, "Orders are referred to by their id, or clid=client_order_id."))
+        }
+    };
+    let username = match credentials {
+        Credentials::Password(username, _) => username.clone(),
+        Credentials::Session(_) => String::new()
+    };
+    Ok(CancelOrder { symbol: symbol.to_uppercase(), order_id, username, client_id })
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&')
+         .filter_map(|pair| pair.split_once('='))
+         .find(|(key, _)| *key == name)
+         .map(|(_, value)| value)
+}
+
+fn count_param(query: &str, name: &str, default: usize) -> Result<usize, ApiResponse> {
+    match query_param(query, name) {
+        Some(value) => match value.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(ApiResponse::error(400, &format!["\"{}\" must be a whole number greater than 0.", name]))
+        },
+        None => Ok(default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::runtime::Runtime;
+    use crate::server::ClientRequest;
+
+    struct Client {
+        address: String
+    }
+
+    impl Client {
+        // The status code and JSON body of the response.
+        fn send(&self, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, Value) {
+            let mut stream = TcpStream::connect(&self.address).unwrap();
+            let authorization = token.map(|token| format!["Authorization: Bearer {}\r\n", token]).unwrap_or_default();
+            let request = format!["{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+                                  method, path, self.address, authorization, body.len(), body];
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let code = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+            (code, serde_json::from_str(body).unwrap())
+        }
+
+        fn login(&self, username: &str) -> String {
+            let (code, body) = self.send("POST", "/sessions", None, &format![r#"{{"username": "{}", "password": "password"}}"#, username]);
+            assert_eq!(code, 200, "{}", body);
+            body["token"].as_str().unwrap().to_string()
+        }
+
+        // The id of the account's only pending order.
+        fn only_order_id(&self, token: &str) -> i64 {
+            let (_, body) = self.send("GET", "/orders", Some(token), "");
+            body["orders"][0]["order_id"].as_i64().unwrap()
+        }
+    }
+
+    /* Serve the API from an exchange with a GME market, and the accounts admin, alice and bob (who may be short up to 10 shares),
+     * while `client` makes its requests on another thread.
+     **/
+    fn with_api<F>(client: F)
+    where
+        F: FnOnce(&Client) + Send + 'static
+    {
+        let mut runtime = Runtime::in_memory();
+        runtime.store.upgrade_db(&mut "add,GME,GameStop\n".as_bytes(), "rustx");
+        runtime.store.populate_market_statistics(&mut runtime.exchange);
+        runtime.store.populate_has_trades(&mut runtime.exchange);
+        for line in ["account create admin password", "account create alice password", "account create bob password",
+                     "short enable bob 10 100000 admin password"].iter() {
+            assert!(runtime.service(parser::tokenize_input(line.to_string()).unwrap()).is_ok(), "{}", line);
+        }
+
+        // Find a free port for the server.
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        listen(&address, tx.clone()).unwrap();
+
+        // Exit once the client is done, even if it panicked, so serve returns.
+        let handle = thread::spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| client(&Client { address })));
+            let (reply, _) = mpsc::channel();
+            tx.send(Incoming::Line(ClientRequest { line: "exit".to_string(), console: true, reply })).unwrap();
+            if let Err(e) = result {
+                std::panic::resume_unwind(e);
+            }
+        });
+        runtime.serve(rx);
+        runtime.shutdown();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn accounts_and_sessions() {
+        with_api(|client| {
+            let (code, body) = client.send("POST", "/accounts", None, r#"{"username": "carol", "password": "password"}"#);
+            assert_eq!(code, 201, "{}", body);
+            let (code, body) = client.send("POST", "/accounts", None, r#"{"username": "Alice", "password": "password"}"#);
+            assert_eq!((code, body["status"].as_str()), (409, Some("CONFLICT")));
+
+            let (code, _) = client.send("POST", "/sessions", None, r#"{"username": "alice", "password": "wrong"}"#);
+            assert_eq!(code, 401);
+            let token = client.login("carol");
+            assert_eq!(client.send("GET", "/orders", Some(&token), "").0, 200);
+
+            assert_eq!(client.send("DELETE", "/sessions", Some(&token), "").0, 200);
+            assert_eq!(client.send("GET", "/orders", Some(&token), "").0, 401);
+            assert_eq!(client.send("GET", "/orders", None, "").0, 401);
+        });
+    }
+
+    #[test]
+    fn orders_are_placed_amended_and_cancelled() {
+        with_api(|client| {
+            let alice = client.login("alice");
+            let (code, body) = client.send("POST", "/orders", Some(&alice), r#"{"symbol": "gme", "side": "buy", "quantity": 10, "price": 100, "client_id": "A1"}"#);
+            assert_eq!(code, 201, "{}", body);
+            assert_eq!(body["reports"][0]["exec_type"], "NEW");
+            let order_id = body["order"]["order_id"].as_i64().unwrap();
+
+            let (code, body) = client.send("POST", "/orders", Some(&alice), r#"{"symbol": "GME", "side": "buy", "quantity": 0, "price": 100}"#);
+            assert_eq!((code, body["code"].as_str()), (400, Some("INVALID_QUANTITY")));
+
+            let (code, body) = client.send("GET", "/orders", Some(&alice), "");
+            assert_eq!(code, 200);
+            assert_eq!(body["orders"].as_array().unwrap().len(), 1);
+            assert_eq!(body["orders"][0]["client_id"], "a1");
+
+            let (code, body) = client.send("PUT", "/orders/GME/clid=a1", Some(&alice), r#"{"quantity": 8, "price": 101}"#);
+            assert_eq!(code, 200, "{}", body);
+            let (_, body) = client.send("GET", "/markets/GME/book", None, "");
+            assert_eq!(body["bids"], json!([{ "price": 101.0, "quantity": 8 }]));
+            let replacement = client.only_order_id(&alice);
+            assert_ne!(replacement, order_id);
+
+            let (code, body) = client.send("DELETE", &format!["/orders/GME/{}", replacement], Some(&alice), "");
+            assert_eq!(code, 200, "{}", body);
+            let (code, body) = client.send("DELETE", &format!["/orders/GME/{}", replacement], Some(&alice), "");
+            assert_eq!((code, body["code"].as_str()), (404, Some("UNKNOWN_ORDER")));
+
+            client.send("POST", "/orders", Some(&alice), r#"{"symbol": "GME", "side": "buy", "quantity": 1, "price": 90}"#);
+            assert_eq!(client.send("DELETE", "/orders?symbol=GME&side=buy", Some(&alice), "").0, 200);
+            let (_, body) = client.send("GET", "/orders", Some(&alice), "");
+            assert_eq!(body["orders"], json!([]));
+        });
+    }
+
+    #[test]
+    fn markets_show_their_trades() {
+        with_api(|client| {
+            let (alice, bob) = (client.login("alice"), client.login("bob"));
+            let (code, body) = client.send("GET", "/markets/GME/price", None, "");
+            assert_eq!(code, 404, "{}", body);
+
+            client.send("POST", "/orders", Some(&alice), r#"{"symbol": "GME", "side": "buy", "quantity": 10, "price": 100}"#);
+            let (code, body) = client.send("POST", "/orders", Some(&bob), r#"{"symbol": "GME", "side": "sell", "quantity": 4, "price": 99}"#);
+            assert_eq!(code, 201, "{}", body);
+            assert_eq!(body["reports"][1]["exec_type"], "FILL");
+
+            let (_, body) = client.send("GET", "/markets/GME/price", None, "");
+            assert_eq!(body["price"], 100.0);
+            let (_, body) = client.send("GET", "/markets/GME/book?depth=1", None, "");
+            assert_eq!((&body["bids"], &body["asks"]), (&json!([{ "price": 100.0, "quantity": 6 }]), &json!([])));
+            let (_, body) = client.send("GET", "/markets/GME/trades?limit=10", None, "");
+            assert_eq!(body["trades"].as_array().unwrap().len(), 1);
+            assert_eq!(body["trades"][0]["quantity"], 4);
+
+            let (code, body) = client.send("GET", "/trades", Some(&bob), "");
+            assert_eq!((code, body["username"].as_str()), (200, Some("bob")));
+
+            let (code, body) = client.send("GET", "/markets/XYZ/book", None, "");
+            assert_eq!((code, body["code"].as_str()), (404, Some("UNKNOWN_SYMBOL")));
+            assert_eq!(client.send("GET", "/markets/GME/trades?limit=0", None, "").0, 400);
+        });
+    }
+
+    #[test]
+    fn bad_requests_are_answered_with_errors() {
+        with_api(|client| {
+            let alice = client.login("alice");
+            assert_eq!(client.send("GET", "/nowhere", None, "").0, 404);
+            assert_eq!(client.send("PATCH", "/orders", Some(&alice), "").0, 405);
+            assert_eq!(client.send("POST", "/orders", Some(&alice), "not json").0, 400);
+            assert_eq!(client.send("POST", "/orders", Some("sess_nope"), "{}").0, 401);
+            let (code, body) = client.send("POST", "/orders", Some(&alice), r#"{"symbol": "GME", "side": "hold", "quantity": 1, "price": 1}"#);
+            assert_eq!(code, 400, "{}", body);
+
+            let large = format![r#"{{"padding": "{}"}}"#, "a".repeat(MAX_BODY as usize)];
+            let (code, body) = client.send("POST", "/orders", Some(&alice), &large);
+            assert_eq!((code, body["status"].as_str()), (413, Some("TOO_LARGE")));
+        });
+    }
+}
@@ -0,0 +1,98 @@
+use std::env;
+use std::process;
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+
+use exchange::{Runtime, ClientRequest, Incoming};
+use exchange::{parser, runtime, server, api, stream, fix, drop_copy};
+
+/* Serves the exchange over the line protocol (--serve), HTTP (--http), WebSockets (--ws),
+ * FIX (--fix) and the drop copy (--drop-copy). The console keeps working while serving.
+ **/
+fn main() {
+    let argument = match parser::command_args(env::args()) {
+        Ok(arg) => arg,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if !argument.serves() {
+        eprintln!("Please give at least one of --serve, --http, --ws, --fix or --drop-copy, followed by an address to serve on.");
+        process::exit(1);
+    }
+    if !argument.interactive {
+        eprintln!("Script files are run by the exchange binary, ex. cargo run --release -- /path/to/input.txt");
+        process::exit(1);
+    }
+
+    let mut runtime = Runtime::from_arguments(&argument);
+    if let Err(e) = runtime.start_binary_feed(&argument) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    runtime::ignore_interrupts();
+
+    let (request_tx, request_rx) = mpsc::channel();
+    if let Some(address) = &argument.config.listen {
+        if let Err(e) = server::listen(address, request_tx.clone()) {
+            eprintln!("Failed to listen on {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+    if let Some(address) = &argument.config.http {
+        if let Err(e) = api::listen(address, request_tx.clone()) {
+            eprintln!("Failed to serve the HTTP API on {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+    if let Some(address) = &argument.config.ws {
+        if let Err(e) = stream::listen(address, request_tx.clone()) {
+            eprintln!("Failed to stream market data on {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+    if let Some(address) = &argument.config.fix {
+        if let Err(e) = fix::listen(address, request_tx.clone(), runtime.cache_backend.clone()) {
+            eprintln!("Failed to accept FIX sessions on {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+    if let Some(address) = &argument.config.drop_copy {
+        if let Err(e) = drop_copy::listen(address, request_tx.clone()) {
+            eprintln!("Failed to send drop copies on {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+
+    // The console still works while serving, its requests wait in line with everyone else's.
+    thread::spawn(move || {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        loop {
+            let mut input = String::new();
+            match io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => ()
+            }
+            let request = ClientRequest {
+                line: input,
+                console: true,
+                reply: reply_tx.clone()
+            };
+            if request_tx.send(Incoming::Line(request)).is_err() {
+                return;
+            }
+            match reply_rx.recv() {
+                Ok(response) => response.print(),
+                Err(_) => return
+            }
+        }
+    });
+
+    // Every request is serviced here, one at a time, in the order they arrived.
+    runtime.serve(request_rx);
+
+    // Wait for the buffer thread to complete.
+    runtime.shutdown();
+}
@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use itch::{Body, MarketState, Message, Side, SystemEvent, price_from_f64};
+
+use crate::exchange::{BookEvent, Exchange, Order};
+
+// Messages are packed into UDP packets up to this size, so they aren't fragmented.
+const MAX_PACKET_LEN: usize = 1400;
+
+// Where the feed is published.
+enum Sink {
+    Udp(UdpSocket, SocketAddr),
+    File(BufWriter<File>)
+}
+
+/* Publishes the book order by order in the binary format of the itch crate,
+ * over UDP and/or to a file that itch_book can replay.
+ *
+ * The feed starts with every order already on the book, then follows the exchange's BookEvents.
+ * Sequence and match numbers count up from 1 each time the exchange starts.
+ **/
+pub struct BinaryFeed {
+    sinks: Vec<Sink>,
+    seq: u64,
+    match_number: u64,
+    markets: HashSet<String>,   // Markets whose state was sent
+    messages: Vec<Message>      // Waiting to be sent
+}
+
+impl Default for BinaryFeed {
+    fn default() -> Self {
+        BinaryFeed::new()
+    }
+}
+
+impl BinaryFeed {
+    pub fn new() -> Self {
+        BinaryFeed {
+            sinks: Vec::new(),
+            seq: 0,
+            match_number: 0,
+            markets: HashSet::new(),
+            messages: Vec::new()
+        }
+    }
+
+    /* Send the feed to this address, which may be a multicast group, ex. 239.1.1.1:5000. */
+    pub fn add_udp(&mut self, address: &str) -> io::Result<()> {
+        let target = match address.to_socket_addrs()?.next() {
+            Some(target) => target,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))
+        };
+        let bind = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind)?;
+        dark_green!("Publishing the binary feed to udp://{}\n", target);
+        self.sinks.push(Sink::Udp(socket, target));
+        Ok(())
+    }
+
+    /* Record the feed to this file, a recording is replaced each time the exchange starts. */
+    pub fn add_file(&mut self, path: &str) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        dark_green!("Recording the binary feed to {}\n", path);
+        self.sinks.push(Sink::File(BufWriter::new(file)));
+        Ok(())
+    }
+
+    /* Start the feed with the state of every market, then the orders already on the book,
+     * best price and then time first.
+     **/
+    pub fn start(&mut self, exchange: &Exchange) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        self.push(Body::System { event: SystemEvent::StartOfMessages });
+
+        let mut markets: Vec<&String> = exchange.statistics.keys().chain(exchange.live_orders.keys()).collect();
+        markets.sort();
+        markets.dedup();
+        for symbol in markets {
+            self.market_state(symbol, exchange.halted.contains(symbol));
+        }
+
+        let mut symbols: Vec<&String> = exchange.live_orders.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let market = &exchange.live_orders[symbol];
+            let buys = market.buy_orders.clone().into_sorted_vec();
+            let sells = market.sell_orders.clone().into_sorted_vec();
+            for order in buys.iter().rev().chain(sells.iter().rev().map(|order| &order.0)) {
+                self.add_order(order);
+            }
+        }
+        self.flush();
+    }
+
+    /* Send the adds, executions and cancels of the last request.
+     * Called after every request, even with nowhere to publish, so the exchange's list doesn't grow.
+     **/
+    pub fn publish(&mut self, exchange: &mut Exchange) {
+        let events: Vec<BookEvent> = exchange.book_events.drain(..).collect();
+        if self.sinks.is_empty() {
+            return;
+        }
+
+        for event in events.iter() {
+            match event {
+                BookEvent::Added(order) => self.add_order(order),
+                BookEvent::Executed(trade) => {
+                    self.match_number += 1;
+                    self.push(Body::OrderExecuted { order_id: trade.filled_oid as u64, shares: trade.exchanged as u32, match_number: self.match_number });
+                    // The trade's action is the side of the resting order.
+                    let side = if trade.action == "BUY" { Side::Sell } else { Side::Buy };
+                    self.push(Body::Trade {
+                        side,
+                        shares: trade.exchanged as u32,
+                        symbol: trade.symbol.clone(),
+                        price: price_from_f64(trade.price),
+                        match_number: self.match_number
+                    });
+                },
+                BookEvent::Cancelled { order_id, quantity, .. } => {
+                    self.push(Body::OrderCancelled { order_id: *order_id as u64, shares: *quantity as u32 });
+                },
+                BookEvent::State { symbol, halted } => self.market_state(symbol, *halted)
+            }
+        }
+        self.flush();
+    }
+
+    /* Tell consumers nothing else is coming. */
+    pub fn stop(&mut self) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        self.push(Body::System { event: SystemEvent::EndOfMessages });
+        self.flush();
+    }
+
+    fn market_state(&mut self, symbol: &str, halted: bool) {
+        let state = if halted { MarketState::Halted } else { MarketState::Trading };
+        self.markets.insert(symbol.to_string());
+        self.push(Body::MarketState { symbol: symbol.to_string(), state });
+    }
+
+    // Markets are announced before their first order, in case we missed them opening.
+    fn add_order(&mut self, order: &Order) {
+        if !self.markets.contains(&order.symbol) {
+            self.market_state(&order.symbol, false);
+        }
+        let side = if order.action == "BUY" { Side::Buy } else { Side::Sell };
+        self.push(Body::AddOrder {
+            order_id: order.order_id as u64,
+            side,
+            shares: (order.quantity - order.filled) as u32,
+            symbol: order.symbol.clone(),
+            price: price_from_f64(order.price)
+        });
+    }
+
+    fn push(&mut self, body: Body) {
+        self.seq += 1;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or(0);
+        self.messages.push(Message::new(self.seq, timestamp, body));
+    }
+
+    // Send everything waiting, packing as many messages into each packet as fit.
+    fn flush(&mut self) {
+        if self.messages.is_empty() {
+            return;
+        }
+        let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+        for message in self.messages.drain(..) {
+            let framed = message.encode_framed();
+            let packet = packets.last_mut().unwrap();
+            if !packet.is_empty() && packet.len() + framed.len() > MAX_PACKET_LEN {
+                packets.push(framed);
+            } else {
+                packet.extend(framed);
+            }
+        }
+
+        for sink in self.sinks.iter_mut() {
+            let result = match sink {
+                Sink::Udp(socket, target) => packets.iter().try_for_each(|packet| socket.send_to(packet, *target).map(|_| ())),
+                Sink::File(file) => packets.iter().try_for_each(|packet| file.write_all(packet)).and_then(|_| file.flush())
+            };
+            if let Err(e) = result {
+                eprintln!("[BINARY FEED]: Failed to publish: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itch::{decode_frames, Book};
+
+    use crate::config::Config;
+    use crate::exchange::SecStat;
+
+    fn states(messages: &[Message]) -> Vec<(String, MarketState)> {
+        messages.iter().filter_map(|message| match &message.body {
+            Body::MarketState { symbol, state } => Some((symbol.clone(), *state)),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn market_state_changes_are_published() {
+        let path = std::env::temp_dir().join(format!["rustx-feed-{}.itch", std::process::id()]);
+        let mut exchange = Exchange::new(&Config::default());
+        for symbol in ["GME", "AAPL"].iter() {
+            exchange.statistics.insert(symbol.to_string(), SecStat::direct(symbol, 0, 0, 0, 0, None));
+        }
+        exchange.set_halted("AAPL", true);
+        exchange.book_events.clear();
+
+        let mut feed = BinaryFeed::new();
+        feed.add_file(path.to_str().unwrap()).unwrap();
+        feed.start(&exchange);
+
+        // A market opens, one halts, and both resume. Halting twice is one change.
+        assert!(exchange.open_market(SecStat::direct("TSLA", 0, 0, 0, 0, None)));
+        assert!(!exchange.open_market(SecStat::direct("TSLA", 0, 0, 0, 0, None)));
+        feed.publish(&mut exchange);
+        assert!(exchange.set_halted("GME", true));
+        assert!(!exchange.set_halted("GME", true));
+        feed.publish(&mut exchange);
+        exchange.set_halted("GME", false);
+        exchange.set_halted("AAPL", false);
+        feed.publish(&mut exchange);
+        feed.stop();
+        drop(feed);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let (messages, used) = decode_frames(&bytes).unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(messages.first().map(|message| &message.body), Some(&Body::System { event: SystemEvent::StartOfMessages }));
+        assert_eq!(messages.last().map(|message| &message.body), Some(&Body::System { event: SystemEvent::EndOfMessages }));
+
+        let expected = vec![
+            ("AAPL", MarketState::Halted), ("GME", MarketState::Trading),   // At the start
+            ("TSLA", MarketState::Trading),
+            ("GME", MarketState::Halted),
+            ("GME", MarketState::Trading), ("AAPL", MarketState::Trading)
+        ];
+        let expected: Vec<(String, MarketState)> = expected.into_iter().map(|(symbol, state)| (symbol.to_string(), state)).collect();
+        assert_eq!(states(&messages), expected);
+
+        // A consumer rebuilding the book ends with every market trading.
+        let mut book = Book::new();
+        for message in messages.iter() {
+            book.apply(message).unwrap();
+        }
+        for symbol in ["GME", "AAPL", "TSLA"].iter() {
+            assert_eq!(book.markets[*symbol].state, Some(MarketState::Trading));
+        }
+    }
+}
@@ -1,17 +1,35 @@
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::convert::TryInto;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
 use chrono::{DateTime, Utc};
 
-use postgres::Client;
-use crate::database;
+use crate::database::{Store, StoreBackend};
+use crate::config::{self, LogLevel};
 
 use crate::exchange::{Exchange, OrderStatus, Trade, Order};
 use crate::exchange::stats::SecStat;
 
-use crate::{WorkerThreads, Category};
+// Helps us determine what each thread will work on.
+pub enum Category {
+    InsertNew,
+    UpdateKnown,
+    InsertPending,
+    DeletePending,
+    UpdateTotal,
+    UpdateMarketStats,
+    InsertNewTrades
+}
+
+// Helps manage the workload.
+pub struct WorkerThreads<T> {
+    pub threads: Vec<thread::JoinHandle<T>>, // Holds thread handles
+    pub senders: Vec<mpsc::Sender<(UpdateCategories, Category)>>, // Each category is sent to one thread, with all others empty.
+    pub receivers: Vec<mpsc::Receiver<bool>> // When a thread is finished flushing a category, it writes `true` to the channel.
+}
 
 
 /* This struct represents an order that is ready to be written to the database.
@@ -42,11 +60,12 @@ pub struct DatabaseReadyOrder {
     pub quantity:     Option<i32>,
     pub filled:       Option<i32>,
     pub price:        Option<f64>,
-    pub order_id:     Option<i32>,
+    pub order_id:     Option<i64>,
     pub status:       Option<OrderStatus>,
-    pub user_id:      Option<i32>,
+    pub user_id:      Option<i64>,
     pub time_placed:  Option<DateTime<Utc>>,
     pub time_updated: Option<DateTime<Utc>>,
+    pub client_id:    Option<String>,
 }
 
 impl DatabaseReadyOrder {
@@ -63,6 +82,7 @@ impl DatabaseReadyOrder {
             user_id:      None,
             time_placed:  None,
             time_updated: None,
+            client_id:    None,
         }
     }
 
@@ -81,6 +101,7 @@ impl DatabaseReadyOrder {
             user_id: order.user_id,
             time_placed:  Some(Utc::now()),
             time_updated: None,
+            client_id:    order.client_id.clone(),
         }
     }
 
@@ -106,14 +127,20 @@ impl DatabaseReadyOrder {
 pub struct UpdateCategories {
     pub insert_orders: Vec<DatabaseReadyOrder>,
     pub update_orders: Vec<DatabaseReadyOrder>,
-    pub total_orders: i32,
-    pub insert_pending: Vec<i32>,
-    pub delete_pending: Vec<i32>,
+    pub total_orders: i64,
+    pub insert_pending: Vec<i64>,
+    pub delete_pending: Vec<i64>,
     pub markets_modified: HashMap<String, ()>, // Just store symbols of modified markets
     pub insert_trades: Vec<Trade>,
     pub update_markets: Vec<SecStat>
 }
 
+impl Default for UpdateCategories {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UpdateCategories {
     pub fn new() -> Self {
         let update_orders  = Vec::new();
@@ -145,7 +172,7 @@ pub enum BufferState {
 
 #[derive(Debug)]
 pub struct OrderBuffer {
-    data: HashMap<i32, DatabaseReadyOrder>,
+    data: HashMap<i64, DatabaseReadyOrder>,
     state: BufferState
 }
 
@@ -154,7 +181,7 @@ impl OrderBuffer {
     /* TODO: Mess around with this.
      * Capacity is the number of Orders we want to store in the buffer. */
     pub fn new(capacity: u32) -> Self {
-        let data: HashMap<i32, DatabaseReadyOrder> = HashMap::with_capacity(capacity.try_into().unwrap());
+        let data: HashMap<i64, DatabaseReadyOrder> = HashMap::with_capacity(capacity.try_into().unwrap());
         let state = BufferState::EMPTY;
         OrderBuffer {
             data,
@@ -167,11 +194,13 @@ impl OrderBuffer {
      * on the data, returning an iterator for use, but this works so...
      **/
     pub fn drain_buffer(&mut self) {
-        match self.state {
-            BufferState::EMPTY => println!("The Order buffer is empty, there is nothing to drain."),
-            BufferState::NONEMPTY => println!("The Order buffer was not full, we could have waited before draining."),
-            BufferState::FORCEFLUSH => println!("The Order buffer was forced to flush."),
-            BufferState::FULL => ()
+        if config::logs(LogLevel::Debug) {
+            match self.state {
+                BufferState::EMPTY => println!("The Order buffer is empty, there is nothing to drain."),
+                BufferState::NONEMPTY => println!("The Order buffer was not full, we could have waited before draining."),
+                BufferState::FORCEFLUSH => println!("The Order buffer was forced to flush."),
+                BufferState::FULL => ()
+            }
         }
         self.state = BufferState::EMPTY;
         self.data.clear();
@@ -203,13 +232,22 @@ impl OrderBuffer {
             _ => ()
         }
 
-        match self.data.insert(order.order_id, DatabaseReadyOrder::prepare_new_order(order)) {
-            Some(_) => {
-                panic!("\
+        if self.data.insert(order.order_id, DatabaseReadyOrder::prepare_new_order(order)).is_some() {
+            panic!("\
     Order added to OrderBuffer was already stored in OrderBuffer!\
     Find where add_unknown_to_order_buffer is called, and make sure to only add newly submitted orders!")
-            },
-            None => ()
+        }
+    }
+
+    /* The changes to an order that haven't been written to the database yet, if any. */
+    pub fn view_order(&self, order_id: i64) -> Option<&DatabaseReadyOrder> {
+        self.data.get(&order_id)
+    }
+
+    /* Add a batch of cancelled orders to the buffer, ex. from a mass cancel. */
+    pub fn add_cancelled_orders(&mut self, order_ids: &[i64]) {
+        for order_id in order_ids.iter() {
+            self.add_or_update_entry_in_order_buffer(&Order::from_cancelled(*order_id), false);
         }
     }
 
@@ -249,26 +287,26 @@ Be sure to clear the buffer well before it reaches capacity!");
                     categories.insert_orders.push(order.clone());
 
                     if let OrderStatus::PENDING = order.status.unwrap() {
-                        categories.insert_pending.push(order.order_id.unwrap().clone());
+                        categories.insert_pending.push(order.order_id.unwrap());
                     }
                 },
                 // Known order
                 // care about delete pending, update order
                 None => {
                     // First, add the order ID.
-                    order.order_id = Some(id.clone());
+                    order.order_id = Some(*id);
                     categories.update_orders.push(order.clone());
 
                     // If cancelled/complete
-                    if let Some(_) = order.status {
-                        categories.delete_pending.push(order.order_id.unwrap().clone());
+                    if order.status.is_some() {
+                        categories.delete_pending.push(order.order_id.unwrap());
                     }
                 }
             }
         }
         // Create iterator of modified SecStat's and pass that to DB api.
         categories.total_orders = exchange.total_orders;
-        categories.update_markets = exchange.statistics.values().cloned().filter(|market| market.modified == true).collect();
+        categories.update_markets = exchange.statistics.values().filter(|&market| market.modified).cloned().collect();
     }
 }
 
@@ -308,16 +346,23 @@ impl TradeBuffer {
      * on the data, returning an iterator for use, but this works so...
      **/
     pub fn drain_buffer(&mut self) {
-        match self.state {
-            BufferState::EMPTY => println!("The Trade buffer is empty, there is nothing to drain."),
-            BufferState::NONEMPTY => println!("The Trade buffer was not full, we could have waited before draining."),
-            BufferState::FORCEFLUSH => println!("The Trade buffer was forced to flush."),
-            BufferState::FULL => ()
+        if config::logs(LogLevel::Debug) {
+            match self.state {
+                BufferState::EMPTY => println!("The Trade buffer is empty, there is nothing to drain."),
+                BufferState::NONEMPTY => println!("The Trade buffer was not full, we could have waited before draining."),
+                BufferState::FORCEFLUSH => println!("The Trade buffer was forced to flush."),
+                BufferState::FULL => ()
+            }
         }
         self.state = BufferState::EMPTY;
         self.data.clear();
     }
 
+    /* The trades in this market that haven't been written to the database yet, oldest first. */
+    pub fn view_trades<'a>(&'a self, symbol: &'a str) -> impl Iterator<Item = &'a Trade> + 'a {
+        self.data.iter().filter(move |trade| trade.symbol == symbol)
+    }
+
     pub fn add_trade_to_buffer(&mut self, trade: Trade) {
         match self.state {
             BufferState::FULL => panic!("Attempting to write a trade to a full buffer!"),
@@ -346,10 +391,125 @@ impl TradeBuffer {
 pub struct BufferCollection {
     pub buffered_orders: OrderBuffer, // where we temporarily store order updates that will be inserted/updated to the DB.
     pub buffered_trades: TradeBuffer, // where we temporarily store trades that will be inserted in the DB
-    pub tx: Option<mpsc::Sender<Option<UpdateCategories>>> // Transmitter to thread that writes to the database
+    pub tx: Option<mpsc::Sender<Option<UpdateCategories>>>, // Transmitter to thread that writes to the database
+    sent: u64,              // Batches sent to the writer thread
+    written: Arc<AtomicU64> // Batches the writer thread has finished writing
 }
 
 impl BufferCollection {
+    /* Start the thread that writes the buffers to the store, returns its handle.
+     * Each of its `threads` workers gets its own connection to the backend, at most one per category.
+     * It exits once it's sent None, see the ExitReq.
+     **/
+    pub fn start_writer(&mut self, backend: &StoreBackend, threads: usize) -> thread::JoinHandle<()> {
+        let backend = backend.clone();
+        let (tx, rx) = mpsc::channel();
+        self.set_transmitter(tx);
+        let written = Arc::clone(&self.written);
+
+        /* This thread's job is to read categorized buffer data and write it to the database.
+         *
+         * It behaves in the following way:
+         *  1. Set up worker threads and additional channels
+         *  loop {
+         *      2.  Read the categories, if we got None, we must shutdown immediately.
+         *      3.  If we got Some(data), send each component to the appropriate worker thread
+         *          to be written to the database.
+         *  }
+         *
+         **/
+        thread::spawn(move || {
+
+            let mut workers = WorkerThreads {
+                threads: Vec::new(),
+                senders: Vec::new(),
+                receivers: Vec::new()
+            };
+
+            // These are our worker threads. The buffer handling thread
+            // will write each category to its respective worker thread to be
+            // written to the database.
+            for _ in 0..threads.clamp(1, config::MAX_WRITER_THREADS) {
+                // Set up the transmitter x receiver channel for sending data to worker,
+                // then set up response channel to get `true` message of completion.
+                let (transmitter, receiver) = mpsc::channel();
+                let (response_tx, response_rx) = mpsc::channel();
+                workers.senders.push(transmitter);
+                workers.receivers.push(response_rx);
+
+                let mut store = backend.connect();
+
+                workers.threads.push(thread::spawn(move || {
+                    loop {
+                        let (data, category_type): (UpdateCategories, Category) = match receiver.recv() {
+                            Ok((data, category_type)) => (data, category_type),
+                            Err(_) => {
+                                return;
+                            }
+                        };
+
+                        // Perform the database write here depending on the type of category.
+                        match category_type {
+                            Category::InsertNew            => BufferCollection::launch_insert_orders(&data.insert_orders, &mut *store),
+                            Category::UpdateKnown          => BufferCollection::launch_update_orders(&data.update_orders, &mut *store),
+                            Category::InsertPending        => BufferCollection::launch_insert_pending_orders(&data.insert_pending, &mut *store),
+                            Category::DeletePending        => BufferCollection::launch_delete_pending_orders(&data.delete_pending, &mut *store),
+                            Category::UpdateTotal          => BufferCollection::launch_exchange_stats_update(data.total_orders, &mut *store),
+                            Category::UpdateMarketStats    => BufferCollection::launch_update_market(&data.update_markets, &mut *store),
+                            Category::InsertNewTrades      => BufferCollection::launch_insert_trades(&data.insert_trades, &mut *store)
+                        }
+                        // Return the successful response message
+                        response_tx.send(true).unwrap();
+                    }
+                }));
+            }
+
+            // This is the main loop for the Buffer handling thread.
+            // We read the categories from the main thread, then send them
+            // to the worker threads. On shutdown, we clean everything up.
+            loop {
+                let categories: UpdateCategories = match rx.recv() {
+                    Ok(option) => match option {
+                        Some(data) => data,
+                        // We write None to channel on shutdown.
+                        // Better way would be to close Sender, but I'm having trouble with that...
+                        None => {
+                            let debug = config::logs(LogLevel::Debug);
+                            if debug {
+                                dark_blue!("[Buffer Thread]: received shutdown request.\n");
+                            }
+                            drop(rx);
+                            if debug {
+                                dark_blue!("[Buffer Thread]: waiting on worker threads to complete...\n");
+                            }
+
+                            for tx in workers.senders {
+                                drop(tx);
+                            }
+                            for handle in workers.threads {
+                                handle.join().unwrap();
+                            }
+                            return;
+                        }
+                    },
+                    Err(_) => {
+                        return;
+                    }
+                };
+
+                let debug = config::logs(LogLevel::Debug);
+                if debug {
+                    dark_blue!("[BUFFER THREAD]: Initiating database writes.\n");
+                }
+                BufferCollection::launch_batch_db_updates(&categories, &mut workers);
+                written.fetch_add(1, Ordering::SeqCst);
+                if debug {
+                    dark_blue!("[BUFFER THREAD]: Writes successfully flushed.\n");
+                }
+            }
+        })
+    }
+
     pub fn new(order_buffer_cap: u32, trade_buffer_cap: u32) -> Self {
         let buffered_orders: OrderBuffer = OrderBuffer::new(order_buffer_cap);
         let buffered_trades: TradeBuffer = TradeBuffer::new(trade_buffer_cap);
@@ -357,10 +517,24 @@ impl BufferCollection {
         BufferCollection {
             buffered_orders,
             buffered_trades,
-            tx: None
+            tx: None,
+            sent: 0,
+            written: Arc::new(AtomicU64::new(0))
         }
     }
 
+    /* The number of batches sent to the writer thread so far.
+     * The changes of the batch last sent are in the database once batches_written() reaches this.
+     **/
+    pub fn batches_sent(&self) -> u64 {
+        self.sent
+    }
+
+    /* The number of batches the writer thread has finished writing. */
+    pub fn batches_written(&self) -> u64 {
+        self.written.load(Ordering::SeqCst)
+    }
+
     // No, we don't need a function for this, but it's called once and it makes
     // it clear what's happening to the Sender.
     pub fn set_transmitter(&mut self, tx: mpsc::Sender<Option<UpdateCategories>>) {
@@ -374,7 +548,9 @@ impl BufferCollection {
             BufferState::FORCEFLUSH => {
                 self.buffered_orders.state = BufferState::FORCEFLUSH
             },
-            _ => println!("Order buffer empty, nothing to flush.")
+            _ => if config::logs(LogLevel::Debug) {
+                println!("Order buffer empty, nothing to flush.");
+            }
         }
 
         match self.buffered_trades.state {
@@ -383,7 +559,9 @@ impl BufferCollection {
             BufferState::FORCEFLUSH => {
                 self.buffered_trades.state = BufferState::FORCEFLUSH
             },
-            _ => println!("Trades buffer empty, nothing to flush.")
+            _ => if config::logs(LogLevel::Debug) {
+                println!("Trades buffer empty, nothing to flush.");
+            }
         }
 
         self.transmit_buffer_data(exchange);
@@ -393,7 +571,9 @@ impl BufferCollection {
         self.update_buffer_states();
 
         self.force_flush(exchange);
-        println!("Shutdown request has been propagated.");
+        if config::logs(LogLevel::Info) {
+            println!("Shutdown request has been propagated.");
+        }
     }
 
     /* Sends the buffer data down the channel for the other thread to handle.
@@ -437,8 +617,9 @@ impl BufferCollection {
         // Send the categories to the thread if we have updates.
         if pending_updates {
             self.tx.as_ref().unwrap().send(Some(categories)).unwrap();
+            self.sent += 1;
         }
-        return orders_drained;
+        orders_drained
     }
 
     /* If our buffers are close to capacity, we will update their state to full. */
@@ -463,64 +644,66 @@ impl BufferCollection {
      *      2. Send ALL other categories to their respective threads to be inserted.
      *      3. Wait for these threads to complete before returning.
      *
+     * With fewer than 7 worker threads, category N goes to worker N % threads,
+     * so some workers write several categories one after the other.
+     *
      * TODO: We seem to be running each query much slower concurrently than sequentially.
      *       I've checked, and it seems absolutely clear that each category writes to
      *       distinct rows, so we shouldn't have any problems with row contention.
      **/
     pub fn launch_batch_db_updates<T>(categories: &UpdateCategories, workers: &mut WorkerThreads<T>) {
+        let threads = workers.senders.len();
 
         // 1. Write to worker 1
-        let tx = workers.senders.get(0).unwrap();
+        let tx = workers.senders.first().unwrap();
         let mut insert_container = UpdateCategories::new();
         insert_container.insert_orders = categories.insert_orders.clone();
         tx.send((insert_container, Category::InsertNew)).unwrap();
 
         // 2. Wait for response 'true' from insert thread
-        if workers.receivers.get(0).unwrap().recv().unwrap() {
+        if workers.receivers.first().unwrap().recv().unwrap() {
             // Send corresponding data to each worker thread
             // 2. update orders
-            let tx = workers.senders.get(1).unwrap();
+            let tx = workers.senders.get(1 % threads).unwrap();
             let mut update_order_container = UpdateCategories::new();
             update_order_container.update_orders = categories.update_orders.clone();
             tx.send((update_order_container, Category::UpdateKnown)).unwrap();
 
             // 3. insert pending
-            let tx = workers.senders.get(2).unwrap();
+            let tx = workers.senders.get(2 % threads).unwrap();
             let mut insert_pending_container = UpdateCategories::new();
             insert_pending_container.insert_pending = categories.insert_pending.clone();
             tx.send((insert_pending_container, Category::InsertPending)).unwrap();
 
             // 4. delete pending
-            let tx = workers.senders.get(3).unwrap();
+            let tx = workers.senders.get(3 % threads).unwrap();
             let mut delete_pending_container = UpdateCategories::new();
             delete_pending_container.delete_pending = categories.delete_pending.clone();
             tx.send((delete_pending_container, Category::DeletePending)).unwrap();
 
             // 5. update exchange stats
-            let tx = workers.senders.get(4).unwrap();
+            let tx = workers.senders.get(4 % threads).unwrap();
             let mut update_total_container = UpdateCategories::new();
-            update_total_container.total_orders = categories.total_orders.clone();
+            update_total_container.total_orders = categories.total_orders;
             tx.send((update_total_container, Category::UpdateTotal)).unwrap();
 
             // 6. update market stats
-            let tx = workers.senders.get(5).unwrap();
+            let tx = workers.senders.get(5 % threads).unwrap();
             let mut update_market_container = UpdateCategories::new();
             update_market_container.update_markets = categories.update_markets.clone();
             tx.send((update_market_container, Category::UpdateMarketStats)).unwrap();
 
             // 7. insert new trades
-            let tx = workers.senders.get(6).unwrap();
+            let tx = workers.senders.get(6 % threads).unwrap();
             let mut insert_trades_container = UpdateCategories::new();
             insert_trades_container.insert_trades = categories.insert_trades.clone();
             tx.send((insert_trades_container, Category::InsertNewTrades)).unwrap();
 
-            // Iterate over the received responses, this is like doing a thread join,
+            // Read a response for each category we sent, this is like doing a thread join,
             // except with message passing. We're effectively waiting for all threads
             // to finish their work.
-            let mut write_responses = workers.receivers.iter();
-            write_responses.next(); // Skip the first because we already read it
-            for response in write_responses {
-                if response.recv().unwrap() {
+            for category in 1..7 {
+                if workers.receivers.get(category % threads).unwrap().recv().unwrap() {
                     continue;
                 }
             }
@@ -528,41 +711,41 @@ impl BufferCollection {
         /*
         // TODO: We can decrease the computation time for this, see comment
         //       in prepare_for_db_update.
-        BufferCollection::launch_update_market(&categories.update_markets, conn);
+        BufferCollection::launch_update_market(&categories.update_markets, store);
         */
     }
 
     /* Entry point for batch inserting unknown orders to database */
-    pub fn launch_insert_orders(orders_to_insert: &Vec<DatabaseReadyOrder>, conn: &mut Client) {
-        database::insert_buffered_orders(orders_to_insert, conn);
+    pub fn launch_insert_orders(orders_to_insert: &[DatabaseReadyOrder], store: &mut dyn Store) {
+        store.insert_buffered_orders(orders_to_insert);
     }
 
     /* Entry point for batch updating known orders in database */
-    pub fn launch_update_orders(orders_to_update: &Vec<DatabaseReadyOrder>, conn: &mut Client) {
-        database::update_buffered_orders(orders_to_update, conn);
+    pub fn launch_update_orders(orders_to_update: &[DatabaseReadyOrder], store: &mut dyn Store) {
+        store.update_buffered_orders(orders_to_update);
     }
 
     /* Entry point for batch inserting pending orders for unknown Orders to database  */
-    pub fn launch_insert_pending_orders(pending_to_insert: &Vec<i32>, conn: &mut Client) {
-        database::insert_buffered_pending(pending_to_insert, conn);
+    pub fn launch_insert_pending_orders(pending_to_insert: &[i64], store: &mut dyn Store) {
+        store.insert_buffered_pending(pending_to_insert);
     }
 
     /* Entry point for batch deleting pending orders from database  */
-    pub fn launch_delete_pending_orders(pending_to_delete: &Vec<i32>, conn: &mut Client) {
-        database::delete_buffered_pending(pending_to_delete, conn);
+    pub fn launch_delete_pending_orders(pending_to_delete: &[i64], store: &mut dyn Store) {
+        store.delete_buffered_pending(pending_to_delete);
     }
 
     /* Entry point for batch market stats updates. */
-    pub fn launch_exchange_stats_update(total_orders: i32, conn: &mut Client) {
-        database::update_total_orders(total_orders, conn);
+    pub fn launch_exchange_stats_update(total_orders: i64, store: &mut dyn Store) {
+        store.update_total_orders(total_orders);
     }
 
     /* Entry point for batch updating market stats in database  */
-    pub fn launch_update_market(update_markets: &Vec<SecStat>, conn: &mut Client) {
-        database::update_buffered_markets(&update_markets, conn);
+    pub fn launch_update_market(update_markets: &[SecStat], store: &mut dyn Store) {
+        store.update_buffered_markets(update_markets);
     }
 
-    pub fn launch_insert_trades(trades_to_insert: &Vec<Trade>, conn: &mut Client) {
-        database::insert_buffered_trades(trades_to_insert, conn);
+    pub fn launch_insert_trades(trades_to_insert: &[Trade], store: &mut dyn Store) {
+        store.insert_buffered_trades(trades_to_insert);
     }
 }
@@ -0,0 +1,507 @@
+use std::env;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use redis::IntoConnectionInfo;
+
+use crate::account::{MarginCallAction, MarginPolicy};
+use crate::exchange::RateLimits;
+
+/* ---- Configuration ----
+ *
+ *  Every setting has a key in the TOML file, an environment variable and a command line flag.
+ *  Later sources win: the defaults, then the file, then the environment, then the command line.
+ *
+ *  The file is the one given by --config or RUSTX_CONFIG, otherwise rustx.toml if there is one
+ *  in the working directory. See rustx.example.toml for every setting.
+ **/
+pub const DEFAULT_CONFIG_FILE: &str = "rustx.toml";
+
+// The buffer writer sends each category of write to its own worker, so more would sit idle.
+pub const MAX_WRITER_THREADS: usize = 7;
+
+struct Setting {
+    key: &'static str,      // Section and name in the TOML file
+    flag: &'static str,     // Command line flag, followed by the value
+    var: &'static str,      // Environment variable
+    example: &'static str
+}
+
+const SETTINGS: [Setting; 26] = [
+    Setting { key: "database.postgres",                   flag: "--postgres",           var: "RUSTX_POSTGRES",            example: "\"host=localhost user=postgres dbname=rustx\"" },
+    Setting { key: "database.sqlite",                     flag: "--sqlite",             var: "RUSTX_SQLITE",              example: "rustx.db" },
+    Setting { key: "database.in_memory",                  flag: "--in-memory",          var: "RUSTX_IN_MEMORY",           example: "true" },
+    Setting { key: "cache.redis",                         flag: "--redis",              var: "RUSTX_REDIS",               example: "redis://127.0.0.1/" },
+    Setting { key: "cache.file",                          flag: "--cache-file",         var: "RUSTX_CACHE_FILE",          example: "rustx-cache.json" },
+    Setting { key: "cache.users",                         flag: "--user-cache",         var: "RUSTX_USER_CACHE_CAPACITY", example: "1000" },
+    Setting { key: "buffers.orders",                      flag: "--order-buffer",       var: "RUSTX_ORDER_BUFFER",        example: "200000" },
+    Setting { key: "buffers.trades",                      flag: "--trade-buffer",       var: "RUSTX_TRADE_BUFFER",        example: "200000" },
+    Setting { key: "buffers.writer_threads",              flag: "--writer-threads",     var: "RUSTX_WRITER_THREADS",      example: "7" },
+    Setting { key: "limits.orders_per_sec",               flag: "--orders-per-sec",     var: "RUSTX_ORDERS_PER_SEC",      example: "50" },
+    Setting { key: "limits.cancels_per_sec",              flag: "--cancels-per-sec",    var: "RUSTX_CANCELS_PER_SEC",     example: "50" },
+    Setting { key: "limits.open_orders",                  flag: "--open-orders",        var: "RUSTX_OPEN_ORDERS",         example: "1000" },
+    Setting { key: "limits.market_maker_orders_per_sec",  flag: "--mm-orders-per-sec",  var: "RUSTX_MM_ORDERS_PER_SEC",   example: "500" },
+    Setting { key: "limits.market_maker_cancels_per_sec", flag: "--mm-cancels-per-sec", var: "RUSTX_MM_CANCELS_PER_SEC",  example: "500" },
+    Setting { key: "limits.market_maker_open_orders",     flag: "--mm-open-orders",     var: "RUSTX_MM_OPEN_ORDERS",      example: "10000" },
+    Setting { key: "margin.initial",                      flag: "--initial-margin",     var: "RUSTX_INITIAL_MARGIN",      example: "0.5" },
+    Setting { key: "margin.maintenance",                  flag: "--maintenance-margin", var: "RUSTX_MAINTENANCE_MARGIN",  example: "0.3" },
+    Setting { key: "margin.call_action",                  flag: "--margin-call",        var: "RUSTX_MARGIN_CALL",         example: "flag" },
+    Setting { key: "listen.serve",                        flag: "--serve",              var: "RUSTX_SERVE",               example: "127.0.0.1:7878" },
+    Setting { key: "listen.http",                         flag: "--http",               var: "RUSTX_HTTP",                example: "127.0.0.1:8080" },
+    Setting { key: "listen.ws",                           flag: "--ws",                 var: "RUSTX_WS",                  example: "127.0.0.1:9001" },
+    Setting { key: "listen.fix",                          flag: "--fix",                var: "RUSTX_FIX",                 example: "127.0.0.1:9878" },
+    Setting { key: "listen.drop_copy",                    flag: "--drop-copy",          var: "RUSTX_DROP_COPY",           example: "127.0.0.1:9879" },
+    Setting { key: "feed.itch_udp",                       flag: "--itch-udp",           var: "RUSTX_ITCH_UDP",            example: "239.1.1.1:5000" },
+    Setting { key: "feed.itch_file",                      flag: "--itch-file",          var: "RUSTX_ITCH_FILE",           example: "feed.itch" },
+    Setting { key: "log.level",                           flag: "--log-level",          var: "RUSTX_LOG_LEVEL",           example: "info" }
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,  // Only errors
+    Warn,   // ... and requests we couldn't understand
+    Info,   // ... and what the exchange is doing, ex. setup times and the requests it services
+    Debug   // ... and the database writer's progress
+}
+
+impl LogLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn  => "warn",
+            LogLevel::Info  => "info",
+            LogLevel::Debug => "debug"
+        }
+    }
+
+    fn parse(level: &str) -> Option<LogLevel> {
+        match level.to_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "warn"  => Some(LogLevel::Warn),
+            "info"  => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Info as usize);
+
+/* Set the level every thread logs at, see logs. */
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/* True if messages of this level should be printed. */
+pub fn logs(level: LogLevel) -> bool {
+    level as usize <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub postgres: String,                   // Connection string of the Postgres store
+    pub sqlite: Option<String>,             // Keep the exchange's data in this SQLite file, rather than in Postgres
+    pub in_memory: bool,                    // Keep everything in the process, rather than in Postgres and Redis
+    pub redis: String,                      // URL of the Redis cache
+    pub cache_file: Option<String>,         // Keep the cache in the process and save it to this file, rather than in Redis
+    pub user_cache_capacity: usize,         // The number of accounts kept in memory
+    pub order_buffer_capacity: u32,         // Order updates buffered before they're written to the database
+    pub trade_buffer_capacity: u32,         // Trades buffered before they're written to the database
+    pub writer_threads: usize,              // Worker threads (and connections) of the database writer
+    pub rate_limits: RateLimits,            // The rate limits of accounts without their own, see exchange/throttle.rs
+    pub market_maker_limits: RateLimits,    // ... and of market makers
+    pub margin_policy: MarginPolicy,        // Margins on short positions, and what to do on a margin call
+    pub listen: Option<String>,             // Serve the line protocol on this address, ex. 127.0.0.1:7878
+    pub http: Option<String>,               // Serve the HTTP API on this address, ex. 127.0.0.1:8080
+    pub ws: Option<String>,                 // Stream market data over WebSockets on this address, ex. 127.0.0.1:9001
+    pub fix: Option<String>,                // Accept FIX 4.4 sessions on this address, ex. 127.0.0.1:9878
+    pub drop_copy: Option<String>,          // Send drop copies of executions on this address, ex. 127.0.0.1:9879
+    pub itch_udp: Option<String>,           // Publish the binary feed to this address, ex. 239.1.1.1:5000
+    pub itch_file: Option<String>,          // Record the binary feed to this file
+    pub log_level: LogLevel
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            postgres: "host=localhost user=postgres dbname=rustx".to_string(),
+            sqlite: None,
+            in_memory: false,
+            redis: "redis://127.0.0.1/".to_string(),
+            cache_file: None,
+            user_cache_capacity: 1000,
+            order_buffer_capacity: 200000,
+            trade_buffer_capacity: 200000,
+            writer_threads: MAX_WRITER_THREADS,
+            rate_limits: RateLimits::new(50.0, 50.0, 1000),
+            market_maker_limits: RateLimits::new(500.0, 500.0, 10000),
+            margin_policy: MarginPolicy::new(0.5, 0.3, MarginCallAction::Flag),
+            listen: None,
+            http: None,
+            ws: None,
+            fix: None,
+            drop_copy: None,
+            itch_udp: None,
+            itch_file: None,
+            log_level: LogLevel::Info
+        }
+    }
+}
+
+impl Config {
+    /* The defaults, overridden by the config file and then the environment.
+     * `path` is the file given on the command line, if any.
+     **/
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let mut config = Config::default();
+        let path = match path {
+            Some(path) => Some(path.to_string()),
+            None => match env::var("RUSTX_CONFIG") {
+                Ok(path) => Some(path),
+                Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => Some(DEFAULT_CONFIG_FILE.to_string()),
+                Err(_) => None
+            }
+        };
+        if let Some(path) = path {
+            config.read_file(&path)?;
+        }
+        config.read_environment()?;
+        Ok(config)
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<(), String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => return Err(format!["Failed to read the config file {}: {}", path, e])
+        };
+        let sections: toml::value::Table = match contents.parse::<toml::Value>() {
+            Ok(toml::Value::Table(sections)) => sections,
+            Ok(_) => return Err(format!["{} should be a TOML table, see rustx.example.toml", path]),
+            Err(e) => return Err(format!["{} isn't valid TOML: {}", path, e])
+        };
+
+        for (section, settings) in sections.iter() {
+            let settings = match settings {
+                toml::Value::Table(settings) => settings,
+                _ => return Err(format!["{}: `{}` should be a section, ex. [{}]", path, section, section])
+            };
+            for (name, value) in settings.iter() {
+                let key = format!["{}.{}", section, name];
+                let value = match value {
+                    toml::Value::String(value) => value.clone(),
+                    toml::Value::Integer(value) => value.to_string(),
+                    toml::Value::Float(value) => value.to_string(),
+                    toml::Value::Boolean(value) => value.to_string(),
+                    _ => return Err(format!["{}: `{}` should be a string, a number or a boolean", path, key])
+                };
+                self.set(&key, &value, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_environment(&mut self) -> Result<(), String> {
+        for setting in SETTINGS.iter() {
+            if let Ok(value) = env::var(setting.var) {
+                self.set(setting.key, &value, setting.var)?;
+            }
+        }
+        Ok(())
+    }
+
+    /* Apply a command line flag, taking its value from `args` if it needs one.
+     * Returns Ok(false) if it isn't one of our flags.
+     **/
+    pub fn read_flag<I>(&mut self, flag: &str, args: &mut I) -> Result<bool, String>
+    where
+        I: Iterator<Item = String>
+    {
+        let setting = match SETTINGS.iter().find(|setting| setting.flag == flag) {
+            Some(setting) => setting,
+            None => return Ok(false)
+        };
+        // The only boolean flag doesn't take a value.
+        if setting.key == "database.in_memory" {
+            self.in_memory = true;
+            return Ok(true);
+        }
+        match args.next() {
+            Some(value) => self.set(setting.key, &value, flag)?,
+            None => return Err(format!["Please provide a value for {}, ex. {} {}", flag, flag, setting.example])
+        }
+        Ok(true)
+    }
+
+    /* Set the setting with this key, `source` says where the value came from for errors. */
+    fn set(&mut self, key: &str, value: &str, source: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "database.postgres"                   => self.postgres = value.to_string(),
+            "database.sqlite"                     => self.sqlite = Some(value.to_string()),
+            "database.in_memory"                  => self.in_memory = parse_bool(key, value, source)?,
+            "cache.redis"                         => self.redis = value.to_string(),
+            "cache.file"                          => self.cache_file = Some(value.to_string()),
+            "cache.users"                         => self.user_cache_capacity = parse_positive(key, value, source)?,
+            "buffers.orders"                      => self.order_buffer_capacity = parse_positive(key, value, source)?,
+            "buffers.trades"                      => self.trade_buffer_capacity = parse_positive(key, value, source)?,
+            "buffers.writer_threads"              => self.writer_threads = parse_positive(key, value, source)?,
+            "limits.orders_per_sec"               => self.rate_limits.orders_per_sec = parse_rate(key, value, source)?,
+            "limits.cancels_per_sec"              => self.rate_limits.cancels_per_sec = parse_rate(key, value, source)?,
+            "limits.open_orders"                  => self.rate_limits.max_open_orders = parse_positive(key, value, source)?,
+            "limits.market_maker_orders_per_sec"  => self.market_maker_limits.orders_per_sec = parse_rate(key, value, source)?,
+            "limits.market_maker_cancels_per_sec" => self.market_maker_limits.cancels_per_sec = parse_rate(key, value, source)?,
+            "limits.market_maker_open_orders"     => self.market_maker_limits.max_open_orders = parse_positive(key, value, source)?,
+            "margin.initial"                      => self.margin_policy.initial = parse_fraction(key, value, source)?,
+            "margin.maintenance"                  => self.margin_policy.maintenance = parse_fraction(key, value, source)?,
+            "margin.call_action"                  => {
+                self.margin_policy.action = match MarginCallAction::parse(&value.to_lowercase()) {
+                    Some(action) => action,
+                    None => return Err(format!["{}: `{}` should be flag or liquidate, not `{}`", source, key, value])
+                }
+            },
+            "listen.serve"                        => self.listen = Some(value.to_string()),
+            "listen.http"                         => self.http = Some(value.to_string()),
+            "listen.ws"                           => self.ws = Some(value.to_string()),
+            "listen.fix"                          => self.fix = Some(value.to_string()),
+            "listen.drop_copy"                    => self.drop_copy = Some(value.to_string()),
+            "feed.itch_udp"                       => self.itch_udp = Some(value.to_string()),
+            "feed.itch_file"                      => self.itch_file = Some(value.to_string()),
+            "log.level"                           => {
+                self.log_level = match LogLevel::parse(value) {
+                    Some(level) => level,
+                    None => return Err(format!["{}: `{}` should be one of error, warn, info or debug, not `{}`", source, key, value])
+                }
+            },
+            _ => {
+                let keys: Vec<&str> = SETTINGS.iter().map(|setting| setting.key).collect();
+                return Err(format!["{}: unknown setting `{}`, the settings are: {}", source, key, keys.join(", ")]);
+            }
+        }
+        Ok(())
+    }
+
+    /* Check the settings make sense together, before we connect to anything. */
+    pub fn validate(&self) -> Result<(), String> {
+        if self.in_memory && self.sqlite.is_some() {
+            return Err("Please choose one of --in-memory or --sqlite.".to_string());
+        }
+        if self.in_memory && self.cache_file.is_some() {
+            return Err("Please choose one of --in-memory or --cache-file.".to_string());
+        }
+        if self.writer_threads > MAX_WRITER_THREADS {
+            return Err(format!["`buffers.writer_threads` can be at most {}, one per kind of database write, not {}", MAX_WRITER_THREADS, self.writer_threads]);
+        }
+        if self.margin_policy.maintenance > self.margin_policy.initial {
+            return Err(format!["`margin.maintenance` ({}) can't be above `margin.initial` ({}).", self.margin_policy.maintenance, self.margin_policy.initial]);
+        }
+        if !self.in_memory && self.sqlite.is_none() {
+            if let Err(e) = self.postgres.parse::<postgres::Config>() {
+                return Err(format!["`database.postgres` isn't a valid connection string, ex. {}: {}", SETTINGS[0].example, e]);
+            }
+        }
+        if !self.in_memory && self.cache_file.is_none() {
+            if let Err(e) = self.redis.as_str().into_connection_info() {
+                return Err(format!["`cache.redis` isn't a valid Redis URL, ex. {}: {}", SETTINGS[3].example, e]);
+            }
+        }
+
+        let addresses = [
+            ("listen.serve", &self.listen),
+            ("listen.http", &self.http),
+            ("listen.ws", &self.ws),
+            ("listen.fix", &self.fix),
+            ("listen.drop_copy", &self.drop_copy),
+            ("feed.itch_udp", &self.itch_udp)
+        ];
+        for (i, (key, address)) in addresses.iter().enumerate() {
+            let address = match address {
+                Some(address) => address,
+                None => continue
+            };
+            if let Err(e) = address.to_socket_addrs() {
+                return Err(format!["`{}` isn't an address, ex. host:port: {} ({})", key, address, e]);
+            }
+            // The feed is sent to its address, rather than listening on it.
+            if *key == "feed.itch_udp" {
+                continue;
+            }
+            for (other, other_address) in addresses[..i].iter() {
+                if Some(address) == other_address.as_ref() {
+                    return Err(format!["`{}` and `{}` are both {}, please give each its own address.", other, key, address]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* True if we were asked to serve clients on any interface. */
+    pub fn serves(&self) -> bool {
+        self.listen.is_some() || self.http.is_some() || self.ws.is_some() || self.fix.is_some() || self.drop_copy.is_some()
+    }
+}
+
+fn parse_bool(key: &str, value: &str, source: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!["{}: `{}` should be true or false, not `{}`", source, key, value])
+    }
+}
+
+fn parse_rate(key: &str, value: &str, source: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!["{}: `{}` should be a positive number of requests per second, not `{}`", source, key, value])
+    }
+}
+
+// Margins are fractions of the short market value, ex. 0.5 for 50%.
+fn parse_fraction(key: &str, value: &str, source: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!["{}: `{}` should be a fraction of the short market value from 0 to 1, ex. 0.5, not `{}`", source, key, value])
+    }
+}
+
+fn parse_positive<T>(key: &str, value: &str, source: &str) -> Result<T, String>
+where
+    T: std::str::FromStr + PartialOrd + Default
+{
+    match value.parse::<T>() {
+        Ok(number) if number > T::default() => Ok(number),
+        _ => Err(format!["{}: `{}` should be a positive integer, not `{}`", source, key, value])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!["rustx-config-test-{}-{}.toml", name, std::process::id()]);
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn flags(config: &mut Config, args: &[&str]) -> Result<(), String> {
+        let mut args = args.iter().map(|arg| arg.to_string());
+        while let Some(flag) = args.next() {
+            if !config.read_flag(&flag, &mut args)? {
+                return Err(format!["Unknown flag {}", flag]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn the_file_sets_every_kind_of_setting() {
+        let path = write_file("parse", "
+            [database]
+            in_memory = true
+            [cache]
+            users = 50
+            [limits]
+            orders_per_sec = 2.5
+            open_orders = 7
+            [margin]
+            initial = 0.6
+            maintenance = 0.4
+            call_action = \"Liquidate\"
+            [listen]
+            http = \"127.0.0.1:8080\"
+            [log]
+            level = \"debug\"
+        ");
+        let mut config = Config::default();
+        config.read_file(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert!(config.in_memory);
+        assert_eq!(config.user_cache_capacity, 50);
+        assert_eq!(config.rate_limits.orders_per_sec, 2.5);
+        assert_eq!(config.rate_limits.max_open_orders, 7);
+        assert_eq!(config.margin_policy.initial, 0.6);
+        assert_eq!(config.margin_policy.maintenance, 0.4);
+        assert!(matches!(config.margin_policy.action, MarginCallAction::Liquidate));
+        assert_eq!(config.http, Some("127.0.0.1:8080".to_string()));
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert!(config.serves());
+        assert!(config.validate().is_ok());
+    }
+
+    // The only test that touches the environment, since tests share it.
+    #[test]
+    fn the_environment_overrides_the_file_and_flags_override_both() {
+        let path = write_file("precedence", "
+            [cache]
+            users = 10
+            [margin]
+            initial = 0.6
+            maintenance = 0.2
+        ");
+        env::set_var("RUSTX_USER_CACHE_CAPACITY", "20");
+        env::set_var("RUSTX_MAINTENANCE_MARGIN", "0.25");
+        let loaded = Config::load(Some(&path));
+        env::remove_var("RUSTX_USER_CACHE_CAPACITY");
+        env::remove_var("RUSTX_MAINTENANCE_MARGIN");
+        fs::remove_file(&path).ok();
+
+        let mut config = loaded.unwrap();
+        assert_eq!(config.user_cache_capacity, 20);
+        assert_eq!(config.margin_policy.initial, 0.6);
+        assert_eq!(config.margin_policy.maintenance, 0.25);
+
+        flags(&mut config, &["--user-cache", "30", "--in-memory", "--margin-call", "liquidate"]).unwrap();
+        assert_eq!(config.user_cache_capacity, 30);
+        assert!(config.in_memory);
+        assert!(matches!(config.margin_policy.action, MarginCallAction::Liquidate));
+        assert_eq!(config.margin_policy.maintenance, 0.25);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        let mut config = Config::default();
+        assert!(flags(&mut config, &["--user-cache", "0"]).unwrap_err().contains("positive integer"));
+        assert!(flags(&mut config, &["--initial-margin", "1.5"]).unwrap_err().contains("from 0 to 1"));
+        assert!(flags(&mut config, &["--margin-call", "panic"]).unwrap_err().contains("flag or liquidate"));
+        assert!(flags(&mut config, &["--log-level", "loud"]).is_err());
+        assert!(flags(&mut config, &["--user-cache"]).unwrap_err().contains("Please provide a value"));
+
+        let path = write_file("unknown", "[cache]\nsize = 10\n");
+        assert!(config.read_file(&path).unwrap_err().contains("unknown setting `cache.size`"));
+        fs::remove_file(&path).ok();
+
+        let path = write_file("invalid", "[cache\n");
+        assert!(config.read_file(&path).unwrap_err().contains("isn't valid TOML"));
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn settings_are_validated_together() {
+        let mut config = Config { in_memory: true, ..Config::default() };
+        assert!(config.validate().is_ok());
+
+        config.margin_policy.initial = 0.2;
+        config.margin_policy.maintenance = 0.3;
+        assert!(config.validate().unwrap_err().contains("can't be above `margin.initial`"));
+        config.margin_policy.maintenance = 0.2;
+        assert!(config.validate().is_ok());
+
+        config.sqlite = Some("rustx.db".to_string());
+        assert!(config.validate().unwrap_err().contains("--in-memory or --sqlite"));
+        config.sqlite = None;
+
+        config.writer_threads = MAX_WRITER_THREADS + 1;
+        assert!(config.validate().is_err());
+        config.writer_threads = MAX_WRITER_THREADS;
+
+        config.http = Some("127.0.0.1:8080".to_string());
+        config.ws = Some("127.0.0.1:8080".to_string());
+        assert!(config.validate().unwrap_err().contains("please give each its own address"));
+        config.ws = Some("not an address".to_string());
+        assert!(config.validate().unwrap_err().contains("isn't an address"));
+    }
+}
@@ -1,18 +1,35 @@
-use postgres::{Client, NoTls};
+use postgres::Client;
+use postgres::error::SqlState;
 use chrono::{DateTime, Utc};
 use std::time::Instant;
 
 use std::collections::BinaryHeap;
 use std::cmp::Reverse;
-use std::convert::TryFrom;
 
 // IO stuff
 use std::io::prelude::*;
 
-use crate::exchange::{Exchange, Market, Order, SecStat, Trade, UserAccount, OrderStatus};
-use crate::account::AuthError;
+use crate::exchange::{Exchange, Market, Order, SecStat, Trade, TradeSides, UserAccount, OrderStatus, RateLimits};
+use crate::account::{AuthError, AccountStatus, MarginAccount};
+use crate::account::{password, roles};
 
 use crate::buffer::{DatabaseReadyOrder};
+
+pub mod ids;
+pub use crate::database::ids::IdAllocator;
+
+pub mod store;
+pub use crate::database::store::{Store, AccountStore, OrderStore, TradeStore, MarketStore, StoreBackend, StoreError};
+
+pub mod cache;
+pub use crate::database::cache::{Cache, CacheBackend};
+
+pub mod pg;
+pub mod sqlite;
+
+pub mod memory;
+pub use crate::database::memory::{MemoryStore, MemoryCache};
+
 /* ---- Specification for the db API ----
  *
  *      Functions that start with populate will read from the db on program startup ONLY.
@@ -30,7 +47,7 @@ use crate::buffer::{DatabaseReadyOrder};
  * If the market didn't exist, we will return it as Some(Market)
  * so the calling function can add it to the exchange.
  */
-fn direct_insert_to_market(potential_market: Option<&mut Market>, order: &Order) -> Option<Market> {
+pub fn direct_insert_to_market(potential_market: Option<&mut Market>, order: &Order) -> Option<Market> {
     // Get the market, or create it if it doesn't exist yet.
     match potential_market {
         Some(market) => {
@@ -67,7 +84,7 @@ fn direct_insert_to_market(potential_market: Option<&mut Market>, order: &Order)
             return Some(new_market);
         }
     }
-    return None;
+    None
 }
 
 /* Default initializes all entries in hashmap to false,
@@ -120,16 +137,16 @@ pub fn populate_exchange_markets(exchange: &mut Exchange, conn: &mut Client) {
 SELECT o.* FROM PendingOrders p, Orders o
 WHERE o.order_ID=p.order_ID;", &[]).expect("Something went wrong in the query.") {
 
-        let order_id: i32 = row.get(0);
+        let order_id: i64 = row.get(0);
         let symbol: &str = row.get(1);
         let action: &str = row.get(2);
         let quantity: i32 = row.get(3);
         let filled: i32 = row.get(4);
         let price: f64 = row.get(5);
-        let user_id: i32 = row.get(6);
+        let user_id: i64 = row.get(6);
         // No need to get status, it's obviously pending.
 
-        let order = Order::direct(action, symbol, quantity, filled, price, order_id, OrderStatus::PENDING, user_id);
+        let order = Order::direct(action, symbol, quantity, price, order_id, user_id).with_fills(filled, OrderStatus::PENDING);
         // Add the order we found to the market.
         // If a new market was created, update the exchange.
         if let Some(market) = direct_insert_to_market(exchange.live_orders.get_mut(&order.symbol), &order) {
@@ -171,7 +188,7 @@ pub fn populate_exchange_statistics(exchange: &mut Exchange, conn: &mut Client)
     for row in conn.query("SELECT total_orders FROM ExchangeStats", &[])
         .expect("Something went wrong in the query.") {
 
-        let total_orders: Option<i32> = row.get(0);
+        let total_orders: Option<i64> = row.get(0);
         match total_orders {
             Some(count) => exchange.total_orders = count,
             None => exchange.total_orders = 0
@@ -180,17 +197,27 @@ pub fn populate_exchange_statistics(exchange: &mut Exchange, conn: &mut Client)
 }
 
 /* Upgrade the database according to the config file.
+ * We only upgrade the database we're connected to (see database.postgres in the config),
+ * so db_name must be its name.
  * TODO:
  *      When we fulfill a request, replace the first word with #
  *      as it can signify a comment/completed task.
  * */
-pub fn upgrade_db<R>(reader: std::io::BufReader<R>, db_name: &String)
+pub fn upgrade_db<R>(reader: std::io::BufReader<R>, db_name: &str, conn: &mut Client)
 where
     R: std::io::Read
 {
-    let db_config = format!["host=localhost user=postgres dbname={}", db_name];
-    let mut conn = Client::connect(db_config.as_str(), NoTls)
-        .expect("Failed to connect to Database!");
+    let connected: String = match conn.query_one("SELECT current_database();", &[]) {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            panic!("Query to find the database name failed!");
+        }
+    };
+    if connected != *db_name {
+        eprintln!("The exchange is connected to the database {}, not {}. Please set database.postgres to upgrade another.", connected, db_name);
+        return;
+    }
 
     let mut query_string = String::from("\
 INSERT INTO Markets
@@ -206,7 +233,7 @@ Values
                 let company_name = str::replace(components.next().unwrap(), "'", "''"); // sanitize input
 
                 if action == "add" {
-                    query_string.push_str(format!["('{}', '{}', 0, 0, 0, 0, NULL),\n", symbol, company_name].as_str());
+                    query_string.push_str(&format!["('{}', '{}', 0, 0, 0, 0, NULL),\n", symbol, company_name]);
                 }
             },
             Err(e) => eprintln!("{}", e)
@@ -227,59 +254,95 @@ Values
 
 }
 
-/* Reads total user count from database for new user IDs. */
-pub fn read_total_accounts(conn: &mut Client) -> i32 {
-    match conn.query("SELECT count(*) FROM Account;", &[]) {
-        Ok(result) => {
-            let row = &result[0];
-            let count: i64 = row.get(0);
-            return i32::try_from(count).unwrap();
-        },
+/* Replace every plaintext password left over from before we hashed passwords.
+ * This is called on startup, and only does work the first time after upgrading.
+ *
+ * Each account is updated in its own statement, but all in one transaction,
+ * so we never end up with a partially migrated table.
+ **/
+pub fn write_hash_plaintext_passwords(conn: &mut Client) -> usize {
+    let rows = match conn.query("SELECT ID, password FROM Account WHERE password NOT LIKE '$argon2%';", &[]) {
+        Ok(rows) => rows,
         Err(e) => {
             eprintln!("{}", e);
-            panic!("Query to get total accounts number failed");
+            panic!("Query to find plaintext passwords failed!");
+        }
+    };
+
+    if rows.is_empty() {
+        return 0;
+    }
+
+    let mut transaction = conn.transaction().expect("Failed to initiate transaction!");
+    for row in rows.iter() {
+        let id: i64 = row.get(0);
+        let plaintext: &str = row.get(1);
+        if let Err(e) = transaction.execute("UPDATE Account SET password=$1 WHERE ID=$2;", &[&password::hash(plaintext), &id]) {
+            eprintln!("{}", e);
+            panic!("Query to hash a plaintext password failed!");
         }
     }
+    transaction.commit().expect("Failed to commit password hashing transaction.");
+    rows.len()
+}
+
+/* Set the stored password hash of an account. */
+pub fn write_update_password(id: i64, password_hash: &str, conn: &mut Client) {
+    if let Err(e) = conn.execute("UPDATE Account SET password=$1 WHERE ID=$2;", &[&password_hash, &id]) {
+        eprintln!("{}", e);
+        panic!("Query to update an account's password failed!");
+    }
 }
 
 /* Check the database to see if the account user exists.  */
-pub fn read_account_exists(username: &String, conn: &mut Client) -> bool {
+pub fn read_account_exists(username: &str, conn: &mut Client) -> bool {
     for row in conn.query("SELECT ID FROM Account WHERE Account.username = $1",
-                          &[username]).expect("There was an issue while checking if the user is in the database.") {
+                          &[&username]).expect("There was an issue while checking if the user is in the database.") {
 
-        let id: Option<i32> = row.get(0);
-        if let Some(_) = id {
+        let id: Option<i64> = row.get(0);
+        if id.is_some() {
             return true;
         }
     }
-    return false;
+    false
 }
 
 /* Compare the provided username + password combo against the database.
  * If they match, return the UserAccount, otherwise, return the error that occurred.
  **/
-pub fn read_auth_user<'a>(username: &'a String, password: &String, conn: &mut Client) -> Result<UserAccount, AuthError<'a>> {
-    let query_string = "SELECT ID, username, password FROM Account WHERE Account.username = $1";
+pub fn read_auth_user<'a>(username: &'a str, password: &str, conn: &mut Client) -> Result<UserAccount, AuthError<'a>> {
+    let query_string = "SELECT ID, username, password, short_enabled, borrow_limit, collateral, roles, status FROM Account WHERE Account.username = $1";
     match conn.query(query_string, &[&username]) {
         Ok(result) => {
             // Did not find the user
-            if result.len() == 0 {
+            if result.is_empty() {
                 return Err(AuthError::NoUser(username));
             }
 
             // Found a user, usernames are unique so we get 1 row.
             let row = &result[0];
-            let recv_id: i32 = row.get(0);
+            let recv_id: i64 = row.get(0);
             let recv_username: &str = row.get(1);
             let recv_password: &str = row.get(2);
 
             // User authenticated.
-            if *password == recv_password {
-                return Ok(UserAccount::direct(recv_id, recv_username, recv_password));
+            if password::verify(password, recv_password) {
+                let mut account = UserAccount::direct(recv_id, recv_username, recv_password);
+
+                // This account still has a plaintext password, replace it with a hash.
+                if !password::is_hashed(recv_password) {
+                    account.password = password::hash(password);
+                    write_update_password(recv_id, &account.password, conn);
+                }
+                account.margin = MarginAccount::direct(row.get(3), row.get(4), row.get(5));
+                let role_names: Vec<String> = row.get(6);
+                account.roles = roles::from_names(&role_names);
+                account.status = AccountStatus::from(row.get(7));
+                return Ok(account);
             }
 
             // Password was incorrect.
-            return Err(AuthError::BadPassword(None));
+            Err(AuthError::BadPassword(None))
         },
         Err(e) => {
             eprintln!("{}", e);
@@ -290,35 +353,40 @@ pub fn read_auth_user<'a>(username: &'a String, password: &String, conn: &mut Cl
 }
 
 /* Read the account with the given username and return the account. */
-pub fn read_account(username: &String, conn: &mut Client) -> Result<UserAccount, postgres::error::Error> {
-    match conn.query("SELECT ID, username, password FROM Account where Account.username = $1", &[username]) {
+pub fn read_account(username: &str, conn: &mut Client) -> Result<UserAccount, postgres::error::Error> {
+    match conn.query("SELECT ID, username, password, short_enabled, borrow_limit, collateral, roles, status FROM Account where Account.username = $1", &[&username]) {
         Ok(result) => {
             let row = &result[0];
-            let recv_id: i32 = row.get(0);
+            let recv_id: i64 = row.get(0);
             let recv_username: &str = row.get(1);
             let recv_password: &str = row.get(2);
 
-            return Ok(UserAccount::direct(recv_id, recv_username, recv_password));
+            let mut account = UserAccount::direct(recv_id, recv_username, recv_password);
+            account.margin = MarginAccount::direct(row.get(3), row.get(4), row.get(5));
+            let role_names: Vec<String> = row.get(6);
+            account.roles = roles::from_names(&role_names);
+            account.status = AccountStatus::from(row.get(7));
+            Ok(account)
         },
         Err(e) => {
             eprintln!("{}", e);
-            return Err(e);
+            Err(e)
         }
     }
 }
 
 /* Read the account with the given user ID and return the username. */
-pub fn read_user_by_id(id: i32, conn: &mut Client) -> Result<String, postgres::error::Error> {
+pub fn read_user_by_id(id: i64, conn: &mut Client) -> Result<String, postgres::error::Error> {
     match conn.query("SELECT username FROM Account where Account.id = $1", &[&id]) {
         Ok(result) => {
             let row = &result[0];
             let recv_username: &str = row.get(0);
 
-            return Ok(recv_username.to_string());
+            Ok(recv_username.to_string())
         },
         Err(e) => {
             eprintln!("{}", e);
-            return Err(e);
+            Err(e)
         }
     }
 }
@@ -326,7 +394,7 @@ pub fn read_user_by_id(id: i32, conn: &mut Client) -> Result<String, postgres::e
 /* Read the pending orders that belong to this user into their account.
  * This is currently not in use, however, if we only store a subset
  * of market info in the in-mem markets, we will have to call this
- * to get the full view of an account (in, say, print_user).
+ * to get the full view of an account (in, say, show_user).
  **/
 pub fn read_account_pending_orders(user: &mut UserAccount, conn: &mut Client) {
     let query_string = "\
@@ -336,13 +404,13 @@ AND o.user_ID =
     (SELECT ID FROM Account WHERE Account.username = $1)
 ORDER BY o.order_ID;";
     for row in conn.query(query_string, &[&user.username]).expect("Query to fetch pending orders failed!") {
-        let order_id:       i32  = row.get(0);
+        let order_id:       i64  = row.get(0);
         let symbol:         &str = row.get(1);
         let action:         &str = row.get(2);
         let quantity:       i32  = row.get(3);
         let filled:         i32  = row.get(4);
         let price:          f64  = row.get(5);
-        let user_id:        i32  = row.get(6);
+        let user_id:        i64  = row.get(6);
         // let time_placed:    i32  = row.get(8); // <---- TODO
         // let time_updated:   i32  = row.get(9); // <---- TODO
 
@@ -350,11 +418,9 @@ ORDER BY o.order_ID;";
         let order = Order::direct(action,
                                   symbol,
                                   quantity,
-                                  filled,
                                   price,
                                   order_id,
-                                  OrderStatus::PENDING,
-                                  user_id);
+                                  user_id).with_fills(filled, OrderStatus::PENDING);
 
         user.pending_orders.insert_order(order);
     }
@@ -375,10 +441,10 @@ ORDER BY e.execution_time;";
         let symbol:     &str = row.get(0);
         let mut action: &str = row.get(1);
         let price:      f64  = row.get(2);
-        let filled_oid: i32  = row.get(3);
-        let filled_uid: i32  = row.get(4);
-        let filler_oid: i32  = row.get(5);
-        let filler_uid: i32  = row.get(6);
+        let filled_oid: i64  = row.get(3);
+        let filled_uid: i64  = row.get(4);
+        let filler_oid: i64  = row.get(5);
+        let filler_uid: i64  = row.get(6);
         let exchanged:  i32  = row.get(7);
         let execution_time:
             DateTime<Utc>    = row.get(8);
@@ -392,13 +458,11 @@ ORDER BY e.execution_time;";
             }
         }
 
+        let sides = TradeSides { filled_oid, filled_uid, filler_oid, filler_uid };
         let trade = Trade::direct(symbol,
                                   action,
                                   price,
-                                  filled_oid,
-                                  filled_uid,
-                                  filler_oid,
-                                  filler_uid,
+                                  sides,
                                   exchanged,
                                   execution_time);
         executed_trades.push(trade);
@@ -410,34 +474,64 @@ ORDER BY e.execution_time;";
  * Returns Some(Vec<Trade>) if there are trades,
  * otherwise, returns None.
  **/
-pub fn read_trades(symbol: &String, conn: &mut Client) -> Option<Vec<Trade>> {
+pub fn read_trades(symbol: &str, conn: &mut Client) -> Option<Vec<Trade>> {
     let mut trades: Vec<Trade> = Vec::new();
     for row in conn.query("SELECT * FROM ExecutedTrades WHERE symbol=$1",
-                          &[&symbol.as_str()]).expect("Read Trades query (History) failed!") {
+                          &[&symbol]).expect("Read Trades query (History) failed!") {
 
         let symbol:     &str = row.get(0);
         let action:     &str = row.get(1);
         let price:      f64  = row.get(2);
-        let filled_oid: i32  = row.get(3);
-        let filled_uid: i32  = row.get(4);
-        let filler_oid: i32  = row.get(5);
-        let filler_uid: i32  = row.get(6);
+        let filled_oid: i64  = row.get(3);
+        let filled_uid: i64  = row.get(4);
+        let filler_oid: i64  = row.get(5);
+        let filler_uid: i64  = row.get(6);
         let exchanged:  i32  = row.get(7);
         let execution_time:
             DateTime<Utc>    = row.get(8);
 
+        let sides = TradeSides { filled_oid, filled_uid, filler_oid, filler_uid };
         trades.push(Trade::direct(symbol,
                                   action,
                                   price,
-                                  filled_oid,
-                                  filled_uid,
-                                  filler_oid,
-                                  filler_uid,
+                                  sides,
                                   exchanged,
                                   execution_time
                                  ));
     }
-    return Some(trades);
+    Some(trades)
+}
+
+/* Returns the order this user placed with the given client order id, if there is one. */
+pub fn read_client_order(user_id: i64, client_id: &str, conn: &mut Client) -> Option<Order> {
+    let result = conn.query("\
+SELECT order_ID, symbol, action, quantity, filled, price, status
+FROM Orders
+WHERE user_ID = $1
+  AND client_order_ID = $2;", &[&user_id, &client_id]);
+
+    match result {
+        Ok(rows) => {
+            if let Some(row) = rows.first() {
+                let order_id: i64 = row.get(0);
+                let symbol: &str = row.get(1);
+                let action: &str = row.get(2);
+                let quantity: i32 = row.get(3);
+                let filled: i32 = row.get(4);
+                let price: f64 = row.get(5);
+                let status: &str = row.get(6);
+
+                let mut order = Order::direct(action, symbol, quantity, price, order_id, user_id).with_fills(filled, OrderStatus::from(status));
+                order.client_id = Some(client_id.to_string());
+                return Some(order);
+            }
+        },
+        Err(e) => {
+            eprintln!("{:?}", e);
+            panic!("Client order id query failed!");
+        }
+    }
+    None
 }
 
 /* TODO: Doesn't get called ever, since we have a perfect market view.
@@ -445,7 +539,7 @@ pub fn read_trades(symbol: &String, conn: &mut Client) -> Option<Vec<Trade>> {
  *       keeping the rest in the DB, then we may trigger this code.
  *
  * Returns Some(action) if the user owns this pending order, else None. */
-pub fn read_match_pending_order(user_id: i32, order_id: i32, conn: &mut Client) -> Option<String> {
+pub fn read_match_pending_order(user_id: i64, order_id: i64, conn: &mut Client) -> Option<String> {
     let result = conn.query("\
 SELECT action
 FROM Orders o, PendingOrders p
@@ -456,10 +550,8 @@ WHERE p.order_id = $1
     match result {
         Ok(rows) => {
             if rows.len() == 1 {
-                for row in rows {
-                    let action: &str = row.get(0);
-                    return Some(action.to_string().clone());
-                }
+                let action: &str = rows[0].get(0);
+                return Some(action.to_string());
             }
         },
         Err(e) => {
@@ -467,29 +559,124 @@ WHERE p.order_id = $1
             panic!("Match pending order query failed!");
         }
     }
-    return None;
+    None
 }
 
 /* TODO: Prepared statement.
  * Write a new user to the database. */
-pub fn write_insert_new_account(account: &UserAccount, conn: &mut Client) -> Result<(), ()> {
+pub fn write_insert_new_account(account: &UserAccount, conn: &mut Client) -> Result<(), StoreError> {
     let now = Utc::now();
 
-    let query_string = "INSERT INTO Account (ID, username, password, register_time) VALUES ($1, $2, $3, $4);";
-    match conn.execute(query_string, &[&account.id.unwrap(), &account.username, &account.password, &now]) {
-        Ok(_) => return Ok(()),
+    let role_names = roles::to_names(&account.roles);
+    let query_string = "INSERT INTO Account (ID, username, password, register_time, roles) VALUES ($1, $2, $3, $4, $5);";
+    match conn.execute(query_string, &[&account.id.unwrap(), &account.username, &account.password, &now, &role_names]) {
+        Ok(_) => Ok(()),
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => Err(StoreError::Duplicate),
+        Err(e) => Err(StoreError::Backend(e.to_string()))
+    }
+}
+
+
+/* Update the short selling settings of an account.
+ * Returns false if no account has this username.
+ **/
+pub fn write_update_margin_account(username: &str, margin: &MarginAccount, conn: &mut Client) -> bool {
+    let query_string = "UPDATE Account SET short_enabled=$1, borrow_limit=$2, collateral=$3 WHERE username=$4;";
+    match conn.execute(query_string, &[&margin.short_enabled, &margin.borrow_limit, &margin.collateral, &username]) {
+        Ok(rows) => rows == 1,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            panic!("Query to update the margin settings of an account failed!");
+        }
+    }
+}
+
+/* Add a role to (or remove a role from) an account.
+ * Returns the account's roles afterwards, or None if the account doesn't exist.
+ **/
+pub fn write_update_role(username: &str, role: &str, grant: bool, conn: &mut Client) -> Option<Vec<String>> {
+    let query_string = if grant {
+        // Remove first so a role is never listed twice.
+        "UPDATE Account SET roles=array_append(array_remove(roles, $1), $1) WHERE username=$2 RETURNING roles;"
+    } else {
+        "UPDATE Account SET roles=array_remove(roles, $1) WHERE username=$2 RETURNING roles;"
+    };
+    match conn.query(query_string, &[&role, &username]) {
+        Ok(result) => result.first().map(|row| row.get(0)),
         Err(e) => {
             eprintln!("{:?}", e);
-            return Err(());
+            panic!("Query to update the roles of an account failed!");
         }
     }
 }
 
+/* Set the status of an account (active, suspended or closed).
+ * Returns false if no account has this username.
+ **/
+pub fn write_update_status(username: &str, status: &str, conn: &mut Client) -> bool {
+    match conn.execute("UPDATE Account SET status=$1 WHERE username=$2;", &[&status, &username]) {
+        Ok(rows) => rows == 1,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            panic!("Query to update the status of an account failed!");
+        }
+    }
+}
+
+/* Give each account the rate limits it was given with `limits set`. */
+pub fn populate_rate_limits(exchange: &mut Exchange, conn: &mut Client) {
+    let query_string = "\
+SELECT Account.username, orders_per_sec, cancels_per_sec, max_open_orders FROM RateLimits
+JOIN Account ON Account.ID = RateLimits.user_ID;";
+    for row in conn.query(query_string, &[]).expect("Something went wrong in the query.") {
+        let username: &str = row.get(0);
+        let max_open_orders: i32 = row.get(3);
+        let limits = RateLimits::new(row.get(1), row.get(2), max_open_orders as usize);
+        exchange.rate_limiter.set_account_limits(username, Some(limits));
+    }
+}
+
+/* Save the rate limits of an account, or remove them if it's back on the limits of its role. */
+pub fn write_update_rate_limits(username: &str, limits: Option<&RateLimits>, conn: &mut Client) {
+    let result = match limits {
+        Some(limits) => {
+            let query_string = "\
+INSERT INTO RateLimits (user_ID, orders_per_sec, cancels_per_sec, max_open_orders)
+SELECT ID, $2, $3, $4 FROM Account WHERE username=$1
+ON CONFLICT (user_ID) DO UPDATE SET orders_per_sec=EXCLUDED.orders_per_sec, cancels_per_sec=EXCLUDED.cancels_per_sec, max_open_orders=EXCLUDED.max_open_orders;";
+            conn.execute(query_string, &[&username, &limits.orders_per_sec, &limits.cancels_per_sec, &(limits.max_open_orders as i32)])
+        },
+        None => conn.execute("DELETE FROM RateLimits WHERE user_ID IN (SELECT ID FROM Account WHERE username=$1);", &[&username])
+    };
+    if let Err(e) = result {
+        eprintln!("{:?}", e);
+        panic!("Query to update the rate limits of an account failed!");
+    }
+}
+
+/* Change the username of an account. */
+pub fn write_update_username(id: i64, username: &str, conn: &mut Client) {
+    if let Err(e) = conn.execute("UPDATE Account SET username=$1 WHERE ID=$2;", &[&username, &id]) {
+        eprintln!("{}", e);
+        panic!("Query to update an account's username failed!");
+    }
+}
+
+/* Returns true if any account has the admin role. */
+pub fn read_admin_exists(conn: &mut Client) -> bool {
+    match conn.query("SELECT ID FROM Account WHERE 'admin' = ANY(roles) LIMIT 1;", &[]) {
+        Ok(result) => !result.is_empty(),
+        Err(e) => {
+            eprintln!("{}", e);
+            panic!("Query to check for an admin account failed!");
+        }
+    }
+}
 
 /* Returns true if the market exists in our database, false otherwise. */
-pub fn read_market_exists(market: &String, conn: &mut Client) -> bool {
+pub fn read_market_exists(market: &str, conn: &mut Client) -> bool {
     let query_string = "SELECT symbol from Markets where symbol=$1;";
-    match conn.query(query_string, &[market]) {
+    match conn.query(query_string, &[&market]) {
         Ok(result) => {
             if result.len() == 1 {
                 return true;
@@ -501,7 +688,7 @@ pub fn read_market_exists(market: &String, conn: &mut Client) -> bool {
         }
     }
 
-    return false;
+    false
 }
 
 /* Reads the first `n` market symbols into the symbol_vec Vector.
@@ -531,7 +718,7 @@ pub fn read_exchange_markets_simulations(symbol_vec: &mut Vec<String>, conn: &mu
  ******************************************************************************************************/
 /* TODO: Multi-row updates if possible.
  **/
-pub fn insert_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Client) {
+pub fn insert_buffered_orders(orders: &[DatabaseReadyOrder], conn: &mut Client) {
 
     let start = Instant::now();
     // TIMING
@@ -541,10 +728,10 @@ pub fn insert_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Clien
     // Everything is to be updated
     let query_string = "\
 INSERT INTO Orders
-(order_ID, symbol, action, quantity, filled, price, user_ID, status, time_placed, time_updated)
-VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);";
+(order_ID, symbol, action, quantity, filled, price, user_ID, status, time_placed, time_updated, client_order_ID)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11);";
 
-    let statement = match transaction.prepare(&query_string) {
+    let statement = match transaction.prepare(query_string) {
         Ok(stmt) => stmt,
         Err(e) => {
             eprintln!("{}", e);
@@ -565,7 +752,8 @@ VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);";
                                           &order.user_id,
                                           &status,
                                           &order.time_placed,
-                                          &order.time_updated
+                                          &order.time_updated,
+                                          &order.client_id
                                          ]).expect("FAILED TO EXEC INSERT ORDERS");
     }
 
@@ -585,7 +773,7 @@ Insert New Order Speed
 
 /* TODO: Multi-row updates if possible.
  **/
-pub fn update_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Client) {
+pub fn update_buffered_orders(orders: &[DatabaseReadyOrder], conn: &mut Client) {
 
     let start = Instant::now();
 
@@ -602,21 +790,21 @@ pub fn update_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Clien
     let query_exec_time = Instant::now();
     let mut transaction = conn.transaction().expect("Failed to initiate transaction!");
 
-    let filled_stmt = match transaction.prepare(&filled_string) {
+    let filled_stmt = match transaction.prepare(filled_string) {
         Ok(stmt) => stmt,
         Err(e) => {
             eprintln!("{}", e);
             panic!("Failed to create 'filled' prepared statement for updated orders!");
         }
     };
-    let status_stmt = match transaction.prepare(&status_string) {
+    let status_stmt = match transaction.prepare(status_string) {
         Ok(stmt) => stmt,
         Err(e) => {
             eprintln!("{}", e);
             panic!("Failed to create 'status' prepared statement for updated orders!");
         }
     };
-    let total_stmt = match transaction.prepare(&total_string) {
+    let total_stmt = match transaction.prepare(total_string) {
         Ok(stmt) => stmt,
         Err(e) => {
             eprintln!("{}", e);
@@ -625,29 +813,29 @@ pub fn update_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Clien
     };
 
     enum UpdateType {
-        FILL,
-        STATUS,
-        TOTAL,
-        NONE
+        Fill,
+        Status,
+        Total,
+        Unchanged
     }
 
     for order in orders {
-        let mut order_type = UpdateType::NONE;
+        let mut order_type = UpdateType::Unchanged;
 
         let mut filled: Option<i32> = None;
         let mut status: Option<String> = None;
 
         if let Some(amount_filled) = order.filled {
             filled = Some(amount_filled);
-            order_type = UpdateType::FILL;
+            order_type = UpdateType::Fill;
         }
 
         if let Some(new_status) = order.status {
             status = Some(format!["{:?}", new_status]);
-            if let UpdateType::FILL = order_type {
-                order_type = UpdateType::TOTAL;
+            if let UpdateType::Fill = order_type {
+                order_type = UpdateType::Total;
             } else {
-                order_type = UpdateType::STATUS;
+                order_type = UpdateType::Status;
             }
         }
 
@@ -655,21 +843,21 @@ pub fn update_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Clien
             let time_updated = update_time;
 
             match order_type {
-                UpdateType::FILL => {
+                UpdateType::Fill => {
                     let filled = filled.unwrap();
                     if let Err(e) = transaction.execute(&filled_stmt, &[&filled, &time_updated, &order.order_id.unwrap()]) {
                         eprintln!("{}", e);
                         panic!("Something went wrong with the buffered order update statement.");
                     }
                 },
-                UpdateType::STATUS => {
+                UpdateType::Status => {
                     let status = status.unwrap();
                     if let Err(e) = transaction.execute(&status_stmt, &[&status, &time_updated, &order.order_id.unwrap()]) {
                         eprintln!("{}", e);
                         panic!("Something went wrong with the buffered order update statement.");
                     }
                 },
-                UpdateType::TOTAL => {
+                UpdateType::Total => {
                     let filled = filled.unwrap();
                     let status = status.unwrap();
                     if let Err(e) = transaction.execute(&total_stmt, &[&filled, &status, &time_updated, &order.order_id.unwrap()]) {
@@ -677,7 +865,7 @@ pub fn update_buffered_orders(orders: &Vec<DatabaseReadyOrder>, conn: &mut Clien
                         panic!("Something went wrong with the buffered order update statement.");
                     }
                 },
-                UpdateType::NONE => panic!("Our updated order has no data??")
+                UpdateType::Unchanged => panic!("Our updated order has no data??")
             }
         };
     }
@@ -696,7 +884,7 @@ Update Known Order Speed
 
 /* Performs 1 or more multi-row inserts to the pending orders table in
  * a single transaction. */
-pub fn insert_buffered_pending(pending: &Vec<i32>, conn: &mut Client) {
+pub fn insert_buffered_pending(pending: &[i64], conn: &mut Client) {
     // TIMING
     let start = Instant::now();
 
@@ -713,7 +901,7 @@ pub fn insert_buffered_pending(pending: &Vec<i32>, conn: &mut Client) {
 
     for order in pending {
         if counter < cap {
-            queries[index].push_str(&format!["({}),\n", order].as_str());
+            queries[index].push_str(&format!["({}),\n", order]);
         } else {
             // 1. Terminate the current query
             queries[index].pop();
@@ -724,7 +912,7 @@ pub fn insert_buffered_pending(pending: &Vec<i32>, conn: &mut Client) {
             counter = 0;
             // 3. Start new query
             queries.push(query_string.clone());
-            queries[index].push_str(&format!["({}),\n", order].as_str());
+            queries[index].push_str(&format!["({}),\n", order]);
         }
         counter += 1;
     }
@@ -764,7 +952,7 @@ Insert Pending Speed (n = 100000)
 
 /* Performs 1 or more multi-row delete queries to the pending orders table
  * in a single transaction. */
-pub fn delete_buffered_pending(pending: &Vec<i32>, conn: &mut Client) {
+pub fn delete_buffered_pending(pending: &[i64], conn: &mut Client) {
     let start = Instant::now();
 
     // TIMING
@@ -779,7 +967,7 @@ pub fn delete_buffered_pending(pending: &Vec<i32>, conn: &mut Client) {
 
     for order in pending {
         if counter < cap {
-            queries[index].push_str(&format!["{}, ", order].as_str());
+            queries[index].push_str(&format!["{}, ", order]);
         } else {
             // 1. Terminate the current query
             queries[index].pop();
@@ -790,7 +978,7 @@ pub fn delete_buffered_pending(pending: &Vec<i32>, conn: &mut Client) {
             counter = 0;
             // 3. Start new query
             queries.push(query_string.clone());
-            queries[index].push_str(&format!["{}, ", order].as_str());
+            queries[index].push_str(&format!["{}, ", order]);
         }
         counter += 1;
     }
@@ -830,7 +1018,7 @@ Delete Pending Speed (n = 100000)
 
 
 /* A single query to set or update the exchange stats. */
-pub fn update_total_orders(total_orders: i32, conn: &mut Client) {
+pub fn update_total_orders(total_orders: i64, conn: &mut Client) {
     let mut transaction = conn.transaction().expect("Failed to initiate transaction!");
     // Update the exchange total orders
     let query_string = "\
@@ -852,7 +1040,7 @@ UPDATE SET total_orders=$1;";
  * This table always stays small, so the cost of 1 connection per
  * query is negligible, especially when using a prepared statement.
  **/
-pub fn update_buffered_markets(markets: &Vec<SecStat>, conn: &mut Client) {
+pub fn update_buffered_markets(markets: &[SecStat], conn: &mut Client) {
     let mut transaction = conn.transaction().expect("Failed to initiate transaction!");
     let query_string = "\
 UPDATE Markets
@@ -860,7 +1048,7 @@ SET (total_buys, total_sells, filled_buys, filled_sells, latest_price) =
 ($1, $2, $3, $4, $5)
 WHERE Markets.symbol = $6;";
 
-    let statement = match transaction.prepare(&query_string) {
+    let statement = match transaction.prepare(query_string) {
         Ok(stmt) => stmt,
         Err(e) => {
             eprintln!("{}", e);
@@ -882,7 +1070,7 @@ WHERE Markets.symbol = $6;";
 
 
 /* Performs 1 or more multi-row inserts in a single transaction. */
-pub fn insert_buffered_trades(trades: &Vec<Trade>, conn: &mut Client) {
+pub fn insert_buffered_trades(trades: &[Trade], conn: &mut Client) {
     // TIMING
     let start = Instant::now();
 
@@ -909,7 +1097,7 @@ VALUES ");
                                                                                                trade.filler_oid,
                                                                                                trade.filler_uid,
                                                                                                trade.exchanged,
-                                                                                               trade.execution_time.to_rfc3339()].as_str());
+                                                                                               trade.execution_time.to_rfc3339()]);
         } else {
             // 1. Terminate the current query
             queries[index].pop();
@@ -928,7 +1116,7 @@ VALUES ");
                                                                                                trade.filler_oid,
                                                                                                trade.filler_uid,
                                                                                                trade.exchanged,
-                                                                                               trade.execution_time.to_rfc3339()].as_str());
+                                                                                               trade.execution_time.to_rfc3339()]);
         }
         counter += 1;
     }
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use redis::{Commands, RedisError};
+
+use crate::database::memory::MemoryCache;
+
+/* ---- The cache ----
+ *
+ *  What the exchange keeps in Redis, behind a trait so it can be kept in the process instead:
+ *      user:{username}         => hash of the account's fields, so authenticating skips the database.
+ *      id:{user_id}            => hash with the username of the account.
+ *      session:{token}         => username, see account::session.
+ *      sessions:{username}     => set of the account's session tokens.
+ *      filler:{user_id}        => list of trades the account's orders filled, newest first.
+ *      filled:{user_id}        => list of trades that filled the account's orders, newest first.
+ *      active_markets:{id}     => sorted set of the markets the account has pending orders in,
+ *                                 scored by the number of pending orders.
+ *      dropcopy:journal        => list of every execution copied, oldest first.
+ *      fix:seqs:{comp_id}      => hash of a FIX session's next incoming and outgoing sequence numbers.
+ *      fix:sent:{comp_id}      => hash of outgoing sequence number -> message, see fix::session.
+ *
+ *  Trades are stored as space separated fields, see UserAccount::flush_trades.
+ **/
+pub trait Cache: Send {
+    /* The fields of a cached account, empty if it isn't cached. */
+    fn read_cached_account(&mut self, username: &str) -> HashMap<String, String>;
+    fn write_cached_account(&mut self, username: &str, fields: &[(&str, &str)]);
+    fn delete_cached_account(&mut self, username: &str);
+
+    fn read_username(&mut self, id: i64) -> Option<String>;
+    fn write_username(&mut self, id: i64, username: &str);
+
+    /* The username a session belongs to, if it hasn't expired. */
+    fn read_session(&mut self, token: &str) -> Option<String>;
+    fn write_session(&mut self, token: &str, username: &str, ttl: usize);
+    /* Push the expiry of a session, and of the account's set of sessions, back to ttl seconds from now. */
+    fn touch_session(&mut self, token: &str, username: &str, ttl: usize);
+    /* Returns false if the session didn't exist. */
+    fn delete_session(&mut self, token: &str) -> bool;
+    fn delete_sessions(&mut self, username: &str);
+
+    /* Every trade flushed for the account, filler trades then filled trades, each newest first. */
+    fn read_trades(&mut self, user_id: i64) -> Vec<String>;
+    fn write_trades(&mut self, user_id: i64, filler: &[String], filled: &[String]);
+
+    fn read_active_markets(&mut self, user_id: i64) -> Vec<String>;
+    /* Add each market's change in pending orders, removing the markets left with none, all at once. */
+    fn write_active_markets(&mut self, user_id: i64, diffs: &HashMap<String, i32>);
+
+    /* The number of executions journaled. */
+    fn read_journal_len(&mut self) -> u64;
+    /* The journaled executions, from sequence number `from` on. */
+    fn read_journal(&mut self, from: u64) -> Result<Vec<String>, String>;
+    fn write_journal(&mut self, executions: &[String]) -> Result<(), String>;
+
+    /* The next incoming and outgoing sequence numbers of a FIX session, if it has logged on before. */
+    fn read_fix_seq_nums(&mut self, comp_id: &str) -> Option<(u64, u64)>;
+    fn write_fix_seq_nums(&mut self, comp_id: &str, next_in: u64, next_out: u64);
+    fn read_fix_sent(&mut self, comp_id: &str, seq: u64) -> Option<Vec<u8>>;
+    fn write_fix_sent(&mut self, comp_id: &str, seq: u64, raw: &[u8]);
+    fn delete_fix_sent(&mut self, comp_id: &str);
+}
+
+/* Where the exchange caches accounts, sessions and trades, chosen on startup. */
+#[derive(Clone)]
+pub enum CacheBackend {
+    Redis(String),      // URL, ex. redis://127.0.0.1/
+    Memory(MemoryCache) // Shared by every connection, saved on shutdown if opened from a file
+}
+
+impl CacheBackend {
+    pub fn connect(&self) -> Box<dyn Cache> {
+        match self {
+            CacheBackend::Redis(url) => {
+                let client = redis::Client::open(url.as_str()).expect("Failed to open redis");
+                Box::new(client.get_connection().expect("Failed to connect to redis"))
+            },
+            CacheBackend::Memory(cache) => Box::new(cache.clone())
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CacheBackend::Redis(_) => "Redis",
+            CacheBackend::Memory(_) => "in-memory"
+        }
+    }
+
+    /* Save what's in the cache so the next run starts with it. Redis already does. */
+    pub fn persist(&self) -> Result<(), String> {
+        match self {
+            CacheBackend::Redis(_) => Ok(()),
+            CacheBackend::Memory(cache) => cache.save()
+        }
+    }
+}
+
+const JOURNAL: &str = "dropcopy:journal";
+
+impl Cache for redis::Connection {
+    fn read_cached_account(&mut self, username: &str) -> HashMap<String, String> {
+        let response: Result<HashMap<String, String>, RedisError> = self.hgetall(format!["user:{}", username]);
+        match response {
+            Ok(fields) => fields,
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Something went wrong with redis.");
+            }
+        }
+    }
+
+    fn write_cached_account(&mut self, username: &str, fields: &[(&str, &str)]) {
+        let _: () = self.hset_multiple(format!["user:{}", username], fields).unwrap();
+    }
+
+    fn delete_cached_account(&mut self, username: &str) {
+        let _: Result<i32, RedisError> = self.del(format!["user:{}", username]);
+    }
+
+    fn read_username(&mut self, id: i64) -> Option<String> {
+        self.hget(format!["id:{}", id], "username").unwrap_or(None)
+    }
+
+    fn write_username(&mut self, id: i64, username: &str) {
+        let _: Result<(), RedisError> = self.hset(format!["id:{}", id], "username", username);
+    }
+
+    fn read_session(&mut self, token: &str) -> Option<String> {
+        let response: Result<Option<String>, RedisError> = self.get(format!["session:{}", token]);
+        match response {
+            Ok(username) => username,
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Something went wrong with redis.");
+            }
+        }
+    }
+
+    fn write_session(&mut self, token: &str, username: &str, ttl: usize) {
+        let sessions = format!["sessions:{}", username];
+        let _: () = self.set_ex(format!["session:{}", token], username, ttl).unwrap();
+        let _: () = self.sadd(&sessions, token).unwrap();
+        let _: () = self.expire(&sessions, ttl).unwrap();
+    }
+
+    fn touch_session(&mut self, token: &str, username: &str, ttl: usize) {
+        let _: Result<bool, RedisError> = self.expire(format!["session:{}", token], ttl);
+        let _: Result<bool, RedisError> = self.expire(format!["sessions:{}", username], ttl);
+    }
+
+    fn delete_session(&mut self, token: &str) -> bool {
+        let key = format!["session:{}", token];
+        let username: Option<String> = self.get(&key).unwrap_or(None);
+        match username {
+            Some(username) => {
+                let _: () = self.del(&key).unwrap();
+                let _: () = self.srem(format!["sessions:{}", username], token).unwrap();
+                true
+            },
+            None => false
+        }
+    }
+
+    fn delete_sessions(&mut self, username: &str) {
+        let sessions = format!["sessions:{}", username];
+        let tokens: Vec<String> = self.smembers(&sessions).unwrap_or_default();
+        for token in tokens.iter() {
+            let _: Result<i32, RedisError> = self.del(format!["session:{}", token]);
+        }
+        let _: Result<i32, RedisError> = self.del(&sessions);
+    }
+
+    fn read_trades(&mut self, user_id: i64) -> Vec<String> {
+        let mut trades = Vec::new();
+        for list in [format!["filler:{}", user_id], format!["filled:{}", user_id]].iter() {
+            let response: Result<Vec<String>, RedisError> = self.lrange(list, 0, -1);
+            match response {
+                Ok(mut listed) => trades.append(&mut listed),
+                Err(e) => eprintln!("{}", e)
+            }
+        }
+        trades
+    }
+
+    fn write_trades(&mut self, user_id: i64, filler: &[String], filled: &[String]) {
+        // LPUSH with several values pushes them one after the other, so the last is the newest.
+        if !filler.is_empty() {
+            let filler_response: Result<i32, RedisError> = self.lpush(format!["filler:{}", user_id], filler);
+            if let Err(e) = filler_response {
+                eprintln!("{}", e);
+            }
+        }
+        if !filled.is_empty() {
+            let filled_response: Result<i32, RedisError> = self.lpush(format!["filled:{}", user_id], filled);
+            if let Err(e) = filled_response {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    fn read_active_markets(&mut self, user_id: i64) -> Vec<String> {
+        let response: Result<Vec<String>, RedisError> = self.zrange(format!["active_markets:{}", user_id], 0, -1);
+        match response {
+            Ok(markets) => markets,
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Something went wrong with redis.");
+            }
+        }
+    }
+
+    fn write_active_markets(&mut self, user_id: i64, diffs: &HashMap<String, i32>) {
+        if diffs.is_empty() {
+            return;
+        }
+        let key = format!["active_markets:{}", user_id];
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (market, diff) in diffs.iter() {
+            pipe.zincr(&key, market, *diff).ignore();
+        }
+        pipe.zrembyscore(&key, "-inf", 0).ignore();
+
+        if let Err(e) = pipe.query::<()>(self) {
+            eprintln!("{}", e);
+            panic!("Failed to update active_markets:{}!", user_id);
+        }
+    }
+
+    fn read_journal_len(&mut self) -> u64 {
+        self.llen(JOURNAL).unwrap_or(0)
+    }
+
+    fn read_journal(&mut self, from: u64) -> Result<Vec<String>, String> {
+        self.lrange(JOURNAL, from.max(1) as isize - 1, -1).map_err(|e: RedisError| e.to_string())
+    }
+
+    fn write_journal(&mut self, executions: &[String]) -> Result<(), String> {
+        if executions.is_empty() {
+            return Ok(());
+        }
+        self.rpush(JOURNAL, executions).map_err(|e: RedisError| e.to_string())
+    }
+
+    fn read_fix_seq_nums(&mut self, comp_id: &str) -> Option<(u64, u64)> {
+        let seqs: Vec<Option<u64>> = self.hget(format!["fix:seqs:{}", comp_id], &["in", "out"]).unwrap_or_default();
+        match seqs[..] {
+            [Some(next_in), Some(next_out)] => Some((next_in, next_out)),
+            _ => None
+        }
+    }
+
+    fn write_fix_seq_nums(&mut self, comp_id: &str, next_in: u64, next_out: u64) {
+        let _: Result<(), RedisError> = self.hset_multiple(format!["fix:seqs:{}", comp_id], &[("in", next_in), ("out", next_out)]);
+    }
+
+    fn read_fix_sent(&mut self, comp_id: &str, seq: u64) -> Option<Vec<u8>> {
+        self.hget(format!["fix:sent:{}", comp_id], seq).unwrap_or(None)
+    }
+
+    fn write_fix_sent(&mut self, comp_id: &str, seq: u64, raw: &[u8]) {
+        let _: Result<(), RedisError> = self.hset(format!["fix:sent:{}", comp_id], seq, raw);
+    }
+
+    fn delete_fix_sent(&mut self, comp_id: &str) {
+        let _: Result<(), RedisError> = self.del(format!["fix:sent:{}", comp_id]);
+    }
+}
@@ -0,0 +1,64 @@
+use postgres::Client;
+
+use crate::database::store::Store;
+
+/* Ids come from Postgres sequences, so they're unique across restarts,
+ * and across every process that inserts into the database.
+ *
+ * Accounts are created rarely, so we take one id at a time.
+ * Orders are created constantly, so we reserve them in blocks:
+ * the order sequence increments by ORDER_ID_BLOCK, and each nextval
+ * hands us the ORDER_ID_BLOCK ids starting at the value it returns.
+ * Ids left in a block when the exchange shuts down are skipped.
+ *
+ * Other stores hand out ids the same way (see OrderStore::reserve_order_ids).
+ **/
+
+pub const ORDER_ID_BLOCK: i64 = 1000; // Must match INCREMENT BY of order_id_seq in schema.sql
+
+fn nextval(sequence: &str, conn: &mut Client) -> i64 {
+    match conn.query_one("SELECT nextval($1::text::regclass);", &[&sequence]) {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            panic!("Failed to get the next value of sequence {}!", sequence);
+        }
+    }
+}
+
+/* Allocate a new account id. */
+pub fn next_account_id(conn: &mut Client) -> i64 {
+    nextval("account_id_seq", conn)
+}
+
+/* Reserve a block of ORDER_ID_BLOCK order ids, returns the first. */
+pub fn reserve_order_ids(conn: &mut Client) -> i64 {
+    nextval("order_id_seq", conn)
+}
+
+// Hands out order ids from a block reserved from the store.
+#[derive(Debug)]
+pub struct IdAllocator {
+    next: i64,  // The next id to hand out
+    end: i64    // One past the last id of the reserved block
+}
+
+impl IdAllocator {
+    pub fn orders() -> Self {
+        IdAllocator {
+            next: 0,
+            end: 0  // Empty, the first call to next() reserves a block
+        }
+    }
+
+    /* Hand out the next id, reserving a new block if this one is used up. */
+    pub fn next(&mut self, store: &mut dyn Store) -> i64 {
+        if self.next == self.end {
+            self.next = store.reserve_order_ids();
+            self.end = self.next + ORDER_ID_BLOCK;
+        }
+        let id = self.next;
+        self.next += 1;
+        id
+    }
+}
@@ -0,0 +1,657 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+
+use crate::exchange::{Exchange, Order, OrderStatus, SecStat, Trade, UserAccount, RateLimits};
+use crate::account::{AuthError, AccountStatus, MarginAccount};
+use crate::account::{password, roles};
+use crate::buffer::DatabaseReadyOrder;
+use crate::database::{self, ids};
+use crate::database::store::{AccountStore, OrderStore, TradeStore, MarketStore, StoreError};
+use crate::database::cache::Cache;
+
+/* The store and cache used when the exchange runs without Postgres or Redis, ex. to try it out,
+ * or to test it. The store is lost when the exchange exits, the cache too unless it was opened from a file.
+ *
+ * Clones share their data, like connections to the same database. They're behind a mutex since
+ * the database writer's worker threads write to the store at the same time as the main thread reads it.
+ **/
+
+// A row of the Account table
+#[derive(Debug, Clone)]
+struct AccountRow {
+    id: i64,
+    username: String,
+    password: String,
+    margin: MarginAccount,
+    roles: Vec<String>,
+    status: String,
+    rate_limits: Option<RateLimits>     // The RateLimits table, set with `limits set`
+}
+
+impl AccountRow {
+    fn to_account(&self) -> UserAccount {
+        let mut account = UserAccount::direct(self.id, &self.username, &self.password);
+        account.margin = self.margin.clone();
+        account.roles = roles::from_names(&self.roles);
+        account.status = AccountStatus::from(&self.status);
+        account
+    }
+}
+
+// A row of the Orders table
+#[derive(Debug, Clone)]
+struct OrderRow {
+    symbol: String,
+    action: String,
+    quantity: i32,
+    filled: i32,
+    price: f64,
+    user_id: i64,
+    status: String,
+    client_id: Option<String>
+}
+
+#[derive(Default)]
+struct Tables {
+    accounts: BTreeMap<i64, AccountRow>,
+    orders: HashMap<i64, OrderRow>,
+    pending: BTreeSet<i64>,
+    trades: Vec<Trade>,
+    markets: BTreeMap<String, SecStat>,
+    total_orders: i64,
+    last_account_id: i64,
+    last_order_id: i64
+}
+
+impl Tables {
+    fn account_by_username(&mut self, username: &str) -> Option<&mut AccountRow> {
+        self.accounts.values_mut().find(|account| account.username == username)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    tables: Arc<Mutex<Tables>>
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().expect("A thread panicked while writing to the in-memory store!")
+    }
+}
+
+impl AccountStore for MemoryStore {
+    fn populate_rate_limits(&mut self, exchange: &mut Exchange) {
+        for row in self.tables().accounts.values() {
+            if let Some(limits) = row.rate_limits {
+                exchange.rate_limiter.set_account_limits(&row.username, Some(limits));
+            }
+        }
+    }
+
+    fn next_account_id(&mut self) -> i64 {
+        let mut tables = self.tables();
+        tables.last_account_id += 1;
+        tables.last_account_id
+    }
+
+    fn read_account_exists(&mut self, username: &str) -> bool {
+        self.tables().account_by_username(username).is_some()
+    }
+
+    fn read_auth_user<'a>(&mut self, username: &'a str, password: &str) -> Result<UserAccount, AuthError<'a>> {
+        let mut tables = self.tables();
+        let row = match tables.account_by_username(username) {
+            Some(row) => row,
+            None => return Err(AuthError::NoUser(username))
+        };
+        if !password::verify(password, &row.password) {
+            return Err(AuthError::BadPassword(None));
+        }
+        if !password::is_hashed(&row.password) {
+            row.password = password::hash(password);
+        }
+        Ok(row.to_account())
+    }
+
+    fn read_account(&mut self, username: &str) -> Option<UserAccount> {
+        self.tables().account_by_username(username).map(|row| row.to_account())
+    }
+
+    fn read_user_by_id(&mut self, id: i64) -> Option<String> {
+        self.tables().accounts.get(&id).map(|row| row.username.clone())
+    }
+
+    fn read_admin_exists(&mut self) -> bool {
+        self.tables().accounts.values().any(|row| row.roles.iter().any(|role| role == "admin"))
+    }
+
+    fn write_insert_new_account(&mut self, account: &UserAccount) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        let id = account.id.unwrap();
+        if tables.accounts.contains_key(&id) || tables.accounts.values().any(|row| row.username == account.username) {
+            return Err(StoreError::Duplicate);
+        }
+        tables.accounts.insert(id, AccountRow {
+            id,
+            username: account.username.clone(),
+            password: account.password.clone(),
+            margin: MarginAccount::default(),
+            roles: roles::to_names(&account.roles),
+            status: AccountStatus::Active.as_str().to_string(),
+            rate_limits: None
+        });
+        Ok(())
+    }
+
+    fn write_update_margin_account(&mut self, username: &str, margin: &MarginAccount) -> bool {
+        match self.tables().account_by_username(username) {
+            Some(row) => {
+                row.margin = margin.clone();
+                true
+            },
+            None => false
+        }
+    }
+
+    fn write_update_role(&mut self, username: &str, role: &str, grant: bool) -> Option<Vec<String>> {
+        let mut tables = self.tables();
+        let row = tables.account_by_username(username)?;
+        // Remove first so a role is never listed twice.
+        row.roles.retain(|name| name != role);
+        if grant {
+            row.roles.push(role.to_string());
+        }
+        Some(row.roles.clone())
+    }
+
+    fn write_update_status(&mut self, username: &str, status: &str) -> bool {
+        match self.tables().account_by_username(username) {
+            Some(row) => {
+                row.status = status.to_string();
+                true
+            },
+            None => false
+        }
+    }
+
+    fn write_update_rate_limits(&mut self, username: &str, limits: Option<&RateLimits>) {
+        if let Some(row) = self.tables().account_by_username(username) {
+            row.rate_limits = limits.copied();
+        }
+    }
+
+    fn write_update_username(&mut self, id: i64, username: &str) {
+        if let Some(row) = self.tables().accounts.get_mut(&id) {
+            row.username = username.to_string();
+        }
+    }
+
+    fn write_update_password(&mut self, id: i64, password_hash: &str) {
+        if let Some(row) = self.tables().accounts.get_mut(&id) {
+            row.password = password_hash.to_string();
+        }
+    }
+
+    fn write_hash_plaintext_passwords(&mut self) -> usize {
+        // Accounts are only ever created with hashed passwords.
+        0
+    }
+}
+
+impl OrderStore for MemoryStore {
+    fn reserve_order_ids(&mut self) -> i64 {
+        let mut tables = self.tables();
+        let first = tables.last_order_id + 1;
+        tables.last_order_id += ids::ORDER_ID_BLOCK;
+        first
+    }
+
+    fn populate_exchange_markets(&mut self, exchange: &mut Exchange) {
+        let tables = self.tables();
+        for order_id in tables.pending.iter() {
+            let row = &tables.orders[order_id];
+            let order = Order::direct(&row.action, &row.symbol, row.quantity, row.price, *order_id, row.user_id).with_fills(row.filled, OrderStatus::PENDING);
+            if let Some(market) = database::direct_insert_to_market(exchange.live_orders.get_mut(&order.symbol), &order) {
+                exchange.live_orders.insert(order.symbol.clone(), market);
+            };
+        }
+    }
+
+    fn read_client_order(&mut self, user_id: i64, client_id: &str) -> Option<Order> {
+        let tables = self.tables();
+        let (order_id, row) = tables.orders.iter().find(|(_, row)| row.user_id == user_id && row.client_id.as_deref() == Some(client_id))?;
+        let mut order = Order::direct(&row.action, &row.symbol, row.quantity, row.price, *order_id, user_id).with_fills(row.filled, OrderStatus::from(&row.status));
+        order.client_id = Some(client_id.to_string());
+        Some(order)
+    }
+
+    fn read_match_pending_order(&mut self, user_id: i64, order_id: i64) -> Option<String> {
+        let tables = self.tables();
+        if !tables.pending.contains(&order_id) {
+            return None;
+        }
+        tables.orders.get(&order_id).filter(|row| row.user_id == user_id).map(|row| row.action.clone())
+    }
+
+    fn insert_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]) {
+        let mut tables = self.tables();
+        for order in orders {
+            tables.orders.insert(order.order_id.unwrap(), OrderRow {
+                symbol: order.symbol.clone().unwrap(),
+                action: order.action.clone().unwrap(),
+                quantity: order.quantity.unwrap(),
+                filled: order.filled.unwrap(),
+                price: order.price.unwrap(),
+                user_id: order.user_id.unwrap(),
+                status: format!["{:?}", order.status.unwrap()],
+                client_id: order.client_id.clone()
+            });
+        }
+    }
+
+    fn update_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]) {
+        let mut tables = self.tables();
+        for order in orders {
+            if let Some(row) = tables.orders.get_mut(&order.order_id.unwrap()) {
+                if let Some(filled) = order.filled {
+                    row.filled = filled;
+                }
+                if let Some(status) = order.status {
+                    row.status = format!["{:?}", status];
+                }
+            }
+        }
+    }
+
+    fn insert_buffered_pending(&mut self, pending: &[i64]) {
+        self.tables().pending.extend(pending.iter());
+    }
+
+    fn delete_buffered_pending(&mut self, pending: &[i64]) {
+        let mut tables = self.tables();
+        for order_id in pending {
+            tables.pending.remove(order_id);
+        }
+    }
+}
+
+impl TradeStore for MemoryStore {
+    fn populate_has_trades(&mut self, exchange: &mut Exchange) {
+        let tables = self.tables();
+        for symbol in tables.markets.keys() {
+            exchange.has_trades.insert(symbol.clone(), false);
+        }
+        for trade in tables.trades.iter() {
+            exchange.has_trades.insert(trade.symbol.clone(), true);
+        }
+    }
+
+    fn read_trades(&mut self, symbol: &str) -> Option<Vec<Trade>> {
+        Some(self.tables().trades.iter().filter(|trade| trade.symbol == symbol).cloned().collect())
+    }
+
+    fn insert_buffered_trades(&mut self, trades: &[Trade]) {
+        self.tables().trades.extend(trades.iter().cloned());
+    }
+}
+
+impl MarketStore for MemoryStore {
+    fn populate_market_statistics(&mut self, exchange: &mut Exchange) {
+        for (symbol, stats) in self.tables().markets.iter() {
+            exchange.statistics.insert(symbol.clone(), stats.clone());
+        }
+    }
+
+    fn populate_exchange_statistics(&mut self, exchange: &mut Exchange) {
+        exchange.total_orders = self.tables().total_orders;
+    }
+
+    fn read_market_exists(&mut self, market: &str) -> bool {
+        self.tables().markets.contains_key(market)
+    }
+
+    fn read_exchange_markets_simulations(&mut self, symbol_vec: &mut Vec<String>) {
+        let limit = symbol_vec.capacity();
+        symbol_vec.extend(self.tables().markets.keys().take(limit).cloned());
+    }
+
+    /* There's only the one in-memory database, so the name is ignored. */
+    fn upgrade_db(&mut self, markets: &mut dyn BufRead, _db_name: &str) {
+        let mut tables = self.tables();
+        for line in markets.lines() {
+            match line {
+                Ok(line) => {
+                    let mut components = line.split(',');
+                    let action = components.next().unwrap();
+                    let symbol = components.next().unwrap();
+                    if action == "add" {
+                        tables.markets.insert(symbol.to_string(), SecStat::direct(symbol, 0, 0, 0, 0, None));
+                    }
+                },
+                Err(e) => eprintln!("{}", e)
+            }
+        }
+        println!("Upgrade complete!");
+    }
+
+    fn update_total_orders(&mut self, total_orders: i64) {
+        self.tables().total_orders = total_orders;
+    }
+
+    fn update_buffered_markets(&mut self, markets: &[SecStat]) {
+        let mut tables = self.tables();
+        for market in markets {
+            if let Some(stats) = tables.markets.get_mut(&market.symbol) {
+                *stats = market.clone();
+                stats.modified = false;
+            }
+        }
+    }
+}
+
+
+#[derive(Default)]
+struct CacheData {
+    accounts: HashMap<String, HashMap<String, String>>,
+    usernames: HashMap<i64, String>,
+    sessions: HashMap<String, (String, DateTime<Utc>)>,     // token => (username, expiry)
+    user_sessions: HashMap<String, HashSet<String>>,        // username => tokens
+    filler: HashMap<i64, Vec<String>>,                      // Oldest first, unlike Redis
+    filled: HashMap<i64, Vec<String>>,
+    active_markets: HashMap<i64, BTreeMap<String, i32>>,
+    journal: Vec<String>,
+    fix_seqs: HashMap<String, (u64, u64)>,                  // comp_id => (next in, next out)
+    fix_sent: HashMap<String, HashMap<u64, Vec<u8>>>
+}
+
+impl CacheData {
+    fn to_json(&self) -> Value {
+        let sessions: HashMap<&String, (&String, String)> = self.sessions.iter()
+            .map(|(token, (username, expiry))| (token, (username, expiry.to_rfc3339())))
+            .collect();
+        json!({
+            "accounts": self.accounts,
+            "usernames": self.usernames,
+            "sessions": sessions,
+            "filler": self.filler,
+            "filled": self.filled,
+            "active_markets": self.active_markets,
+            "journal": self.journal,
+            "fix_seqs": self.fix_seqs,
+            "fix_sent": self.fix_sent
+        })
+    }
+
+    fn from_json(mut value: Value) -> Result<Self, serde_json::Error> {
+        let mut data = CacheData {
+            accounts: serde_json::from_value(value["accounts"].take())?,
+            usernames: serde_json::from_value(value["usernames"].take())?,
+            filler: serde_json::from_value(value["filler"].take())?,
+            filled: serde_json::from_value(value["filled"].take())?,
+            active_markets: serde_json::from_value(value["active_markets"].take())?,
+            journal: serde_json::from_value(value["journal"].take())?,
+            fix_seqs: serde_json::from_value(value["fix_seqs"].take())?,
+            fix_sent: serde_json::from_value(value["fix_sent"].take())?,
+            ..CacheData::default()
+        };
+
+        // Sessions that expired while the exchange was down are dropped.
+        let sessions: HashMap<String, (String, String)> = serde_json::from_value(value["sessions"].take())?;
+        for (token, (username, expiry)) in sessions.into_iter() {
+            if let Ok(expiry) = DateTime::parse_from_rfc3339(&expiry) {
+                let expiry = expiry.with_timezone(&Utc);
+                if expiry > Utc::now() {
+                    data.user_sessions.entry(username.clone()).or_default().insert(token.clone());
+                    data.sessions.insert(token, (username, expiry));
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+/* The cache used when the exchange runs without Redis.
+ * If it was opened from a file, it's saved back to the file on shutdown (see CacheBackend::persist),
+ * so accounts, sessions and trades outlive the exchange like they would in Redis.
+ **/
+#[derive(Clone, Default)]
+pub struct MemoryCache {
+    data: Arc<Mutex<CacheData>>,
+    path: Option<String>    // Where the cache is saved, if anywhere
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        MemoryCache::default()
+    }
+
+    /* A cache saved to the file at `path`, starting with what was saved there last, if anything. */
+    pub fn open(path: &str) -> Result<Self, String> {
+        let data = match fs::read_to_string(path) {
+            Ok(contents) => {
+                let value: Value = serde_json::from_str(&contents).map_err(|e| format!["{} isn't a saved cache: {}", path, e])?;
+                CacheData::from_json(value).map_err(|e| format!["{} isn't a saved cache: {}", path, e])?
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => CacheData::default(),
+            Err(e) => return Err(format!["Failed to read the cache from {}: {}", path, e])
+        };
+        Ok(MemoryCache {
+            data: Arc::new(Mutex::new(data)),
+            path: Some(path.to_string())
+        })
+    }
+
+    /* Save the cache to its file, if it has one.
+     * We write a temporary file then rename it, so a crash mid-save doesn't lose the last save.
+     **/
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        let contents = self.data().to_json().to_string();
+        let temporary = format!["{}.tmp", path];
+        fs::write(&temporary, contents).map_err(|e| format!["Failed to save the cache to {}: {}", temporary, e])?;
+        fs::rename(&temporary, path).map_err(|e| format!["Failed to save the cache to {}: {}", path, e])
+    }
+
+    fn data(&self) -> MutexGuard<'_, CacheData> {
+        self.data.lock().expect("A thread panicked while writing to the in-memory cache!")
+    }
+}
+
+impl Cache for MemoryCache {
+    fn read_cached_account(&mut self, username: &str) -> HashMap<String, String> {
+        self.data().accounts.get(username).cloned().unwrap_or_default()
+    }
+
+    fn write_cached_account(&mut self, username: &str, fields: &[(&str, &str)]) {
+        let mut data = self.data();
+        let account = data.accounts.entry(username.to_string()).or_default();
+        for (field, value) in fields.iter() {
+            account.insert(field.to_string(), value.to_string());
+        }
+    }
+
+    fn delete_cached_account(&mut self, username: &str) {
+        self.data().accounts.remove(username);
+    }
+
+    fn read_username(&mut self, id: i64) -> Option<String> {
+        self.data().usernames.get(&id).cloned()
+    }
+
+    fn write_username(&mut self, id: i64, username: &str) {
+        self.data().usernames.insert(id, username.to_string());
+    }
+
+    fn read_session(&mut self, token: &str) -> Option<String> {
+        let mut data = self.data();
+        match data.sessions.get(token) {
+            Some((username, expiry)) if *expiry > Utc::now() => Some(username.clone()),
+            Some(_) => {
+                data.sessions.remove(token);
+                None
+            },
+            None => None
+        }
+    }
+
+    fn write_session(&mut self, token: &str, username: &str, ttl: usize) {
+        let mut data = self.data();
+        let expiry = Utc::now() + Duration::seconds(ttl as i64);
+        data.sessions.insert(token.to_string(), (username.to_string(), expiry));
+        data.user_sessions.entry(username.to_string()).or_default().insert(token.to_string());
+    }
+
+    fn touch_session(&mut self, token: &str, _username: &str, ttl: usize) {
+        if let Some(session) = self.data().sessions.get_mut(token) {
+            session.1 = Utc::now() + Duration::seconds(ttl as i64);
+        }
+    }
+
+    fn delete_session(&mut self, token: &str) -> bool {
+        let mut data = self.data();
+        match data.sessions.remove(token) {
+            Some((username, expiry)) => {
+                if let Some(tokens) = data.user_sessions.get_mut(&username) {
+                    tokens.remove(token);
+                }
+                expiry > Utc::now()
+            },
+            None => false
+        }
+    }
+
+    fn delete_sessions(&mut self, username: &str) {
+        let mut data = self.data();
+        if let Some(tokens) = data.user_sessions.remove(username) {
+            for token in tokens.iter() {
+                data.sessions.remove(token);
+            }
+        }
+    }
+
+    fn read_trades(&mut self, user_id: i64) -> Vec<String> {
+        let data = self.data();
+        let filler = data.filler.get(&user_id).into_iter().flatten().rev();
+        let filled = data.filled.get(&user_id).into_iter().flatten().rev();
+        filler.chain(filled).cloned().collect()
+    }
+
+    fn write_trades(&mut self, user_id: i64, filler: &[String], filled: &[String]) {
+        let mut data = self.data();
+        data.filler.entry(user_id).or_default().extend(filler.iter().cloned());
+        data.filled.entry(user_id).or_default().extend(filled.iter().cloned());
+    }
+
+    fn read_active_markets(&mut self, user_id: i64) -> Vec<String> {
+        self.data().active_markets.get(&user_id).map(|markets| markets.keys().cloned().collect()).unwrap_or_default()
+    }
+
+    fn write_active_markets(&mut self, user_id: i64, diffs: &HashMap<String, i32>) {
+        let mut data = self.data();
+        let markets = data.active_markets.entry(user_id).or_default();
+        for (market, diff) in diffs.iter() {
+            *markets.entry(market.clone()).or_insert(0) += *diff;
+        }
+        markets.retain(|_, count| *count > 0);
+    }
+
+    fn read_journal_len(&mut self) -> u64 {
+        self.data().journal.len() as u64
+    }
+
+    fn read_journal(&mut self, from: u64) -> Result<Vec<String>, String> {
+        let data = self.data();
+        let from = (from.max(1) as usize - 1).min(data.journal.len());
+        Ok(data.journal[from..].to_vec())
+    }
+
+    fn write_journal(&mut self, executions: &[String]) -> Result<(), String> {
+        self.data().journal.extend(executions.iter().cloned());
+        Ok(())
+    }
+
+    fn read_fix_seq_nums(&mut self, comp_id: &str) -> Option<(u64, u64)> {
+        self.data().fix_seqs.get(comp_id).cloned()
+    }
+
+    fn write_fix_seq_nums(&mut self, comp_id: &str, next_in: u64, next_out: u64) {
+        self.data().fix_seqs.insert(comp_id.to_string(), (next_in, next_out));
+    }
+
+    fn read_fix_sent(&mut self, comp_id: &str, seq: u64) -> Option<Vec<u8>> {
+        self.data().fix_sent.get(comp_id).and_then(|sent| sent.get(&seq).cloned())
+    }
+
+    fn write_fix_sent(&mut self, comp_id: &str, seq: u64, raw: &[u8]) {
+        self.data().fix_sent.entry(comp_id.to_string()).or_default().insert(seq, raw.to_vec());
+    }
+
+    fn delete_fix_sent(&mut self, comp_id: &str) {
+        self.data().fix_sent.remove(comp_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn the_cache_file_outlives_a_restart() {
+        let path = env::temp_dir().join(format!["rustx-cache-test-{}.json", std::process::id()]);
+        let path = path.to_string_lossy().to_string();
+        fs::remove_file(&path).ok();
+
+        let mut cache = MemoryCache::open(&path).unwrap();
+        cache.write_cached_account("alice", &[("id", "2"), ("tokens", "100")]);
+        cache.write_username(2, "alice");
+        cache.write_session("live", "alice", 3600);
+        cache.write_session("expired", "alice", 0);
+        cache.write_trades(2, &["bought".to_string()], &["sold".to_string()]);
+        cache.write_fix_seq_nums("ALICE", 3, 5);
+        cache.save().unwrap();
+        assert!(!std::path::Path::new(&format!["{}.tmp", path]).exists());
+
+        let mut cache = MemoryCache::open(&path).unwrap();
+        assert_eq!(cache.read_cached_account("alice").get("tokens").map(String::as_str), Some("100"));
+        assert_eq!(cache.read_username(2), Some("alice".to_string()));
+        assert_eq!(cache.read_session("live"), Some("alice".to_string()));
+        assert_eq!(cache.read_session("expired"), None);
+        assert_eq!(cache.read_trades(2), vec!["bought".to_string(), "sold".to_string()]);
+        assert_eq!(cache.read_fix_seq_nums("ALICE"), Some((3, 5)));
+
+        // Deleting every session of the account only finds the one that survived.
+        cache.delete_sessions("alice");
+        assert!(!cache.delete_session("live"));
+
+        fs::write(&path, "not a cache").unwrap();
+        assert!(MemoryCache::open(&path).is_err());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn a_missing_file_is_an_empty_cache() {
+        let path = env::temp_dir().join(format!["rustx-cache-test-missing-{}.json", std::process::id()]);
+        let path = path.to_string_lossy().to_string();
+        fs::remove_file(&path).ok();
+
+        let mut cache = MemoryCache::open(&path).unwrap();
+        assert!(cache.read_cached_account("alice").is_empty());
+        assert_eq!(cache.read_journal_len(), 0);
+    }
+}
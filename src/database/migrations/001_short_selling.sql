@@ -0,0 +1,6 @@
+-- Adds the short selling settings to existing Account tables.
+-- New databases get these columns from schema.sql.
+ALTER TABLE Account
+    ADD COLUMN short_enabled   boolean NOT NULL DEFAULT false,
+    ADD COLUMN borrow_limit    int NOT NULL DEFAULT 0,
+    ADD COLUMN collateral      float8 NOT NULL DEFAULT 0;
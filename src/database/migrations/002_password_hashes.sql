@@ -0,0 +1,4 @@
+-- Password hashes don't fit in the old varchar(20) column.
+-- After this, start the exchange once: any remaining plaintext passwords
+-- are hashed on startup (see database::write_hash_plaintext_passwords).
+ALTER TABLE Account ALTER COLUMN password TYPE text;
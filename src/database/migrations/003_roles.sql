@@ -0,0 +1,7 @@
+-- Adds roles to existing Account tables.
+-- New databases get this column from schema.sql.
+ALTER TABLE Account
+    ADD COLUMN roles text[] NOT NULL DEFAULT '{trader}';
+
+-- The admin used to be whoever had the username "admin".
+UPDATE Account SET roles = '{admin}' WHERE username = 'admin';
@@ -0,0 +1,4 @@
+-- Adds the account lifecycle to existing Account tables.
+-- New databases get this column from schema.sql.
+ALTER TABLE Account
+    ADD COLUMN status varchar(10) NOT NULL DEFAULT 'active';
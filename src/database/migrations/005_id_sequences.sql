@@ -0,0 +1,16 @@
+-- Allocates account and order ids from sequences, and widens account ids to 64 bits.
+-- New databases get these from schema.sql.
+ALTER TABLE Account
+    ALTER COLUMN ID TYPE bigint;
+ALTER TABLE Orders
+    ALTER COLUMN user_ID TYPE bigint;
+ALTER TABLE ExecutedTrades
+    ALTER COLUMN filled_UID TYPE bigint,
+    ALTER COLUMN filler_UID TYPE bigint;
+
+CREATE SEQUENCE account_id_seq;
+CREATE SEQUENCE order_id_seq INCREMENT BY 1000;
+
+-- Start both sequences after the ids already in use.
+SELECT setval('account_id_seq', COALESCE(MAX(ID), 0) + 1, false) FROM Account;
+SELECT setval('order_id_seq', COALESCE(MAX(order_ID), 0) + 1, false) FROM Orders;
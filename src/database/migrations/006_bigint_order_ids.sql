@@ -0,0 +1,11 @@
+-- Widens order ids, and the order count, to 64 bits.
+-- New databases get these from schema.sql.
+ALTER TABLE Orders
+    ALTER COLUMN order_ID TYPE bigint;
+ALTER TABLE PendingOrders
+    ALTER COLUMN order_ID TYPE bigint;
+ALTER TABLE ExecutedTrades
+    ALTER COLUMN filled_OID TYPE bigint,
+    ALTER COLUMN filler_OID TYPE bigint;
+ALTER TABLE ExchangeStats
+    ALTER COLUMN total_orders TYPE bigint;
@@ -0,0 +1,5 @@
+-- Adds client order ids to existing Orders tables.
+-- New databases get this column from schema.sql.
+ALTER TABLE Orders
+    ADD COLUMN client_order_ID varchar(36),
+    ADD UNIQUE (user_ID, client_order_ID);
@@ -0,0 +1,11 @@
+-- Adds the rate limits of accounts to existing databases.
+-- New databases get this table from schema.sql.
+CREATE TABLE RateLimits (
+    user_ID         bigint,
+    orders_per_sec  float8 NOT NULL,
+    cancels_per_sec float8 NOT NULL,
+    max_open_orders int NOT NULL,
+    PRIMARY KEY(user_ID),
+    FOREIGN KEY(user_ID)
+        REFERENCES Account(ID)
+);
@@ -0,0 +1,151 @@
+use std::io::{BufRead, BufReader};
+
+use postgres::Client;
+
+use crate::exchange::{Exchange, Order, SecStat, Trade, UserAccount, RateLimits};
+use crate::account::{AuthError, MarginAccount};
+use crate::buffer::DatabaseReadyOrder;
+use crate::database::{self, ids};
+use crate::database::store::{AccountStore, OrderStore, TradeStore, MarketStore, StoreError};
+
+/* The Postgres store is a connection, the queries live in database.rs. */
+
+impl AccountStore for Client {
+    fn populate_rate_limits(&mut self, exchange: &mut Exchange) {
+        database::populate_rate_limits(exchange, self)
+    }
+
+    fn next_account_id(&mut self) -> i64 {
+        ids::next_account_id(self)
+    }
+
+    fn read_account_exists(&mut self, username: &str) -> bool {
+        database::read_account_exists(username, self)
+    }
+
+    fn read_auth_user<'a>(&mut self, username: &'a str, password: &str) -> Result<UserAccount, AuthError<'a>> {
+        database::read_auth_user(username, password, self)
+    }
+
+    fn read_account(&mut self, username: &str) -> Option<UserAccount> {
+        database::read_account(username, self).ok()
+    }
+
+    fn read_user_by_id(&mut self, id: i64) -> Option<String> {
+        database::read_user_by_id(id, self).ok()
+    }
+
+    fn read_admin_exists(&mut self) -> bool {
+        database::read_admin_exists(self)
+    }
+
+    fn write_insert_new_account(&mut self, account: &UserAccount) -> Result<(), StoreError> {
+        database::write_insert_new_account(account, self)
+    }
+
+    fn write_update_margin_account(&mut self, username: &str, margin: &MarginAccount) -> bool {
+        database::write_update_margin_account(username, margin, self)
+    }
+
+    fn write_update_role(&mut self, username: &str, role: &str, grant: bool) -> Option<Vec<String>> {
+        database::write_update_role(username, role, grant, self)
+    }
+
+    fn write_update_status(&mut self, username: &str, status: &str) -> bool {
+        database::write_update_status(username, status, self)
+    }
+
+    fn write_update_rate_limits(&mut self, username: &str, limits: Option<&RateLimits>) {
+        database::write_update_rate_limits(username, limits, self)
+    }
+
+    fn write_update_username(&mut self, id: i64, username: &str) {
+        database::write_update_username(id, username, self)
+    }
+
+    fn write_update_password(&mut self, id: i64, password_hash: &str) {
+        database::write_update_password(id, password_hash, self)
+    }
+
+    fn write_hash_plaintext_passwords(&mut self) -> usize {
+        database::write_hash_plaintext_passwords(self)
+    }
+}
+
+impl OrderStore for Client {
+    fn reserve_order_ids(&mut self) -> i64 {
+        ids::reserve_order_ids(self)
+    }
+
+    fn populate_exchange_markets(&mut self, exchange: &mut Exchange) {
+        database::populate_exchange_markets(exchange, self)
+    }
+
+    fn read_client_order(&mut self, user_id: i64, client_id: &str) -> Option<Order> {
+        database::read_client_order(user_id, client_id, self)
+    }
+
+    fn read_match_pending_order(&mut self, user_id: i64, order_id: i64) -> Option<String> {
+        database::read_match_pending_order(user_id, order_id, self)
+    }
+
+    fn insert_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]) {
+        database::insert_buffered_orders(orders, self)
+    }
+
+    fn update_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]) {
+        database::update_buffered_orders(orders, self)
+    }
+
+    fn insert_buffered_pending(&mut self, pending: &[i64]) {
+        database::insert_buffered_pending(pending, self)
+    }
+
+    fn delete_buffered_pending(&mut self, pending: &[i64]) {
+        database::delete_buffered_pending(pending, self)
+    }
+}
+
+impl TradeStore for Client {
+    fn populate_has_trades(&mut self, exchange: &mut Exchange) {
+        database::populate_has_trades(exchange, self)
+    }
+
+    fn read_trades(&mut self, symbol: &str) -> Option<Vec<Trade>> {
+        database::read_trades(symbol, self)
+    }
+
+    fn insert_buffered_trades(&mut self, trades: &[Trade]) {
+        database::insert_buffered_trades(trades, self)
+    }
+}
+
+impl MarketStore for Client {
+    fn populate_market_statistics(&mut self, exchange: &mut Exchange) {
+        database::populate_market_statistics(exchange, self)
+    }
+
+    fn populate_exchange_statistics(&mut self, exchange: &mut Exchange) {
+        database::populate_exchange_statistics(exchange, self)
+    }
+
+    fn read_market_exists(&mut self, market: &str) -> bool {
+        database::read_market_exists(market, self)
+    }
+
+    fn read_exchange_markets_simulations(&mut self, symbol_vec: &mut Vec<String>) {
+        database::read_exchange_markets_simulations(symbol_vec, self)
+    }
+
+    fn upgrade_db(&mut self, markets: &mut dyn BufRead, db_name: &str) {
+        database::upgrade_db(BufReader::new(markets), db_name, self)
+    }
+
+    fn update_total_orders(&mut self, total_orders: i64) {
+        database::update_total_orders(total_orders, self)
+    }
+
+    fn update_buffered_markets(&mut self, markets: &[SecStat]) {
+        database::update_buffered_markets(markets, self)
+    }
+}
@@ -1,23 +1,48 @@
+-- Ids come from these sequences, see database/ids.rs
+CREATE SEQUENCE account_id_seq;
+-- Order ids are reserved in blocks, this must match ORDER_ID_BLOCK
+CREATE SEQUENCE order_id_seq INCREMENT BY 1000;
+
 CREATE TABLE Account (
-    ID              int,
+    ID              bigint,
     username        varchar(15) NOT NULL,
-    password        varchar(20) NOT NULL,
+    password        text NOT NULL, -- Salted Argon2id hash, see account/password.rs
     register_time   TIMESTAMP WITH TIME ZONE,
+    -- Short selling settings, see account/margin.rs
+    short_enabled   boolean NOT NULL DEFAULT false,
+    borrow_limit    int NOT NULL DEFAULT 0,
+    collateral      float8 NOT NULL DEFAULT 0,
+    -- What the account may do, see account/roles.rs
+    roles           text[] NOT NULL DEFAULT '{trader}',
+    status          varchar(10) NOT NULL DEFAULT 'active', -- active, suspended or closed
     PRIMARY KEY(ID)
 );
 
+-- Accounts given their own rate limits with `limits set`, see exchange/throttle.rs
+CREATE TABLE RateLimits (
+    user_ID         bigint,
+    orders_per_sec  float8 NOT NULL,
+    cancels_per_sec float8 NOT NULL,
+    max_open_orders int NOT NULL,
+    PRIMARY KEY(user_ID),
+    FOREIGN KEY(user_ID)
+        REFERENCES Account(ID)
+);
+
 CREATE TABLE Orders (
-    order_ID        int,
+    order_ID        bigint,
     symbol          varchar(10) NOT NULL,
     action          varchar(4) NOT NULL,
     quantity        int,
     filled          int,
     price           float8,
-    user_ID         int,
+    user_ID         bigint,
     status          varchar(9) NOT NULL,
     time_placed     TIMESTAMP WITH TIME ZONE,
     time_updated    TIMESTAMP WITH TIME ZONE,
+    client_order_ID varchar(36), -- Chosen by the client, unique per account
     PRIMARY KEY(order_ID),
+    UNIQUE(user_ID, client_order_ID),
     FOREIGN KEY(user_ID)
         REFERENCES Account(ID)
 );
@@ -28,7 +53,7 @@ CREATE TABLE Orders (
 ALTER TABLE Orders SET (fillfactor = 70);
 
 CREATE TABLE PendingOrders (
-    order_ID        int,
+    order_ID        bigint,
     PRIMARY KEY(order_ID),
     FOREIGN KEY(order_ID)
         REFERENCES Orders(order_ID)
@@ -38,10 +63,10 @@ CREATE TABLE ExecutedTrades (
     symbol          varchar(10) NOT NULL,
     action          varchar(4) NOT NULL,
     price           float8,
-    filled_OID      int,
-    filled_UID      int,
-    filler_OID      int,
-    filler_UID      int,
+    filled_OID      bigint,
+    filled_UID      bigint,
+    filler_OID      bigint,
+    filler_UID      bigint,
     exchanged       int,
     execution_time  TIMESTAMP WITH TIME ZONE,
     -- Will never have 2+ trades with the same
@@ -73,6 +98,6 @@ CREATE TABLE Markets (
 -- with SELECT count(*) from Orders; would be prohibitively expensive
 CREATE TABLE ExchangeStats (
     key             int,
-    total_orders    int,
+    total_orders    bigint,
     PRIMARY KEY (key)
 );
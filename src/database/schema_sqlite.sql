@@ -0,0 +1,90 @@
+-- The SQLite version of schema.sql, see database/sqlite.rs.
+-- It's applied every time the exchange opens the file, so every statement must be idempotent.
+
+-- SQLite has no sequences, so we keep the last id handed out of each here.
+CREATE TABLE IF NOT EXISTS Sequences (
+    name            text NOT NULL,
+    value           integer NOT NULL,
+    PRIMARY KEY(name)
+);
+INSERT OR IGNORE INTO Sequences VALUES ('account_id_seq', 0);
+-- Order ids are reserved in blocks of ORDER_ID_BLOCK, see database/ids.rs
+INSERT OR IGNORE INTO Sequences VALUES ('order_id_seq', 0);
+
+CREATE TABLE IF NOT EXISTS Account (
+    ID              integer,
+    username        varchar(15) NOT NULL UNIQUE,
+    password        text NOT NULL, -- Salted Argon2id hash, see account/password.rs
+    register_time   text, -- RFC 3339
+    -- Short selling settings, see account/margin.rs
+    short_enabled   boolean NOT NULL DEFAULT 0,
+    borrow_limit    int NOT NULL DEFAULT 0,
+    collateral      float8 NOT NULL DEFAULT 0,
+    -- What the account may do, comma separated, see account/roles.rs
+    roles           text NOT NULL DEFAULT 'trader',
+    status          varchar(10) NOT NULL DEFAULT 'active', -- active, suspended or closed
+    PRIMARY KEY(ID)
+);
+
+-- Accounts given their own rate limits with `limits set`, see exchange/throttle.rs
+CREATE TABLE IF NOT EXISTS RateLimits (
+    user_ID         integer,
+    orders_per_sec  float8 NOT NULL,
+    cancels_per_sec float8 NOT NULL,
+    max_open_orders int NOT NULL,
+    PRIMARY KEY(user_ID)
+);
+
+-- The database writer inserts orders, pending orders and trades on separate connections,
+-- so unlike schema.sql, we don't declare the foreign keys between these tables.
+CREATE TABLE IF NOT EXISTS Orders (
+    order_ID        integer,
+    symbol          varchar(10) NOT NULL,
+    action          varchar(4) NOT NULL,
+    quantity        int,
+    filled          int,
+    price           float8,
+    user_ID         integer,
+    status          varchar(9) NOT NULL,
+    time_placed     text,
+    time_updated    text,
+    client_order_ID varchar(36), -- Chosen by the client, unique per account
+    PRIMARY KEY(order_ID),
+    UNIQUE(user_ID, client_order_ID)
+);
+
+CREATE TABLE IF NOT EXISTS PendingOrders (
+    order_ID        integer,
+    PRIMARY KEY(order_ID)
+);
+
+CREATE TABLE IF NOT EXISTS ExecutedTrades (
+    symbol          varchar(10) NOT NULL,
+    action          varchar(4) NOT NULL,
+    price           float8,
+    filled_OID      integer,
+    filled_UID      integer,
+    filler_OID      integer,
+    filler_UID      integer,
+    exchanged       int,
+    execution_time  text,
+    PRIMARY KEY(filled_OID, filler_OID)
+);
+CREATE INDEX IF NOT EXISTS ExecutedTradesSymbol ON ExecutedTrades(symbol);
+
+CREATE TABLE IF NOT EXISTS Markets (
+    symbol          varchar(10) NOT NULL,
+    name            varchar(300) NOT NULL,
+    total_buys      int,
+    total_sells     int,
+    filled_buys     int,
+    filled_sells    int,
+    latest_price    float8,
+    PRIMARY KEY(symbol)
+);
+
+CREATE TABLE IF NOT EXISTS ExchangeStats (
+    key             int,
+    total_orders    integer,
+    PRIMARY KEY (key)
+);
@@ -0,0 +1,627 @@
+use std::io::BufRead;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::exchange::{Exchange, Order, OrderStatus, SecStat, Trade, TradeSides, UserAccount, RateLimits};
+use crate::account::{AuthError, AccountStatus, MarginAccount};
+use crate::account::{password, roles};
+use crate::buffer::DatabaseReadyOrder;
+use crate::database::{self, ids};
+use crate::database::store::{AccountStore, OrderStore, TradeStore, MarketStore, StoreError};
+
+/* The SQLite store is a connection to a database file, for running the exchange without a Postgres server.
+ *
+ * The tables are the same as schema.sql's (see schema_sqlite.sql), with a few differences:
+ *  - Ids come from the Sequences table, since SQLite has no sequences.
+ *  - An account's roles are comma separated, since SQLite has no arrays.
+ *  - Times are RFC 3339 text.
+ *
+ * The database writer's worker threads each open the file, so a connection waits on the others
+ * for up to BUSY_TIMEOUT, rather than failing when they're writing.
+ **/
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/* Open (or create) the database file, and create any tables it's missing. */
+pub fn connect(path: &str) -> Connection {
+    let conn = match Connection::open(path) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("{}", e);
+            panic!("Failed to open the SQLite database {}!", path);
+        }
+    };
+    conn.busy_timeout(BUSY_TIMEOUT).expect("Failed to set the SQLite busy timeout.");
+    // Readers don't block the writer (or each other) in WAL mode.
+    let _: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))
+        .expect("Failed to put the SQLite database in WAL mode.");
+    if let Err(e) = conn.execute_batch(include_str!("schema_sqlite.sql")) {
+        eprintln!("{}", e);
+        panic!("Failed to create the SQLite tables in {}!", path);
+    }
+    conn
+}
+
+/* Add `increment` to the sequence, returns its new value. */
+fn nextval(conn: &mut Connection, sequence: &str, increment: i64) -> i64 {
+    match conn.query_row("UPDATE Sequences SET value=value + ?1 WHERE name=?2 RETURNING value;", params![increment, sequence], |row| row.get(0)) {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("{}", e);
+            panic!("Failed to get the next value of sequence {}!", sequence);
+        }
+    }
+}
+
+const ACCOUNT_COLUMNS: &str = "ID, username, password, short_enabled, borrow_limit, collateral, roles, status";
+
+// Builds an account from a row of ACCOUNT_COLUMNS.
+fn account_from_row(row: &Row) -> rusqlite::Result<UserAccount> {
+    let id: i64 = row.get(0)?;
+    let username: String = row.get(1)?;
+    let password: String = row.get(2)?;
+    let role_names: String = row.get(6)?;
+    let status: String = row.get(7)?;
+
+    let mut account = UserAccount::direct(id, &username, &password);
+    account.margin = MarginAccount::direct(row.get(3)?, row.get(4)?, row.get(5)?);
+    account.roles = roles::from_names(&split_roles(&role_names));
+    account.status = AccountStatus::from(status.as_str());
+    Ok(account)
+}
+
+fn split_roles(role_names: &str) -> Vec<&str> {
+    role_names.split(',').filter(|name| !name.is_empty()).collect()
+}
+
+// Builds a trade from a row of ExecutedTrades.
+fn trade_from_row(row: &Row) -> rusqlite::Result<Trade> {
+    let symbol: String = row.get(0)?;
+    let action: String = row.get(1)?;
+    let execution_time: DateTime<Utc> = row.get(8)?;
+    let sides = TradeSides { filled_oid: row.get(3)?, filled_uid: row.get(4)?, filler_oid: row.get(5)?, filler_uid: row.get(6)? };
+    Ok(Trade::direct(&symbol, &action, row.get(2)?, sides, row.get(7)?, execution_time))
+}
+
+impl AccountStore for Connection {
+    fn populate_rate_limits(&mut self, exchange: &mut Exchange) {
+        let mut statement = self.prepare("\
+SELECT Account.username, orders_per_sec, cancels_per_sec, max_open_orders FROM RateLimits
+JOIN Account ON Account.ID = RateLimits.user_ID;").expect("Something went wrong in the query.");
+        let rows = statement.query_map([], |row| {
+            let max_open_orders: i64 = row.get(3)?;
+            Ok((row.get::<_, String>(0)?, RateLimits::new(row.get(1)?, row.get(2)?, max_open_orders as usize)))
+        }).expect("Something went wrong in the query.");
+        for row in rows {
+            let (username, limits) = row.expect("Something went wrong in the query.");
+            exchange.rate_limiter.set_account_limits(&username, Some(limits));
+        }
+    }
+
+    fn next_account_id(&mut self) -> i64 {
+        nextval(self, "account_id_seq", 1)
+    }
+
+    fn read_account_exists(&mut self, username: &str) -> bool {
+        match self.query_row("SELECT ID FROM Account WHERE username=?1;", params![username], |row| row.get::<_, i64>(0)).optional() {
+            Ok(id) => id.is_some(),
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("There was an issue while checking if the user is in the database.");
+            }
+        }
+    }
+
+    fn read_auth_user<'a>(&mut self, username: &'a str, password: &str) -> Result<UserAccount, AuthError<'a>> {
+        let mut account = match self.read_account(username) {
+            Some(account) => account,
+            None => return Err(AuthError::NoUser(username))
+        };
+        if !password::verify(password, &account.password) {
+            return Err(AuthError::BadPassword(None));
+        }
+
+        // This account still has a plaintext password, replace it with a hash.
+        if !password::is_hashed(&account.password) {
+            account.password = password::hash(password);
+            self.write_update_password(account.id.unwrap(), &account.password);
+        }
+        Ok(account)
+    }
+
+    fn read_account(&mut self, username: &str) -> Option<UserAccount> {
+        let query_string = format!["SELECT {} FROM Account WHERE username=?1;", ACCOUNT_COLUMNS];
+        match self.query_row(&query_string, params![username], account_from_row).optional() {
+            Ok(account) => account,
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Something went wrong while reading an account!");
+            }
+        }
+    }
+
+    fn read_user_by_id(&mut self, id: i64) -> Option<String> {
+        match self.query_row("SELECT username FROM Account WHERE ID=?1;", params![id], |row| row.get(0)).optional() {
+            Ok(username) => username,
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Query to get user by id failed!");
+            }
+        }
+    }
+
+    fn read_admin_exists(&mut self) -> bool {
+        match self.query_row("SELECT ID FROM Account WHERE ',' || roles || ',' LIKE '%,admin,%' LIMIT 1;", [], |row| row.get::<_, i64>(0)).optional() {
+            Ok(id) => id.is_some(),
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Query to check for an admin account failed!");
+            }
+        }
+    }
+
+    fn write_insert_new_account(&mut self, account: &UserAccount) -> Result<(), StoreError> {
+        let role_names = roles::to_names(&account.roles).join(",");
+        let query_string = "INSERT INTO Account (ID, username, password, register_time, roles) VALUES (?1, ?2, ?3, ?4, ?5);";
+        match self.execute(query_string, params![account.id.unwrap(), account.username, account.password, Utc::now(), role_names]) {
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => Err(StoreError::Duplicate),
+            Err(e) => Err(StoreError::Backend(e.to_string()))
+        }
+    }
+
+    fn write_update_margin_account(&mut self, username: &str, margin: &MarginAccount) -> bool {
+        let query_string = "UPDATE Account SET short_enabled=?1, borrow_limit=?2, collateral=?3 WHERE username=?4;";
+        match self.execute(query_string, params![margin.short_enabled, margin.borrow_limit, margin.collateral, username]) {
+            Ok(rows) => rows == 1,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                panic!("Query to update the margin settings of an account failed!");
+            }
+        }
+    }
+
+    fn write_update_role(&mut self, username: &str, role: &str, grant: bool) -> Option<Vec<String>> {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        let role_names: Option<String> = match transaction.query_row("SELECT roles FROM Account WHERE username=?1;", params![username], |row| row.get(0)).optional() {
+            Ok(role_names) => role_names,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                panic!("Query to update the roles of an account failed!");
+            }
+        };
+
+        // Remove first so a role is never listed twice.
+        let mut names: Vec<String> = split_roles(&role_names?).into_iter().filter(|name| *name != role).map(|name| name.to_string()).collect();
+        if grant {
+            names.push(role.to_string());
+        }
+        if let Err(e) = transaction.execute("UPDATE Account SET roles=?1 WHERE username=?2;", params![names.join(","), username]) {
+            eprintln!("{:?}", e);
+            panic!("Query to update the roles of an account failed!");
+        }
+        transaction.commit().expect("Failed to commit role update transaction.");
+        Some(names)
+    }
+
+    fn write_update_status(&mut self, username: &str, status: &str) -> bool {
+        match self.execute("UPDATE Account SET status=?1 WHERE username=?2;", params![status, username]) {
+            Ok(rows) => rows == 1,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                panic!("Query to update the status of an account failed!");
+            }
+        }
+    }
+
+    fn write_update_rate_limits(&mut self, username: &str, limits: Option<&RateLimits>) {
+        let result = match limits {
+            Some(limits) => {
+                let query_string = "\
+INSERT INTO RateLimits (user_ID, orders_per_sec, cancels_per_sec, max_open_orders)
+SELECT ID, ?2, ?3, ?4 FROM Account WHERE username=?1
+ON CONFLICT (user_ID) DO UPDATE SET orders_per_sec=excluded.orders_per_sec, cancels_per_sec=excluded.cancels_per_sec, max_open_orders=excluded.max_open_orders;";
+                self.execute(query_string, params![username, limits.orders_per_sec, limits.cancels_per_sec, limits.max_open_orders as i64])
+            },
+            None => self.execute("DELETE FROM RateLimits WHERE user_ID IN (SELECT ID FROM Account WHERE username=?1);", params![username])
+        };
+        if let Err(e) = result {
+            eprintln!("{:?}", e);
+            panic!("Query to update the rate limits of an account failed!");
+        }
+    }
+
+    fn write_update_username(&mut self, id: i64, username: &str) {
+        if let Err(e) = self.execute("UPDATE Account SET username=?1 WHERE ID=?2;", params![username, id]) {
+            eprintln!("{}", e);
+            panic!("Query to update an account's username failed!");
+        }
+    }
+
+    fn write_update_password(&mut self, id: i64, password_hash: &str) {
+        if let Err(e) = self.execute("UPDATE Account SET password=?1 WHERE ID=?2;", params![password_hash, id]) {
+            eprintln!("{}", e);
+            panic!("Query to update an account's password failed!");
+        }
+    }
+
+    fn write_hash_plaintext_passwords(&mut self) -> usize {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        let plaintext: Vec<(i64, String)> = {
+            let mut statement = transaction.prepare("SELECT ID, password FROM Account WHERE password NOT LIKE '$argon2%';")
+                .expect("Query to find plaintext passwords failed!");
+            let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .expect("Query to find plaintext passwords failed!");
+            rows.collect::<rusqlite::Result<Vec<(i64, String)>>>().expect("Query to find plaintext passwords failed!")
+        };
+
+        for (id, password) in plaintext.iter() {
+            if let Err(e) = transaction.execute("UPDATE Account SET password=?1 WHERE ID=?2;", params![password::hash(password), id]) {
+                eprintln!("{}", e);
+                panic!("Query to hash a plaintext password failed!");
+            }
+        }
+        transaction.commit().expect("Failed to commit password hashing transaction.");
+        plaintext.len()
+    }
+}
+
+impl OrderStore for Connection {
+    fn reserve_order_ids(&mut self) -> i64 {
+        // The sequence holds the last id of the last block reserved.
+        nextval(self, "order_id_seq", ids::ORDER_ID_BLOCK) - ids::ORDER_ID_BLOCK + 1
+    }
+
+    fn populate_exchange_markets(&mut self, exchange: &mut Exchange) {
+        let mut statement = self.prepare("\
+SELECT o.order_ID, o.symbol, o.action, o.quantity, o.filled, o.price, o.user_ID FROM PendingOrders p, Orders o
+WHERE o.order_ID=p.order_ID;").expect("Something went wrong in the query.");
+        let orders = statement.query_map([], |row| {
+            let symbol: String = row.get(1)?;
+            let action: String = row.get(2)?;
+            // No need to get status, it's obviously pending.
+            Ok(Order::direct(&action, &symbol, row.get(3)?, row.get(5)?, row.get(0)?, row.get(6)?).with_fills(row.get(4)?, OrderStatus::PENDING))
+        }).expect("Something went wrong in the query.");
+
+        for order in orders {
+            let order = order.expect("Something went wrong in the query.");
+            // Add the order we found to the market.
+            // If a new market was created, update the exchange.
+            if let Some(market) = database::direct_insert_to_market(exchange.live_orders.get_mut(&order.symbol), &order) {
+                exchange.live_orders.insert(order.symbol.clone(), market);
+            };
+        }
+    }
+
+    fn read_client_order(&mut self, user_id: i64, client_id: &str) -> Option<Order> {
+        let query_string = "SELECT order_ID, symbol, action, quantity, filled, price, status FROM Orders WHERE user_ID=?1 AND client_order_ID=?2;";
+        let result = self.query_row(query_string, params![user_id, client_id], |row| {
+            let symbol: String = row.get(1)?;
+            let action: String = row.get(2)?;
+            let status: String = row.get(6)?;
+            Ok(Order::direct(&action, &symbol, row.get(3)?, row.get(5)?, row.get(0)?, user_id).with_fills(row.get(4)?, OrderStatus::from(status.as_str())))
+        }).optional();
+
+        match result {
+            Ok(order) => order.map(|mut order| {
+                order.client_id = Some(client_id.to_string());
+                order
+            }),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                panic!("Client order id query failed!");
+            }
+        }
+    }
+
+    fn read_match_pending_order(&mut self, user_id: i64, order_id: i64) -> Option<String> {
+        let query_string = "\
+SELECT action
+FROM Orders o, PendingOrders p
+WHERE p.order_id = ?1
+  AND o.order_id = p.order_id
+  AND o.user_id  = ?2;";
+        match self.query_row(query_string, params![order_id, user_id], |row| row.get(0)).optional() {
+            Ok(action) => action,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                panic!("Match pending order query failed!");
+            }
+        }
+    }
+
+    fn insert_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        {
+            let mut statement = transaction.prepare("\
+INSERT INTO Orders
+(order_ID, symbol, action, quantity, filled, price, user_ID, status, time_placed, time_updated, client_order_ID)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11);").expect("Failed to insert new orders to database!");
+
+            for order in orders {
+                let status: String = format!["{:?}", order.status.unwrap()];
+                statement.execute(params![order.order_id,
+                                          order.symbol,
+                                          order.action,
+                                          order.quantity,
+                                          order.filled,
+                                          order.price,
+                                          order.user_id,
+                                          status,
+                                          order.time_placed,
+                                          order.time_updated,
+                                          order.client_id]).expect("FAILED TO EXEC INSERT ORDERS");
+            }
+        }
+        transaction.commit().expect("Failed to commit buffered order insert transaction.");
+    }
+
+    fn update_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        {
+            // Columns we weren't given keep their value.
+            let mut statement = transaction.prepare("\
+UPDATE Orders SET filled=COALESCE(?1, filled), status=COALESCE(?2, status), time_updated=?3 WHERE order_id=?4;")
+                .expect("Failed to create prepared statement for updated orders!");
+
+            for order in orders {
+                if order.filled.is_none() && order.status.is_none() {
+                    panic!("Our updated order has no data??");
+                }
+                // Orders without an update time are skipped, like in database::update_buffered_orders.
+                if let Some(time_updated) = order.time_updated {
+                    let status = order.status.map(|status| format!["{:?}", status]);
+                    if let Err(e) = statement.execute(params![order.filled, status, time_updated, order.order_id.unwrap()]) {
+                        eprintln!("{}", e);
+                        panic!("Something went wrong with the buffered order update statement.");
+                    }
+                }
+            }
+        }
+        transaction.commit().expect("Failed to commit buffered order update transaction.");
+    }
+
+    fn insert_buffered_pending(&mut self, pending: &[i64]) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        {
+            let mut statement = transaction.prepare("INSERT INTO PendingOrders (order_id) VALUES (?1);")
+                .expect("Failed to exec insert PendingOrders.");
+            for order_id in pending {
+                if let Err(e) = statement.execute(params![order_id]) {
+                    eprintln!("{}", e);
+                    panic!("Failed to exec insert PendingOrders.");
+                }
+            }
+        }
+        transaction.commit().expect("Failed to commit buffered pending order insert transaction.");
+    }
+
+    fn delete_buffered_pending(&mut self, pending: &[i64]) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        {
+            let mut statement = transaction.prepare("DELETE FROM PendingOrders WHERE order_id=?1;")
+                .expect("Failed to exec delete pending query.");
+            for order_id in pending {
+                if let Err(e) = statement.execute(params![order_id]) {
+                    eprintln!("{}", e);
+                    panic!("Failed to exec delete pending query.");
+                }
+            }
+        }
+        transaction.commit().expect("Failed to commit buffered pending order delete transaction.");
+    }
+}
+
+impl TradeStore for Connection {
+    fn populate_has_trades(&mut self, exchange: &mut Exchange) {
+        // 1. Read all markets in our exchange, 2. set markets with trades to true.
+        for (query_string, has_trades) in [("SELECT symbol FROM Markets;", false), ("SELECT DISTINCT symbol FROM ExecutedTrades;", true)].iter() {
+            let mut statement = self.prepare(query_string).expect("Query to read market symbols failed!");
+            let symbols = statement.query_map([], |row| row.get::<_, String>(0)).expect("Query to read market symbols failed!");
+            for symbol in symbols {
+                exchange.has_trades.insert(symbol.expect("Query to read market symbols failed!"), *has_trades);
+            }
+        }
+    }
+
+    fn read_trades(&mut self, symbol: &str) -> Option<Vec<Trade>> {
+        let mut statement = self.prepare("SELECT * FROM ExecutedTrades WHERE symbol=?1;").expect("Read Trades query (History) failed!");
+        let trades = statement.query_map(params![symbol], trade_from_row).expect("Read Trades query (History) failed!");
+        Some(trades.collect::<rusqlite::Result<Vec<Trade>>>().expect("Read Trades query (History) failed!"))
+    }
+
+    fn insert_buffered_trades(&mut self, trades: &[Trade]) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        {
+            let mut statement = transaction.prepare("\
+INSERT INTO ExecutedTrades
+(symbol, action, price, filled_OID, filled_UID, filler_OID, filler_UID, exchanged, execution_time)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);").expect("Failed to exec insert ExecutedTrades.");
+
+            for trade in trades {
+                if let Err(e) = statement.execute(params![trade.symbol,
+                                                          trade.action,
+                                                          trade.price,
+                                                          trade.filled_oid,
+                                                          trade.filled_uid,
+                                                          trade.filler_oid,
+                                                          trade.filler_uid,
+                                                          trade.exchanged,
+                                                          trade.execution_time]) {
+                    eprintln!("{}", e);
+                    panic!("Failed to exec insert ExecutedTrades.");
+                }
+            }
+        }
+        transaction.commit().expect("Failed to commit buffered trade insert transaction.");
+    }
+}
+
+impl MarketStore for Connection {
+    fn populate_market_statistics(&mut self, exchange: &mut Exchange) {
+        let mut statement = self.prepare("SELECT symbol, total_buys, total_sells, filled_buys, filled_sells, latest_price FROM Markets;")
+            .expect("Something went wrong in the query.");
+        let markets = statement.query_map([], |row| {
+            let symbol: String = row.get(0)?;
+            // Price might be NULL if no trades occured.
+            Ok(SecStat::direct(&symbol, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+        }).expect("Something went wrong in the query.");
+
+        for market_stats in markets {
+            let market_stats = market_stats.expect("Something went wrong in the query.");
+            exchange.statistics.insert(market_stats.symbol.clone(), market_stats);
+        }
+    }
+
+    fn populate_exchange_statistics(&mut self, exchange: &mut Exchange) {
+        let total_orders: Option<i64> = self.query_row("SELECT total_orders FROM ExchangeStats WHERE key=1;", [], |row| row.get(0))
+            .optional()
+            .expect("Something went wrong in the query.")
+            .flatten();
+        exchange.total_orders = total_orders.unwrap_or(0);
+    }
+
+    fn read_market_exists(&mut self, market: &str) -> bool {
+        match self.query_row("SELECT symbol FROM Markets WHERE symbol=?1;", params![market], |row| row.get::<_, String>(0)).optional() {
+            Ok(symbol) => symbol.is_some(),
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("Something went wrong while querying the database for the market symbol.");
+            }
+        }
+    }
+
+    fn read_exchange_markets_simulations(&mut self, symbol_vec: &mut Vec<String>) {
+        let limit = symbol_vec.capacity();
+        let mut statement = self.prepare("SELECT symbol FROM Markets LIMIT ?1;").expect("Something went wrong in the query.");
+        let symbols = statement.query_map(params![limit as i64], |row| row.get::<_, String>(0)).expect("Something went wrong in the query.");
+        for symbol in symbols {
+            symbol_vec.push(symbol.expect("Something went wrong in the query."));
+        }
+    }
+
+    /* The markets are added to the file we're connected to, so the name is ignored. */
+    fn upgrade_db(&mut self, markets: &mut dyn BufRead, _db_name: &str) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        for line in markets.lines() {
+            match line {
+                Ok(line) => {
+                    let mut components = line.split(',');
+                    let action = components.next().unwrap();
+                    let symbol = components.next().unwrap();
+                    let company_name = components.next().unwrap();
+
+                    if action == "add" {
+                        let query_string = "INSERT OR IGNORE INTO Markets VALUES (?1, ?2, 0, 0, 0, 0, NULL);";
+                        if let Err(e) = transaction.execute(query_string, params![symbol, company_name]) {
+                            eprintln!("{:?}", e);
+                            panic!("Query to upgrade database failed!");
+                        }
+                    }
+                },
+                Err(e) => eprintln!("{}", e)
+            }
+        }
+        transaction.commit().expect("Failed to commit the database upgrade.");
+        println!("Upgrade complete!");
+    }
+
+    fn update_total_orders(&mut self, total_orders: i64) {
+        let query_string = "\
+INSERT INTO ExchangeStats
+VALUES (1, ?1)
+ON CONFLICT (key) DO
+UPDATE SET total_orders=?1;";
+        if let Err(e) = self.execute(query_string, params![total_orders]) {
+            eprintln!("{:?}", e);
+            panic!("Something went wrong with the exchange total orders update query!");
+        }
+    }
+
+    fn update_buffered_markets(&mut self, markets: &[SecStat]) {
+        let transaction = self.transaction().expect("Failed to initiate transaction!");
+        {
+            let mut statement = transaction.prepare("\
+UPDATE Markets
+SET total_buys=?1, total_sells=?2, filled_buys=?3, filled_sells=?4, latest_price=?5
+WHERE symbol=?6;").expect("Failed to update markets in the database!");
+
+            for market in markets {
+                statement.execute(params![market.total_buys,
+                                          market.total_sells,
+                                          market.filled_buys,
+                                          market.filled_sells,
+                                          market.last_price,
+                                          market.symbol]).expect("FAILED TO EXEC UPDATE MARKETS");
+            }
+        }
+        transaction.commit().expect("Failed to commit buffered market update transaction.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::database::{StoreBackend, CacheBackend, MemoryCache};
+    use crate::exchange::Request;
+    use crate::parser::{self, Response};
+    use crate::runtime::Runtime;
+
+    fn request(runtime: &mut Runtime, line: &str) -> Response {
+        runtime.service(parser::tokenize_input(line.to_string()).unwrap())
+    }
+
+    // Each run starts with an empty cache, so everything it knows was read from the file.
+    fn start(backend: &StoreBackend) -> Runtime {
+        Runtime::with_backends(backend.clone(), CacheBackend::Memory(MemoryCache::new()))
+    }
+
+    fn stop(mut runtime: Runtime) {
+        runtime.service(Request::ExitReq);
+        runtime.shutdown();
+    }
+
+    #[test]
+    fn the_exchange_outlives_a_restart() {
+        let path = env::temp_dir().join(format!["rustx-sqlite-test-{}.db", std::process::id()]);
+        let path = path.to_string_lossy().to_string();
+        for suffix in ["", "-wal", "-shm"].iter() {
+            fs::remove_file(format!["{}{}", path, suffix]).ok();
+        }
+        let backend = StoreBackend::Sqlite(path.clone());
+
+        let mut runtime = start(&backend);
+        runtime.store.upgrade_db(&mut "add,GME,GameStop\n".as_bytes(), "rustx");
+        runtime.store.populate_market_statistics(&mut runtime.exchange);
+        runtime.store.populate_has_trades(&mut runtime.exchange);
+        for line in ["account create admin password", "account create alice password", "account create bob password",
+                     "short enable bob 10 100000 admin password", "buy GME 10 100 clid=gme-1 alice password", "sell GME 4 100 bob password"].iter() {
+            assert!(request(&mut runtime, line).is_ok(), "{}", line);
+        }
+        stop(runtime);
+
+        let mut runtime = start(&backend);
+        // The rest of alice's order is back on the book, and the trade and the market's stats were kept.
+        assert_eq!(runtime.exchange.live_orders["GME"].depth(10), (vec![(100.0, 6)], vec![]));
+        let trades = runtime.store.read_trades("GME").unwrap_or_default();
+        assert_eq!(trades.iter().map(|trade| (trade.exchanged, trade.price)).collect::<Vec<(i32, f64)>>(), vec![(4, 100.0)]);
+        assert_eq!(runtime.exchange.statistics["GME"].last_price, Some(100.0));
+
+        // The accounts were kept, passwords and roles included, and so was the client order id.
+        assert!(request(&mut runtime, "account show alice password").is_ok());
+        assert!(!request(&mut runtime, "account show alice wrong").is_ok());
+        assert!(request(&mut runtime, "short calls admin password").is_ok());
+        let response = request(&mut runtime, "buy GME 10 100 clid=gme-1 alice password");
+        assert!(response.is_ok() && response.reports.is_empty(), "{}", response.message);
+        assert_eq!(response.order.map(|order| (order.quantity, order.filled)), Some((10, 4)));
+        stop(runtime);
+
+        for suffix in ["", "-wal", "-shm"].iter() {
+            fs::remove_file(format!["{}{}", path, suffix]).ok();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::io::BufRead;
+
+use postgres::{Client, NoTls};
+
+use crate::exchange::{Exchange, Order, SecStat, Trade, UserAccount, RateLimits};
+use crate::account::{AuthError, MarginAccount};
+use crate::buffer::DatabaseReadyOrder;
+use crate::database::memory::MemoryStore;
+use crate::database::sqlite;
+
+/* ---- Persistence traits ----
+ *
+ *  The exchange only talks to its database through these traits, so the matching engine
+ *  doesn't care where its data lives. The methods follow the db API spec in database.rs:
+ *      populate_* is called on startup ONLY, read_* and write_* during normal execution,
+ *      and the *_buffered_* methods are called by the database writer (see BufferCollection::start_writer).
+ *
+ *  Postgres (database.rs) is what we run in production, SQLite (database/sqlite.rs) keeps everything
+ *  in a local file, and MemoryStore keeps everything in the process, so the exchange can run
+ *  without any external services.
+ **/
+
+/* Why a write to the store failed. */
+#[derive(Debug)]
+pub enum StoreError {
+    Duplicate,          // A row with the same key already exists, ex. the username is taken
+    Backend(String)     // Anything else, with the database's error
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Duplicate => write!(f, "A row with the same key already exists."),
+            StoreError::Backend(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+// Accounts, their settings and roles.
+pub trait AccountStore {
+    /* Give accounts the rate limits they were given with `limits set`. */
+    fn populate_rate_limits(&mut self, exchange: &mut Exchange);
+    fn next_account_id(&mut self) -> i64;
+    fn read_account_exists(&mut self, username: &str) -> bool;
+    fn read_auth_user<'a>(&mut self, username: &'a str, password: &str) -> Result<UserAccount, AuthError<'a>>;
+    fn read_account(&mut self, username: &str) -> Option<UserAccount>;
+    fn read_user_by_id(&mut self, id: i64) -> Option<String>;
+    fn read_admin_exists(&mut self) -> bool;
+    fn write_insert_new_account(&mut self, account: &UserAccount) -> Result<(), StoreError>;
+    fn write_update_margin_account(&mut self, username: &str, margin: &MarginAccount) -> bool;
+    fn write_update_role(&mut self, username: &str, role: &str, grant: bool) -> Option<Vec<String>>;
+    fn write_update_status(&mut self, username: &str, status: &str) -> bool;
+    /* Give an account its own rate limits, or None to go back to the limits of its role. */
+    fn write_update_rate_limits(&mut self, username: &str, limits: Option<&RateLimits>);
+    fn write_update_username(&mut self, id: i64, username: &str);
+    fn write_update_password(&mut self, id: i64, password_hash: &str);
+    fn write_hash_plaintext_passwords(&mut self) -> usize;
+}
+
+// Orders, and which of them are still pending.
+pub trait OrderStore {
+    /* Reserve the next ORDER_ID_BLOCK order ids, returns the first (see database::ids). */
+    fn reserve_order_ids(&mut self) -> i64;
+    fn populate_exchange_markets(&mut self, exchange: &mut Exchange);
+    fn read_client_order(&mut self, user_id: i64, client_id: &str) -> Option<Order>;
+    fn read_match_pending_order(&mut self, user_id: i64, order_id: i64) -> Option<String>;
+    fn insert_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]);
+    fn update_buffered_orders(&mut self, orders: &[DatabaseReadyOrder]);
+    fn insert_buffered_pending(&mut self, pending: &[i64]);
+    fn delete_buffered_pending(&mut self, pending: &[i64]);
+}
+
+// Executed trades.
+pub trait TradeStore {
+    fn populate_has_trades(&mut self, exchange: &mut Exchange);
+    fn read_trades(&mut self, symbol: &str) -> Option<Vec<Trade>>;
+    fn insert_buffered_trades(&mut self, trades: &[Trade]);
+}
+
+// The markets we host, and the statistics of each market and the exchange.
+pub trait MarketStore {
+    fn populate_market_statistics(&mut self, exchange: &mut Exchange);
+    fn populate_exchange_statistics(&mut self, exchange: &mut Exchange);
+    fn read_market_exists(&mut self, market: &str) -> bool;
+    fn read_exchange_markets_simulations(&mut self, symbol_vec: &mut Vec<String>);
+    /* Add the markets listed in the file, see database::upgrade_db. */
+    fn upgrade_db(&mut self, markets: &mut dyn BufRead, db_name: &str);
+    fn update_total_orders(&mut self, total_orders: i64);
+    fn update_buffered_markets(&mut self, markets: &[SecStat]);
+}
+
+/* Everything the exchange stores. Send, so the database writer's worker threads can own one. */
+pub trait Store: AccountStore + OrderStore + TradeStore + MarketStore + Send {}
+
+impl<T: AccountStore + OrderStore + TradeStore + MarketStore + Send> Store for T {}
+
+/* Where the exchange keeps its data, chosen on startup.
+ * The database writer connects once per worker thread, so we hold on to how to connect.
+ **/
+#[derive(Clone)]
+pub enum StoreBackend {
+    Postgres(String),   // Connection string, ex. host=localhost user=postgres dbname=rustx
+    Sqlite(String),     // Path of the database file, created if it doesn't exist
+    Memory(MemoryStore) // Shared by every connection, gone when the exchange exits
+}
+
+impl StoreBackend {
+    pub fn connect(&self) -> Box<dyn Store> {
+        match self {
+            StoreBackend::Postgres(config) => {
+                let client = Client::connect(config.as_str(), NoTls)
+                    .expect("Failed to connect to Database. Please ensure it is up and running.");
+                Box::new(client)
+            },
+            StoreBackend::Sqlite(path) => Box::new(sqlite::connect(path)),
+            StoreBackend::Memory(store) => Box::new(store.clone())
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StoreBackend::Postgres(_) => "Postgres",
+            StoreBackend::Sqlite(_) => "SQLite",
+            StoreBackend::Memory(_) => "in-memory"
+        }
+    }
+}
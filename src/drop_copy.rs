@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::database::{Store, Cache};
+use crate::account::{Users, Credentials, Permission};
+use crate::exchange::Execution;
+use crate::parser;
+use crate::server::Incoming;
+
+// Hands out an id to each drop copy connection, so we know whose subscription to remove.
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(1);
+
+/* Sent to the main thread by the drop copy connections. */
+pub enum DropCopyCommand {
+    Subscribe {
+        connection: usize,
+        accounts: Option<HashSet<String>>,  // None means every account
+        from: Option<u64>,                  // Replay the executions from this sequence number first
+        credentials: Credentials,
+        updates: mpsc::Sender<String>
+    },
+    Disconnect(usize)
+}
+
+struct Subscriber {
+    accounts: Option<HashSet<String>>,
+    updates: mpsc::Sender<String>
+}
+
+/* A real time copy of every execution, for risk and back office systems.
+ *
+ * Every execution gets the next sequence number, and is journaled in the cache whether or not anyone
+ * is subscribed, so a subscriber that was disconnected can replay what it missed:
+ *      {"type": "execution", "seq": 42, "symbol": "GME", "price": 167.34, "quantity": 10, "time": "...",
+ *       "resting": {"account": "bigmoney", "order_id": 7, "side": "SELL"},
+ *       "aggressor": {"account": "smallmoney", "order_id": 9, "side": "BUY"}}
+ **/
+pub struct DropCopy {
+    seq: u64,                                   // The sequence number of the last execution journaled
+    subscribers: HashMap<usize, Subscriber>,
+    cache: Box<dyn Cache>                       // Every execution ever copied, oldest first. Its sequence number is its position + 1.
+}
+
+impl DropCopy {
+    pub fn new(mut cache: Box<dyn Cache>) -> Self {
+        let seq: u64 = cache.read_journal_len();
+        DropCopy {
+            seq,
+            subscribers: HashMap::new(),
+            cache
+        }
+    }
+
+    pub fn command(&mut self, command: DropCopyCommand, users: &mut Users, store: &mut dyn Store) {
+        match command {
+            DropCopyCommand::Subscribe { connection, accounts, from, credentials, updates } => {
+                if let Err(e) = users.authorize(&credentials, Permission::DropCopy, store) {
+                    updates.send(json!({ "type": "error", "message": e.to_string() }).to_string()).ok();
+                    return;
+                }
+                let mut names: Vec<&String> = accounts.iter().flatten().collect();
+                names.sort();
+                let subscribed = json!({ "type": "subscribed", "accounts": if accounts.is_some() { json!(names) } else { json!("*") }, "seq": self.seq });
+                if updates.send(subscribed.to_string()).is_err() {
+                    return;
+                }
+
+                // Requests are serviced one at a time, so nothing is missed between the replay and live updates.
+                if let Some(from) = from {
+                    let journaled: Vec<String> = match self.cache.read_journal(from) {
+                        Ok(journaled) => journaled,
+                        Err(e) => {
+                            updates.send(json!({ "type": "error", "message": format!["Replay failed: {}", e] }).to_string()).ok();
+                            return;
+                        }
+                    };
+                    for execution in journaled {
+                        let copied = serde_json::from_str::<Value>(&execution).map(|execution| involves(&accounts, &execution)).unwrap_or(false);
+                        if copied && updates.send(execution).is_err() {
+                            return;
+                        }
+                    }
+                }
+                self.subscribers.insert(connection, Subscriber { accounts, updates });
+            },
+            DropCopyCommand::Disconnect(connection) => {
+                self.subscribers.remove(&connection);
+            }
+        }
+    }
+
+    /* Journal the executions of the last request, and copy them to their subscribers.
+     * Called after every request, so the account cache's list doesn't grow.
+     **/
+    pub fn publish(&mut self, users: &mut Users) {
+        if users.executions.is_empty() {
+            return;
+        }
+        let executions: Vec<Execution> = users.executions.drain(..).collect();
+
+        let mut journal = Vec::with_capacity(executions.len());
+        let mut copies = Vec::with_capacity(executions.len());
+        for execution in executions.iter() {
+            self.seq += 1;
+            let trade = &execution.trade;
+            // The trade's action is the side of the resting order.
+            let aggressor_side = if trade.action == "BUY" { "SELL" } else { "BUY" };
+            let copy = json!({
+                "type": "execution",
+                "seq": self.seq,
+                "symbol": trade.symbol,
+                "price": trade.price,
+                "quantity": trade.exchanged,
+                "time": trade.execution_time.to_rfc3339(),
+                "resting": { "account": execution.filled_username, "order_id": trade.filled_oid, "side": trade.action },
+                "aggressor": { "account": execution.filler_username, "order_id": trade.filler_oid, "side": aggressor_side }
+            });
+            journal.push(copy.to_string());
+            copies.push(copy);
+        }
+        if let Err(e) = self.cache.write_journal(&journal) {
+            eprintln!("[DROP COPY]: Failed to journal executions {} to {}: {}", self.seq + 1 - executions.len() as u64, self.seq, e);
+        }
+
+        for copy in copies.iter() {
+            let message = copy.to_string();
+            for subscriber in self.subscribers.values() {
+                if involves(&subscriber.accounts, copy) {
+                    subscriber.updates.send(message.clone()).ok();
+                }
+            }
+        }
+    }
+}
+
+// True if either account of the execution is one of the subscriber's.
+fn involves(accounts: &Option<HashSet<String>>, execution: &Value) -> bool {
+    let accounts = match accounts {
+        Some(accounts) => accounts,
+        None => return true
+    };
+    ["resting", "aggressor"].iter().any(|side| {
+        match execution[side]["account"].as_str() {
+            Some(account) => accounts.contains(account),
+            None => false
+        }
+    })
+}
+
+/* Parse a subscription, ex.
+ *      subscribe bigMoney,smallMoney from=120 operator password
+ *      subscribe * sess_0123456789abcdef0123456789abcdef
+ **/
+fn parse_subscription(line: &str, connection: usize, updates: mpsc::Sender<String>) -> Result<DropCopyCommand, String> {
+    let words: Vec<String> = line.split_whitespace().map(|word| word.to_lowercase()).collect();
+    if words.len() < 3 || words[0] != "subscribe" {
+        return Err("Send subscribe ACCOUNT[,ACCOUNT...] or *, then optionally from=SEQ, then your credentials.".to_string());
+    }
+    let accounts = match words[1].as_str() {
+        "*" => None,
+        accounts => Some(accounts.split(',').filter(|account| !account.is_empty()).map(|account| account.to_string()).collect())
+    };
+    let (from, credentials_start) = match words[2].strip_prefix("from=") {
+        Some(seq) => match seq.parse::<u64>() {
+            Ok(seq) => (Some(seq), 3),
+            Err(_) => return Err("from= must be a sequence number.".to_string())
+        },
+        None => (None, 2)
+    };
+    let credentials = match parser::parse_credentials(&words[credentials_start..]) {
+        Some(credentials) => credentials,
+        None => return Err("Please end the subscription with a username and password, or a session token.".to_string())
+    };
+    Ok(DropCopyCommand::Subscribe { connection, accounts, from, credentials, updates })
+}
+
+/* Accept drop copy connections on the given address. A client sends one subscription line,
+ * then receives one JSON object per line, as described on DropCopy.
+ *
+ * Returns the handle of the accepting thread, or an error if we can't listen on the address.
+ **/
+pub fn listen(address: &str, requests: mpsc::Sender<Incoming>) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(address)?;
+    dark_green!("Sending drop copies on {}\n", listener.local_addr()?);
+
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let requests = requests.clone();
+                    thread::spawn(move || {
+                        let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+                        if let Err(e) = handle_subscriber(stream, connection, &requests) {
+                            eprintln!("Drop copy connection closed: {}", e);
+                        }
+                        requests.send(Incoming::DropCopy(DropCopyCommand::Disconnect(connection))).ok();
+                    });
+                },
+                Err(e) => eprintln!("Failed to accept a connection: {}", e)
+            }
+        }
+    });
+    Ok(handle)
+}
+
+/* Reads the client's subscription, then writes its executions until either side goes away. */
+fn handle_subscriber(stream: TcpStream, connection: usize, requests: &mpsc::Sender<Incoming>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let (update_tx, update_rx) = mpsc::channel::<String>();
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(());
+    }
+    match parse_subscription(&line, connection, update_tx) {
+        Ok(command) => {
+            if requests.send(Incoming::DropCopy(command)).is_err() {
+                return Ok(());
+            }
+        },
+        Err(e) => {
+            writer.write_all(format!["{}\n", json!({ "type": "error", "message": e })].as_bytes())?;
+            return Ok(());
+        }
+    }
+
+    // The exchange drops our sender if it rejected the subscription, or is shutting down.
+    for update in update_rx.iter() {
+        writer.write_all(format!["{}\n", update].as_bytes())?;
+    }
+    Ok(())
+}
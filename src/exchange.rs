@@ -1,26 +1,41 @@
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::cmp::Reverse;
 
 pub mod requests;
-pub use crate::exchange::requests::{Order, InfoRequest, CancelOrder, Request, Simulation, OrderStatus};
+pub use crate::exchange::requests::{Order, InfoRequest, CancelOrder, AmendOrder, CancelAll, Request, Simulation, OrderStatus, MarginRequest, RoleRequest, LimitRequest, MarketRequest, AccountRequest};
 
 pub mod filled;
-pub use crate::exchange::filled::Trade;
+pub use crate::exchange::filled::{Trade, TradeSides, Execution};
 
 pub mod stats;
 pub use crate::exchange::stats::SecStat;
 
 pub mod market;
-pub use crate::exchange::market::Market;
+pub use crate::exchange::market::{Market, PriceLevel};
 
-pub use crate::account::{UserAccount, Users};
+pub mod events;
+pub use crate::exchange::events::BookEvent;
 
-pub use crate::database;
+pub mod reports;
+pub use crate::exchange::reports::{ExecutionReport, ExecType};
+
+pub mod errors;
+pub use crate::exchange::errors::{ErrorCode, OrderError};
+
+pub mod throttle;
+pub use crate::exchange::throttle::{RateLimiter, RateLimits, Throttled};
+
+pub use crate::account::{UserAccount, Users, MarginCall, MarginCallAction, MarginPolicy, MarginAccount};
+
+pub use crate::database::{self, IdAllocator, Store, Cache};
 
 pub use crate::buffer::BufferCollection;
 
-use postgres::Client;
-use redis::{Commands, RedisError};
+use crate::config::Config;
+
+// Simulations publish their trades, book events and executions every this many orders.
+pub const SIM_PUBLISH_INTERVAL: u32 = 1000;
+
 
 use std::time::Instant;
 
@@ -36,12 +51,21 @@ pub struct Exchange {
     pub live_orders: HashMap<String, Market>,    // Orders on the market
     pub has_trades: HashMap<String, bool>,
     pub statistics: HashMap<String, SecStat>,    // The general statistics of each symbol
-    pub total_orders: i32
+    pub total_orders: i64,
+    pub order_ids: IdAllocator,                  // Hands out order ids from the database
+    pub margin_policy: MarginPolicy,             // Initial/maintenance margin rules for short positions
+    pub margin_checks: HashSet<String>,          // Markets whose price changed since the margin monitor last ran
+    pub margin_calls: HashMap<String, MarginCall>, // Flagged accounts (by username) that are below maintenance margin
+    pub rate_limiter: RateLimiter,               // Per account order/cancel rates and open order limits
+    pub trade_prints: Vec<Trade>,                // Trades since the market data feed last published
+    pub book_changes: HashSet<String>,           // Markets whose book changed since the market data feed last published
+    pub book_events: Vec<BookEvent>,             // Adds, executions and cancels since the binary feed last published
+    pub halted: HashSet<String>                  // Markets that aren't accepting orders, until they're resumed
 }
 
 impl Exchange {
-    // Create a new exchange on startup
-    pub fn new() -> Self {
+    // Create a new exchange on startup, with the rate limits and margin policy of the config
+    pub fn new(config: &Config) -> Self {
         let live_orders: HashMap<String, Market> = HashMap::new();
         let has_trades: HashMap<String, bool> = HashMap::new();
         let statistics: HashMap<String, SecStat> = HashMap::new();
@@ -49,7 +73,16 @@ impl Exchange {
             live_orders,
             has_trades,
             statistics,
-            total_orders: 0
+            total_orders: 0,
+            order_ids: IdAllocator::orders(),
+            margin_policy: config.margin_policy.clone(),
+            margin_checks: HashSet::new(),
+            margin_calls: HashMap::new(),
+            rate_limiter: RateLimiter::new(config.rate_limits, config.market_maker_limits),
+            trade_prints: Vec::new(),
+            book_changes: HashSet::new(),
+            book_events: Vec::new(),
+            halted: HashSet::new()
         }
     }
 
@@ -58,7 +91,7 @@ impl Exchange {
      *
      * Returns Some(price) if trade occured, or None.
      */
-    fn update_state(&mut self, order: &Order, users: &mut Users, buffers: &mut BufferCollection, exchange_event: Option<(Vec<Order>, Vec<Trade>)>, conn: &mut Client) -> Option<f64> {
+    fn update_state(&mut self, order: &Order, users: &mut Users, buffers: &mut BufferCollection, exchange_event: Option<(Vec<Order>, Vec<Trade>)>, store: &mut dyn Store) -> Option<f64> {
 
         let stats: &mut SecStat = self.statistics.get_mut(&order.symbol).unwrap();
         stats.modified = true;
@@ -76,12 +109,20 @@ impl Exchange {
 
         let mut new_price = None;
 
+        // The order either rested, filled resting orders, or both.
+        self.book_changes.insert(order.symbol.clone());
+
         // Update the price and filled orders if a trade occurred.
         if let Some((mut modified_orders, mut trades)) = exchange_event {
             let price = trades[trades.len() - 1].price;
             new_price = Some(price);
             // Updates in-mem data
             stats.update_market_stats(price, &trades);
+            // Short positions in this market need their margin re-checked.
+            self.margin_checks.insert(order.symbol.clone());
+            // Print the trades to the market data feed.
+            self.trade_prints.extend(trades.iter().cloned());
+            self.book_events.extend(trades.iter().cloned().map(BookEvent::Executed));
 
             /* TODO: Updating accounts seems like something that
              *       shouldn't slow down order execution.
@@ -94,12 +135,12 @@ impl Exchange {
              * in the mean time?)
              */
             // Updates database too.
-            users.update_account_orders(&mut modified_orders, &mut trades, buffers, conn);
+            users.update_account_orders(&mut modified_orders, &mut trades, buffers, store);
             self.has_trades.insert(order.symbol.clone(), true);
         };
 
         self.total_orders += 1;
-        return new_price;
+        new_price
     }
 
     /* Returns the price of the given symbol, or one of two errors.
@@ -107,7 +148,7 @@ impl Exchange {
      *  - No market found: No orders have been placed
      *  - No trades executed: Orders may have been placed, but no trade = no price.
      */
-    pub fn get_price(&self, symbol: &String) -> Result<f64, PriceError> {
+    pub fn get_price(&self, symbol: &str) -> Result<f64, PriceError> {
         // Get the market
         let stats = match self.statistics.get(symbol) {
             Some(stat) => stat,
@@ -128,41 +169,32 @@ impl Exchange {
     /* Find all pending orders associated with the user, and store them in their account.
      *
      * We check only the relevant subset of markets for the given user.
-     *  The markets this user has pending orders in are stored in active_markets:uid in a Redis sorted set.
+     *  The markets this user has pending orders in are stored in active_markets:uid in the cache, a sorted set.
      *  The score associated with each market is the number of pending orders currently in the market, once
      *  a markets score is 0, it's removed from the set. Think Rust Reference Counters.
      **/
-    pub fn fetch_account_pending_orders(&self, user: &mut UserAccount, redis_conn: &mut redis::Connection) {
+    pub fn fetch_account_pending_orders(&self, user: &mut UserAccount, cache: &mut dyn Cache) {
         if user.pending_orders.is_complete {
             panic!("Hey coder genius. You're calling fetch_account_pending_orders on an account that is up-to-date.");
         }
 
-        let response: Result<Vec<String>, RedisError>= redis_conn.zrange(format!["active_markets:{}", user.id.unwrap()], 0, -1);
-        match response {
-            Ok(markets) => {
-                for symbol in &markets {
-                    let market = self.live_orders.get(symbol).unwrap();
+        for symbol in cache.read_active_markets(user.id.unwrap()).iter() {
+            let market = self.live_orders.get(symbol).unwrap();
 
-                    // Check all the buy orders of this market
-                    for buy in market.buy_orders.iter() {
-                        if buy.user_id == user.id {
-                            user.pending_orders.insert_order(buy.clone());
-                        }
-                    }
+            // Check all the buy orders of this market
+            for buy in market.buy_orders.iter() {
+                if buy.user_id == user.id {
+                    user.pending_orders.insert_order(buy.clone());
+                }
+            }
 
-                    // Check all the sell orders of this market.
-                    for sell_container in market.sell_orders.iter() {
-                        let sell = &sell_container.0;
+            // Check all the sell orders of this market.
+            for sell_container in market.sell_orders.iter() {
+                let sell = &sell_container.0;
 
-                        if sell.user_id == user.id {
-                            user.pending_orders.insert_order(sell.clone());
-                        }
-                    }
+                if sell.user_id == user.id {
+                    user.pending_orders.insert_order(sell.clone());
                 }
-            },
-            Err(e) => {
-                eprintln!("{}", e);
-                panic!("Fix the error in fetch_Account_pending");
             }
         }
         // updates some account state data
@@ -170,22 +202,20 @@ impl Exchange {
 
     }
 
-    // Print a market
-    pub fn show_market(&self, symbol: &String) {
+    // Show a market's best orders and its statistics.
+    pub fn show_market(&self, symbol: &str) -> String {
         let market = match self.live_orders.get(symbol) {
             Some(market) => market,
-            None => {
-                println!("${} has no pending orders!", symbol);
-                return;
-            }
+            None => return format!["${} has no pending orders!", symbol]
         };
         let num_orders_to_view = 10;
+        let mut lines: Vec<String> = Vec::new();
 
-        println!("\nMarket: ${}", symbol);
+        lines.push(format!["\nMarket: ${}", symbol]);
 
-        println!("\t--SELLS--");
-        println!("\t\t| ID | Price \t| Quantity | Filled |");
-        println!("\t\t-------------------------------------");
+        lines.push("\t--SELLS--".to_string());
+        lines.push("\t\t| ID | Price \t| Quantity | Filled |".to_string());
+        lines.push("\t\t-------------------------------------".to_string());
 
         let sells = market.sell_orders.clone().into_sorted_vec();
         let start = std::cmp::min(sells.len(), num_orders_to_view);
@@ -193,79 +223,118 @@ impl Exchange {
 
         for result in lowest_sells.iter() {
             let order = &result.0;
-            println!("\t\t| {}\t${:.2}\t     {}\t  \t{}   |", order.order_id, order.price, order.quantity, order.filled);
+            lines.push(format!["\t\t| {}\t${:.2}\t     {}\t  \t{}   |", order.order_id, order.price, order.quantity, order.filled]);
         }
-        println!("\t\t-------------------------------------\n");
+        lines.push("\t\t-------------------------------------\n".to_string());
 
-        println!("\t--BUYS--");
-        println!("\t\t| ID | Price \t| Quantity | Filled |");
-        println!("\t\t-------------------------------------");
+        lines.push("\t--BUYS--".to_string());
+        lines.push("\t\t| ID | Price \t| Quantity | Filled |".to_string());
+        lines.push("\t\t-------------------------------------".to_string());
         let buys = market.buy_orders.clone().into_sorted_vec();
         let mut order_count = 0;
         for order in buys.iter().rev() {
             order_count += 1;
-            println!("\t\t| {}\t${:.2}\t     {}\t  \t{}   |", order.order_id, order.price, order.quantity, order.filled);
+            lines.push(format!["\t\t| {}\t${:.2}\t     {}\t  \t{}   |", order.order_id, order.price, order.quantity, order.filled]);
             if order_count == num_orders_to_view {
                 break
             }
         }
-        println!("\t\t-------------------------------------\n");
+        lines.push("\t\t-------------------------------------\n".to_string());
 
 
         let market = self.statistics.get(symbol).expect("NO VALUE");
-        println!("STATS");
-        println!("\t{:?}", market);
-
+        lines.push("STATS".to_string());
+        lines.push(format!["\t{:?}", market]);
+        lines.join("\n")
     }
 
     // TODO: Once we store time, lets include timeframes?
     //       Might be good for graphing price.
     // Shows the history of orders in this market.
-    pub fn show_market_history(&self, symbol: &String, conn: &mut Client) {
-        if let Some(trades) = database::read_trades(symbol, conn) {
-            println!("\nMarket History: ${}", symbol);
-            println!("\t\t| Filled by Order | Order | Shares Exchanged | Price |");
-            println!("\t\t------------------------------------------------------");
-            for past_order in trades {
-                println!("\t\t|\t{}\t\t{}\t     {}\t  \t${:.2}   |", past_order.filler_oid, past_order.filled_oid, past_order.exchanged, past_order.price);
-            }
-            println!("\t\t------------------------------------------------------\n");
-        } else {
-            eprintln!("The security that was requested either doesn't exist or has no past trades.");
+    // Returns None if the security doesn't exist or has no past trades.
+    pub fn show_market_history(&self, symbol: &str, store: &mut dyn Store) -> Option<String> {
+        let trades = store.read_trades(symbol)?;
+        let mut lines: Vec<String> = Vec::new();
+        lines.push(format!["\nMarket History: ${}", symbol]);
+        lines.push("\t\t| Filled by Order | Order | Shares Exchanged | Price |".to_string());
+        lines.push("\t\t------------------------------------------------------".to_string());
+        for past_order in trades {
+            lines.push(format!["\t\t|\t{}\t\t{}\t     {}\t  \t${:.2}   |", past_order.filler_oid, past_order.filled_oid, past_order.exchanged, past_order.price]);
         }
+        lines.push("\t\t------------------------------------------------------\n".to_string());
+        Some(lines.join("\n"))
+    }
+
+    /* Start trading a market added while we're running, ex. by upgrade_db.
+     * Returns false if we already had it.
+     **/
+    pub fn open_market(&mut self, stats: SecStat) -> bool {
+        if self.statistics.contains_key(&stats.symbol) {
+            return false;
+        }
+        self.book_events.push(BookEvent::State { symbol: stats.symbol.clone(), halted: false });
+        self.statistics.insert(stats.symbol.clone(), stats);
+        true
+    }
+
+    /* Halt or resume trading in a market. Returns false if it was already in that state. */
+    pub fn set_halted(&mut self, symbol: &str, halted: bool) -> bool {
+        let changed = if halted { self.halted.insert(symbol.to_string()) } else { self.halted.remove(symbol) };
+        if changed {
+            self.book_events.push(BookEvent::State { symbol: symbol.to_string(), halted });
+        }
+        changed
     }
 
     /* Add an order to the market's order list,
      * and may fill pending orders whose conditions are satisfied.
      * Assumes user has already been authenticated.
      *
-     * Returns the new price if trade occurred, otherwise, None or errors.
+     * Returns the order as it stands after matching (its id, how much was filled and its status),
+     * and its execution reports: an ack, then one per fill. Otherwise errors.
     */
-    pub fn submit_order_to_market(&mut self, users: &mut Users, buffers: &mut BufferCollection, order: Order, username: &String, auth: bool, conn: &mut Client) -> Result<Option<f64>, String> {
+    pub fn submit_order_to_market(&mut self, users: &mut Users, buffers: &mut BufferCollection, order: Order, username: &str, auth: bool, store: &mut dyn Store) -> Result<(Order, Vec<ExecutionReport>), OrderError> {
+        if self.halted.contains(&order.symbol) {
+            return Err(OrderError::MarketHalted(order.symbol));
+        }
+
+        // We need to know what the account holds to tell if a sell is a short sale.
+        if auth && order.action.as_str() == "SELL" {
+            users.load_positions(username);
+        }
 
         // Mutable reference to the account associated with given username.
         let account = match users.get_mut(username, auth) {
             Ok(acc) => acc,
-            Err(e) => {
-                Users::print_auth_error(e);
-                return Err("".to_string());
-            }
+            Err(e) => return Err(OrderError::Unauthorized(e.to_string()))
         };
 
+        account.validate_short_sale(&order, None, &self.statistics, &self.margin_policy)?;
+
         let mut order: Order = order;
-        let mut new_price = None; // new price if trade occurs
 
         // PER-6 account is being modified so set modified to true.
         account.modified = true;
 
         // Set the order_id for the order.
-        order.order_id = self.total_orders + 1;
+        order.order_id = self.order_ids.next(store);
+
+        // Remember the client order id, so a resubmission finds this order.
+        if let Some(client_id) = &order.client_id {
+            account.client_orders.insert(client_id.clone(), order.clone());
+        }
+
+        // The (shares, price) of each trade the new order makes, for its execution reports.
+        let mut fills: Vec<(i32, f64)> = Vec::new();
 
         // Try to access the security in the HashMap
         match self.live_orders.get_mut(&order.symbol) {
             Some(market) => {
                 // Try to fill the new order with existing orders on the market.
                 let exchange_event = market.fill_existing_orders(&mut order);
+                if let Some((_, trades)) = &exchange_event {
+                    fills = trades.iter().map(|trade| (trade.exchanged, trade.price)).collect();
+                }
 
                 // Add the new order to the buy/sell heap if it wasn't completely filled,
                 // as well as the users account.
@@ -293,12 +362,15 @@ impl Exchange {
                 buffers.buffered_orders.add_unknown_to_order_buffer(&order);
 
                 // Update the state of the exchange.
-                new_price = self.update_state(&order, users, buffers, exchange_event, conn);
+                self.update_state(&order, users, buffers, exchange_event, store);
+                if order.quantity != order.filled {
+                    self.book_events.push(BookEvent::Added(order.clone()));
+                }
             },
             // The market doesn't exist, create it if found in DB,
             // otherwise the user entered a market that DNE.
             None => {
-                if database::read_market_exists(&order.symbol, conn) {
+                if store.read_market_exists(&order.symbol) {
                     // buy is a max heap, sell is a min heap.
                     let mut buy_heap: BinaryHeap<Order> = BinaryHeap::new();
                     let mut sell_heap: BinaryHeap<Reverse<Order>> = BinaryHeap::new();
@@ -330,17 +402,20 @@ impl Exchange {
                     buffers.buffered_orders.add_unknown_to_order_buffer(&order);
 
                     // Since this is the first order, initialize the stats for this security.
-                    new_price = self.update_state(&order, users, buffers, None, conn);
+                    self.update_state(&order, users, buffers, None, store);
+                    self.book_events.push(BookEvent::Added(order.clone()));
                 } else {
-                    return Err(format!["The market ${} was not found in the database. User error!", order.symbol]);
+                    return Err(OrderError::UnknownSymbol(order.symbol));
                 }
             }
         }
 
-        return Ok(new_price);
+        let reports = ExecutionReport::for_new_order(&order, &fills);
+        Ok((order, reports))
     }
 
     /* Cancel the order in the given market with the given order ID.
+     * Returns the execution report of the cancellation.
      *
      * The user has been authenticated by this point, however we still
      * need to ensure that the order being cancelled was placed by them.
@@ -349,19 +424,22 @@ impl Exchange {
      *       whatever *remains* of an order, i.e any fulfilled portion
      *       cannot be cancelled.
      * */
-    pub fn cancel_order(&mut self, order_to_cancel: &CancelOrder, users: &mut Users, buffers: &mut BufferCollection, conn: &mut Client, redis_conn: &mut redis::Connection) -> Result<(), String>{
+    pub fn cancel_order(&mut self, order_to_cancel: &CancelOrder, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) -> Result<ExecutionReport, OrderError>{
         if let Ok(account) = users.get_mut(&(order_to_cancel.username), true) {
 
             // If we don't have the full picture of this users pending orders,
             // get it. This is so we can ensure they don't fill their own order,
             // and accurately represent their account state.
             if !account.pending_orders.is_complete {
-                self.fetch_account_pending_orders(account, redis_conn);
+                self.fetch_account_pending_orders(account, cache);
             }
 
             // 1. Ensure the order belongs to the user
-            if let Some(action) = account.user_placed_pending_order(&order_to_cancel.symbol, order_to_cancel.order_id, conn) {
+            if let Some(action) = account.user_placed_pending_order(&order_to_cancel.symbol, order_to_cancel.order_id, store) {
                 if let Some(market) = self.live_orders.get_mut(&(order_to_cancel.symbol)) {
+                    // The order as it rested on the book, so we can report how much of it had filled.
+                    let mut cancelled: Option<Order> = None;
+
                     // 2. Remove order from the market
                     match &action[..] {
                         "BUY" => {
@@ -369,8 +447,13 @@ impl Exchange {
                             // then move it back to the buy heap.
                             let new_size = market.buy_orders.len() - 1;
                             let mut temp = BinaryHeap::with_capacity(new_size);
-                            for order in market.buy_orders.drain().filter(|order| order.order_id != order_to_cancel.order_id) {
-                                temp.push(order); // Worst case is < O(n) since we preallocate
+                            for order in market.buy_orders.drain() {
+                                if order.order_id == order_to_cancel.order_id {
+                                    self.book_events.push(BookEvent::cancelled(&order));
+                                    cancelled = Some(order);
+                                } else {
+                                    temp.push(order); // Worst case is < O(n) since we preallocate
+                                }
                             }
                             market.buy_orders.append(&mut temp);
                         },
@@ -379,13 +462,19 @@ impl Exchange {
                             // then move it back to the sell heap.
                             let new_size = market.sell_orders.len() - 1;
                             let mut temp = BinaryHeap::with_capacity(new_size);
-                            for order in market.sell_orders.drain().filter(|order| order.0.order_id != order_to_cancel.order_id) {
-                                temp.push(order); // Worst case is < O(n) since we preallocate
+                            for order in market.sell_orders.drain() {
+                                if order.0.order_id == order_to_cancel.order_id {
+                                    self.book_events.push(BookEvent::cancelled(&order.0));
+                                    cancelled = Some(order.0);
+                                } else {
+                                    temp.push(order); // Worst case is < O(n) since we preallocate
+                                }
                             }
                             market.sell_orders.append(&mut temp);
                         },
                         _ => () // no other possibilities
                     }
+                    self.book_changes.insert(order_to_cancel.symbol.clone());
 
                     // 3. Remove order from users account
                     if let Ok(account) = users.get_mut(&(order_to_cancel.username), true) {
@@ -401,14 +490,13 @@ impl Exchange {
 
                     // TODO: Do we want to update market stats? total_cancelled maybe?
                     //       If we do, we have to also set stats.modified = true
-                    let mut to_remove = Vec::new();
-                    to_remove.push(order_to_cancel.order_id);
 
                     // Add this cancellation to the database buffer.
                     let order = Order::from_cancelled(order_to_cancel.order_id);
                     buffers.buffered_orders.add_or_update_entry_in_order_buffer(&order, false); // PER-5 update
 
-                    return Ok(());
+                    let cancelled = cancelled.unwrap_or(Order { symbol: order_to_cancel.symbol.clone(), action, ..order });
+                    return Ok(ExecutionReport::cancelled(&cancelled));
 
                 } else {
                     panic!("The market that we want to cancel an order from doesn't exist.\
@@ -416,7 +504,16 @@ impl Exchange {
                     );
                 }
             } else {
-                return Err("The order requested to be cancelled was not found in the associated user's pending orders!".to_string());
+                // Tell the user whether someone else's order has this id, or no order does.
+                let resting = match self.live_orders.get(&order_to_cancel.symbol) {
+                    Some(market) => market.buy_orders.iter().any(|order| order.order_id == order_to_cancel.order_id)
+                                 || market.sell_orders.iter().any(|order| order.0.order_id == order_to_cancel.order_id),
+                    None => false
+                };
+                if resting {
+                    return Err(OrderError::NotOwner(order_to_cancel.order_id));
+                }
+                return Err(OrderError::UnknownOrder(order_to_cancel.order_id));
             }
         }
         panic!("Could not find the user while cancelling an order.\
@@ -424,12 +521,170 @@ impl Exchange {
         );
     }
 
+    /* Cancel every resting order of an account, ex. when it is suspended or closed.
+     * Returns the number of orders cancelled.
+     **/
+    pub fn cancel_account_orders(&mut self, username: &str, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) -> usize {
+        let everything = CancelAll { symbol: None, action: None, username: None };
+        self.cancel_matching_orders(username, &everything, users, buffers, store, cache)
+    }
+
+    /* Cancel the resting orders of an account, optionally only in the market (symbol)
+     * or on the side (BUY or SELL) of the filter. Returns the number of orders cancelled.
+     * The account is the one given, rather than the filter's username.
+     *
+     * Unlike cancelling orders one by one, each market's heaps are rebuilt once,
+     * the cancellations go to the order buffer as one batch,
+     * and the account's active_markets are updated in Redis with one request.
+     **/
+    pub fn cancel_matching_orders(&mut self, username: &str, filter: &CancelAll, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) -> usize {
+        let (symbol, action) = (filter.symbol.as_deref(), filter.action.as_deref());
+        let account = users.get_mut_unauthenticated(username, store);
+        if !account.pending_orders.is_complete {
+            self.fetch_account_pending_orders(account, cache);
+        }
+
+        // symbol -> (buy ids, sell ids) of the orders to cancel.
+        let mut to_cancel: HashMap<String, (HashSet<i64>, HashSet<i64>)> = HashMap::new();
+        for (market_symbol, market) in account.pending_orders.pending.iter() {
+            if symbol.is_some() && symbol != Some(market_symbol.as_str()) {
+                continue;
+            }
+            for order in market.values() {
+                if action.is_some() && action != Some(order.action.as_str()) {
+                    continue;
+                }
+                let (buys, sells) = to_cancel.entry(market_symbol.clone()).or_insert_with(|| (HashSet::new(), HashSet::new()));
+                match &order.action[..] {
+                    "BUY" => buys.insert(order.order_id),
+                    _ => sells.insert(order.order_id)
+                };
+            }
+        }
+
+        let mut cancelled = Vec::new();
+        for (market_symbol, (buys, sells)) in to_cancel.iter() {
+            if let Some(market) = self.live_orders.get_mut(market_symbol) {
+                let removed = market.remove_orders(buys, sells);
+                self.book_events.extend(removed.iter().map(BookEvent::cancelled));
+                self.book_changes.insert(market_symbol.clone());
+            }
+            for order_id in buys.iter().chain(sells.iter()) {
+                account.remove_order_from_account(market_symbol, *order_id);
+                cancelled.push(*order_id);
+            }
+            let market_diff = account.recent_markets.entry(market_symbol.clone()).or_insert(0);
+            *market_diff -= (buys.len() + sells.len()) as i32;
+        }
+
+        if !cancelled.is_empty() {
+            account.modified = true;
+            account.sync_active_markets(cache);
+            buffers.buffered_orders.add_cancelled_orders(&cancelled);
+        }
+        cancelled.len()
+    }
+
+    /* Check the maintenance margin of every account that is short in a market
+     * whose price has changed since we last checked.
+     *
+     * Depending on the margin policy, accounts that fall below the requirement are
+     * either flagged (see margin_calls), or have their short position bought back.
+     * Buying back a short can move the price again, so we loop until nothing is left to check.
+     **/
+    pub fn monitor_margin(&mut self, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) {
+        while !self.margin_checks.is_empty() {
+            let symbols: Vec<String> = self.margin_checks.drain().collect();
+            for symbol in symbols.iter() {
+                for username in users.accounts_short_in(symbol) {
+                    match users.check_maintenance_margin(&username, symbol, &self.statistics, &self.margin_policy, store) {
+                        Some(call) => {
+                            eprintln!("MARGIN CALL: {} has ${:.2} of equity, but needs ${:.2} to cover ${:.2} of short positions.",
+                                      call.username, call.equity, call.requirement, call.short_value);
+                            match self.margin_policy.action {
+                                MarginCallAction::Flag => {
+                                    self.margin_calls.insert(username.clone(), call);
+                                },
+                                MarginCallAction::Liquidate => {
+                                    self.liquidate_short(&call, users, buffers, store, cache);
+                                }
+                            }
+                        },
+                        None => {
+                            self.margin_calls.remove(&username);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /* Buy back an account's short position in the market that triggered the margin call.
+     *
+     * We first cancel the account's pending sells in the market (they would only increase the short,
+     * and could be filled by our own buy), then send a buy priced to take enough of the lowest offers
+     * to cover the position. Any shares we're already trying to buy back count towards the cover.
+     **/
+    fn liquidate_short(&mut self, call: &MarginCall, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) {
+        let account = users.get_mut(&call.username, true).ok().unwrap();
+        if !account.pending_orders.is_complete {
+            self.fetch_account_pending_orders(account, cache);
+        }
+
+        let pending_sells: Vec<i64> = match account.pending_orders.view_market(&call.symbol) {
+            Some(market) => market.values().filter(|order| order.action.as_str() == "SELL").map(|order| order.order_id).collect(),
+            None => Vec::new()
+        };
+        let to_cover = -account.position_in(&call.symbol) - account.pending_quantity(&call.symbol, "BUY");
+        let user_id = account.id;
+
+        for order_id in pending_sells {
+            let cancel = CancelOrder {
+                symbol: call.symbol.clone(),
+                order_id,
+                username: call.username.clone(),
+                client_id: None
+            };
+            if let Err(e) = self.cancel_order(&cancel, users, buffers, store, cache) {
+                eprintln!("{}", e);
+            }
+        }
+
+        if to_cover <= 0 {
+            return;
+        }
+
+        // Walk up the offers until there are enough shares to cover the short.
+        let mut price = match self.statistics.get(&call.symbol).and_then(|stats| stats.last_price) {
+            Some(price) => price,
+            None => return
+        };
+        if let Some(market) = self.live_orders.get(&call.symbol) {
+            let mut offered = 0;
+            for offer in market.sell_orders.clone().into_sorted_vec().iter().rev() {
+                price = offer.0.price;
+                offered += offer.0.quantity - offer.0.filled;
+                if to_cover <= offered {
+                    break;
+                }
+            }
+        }
+
+        println!("Liquidating: buying {} share(s) of ${} at ${:.2} for {}.", to_cover, call.symbol, price, call.username);
+        let order = Order::from("BUY".to_string(), call.symbol.clone(), to_cover, price, OrderStatus::PENDING, user_id);
+        if let Err(e) = self.submit_order_to_market(users, buffers, order, &call.username, true, store) {
+            eprintln!("{}", e);
+        }
+    }
+
     /* Simulate trades, currently just for bandwidth testing.
+     * Every SIM_PUBLISH_INTERVAL orders we call `publish`, which must publish and clear
+     * the trade prints, book events and executions, so they don't pile up for the whole simulation.
      * TODO:
      *      - Maybe simulate individual markets? (This was old behaviour)
      *          - Could be interesting if we want to try some arbitrage algos later?
      **/
-    pub fn simulate_market(&mut self, sim: &Simulation, users: &mut Users, buffers: &mut BufferCollection, conn: &mut Client, redis_conn: &mut redis::Connection) {
+    pub fn simulate_market(&mut self, sim: &Simulation, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache, publish: &mut dyn FnMut(&mut Exchange, &mut Users)) {
 
         // let mut test_client = Client::connect("host=localhost user=postgres dbname=test_db", NoTls).expect("Failed to access test db");
 
@@ -451,7 +706,7 @@ impl Exchange {
         let mut prices: Vec<f64> = Vec::with_capacity(sim.market_count as usize);
 
         // Fill markets
-        database::read_exchange_markets_simulations(&mut markets, conn);
+        store.read_exchange_markets_simulations(&mut markets);
         if markets.len() != (sim.market_count as usize) {
             panic!("{} markets is not {} markets!", markets.len(), sim.market_count);
         }
@@ -464,14 +719,22 @@ impl Exchange {
         let mut action: &String;
         let mut username: &String;
 
+        // Simulated traders sell without holding shares, so they need to be allowed to go short.
+        let sim_margin = MarginAccount::direct(true, 1_000_000, 1_000_000_000.0);
         for name in usernames.iter() {
-            users.new_account(UserAccount::from(name, &"password".to_string()), conn);
+            users.new_account(UserAccount::from(name, "password"), store);
+            users.set_margin_account(name, sim_margin.clone(), store);
         }
 
+        let mut throttled = 0;
         let start = Instant::now();
         println!("Starting sim timer!");
         // Simulation loop
-        for _time_step in 0..sim.duration {
+        for time_step in 0..sim.duration {
+            if 0 < time_step && time_step % SIM_PUBLISH_INTERVAL == 0 {
+                publish(self, users);
+            }
+
             // We want to randomly decide to buy or sell,
             // then perform a random walk from the current price, exchanging within
             // say 1 standard deviation of the mean # of shares per trade.
@@ -500,26 +763,33 @@ impl Exchange {
             // Choose the number of shares
             let shares:i32 = random!(2..=13); // TODO: get random number of shares
 
-            if let Ok(mut account) =  users.authenticate(username, &"password".to_string(), conn) {
-                // Create the order and send it to the market
-                let order = Order::from(action.to_string(), symbol.to_string().clone(), shares, new_price, OrderStatus::PENDING, account.id);
+            // We created these accounts, so there's no need to pay for a password check.
+            let account = users.get_mut_unauthenticated(username, store);
 
-                // If we have an incomplete view of this account, get full view.
-                if !account.pending_orders.is_complete {
-                    self.fetch_account_pending_orders(&mut account, redis_conn);
-                }
+            // Create the order and send it to the market
+            let order = Order::from(action.to_string(), symbol.to_string().clone(), shares, new_price, OrderStatus::PENDING, account.id);
 
-                if let None = account.validate_order(&order) {
-                    if let Err(e) = self.submit_order_to_market(users, buffers, order, username, true, conn) {
-                        eprintln!("{}", e);
-                    }
+            // If we have an incomplete view of this account, get full view.
+            if !account.pending_orders.is_complete {
+                self.fetch_account_pending_orders(account, cache);
+            }
+
+            // Simulated traders are rate limited like everyone else.
+            // There could be a lot of these, so we only count them.
+            if self.rate_limiter.check_order(account).is_err() {
+                throttled += 1;
+            } else if account.validate_order(&order).is_none() {
+                if let Err(e) = self.submit_order_to_market(users, buffers, order, username, true, store) {
+                    eprintln!("{}", e);
                 }
+                self.monitor_margin(users, buffers, store, cache);
             }
 
             buffers.update_buffer_states();
             // If order buffer was drained, we can reset our cached values modified field.
-            if buffers.transmit_buffer_data(&self) {
-                users.reset_users_modified();
+            users.confirm_writes(buffers.batches_written());
+            if buffers.transmit_buffer_data(self) {
+                users.reset_users_modified(buffers.batches_sent());
                 // Set all market stats modified to false
                 for (_key, entry) in self.statistics.iter_mut() {
                     entry.modified = false;
@@ -527,5 +797,8 @@ impl Exchange {
             }
         }
         println!("SIMULATION TOOK: {} seconds!", start.elapsed().as_secs());
+        if 0 < throttled {
+            println!("{} order(s) were rejected by the rate limiter.", throttled);
+        }
     }
 }
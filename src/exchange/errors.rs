@@ -0,0 +1,89 @@
+use std::fmt;
+
+/* Why a request failed, as a code that clients can match on instead of reading the message.
+ * Sent over TCP as `400 BAD_REQUEST [INVALID_PRICE] ...`, and as "code" by the HTTP API.
+ **/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    Malformed,          // The request couldn't be parsed
+    UnknownSymbol,      // There is no market for the symbol
+    Unauthorized,       // Unknown user, wrong password, expired session or closed account
+    Forbidden,          // None of the account's roles allow the request, or the account is suspended
+    InvalidPrice,
+    InvalidQuantity,
+    MarketHalted,       // The market isn't accepting orders
+    InsufficientFunds,  // The account's equity doesn't cover the margin on a short sale
+    ShortSaleRejected,  // Short selling isn't enabled on the account, or it's over its borrow limit
+    UnknownOrder,       // No resting order has the id
+    NotOwner,           // The order belongs to another account
+    RateLimited         // The account is over its order/cancel rate or open order limit
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Malformed            => "MALFORMED",
+            ErrorCode::UnknownSymbol        => "UNKNOWN_SYMBOL",
+            ErrorCode::Unauthorized         => "UNAUTHORIZED",
+            ErrorCode::Forbidden            => "FORBIDDEN",
+            ErrorCode::InvalidPrice         => "INVALID_PRICE",
+            ErrorCode::InvalidQuantity      => "INVALID_QUANTITY",
+            ErrorCode::MarketHalted         => "MARKET_HALTED",
+            ErrorCode::InsufficientFunds    => "INSUFFICIENT_FUNDS",
+            ErrorCode::ShortSaleRejected    => "SHORT_SALE_REJECTED",
+            ErrorCode::UnknownOrder         => "UNKNOWN_ORDER",
+            ErrorCode::NotOwner             => "NOT_OWNER",
+            ErrorCode::RateLimited          => "RATE_LIMITED"
+        }
+    }
+}
+
+/* Why an order couldn't be placed or cancelled. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderError {
+    UnknownSymbol(String),
+    Unauthorized(String),           // Why the account couldn't be used
+    MarketHalted(String),           // Symbol
+    ShortSaleDisabled { symbol: String, held: i32 },
+    BorrowLimit { symbol: String, short: i32, limit: i32 },
+    InsufficientFunds { equity: f64, requirement: f64, short_value: f64 },
+    PositionsUnavailable(String),   // Username, whose positions weren't loaded to check a short sale
+    UnknownOrder(i64),
+    NotOwner(i64)                   // Order id
+}
+
+impl OrderError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            OrderError::UnknownSymbol(_)            => ErrorCode::UnknownSymbol,
+            OrderError::Unauthorized(_)             => ErrorCode::Unauthorized,
+            OrderError::MarketHalted(_)             => ErrorCode::MarketHalted,
+            OrderError::ShortSaleDisabled { .. }    => ErrorCode::ShortSaleRejected,
+            OrderError::BorrowLimit { .. }          => ErrorCode::ShortSaleRejected,
+            OrderError::InsufficientFunds { .. }    => ErrorCode::InsufficientFunds,
+            OrderError::PositionsUnavailable(_)     => ErrorCode::ShortSaleRejected,
+            OrderError::UnknownOrder(_)             => ErrorCode::UnknownOrder,
+            OrderError::NotOwner(_)                 => ErrorCode::NotOwner
+        }
+    }
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderError::UnknownSymbol(symbol) => write!(f, "The market ${} was not found in the database. User error!", symbol),
+            OrderError::Unauthorized(message) => write!(f, "{}", message),
+            OrderError::MarketHalted(symbol) => write!(f, "The market ${} is halted, it isn't accepting orders.", symbol),
+            OrderError::ShortSaleDisabled { symbol, held } => write!(f, "\
+The order could not be placed. You hold {} share(s) of ${} and short selling is not enabled on your account.", held, symbol),
+            OrderError::BorrowLimit { symbol, short, limit } => write!(f, "\
+The order could not be placed. It would leave you short {} share(s) of ${}, but your borrow limit is {} share(s).", short, symbol, limit),
+            OrderError::InsufficientFunds { equity, requirement, short_value } => write!(f, "\
+The order could not be placed. Your equity (${:.2}) does not cover the initial margin of ${:.2} on ${:.2} of short positions.", equity, requirement, short_value),
+            OrderError::PositionsUnavailable(username) => write!(f, "\
+The order could not be placed. The positions of ({}) couldn't be loaded to check the short sale, please try again.", username),
+            OrderError::UnknownOrder(order_id) => write!(f, "Order {} is not resting on the book.", order_id),
+            OrderError::NotOwner(order_id) => write!(f, "Order {} was not placed by your account.", order_id)
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::exchange::{Order, Trade};
+
+/* Something that happened to an order on the book, in the order it happened.
+ * Feeds that describe the book order by order (see binary_feed) are built from these.
+ **/
+#[derive(Debug)]
+pub enum BookEvent {
+    Added(Order),       // What remained of a new order after matching, rests on the book
+    Executed(Trade),    // The resting order (filled_oid) traded with a new order
+    Cancelled {         // What remained of a resting order was cancelled
+        symbol: String,
+        order_id: i64,
+        action: String,
+        quantity: i32   // The unfilled quantity that was cancelled
+    },
+    State {             // The market opened, or trading in it was halted or resumed
+        symbol: String,
+        halted: bool
+    }
+}
+
+impl BookEvent {
+    pub fn cancelled(order: &Order) -> Self {
+        BookEvent::Cancelled {
+            symbol: order.symbol.clone(),
+            order_id: order.order_id,
+            action: order.action.clone(),
+            quantity: order.quantity - order.filled
+        }
+    }
+}
@@ -9,50 +9,63 @@ pub struct Trade {
     pub action: String,
     pub symbol: String,
     pub price: f64,         // price at which this trade was occured
-    pub filled_oid: i32,    // ID of order getting filled
-    pub filled_uid: i32,    // ID of user who placed the order that is being filled
-    pub filler_oid: i32,    // ID of new order that triggered the trade
-    pub filler_uid: i32,    // ID of user who placed new order that triggered the trade
+    pub filled_oid: i64,    // ID of order getting filled
+    pub filled_uid: i64,    // ID of user who placed the order that is being filled
+    pub filler_oid: i64,    // ID of new order that triggered the trade
+    pub filler_uid: i64,    // ID of user who placed new order that triggered the trade
     pub exchanged: i32,     // the amount of shares exchanged
     pub execution_time: DateTime<Utc>
 }
 
+/* The orders on both sides of a trade, and the users who placed them. */
+#[derive(Debug, Copy, Clone)]
+pub struct TradeSides {
+    pub filled_oid: i64,    // The resting order
+    pub filled_uid: i64,
+    pub filler_oid: i64,    // The new order
+    pub filler_uid: i64
+}
+
 impl Trade {
-    fn from(action: &String, symbol: &String, price: f64, filled_oid: i32, filled_uid: i32, filler_oid: i32, filler_uid: i32, exchanged: i32) -> Self {
-        Trade {
-            action: action.clone(),
-            symbol: symbol.clone(),
-            price,
-            filled_oid,
-            filled_uid,
-            filler_oid,
-            filler_uid,
-            exchanged,
-            execution_time: Utc::now()
-        }
+    fn from(action: &str, symbol: &str, price: f64, sides: TradeSides, exchanged: i32) -> Self {
+        Trade::direct(symbol, action, price, sides, exchanged, Utc::now())
     }
 
     // Create a Trade from a pair of Orders.
     pub fn order_to_trade(pending: &Order, filler: &Order, exchanged: i32) -> Self {
-        Trade::from(&pending.action, &pending.symbol, pending.price, pending.order_id, pending.user_id.unwrap(), filler.order_id, filler.user_id.unwrap(), exchanged)
+        let sides = TradeSides {
+            filled_oid: pending.order_id,
+            filled_uid: pending.user_id.unwrap(),
+            filler_oid: filler.order_id,
+            filler_uid: filler.user_id.unwrap()
+        };
+        Trade::from(&pending.action, &pending.symbol, pending.price, sides, exchanged)
     }
 
     /* Used when reading data directly from the database. */
-    pub fn direct(symbol: &str, action: &str, price: f64, filled_oid: i32, filled_uid: i32, filler_oid: i32, filler_uid: i32, exchanged: i32, execution_time: DateTime<Utc>) -> Self {
+    pub fn direct(symbol: &str, action: &str, price: f64, sides: TradeSides, exchanged: i32, execution_time: DateTime<Utc>) -> Self {
         Trade {
-            symbol: symbol.to_string().clone(),
-            action: action.to_string().clone(),
+            symbol: symbol.to_string(),
+            action: action.to_string(),
             price,
-            filled_oid,
-            filled_uid,
-            filler_oid,
-            filler_uid,
+            filled_oid: sides.filled_oid,
+            filled_uid: sides.filled_uid,
+            filler_oid: sides.filler_oid,
+            filler_uid: sides.filler_uid,
             exchanged,
             execution_time
         }
     }
 }
 
+/* A trade, along with the usernames of both accounts, for the drop copy. */
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub trade: Trade,
+    pub filled_username: String,    // Placed the resting order
+    pub filler_username: String     // Placed the new order
+}
+
 impl Clone for Trade {
     fn clone(&self) -> Self {
         Trade {
@@ -1,8 +1,11 @@
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashSet};
 use std::cmp::Reverse;
 use crate::exchange::{Order, Trade, OrderStatus};
 
 // The market for a security
+// A price in the book, and the unfilled quantity of all orders at it.
+pub type PriceLevel = (f64, i32);
+
 #[derive(Debug)]
 pub struct Market {
     pub buy_orders: BinaryHeap<Order>,
@@ -58,24 +61,26 @@ impl Market {
 
                     // Update the orders
                     let mut lowest_offer = self.sell_orders.pop().unwrap();
-                    lowest_offer.0.filled += amount_traded;
+                    let price = lowest_offer.0.price;
+                    lowest_offer.0.record_fill(amount_traded, price);
                     lowest_offer.0.status = OrderStatus::COMPLETE;
 
                     // Add this trade
-                    highest_bid.filled += amount_traded;
-                    trades.push(Trade::order_to_trade(&lowest_offer.0, &highest_bid, amount_traded));
+                    highest_bid.record_fill(amount_traded, price);
+                    trades.push(Trade::order_to_trade(&lowest_offer.0, highest_bid, amount_traded));
                     modified_orders.push(lowest_offer.0.clone());
                 } else {
                     // The buy order was completely filled.
                     let amount_traded = highest_bid_remaining;
 
                     // Update the lowest offer
-                    let mut lowest_offer = &mut (self.sell_orders.peek_mut().unwrap().0);
-                    lowest_offer.filled += amount_traded;
+                    let lowest_offer = &mut (self.sell_orders.peek_mut().unwrap().0);
+                    let price = lowest_offer.price;
+                    lowest_offer.record_fill(amount_traded, price);
 
                     // Newly placed order was filled
-                    highest_bid.filled += amount_traded;
-                    trades.push(Trade::order_to_trade(&lowest_offer, &highest_bid, amount_traded));
+                    highest_bid.record_fill(amount_traded, price);
+                    trades.push(Trade::order_to_trade(lowest_offer, highest_bid, amount_traded));
                     modified_orders.push(lowest_offer.clone());
                 }
             } else {
@@ -84,7 +89,7 @@ impl Market {
             }
         }
 
-        return new_price;
+        new_price
     }
 
     /* Given a sell order, try to fill it with existing buy orders in the market.
@@ -127,13 +132,14 @@ impl Market {
 
                     // Update the orders
                     let mut highest_bid = self.buy_orders.pop().unwrap();
-                    highest_bid.filled += amount_traded;
+                    let price = highest_bid.price;
+                    highest_bid.record_fill(amount_traded, price);
                     highest_bid.status = OrderStatus::COMPLETE;
 
-                    lowest_offer.filled += amount_traded;
+                    lowest_offer.record_fill(amount_traded, price);
 
                     // Add the updated buy to the Vectors we return
-                    trades.push(Trade::order_to_trade(&highest_bid, &lowest_offer, amount_traded));
+                    trades.push(Trade::order_to_trade(&highest_bid, lowest_offer, amount_traded));
                     modified_orders.push(highest_bid.clone());
                 } else {
                     // The sell order was completely filled.
@@ -141,12 +147,13 @@ impl Market {
 
                     // Update the highest bid.
                     let mut highest_bid = self.buy_orders.peek_mut().unwrap();
-                    highest_bid.filled += amount_traded;
+                    let price = highest_bid.price;
+                    highest_bid.record_fill(amount_traded, price);
 
                     // Newly placed order was filled
-                    lowest_offer.filled += amount_traded;
+                    lowest_offer.record_fill(amount_traded, price);
 
-                    trades.push(Trade::order_to_trade(&highest_bid, &lowest_offer, amount_traded));
+                    trades.push(Trade::order_to_trade(&highest_bid, lowest_offer, amount_traded));
                     modified_orders.push(highest_bid.clone());
                 }
             } else {
@@ -155,7 +162,7 @@ impl Market {
             }
         }
 
-        return new_price
+        new_price
     }
 
     // When we get a new order, we will try to fill it with
@@ -187,12 +194,67 @@ impl Market {
         }
 
         // Update the market stats as the state has changed.
-        match new_price {
-            // Price change means orders were filled
-            Some(_) => {
-                return Some((modified_orders, trades));
-            },
-            None => return None
+        // A price change means orders were filled.
+        new_price.map(|_| (modified_orders, trades))
+    }
+
+    /* Remove many orders from the market at once, and return them.
+     * Each heap is rebuilt at most once, no matter how many of its orders are removed.
+     **/
+    pub fn remove_orders(&mut self, buys: &HashSet<i64>, sells: &HashSet<i64>) -> Vec<Order> {
+        let mut removed = Vec::new();
+        if !buys.is_empty() {
+            let mut temp = BinaryHeap::with_capacity(self.buy_orders.len());
+            for order in self.buy_orders.drain() {
+                if buys.contains(&order.order_id) {
+                    removed.push(order);
+                } else {
+                    temp.push(order);
+                }
+            }
+            self.buy_orders.append(&mut temp);
+        }
+        if !sells.is_empty() {
+            let mut temp = BinaryHeap::with_capacity(self.sell_orders.len());
+            for order in self.sell_orders.drain() {
+                if sells.contains(&order.0.order_id) {
+                    removed.push(order.0);
+                } else {
+                    temp.push(order);
+                }
+            }
+            self.sell_orders.append(&mut temp);
+        }
+        removed
+    }
+
+    /* The book aggregated by price, up to `levels` prices on each side.
+     * Returns the (price, remaining quantity) of the buys from highest to lowest,
+     * and of the sells from lowest to highest.
+     **/
+    pub fn depth(&self, levels: usize) -> (Vec<PriceLevel>, Vec<PriceLevel>) {
+        let buys = self.buy_orders.clone().into_sorted_vec();
+        let sells = self.sell_orders.clone().into_sorted_vec();
+        let bids = Market::aggregate(buys.iter().rev(), levels);
+        let asks = Market::aggregate(sells.iter().rev().map(|order| &order.0), levels);
+        (bids, asks)
+    }
+
+    // Sums the unfilled quantity at each price, orders must come best price first.
+    fn aggregate<'a>(orders: impl Iterator<Item = &'a Order>, levels: usize) -> Vec<PriceLevel> {
+        let mut book: Vec<PriceLevel> = Vec::new();
+        for order in orders {
+            let remaining = order.quantity - order.filled;
+            match book.last_mut() {
+                Some((price, quantity)) if *price == order.price => *quantity += remaining,
+                _ => {
+                    if book.len() == levels {
+                        break;
+                    }
+                    book.push((order.price, remaining));
+                }
+            }
         }
+        book
     }
 }
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use crate::exchange::Order;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExecType {
+    New,            // The order was accepted
+    PartialFill,
+    Fill,           // The last of the order was filled
+    Cancelled,      // What remained of the order was cancelled
+    Replaced,       // The order replaced another, see replaces
+    Rejected        // The order wasn't accepted, see reason
+}
+
+impl ExecType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExecType::New => "NEW",
+            ExecType::PartialFill => "PARTIAL_FILL",
+            ExecType::Fill => "FILL",
+            ExecType::Cancelled => "CANCELLED",
+            ExecType::Replaced => "REPLACED",
+            ExecType::Rejected => "REJECTED"
+        }
+    }
+}
+
+/* One thing that happened to an order, and where the order stands afterwards.
+ *
+ * The console, TCP and HTTP interfaces render these the same way, so a client
+ * sees an ack, then one report per fill, rather than the state of the book.
+ **/
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub exec_type: ExecType,
+    pub order_id: i64,              // 0 if the order was rejected before it got an id
+    pub client_id: Option<String>,
+    pub symbol: String,
+    pub action: String,
+    pub quantity: i32,
+    pub price: f64,
+    pub last_quantity: i32,         // Shares traded by this fill, 0 for every other report
+    pub last_price: f64,
+    pub cum_quantity: i32,          // Shares filled so far
+    pub leaves_quantity: i32,       // Shares still open on the book
+    pub avg_price: f64,             // Average price of the shares filled so far
+    pub replaces: Option<i64>,      // The order a replacement replaced
+    pub reason: Option<String>      // Why the order was rejected
+}
+
+impl ExecutionReport {
+    fn from_order(exec_type: ExecType, order: &Order) -> Self {
+        let leaves_quantity = match exec_type {
+            ExecType::Cancelled | ExecType::Rejected => 0,
+            _ => order.quantity - order.filled
+        };
+        ExecutionReport {
+            exec_type,
+            order_id: order.order_id,
+            client_id: order.client_id.clone(),
+            symbol: order.symbol.clone(),
+            action: order.action.clone(),
+            quantity: order.quantity,
+            price: order.price,
+            last_quantity: 0,
+            last_price: 0.0,
+            cum_quantity: order.filled,
+            leaves_quantity,
+            avg_price: order.average_price(),
+            replaces: None,
+            reason: None
+        }
+    }
+
+    /* The reports for a newly placed order: an ack, then one per fill.
+     * The order is as it stands after matching, and fills are the (shares, price) it traded at.
+     **/
+    pub fn for_new_order(order: &Order, fills: &[(i32, f64)]) -> Vec<Self> {
+        // Replay the fills from an unfilled copy, so each report shows the order as it was then.
+        let mut state = order.clone();
+        state.filled = 0;
+        state.fill_value = 0.0;
+
+        let mut reports = vec![ExecutionReport::from_order(ExecType::New, &state)];
+        for (shares, price) in fills.iter() {
+            state.record_fill(*shares, *price);
+            let exec_type = if state.filled == state.quantity { ExecType::Fill } else { ExecType::PartialFill };
+            let mut fill = ExecutionReport::from_order(exec_type, &state);
+            fill.last_quantity = *shares;
+            fill.last_price = *price;
+            reports.push(fill);
+        }
+        reports
+    }
+
+    /* Turn a new order's ack into the ack of a replacement for the given order. */
+    pub fn replace_ack(&mut self, replaced: i64) {
+        self.exec_type = ExecType::Replaced;
+        self.replaces = Some(replaced);
+    }
+
+    /* What remained of this order was cancelled. */
+    pub fn cancelled(order: &Order) -> Self {
+        ExecutionReport::from_order(ExecType::Cancelled, order)
+    }
+
+    /* This order wasn't accepted. */
+    pub fn rejected(order: &Order, reason: &str) -> Self {
+        let mut report = ExecutionReport::from_order(ExecType::Rejected, order);
+        report.reason = Some(reason.to_string());
+        report
+    }
+}
+
+impl fmt::Display for ExecutionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} order {}", self.exec_type.name(), self.order_id)?;
+        if let Some(client_id) = &self.client_id {
+            write!(f, " ({})", client_id)?;
+        }
+        write!(f, ": {} {} ${} at ${:.2}", self.action, self.quantity, self.symbol, self.price)?;
+        match self.exec_type {
+            ExecType::PartialFill | ExecType::Fill => write!(f, ", last {} at ${:.2}", self.last_quantity, self.last_price)?,
+            ExecType::Replaced => if let Some(replaces) = self.replaces {
+                write!(f, ", replaces order {}", replaces)?;
+            },
+            ExecType::Rejected => if let Some(reason) = &self.reason {
+                write!(f, ", {}", reason)?;
+            },
+            _ => ()
+        }
+        write!(f, ", cum {} leaves {} avg ${:.2}", self.cum_quantity, self.leaves_quantity, self.avg_price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::OrderStatus;
+
+    #[test]
+    fn new_orders_are_acked_then_reported_per_fill() {
+        // 10 shares, 4 filled at 100 then 6 at 101.
+        let mut order = Order::direct("BUY", "GME", 10, 101.0, 7, 2);
+        order.record_fill(4, 100.0);
+        order.record_fill(6, 101.0);
+        let reports = ExecutionReport::for_new_order(&order, &[(4, 100.0), (6, 101.0)]);
+
+        let types: Vec<ExecType> = reports.iter().map(|report| report.exec_type).collect();
+        assert_eq!(types, vec![ExecType::New, ExecType::PartialFill, ExecType::Fill]);
+        let quantities: Vec<(i32, i32, i32)> = reports.iter().map(|report| (report.last_quantity, report.cum_quantity, report.leaves_quantity)).collect();
+        assert_eq!(quantities, vec![(0, 0, 10), (4, 4, 6), (6, 10, 0)]);
+        assert_eq!(reports[1].avg_price, 100.0);
+        assert_eq!(reports[2].avg_price, 100.6);
+        assert_eq!(reports[2].last_price, 101.0);
+        assert_eq!(reports[2].to_string(), "FILL order 7: BUY 10 $GME at $101.00, last 6 at $101.00, cum 10 leaves 0 avg $100.60");
+    }
+
+    #[test]
+    fn cancels_rejects_and_replacements_leave_what_they_should() {
+        let order = Order::direct("SELL", "GME", 10, 100.0, 7, 2).with_fills(4, OrderStatus::PENDING);
+        let cancelled = ExecutionReport::cancelled(&order);
+        assert_eq!((cancelled.exec_type, cancelled.cum_quantity, cancelled.leaves_quantity), (ExecType::Cancelled, 4, 0));
+
+        let rejected = ExecutionReport::rejected(&order, "The market $GME is halted, it isn't accepting orders.");
+        assert_eq!((rejected.exec_type, rejected.leaves_quantity), (ExecType::Rejected, 0));
+        assert!(rejected.to_string().ends_with("is halted, it isn't accepting orders., cum 4 leaves 0 avg $100.00"));
+
+        let mut replacement = ExecutionReport::for_new_order(&Order::direct("SELL", "GME", 8, 101.0, 8, 2), &[]).remove(0);
+        replacement.replace_ack(7);
+        assert_eq!((replacement.exec_type, replacement.replaces, replacement.leaves_quantity), (ExecType::Replaced, Some(7), 8));
+        assert!(replacement.to_string().contains(", replaces order 7,"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::cmp::Ordering;
-use crate::account::UserAccount;
+use crate::account::{Credentials, CostBasis, MarginAccount, MarginPolicy, Role, Permission};
+use crate::exchange::RateLimits;
 
 // The status of an order, each is 1 byte (u8)
 #[derive(Copy, Clone, Debug)]
@@ -9,6 +10,29 @@ pub enum OrderStatus {
     CANCELLED
 }
 
+impl OrderStatus {
+    // Statuses are stored in the database as their Debug names, ex. COMPLETE.
+    pub fn from(word: &str) -> Self {
+        match word {
+            "COMPLETE" => OrderStatus::COMPLETE,
+            "CANCELLED" => OrderStatus::CANCELLED,
+            _ => OrderStatus::PENDING
+        }
+    }
+}
+
+/* Client order ids are chosen by the client, ex. buy GME 10 167.34 clid=abc123 username password.
+ * They're unique per account, so resubmitting an order after a dropped connection
+ * returns the state of the original order instead of placing it twice.
+ * They're case insensitive: the console, API and FIX gateway all lowercase them before they get here.
+ **/
+pub const MAX_CLIENT_ID_LEN: usize = 36;
+
+/* Client order ids are 1 to MAX_CLIENT_ID_LEN letters, numbers, dashes or underscores. */
+pub fn is_valid_client_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_CLIENT_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
 // An order type for a security
 #[derive(Debug)]
 pub struct Order {
@@ -17,14 +41,16 @@ pub struct Order {
     pub quantity: i32,
     pub filled: i32,        // Quantity filled so far
     pub price: f64,
-    pub order_id: i32,
+    pub order_id: i64,
     pub status: OrderStatus,
-    pub user_id: Option<i32>// user ID of user who placed order, starts as None during tokenization.
+    pub user_id: Option<i64>,// user ID of user who placed order, starts as None during tokenization.
+    pub client_id: Option<String>, // Optional id chosen by the client, unique per account
+    pub fill_value: f64     // Sum of price * shares of each fill so far, for the average price
 }
 
 impl Order {
     // Used when reading a user from the frontend
-    pub fn from(action: String, symbol: String, quantity: i32, price: f64, status: OrderStatus, user_id: Option<i32>) -> Self {
+    pub fn from(action: String, symbol: String, quantity: i32, price: f64, status: OrderStatus, user_id: Option<i64>) -> Self {
         // Truncate price to 2 decimal places
         let price = f64::trunc(price  * 100.0) / 100.0;
 
@@ -36,12 +62,14 @@ impl Order {
             price,
             order_id: 0, // Updated later.
             status,
-            user_id
+            user_id,
+            client_id: None,
+            fill_value: 0.0
         }
     }
 
-    // Used when reading an existing user from the database
-    pub fn direct(action: &str, symbol: &str, quantity: i32, filled: i32, price: f64, order_id: i32, status: OrderStatus, user_id: i32) -> Self {
+    // Used when reading an existing order from the database, followed by with_fills.
+    pub fn direct(action: &str, symbol: &str, quantity: i32, price: f64, order_id: i64, user_id: i64) -> Self {
         // Truncate price to 2 decimal places
         let price = f64::trunc(price  * 100.0) / 100.0;
 
@@ -50,18 +78,29 @@ impl Order {
             action: action.to_string().clone(),
             symbol: symbol.to_string().clone(),
             quantity,
-            filled,
+            filled: 0,
             price,
             order_id,
-            status,
-            user_id: Some(user_id)
+            status: OrderStatus::PENDING,
+            user_id: Some(user_id),
+            client_id: None,
+            fill_value: 0.0
         }
     }
 
+    /* The shares an order read from the database has filled, and its status. */
+    pub fn with_fills(mut self, filled: i32, status: OrderStatus) -> Self {
+        self.filled = filled;
+        self.status = status;
+        // Fill prices aren't stored with orders, so we assume they filled at their own price.
+        self.fill_value = filled as f64 * self.price;
+        self
+    }
+
     /* This constructs a mostly empty order. We only ever use it when cancelling an order,
      * as we need to modify the orders state in the order buffer.
      **/
-    pub fn from_cancelled(order_id: i32) -> Self {
+    pub fn from_cancelled(order_id: i64) -> Self {
         Order {
             action: "".to_string(),
             symbol: "".to_string(),
@@ -71,6 +110,8 @@ impl Order {
             order_id,
             status: OrderStatus::CANCELLED,
             user_id: None,
+            client_id: None,
+            fill_value: 0.0
         }
     }
 }
@@ -80,11 +121,33 @@ impl Clone for Order {
         Order {
             action: self.action.clone(),
             symbol: self.symbol.clone(),
+            client_id: self.client_id.clone(),
             ..*self
         }
     }
 }
 
+impl Order {
+    pub fn record_fill(&mut self, shares: i32, price: f64) {
+        self.filled += shares;
+        self.fill_value += shares as f64 * price;
+    }
+
+    pub fn average_price(&self) -> f64 {
+        if self.filled == 0 { 0.0 } else { self.fill_value / self.filled as f64 }
+    }
+
+    /* The current state of an order, ex. in response to a duplicate submission. */
+    pub fn describe(&self) -> String {
+        let client_id = match &self.client_id {
+            Some(client_id) => format![" (client id {})", client_id],
+            None => String::new()
+        };
+        format!["Order {}{}: {} {} ${} at ${:.2}, {} filled, {:?}",
+                self.order_id, client_id, self.action, self.quantity, self.symbol, self.price, self.filled, self.status]
+    }
+}
+
 impl Ord for Order {
     fn cmp(&self, other: &Self) -> Ordering {
         if let Ordering::Equal = &self.symbol.cmp(&other.symbol) {
@@ -93,9 +156,9 @@ impl Ord for Order {
             } else if other.price < self.price {
                 return Ordering::Greater;
             }
-            return Ordering::Equal;
+            Ordering::Equal
         } else {
-            return Ordering::Equal;
+            Ordering::Equal
         }
     }
 }
@@ -108,7 +171,7 @@ impl PartialOrd for Order {
 
 impl PartialEq for Order {
     fn eq(&self, other: &Self) -> bool {
-        &self.symbol == &other.symbol && self.price == other.price
+        self.symbol == other.symbol && self.price == other.price
     }
 }
 
@@ -149,16 +212,115 @@ impl Simulation {
 
 pub struct CancelOrder {
     pub symbol: String,
-    pub order_id: i32,
-    pub username: String,
+    pub order_id: i64,      // 0 until authenticated if the request used a client order id.
+    pub username: String,   // Empty until authenticated if the request used a session token.
+    pub client_id: Option<String> // The client order id the request used instead of order_id, if any.
+}
+
+// Cancel every resting order of an account, optionally only in one market or on one side.
+pub struct CancelAll {
+    pub symbol: Option<String>,
+    pub action: Option<String>,     // BUY or SELL
+    pub username: Option<String>    // Cancel for this account rather than the sender's own. Operators/admin only
+}
+
+// Cancel what remains of an order, and replace it with a new one at a new quantity and price.
+pub struct AmendOrder {
+    pub cancel: CancelOrder,
+    pub quantity: i32,
+    pub price: f64,
+    pub client_id: Option<String>   // Client order id of the replacement order
+}
+
+// Admin requests that change short selling settings.
+pub enum MarginRequest {
+    Account(String, MarginAccount), // Username, and their new settings
+    Policy(MarginPolicy),           // New exchange wide margin rules
+    Calls                           // Show the accounts that are below maintenance margin
+}
+
+// Admin requests that change what an account may do.
+pub enum RoleRequest {
+    Grant(String, Role),    // Username, role
+    Revoke(String, Role),
+    Show(String)            // Show the roles of the account
+}
+
+// Admin requests that change or show rate limits.
+pub enum LimitRequest {
+    Show(String),               // Show an account's limits and usage
+    Set(String, RateLimits),    // Give an account its own limits
+    Reset(String),              // Put an account back on the limits of its role
+    Default(RateLimits)         // Change the limits of accounts without their own
+}
+
+// Operator requests that stop or restart trading in a market.
+pub enum MarketRequest {
+    Halt(String),           // Reject new orders in the market, resting orders can still be cancelled
+    Resume(String)
+}
+
+// Requests that change an account's state or credentials.
+pub enum AccountRequest {
+    Suspend(String, bool),  // Username, and whether to cancel its resting orders. Operators/admin only
+    Reactivate(String),     // Operators/admin only
+    Close(String),          // Operators/admin only
+    ChangePassword(String), // New password for the sender's own account
+    ChangeUsername(String)  // New username for the sender's own account
 }
 
 pub enum Request {
-    OrderReq(Order, String, String),// first string is username, second password
-    CancelReq(CancelOrder, String), // string is password
+    OrderReq(Order, Credentials),
+    CancelReq(CancelOrder, Credentials),
+    AmendReq(AmendOrder, Credentials),
+    CancelAllReq(CancelAll, Credentials),
     InfoReq(InfoRequest),
     SimReq(Simulation),
-    UserReq(UserAccount, String), // Account followed by action
-    UpgradeDbReq(String, String, String), // db_name, username, password. Only admin can call this
+    UserReq(Credentials, String, Option<CostBasis>), // Credentials followed by action, and optionally how to calculate P&L
+    LoginReq(String, String),   // username, password
+    LogoutReq(String),          // session token
+    UpgradeDbReq(String, Credentials), // db_name
+    MarginReq(MarginRequest, Credentials),
+    RoleReq(RoleRequest, Credentials),
+    LimitReq(LimitRequest, Credentials),
+    MarketReq(MarketRequest, Credentials),
+    AccountReq(AccountRequest, Credentials),
+    CacheReq,   // Show the user cache's stats
+    HelpReq,    // Show the instructions
     ExitReq,
 }
+
+impl Request {
+    /* The permission the sender of this request needs. */
+    pub fn permission(&self) -> Permission {
+        match self {
+            Request::OrderReq(_, _)         => Permission::PlaceOrder,
+            Request::CancelReq(_, _)        => Permission::CancelOrder,
+            Request::AmendReq(_, _)         => Permission::PlaceOrder,
+            Request::CancelAllReq(req, _)   => match req.username {
+                Some(_) => Permission::ManageAccounts,
+                None    => Permission::CancelOrder
+            },
+            Request::InfoReq(_)             => Permission::Public,
+            Request::SimReq(_)              => Permission::Console,
+            Request::UserReq(_, action, _)  => match &action[..] {
+                "create" => Permission::Public,
+                _        => Permission::ViewAccount
+            },
+            Request::LoginReq(_, _)         => Permission::Public,
+            Request::LogoutReq(_)           => Permission::Public,
+            Request::UpgradeDbReq(_, _)     => Permission::UpgradeDb,
+            Request::MarginReq(_, _)        => Permission::ManageMargin,
+            Request::RoleReq(_, _)          => Permission::ManageRoles,
+            Request::LimitReq(_, _)         => Permission::ManageLimits,
+            Request::MarketReq(_, _)        => Permission::ManageMarkets,
+            Request::AccountReq(req, _)     => match req {
+                AccountRequest::ChangePassword(_) | AccountRequest::ChangeUsername(_) => Permission::EditAccount,
+                _ => Permission::ManageAccounts
+            },
+            Request::CacheReq               => Permission::Console,
+            Request::HelpReq                => Permission::Public,
+            Request::ExitReq                => Permission::Console
+        }
+    }
+}
@@ -30,12 +30,12 @@ impl SecStat {
         let last_price = None;
 
         SecStat {
-            symbol: symbol,
-            total_buys: total_buys,
-            total_sells: total_sells,
+            symbol,
+            total_buys,
+            total_sells,
             filled_buys: 0,
             filled_sells: 0,
-            last_price: last_price,
+            last_price,
             modified: false
         }
     }
@@ -72,11 +72,11 @@ impl SecStat {
             Some(price) => {
                 let diff = price - new_price;
                 self.last_price = Some(new_price);
-                return diff;
+                diff
             },
             None => {
                 self.last_price = Some(new_price);
-                return new_price;
+                new_price
             }
         }
     }
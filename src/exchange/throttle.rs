@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+use crate::account::{UserAccount, Role};
+use crate::exchange::ErrorCode;
+
+/* A token bucket holds up to `capacity` tokens, and refills at `rate` tokens per second.
+ * Every request takes a token, so an account can burst up to `capacity` requests,
+ * but can't keep up more than `rate` requests per second.
+ **/
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant
+}
+
+impl TokenBucket {
+    // The bucket starts full, and can hold one second's worth of tokens.
+    pub fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            rate,
+            last_refill: Instant::now()
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /* Take a token if there is one. Returns false if the bucket is empty. */
+    pub fn try_take(&mut self) -> bool {
+        self.refill();
+        if 1.0 <= self.tokens {
+            self.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    pub fn available(&mut self) -> f64 {
+        self.refill();
+        self.tokens
+    }
+}
+
+// The limits that apply to a single account.
+#[derive(Debug, Copy, Clone)]
+pub struct RateLimits {
+    pub orders_per_sec: f64,
+    pub cancels_per_sec: f64,
+    pub max_open_orders: usize
+}
+
+impl RateLimits {
+    pub fn new(orders_per_sec: f64, cancels_per_sec: f64, max_open_orders: usize) -> Self {
+        RateLimits {
+            orders_per_sec,
+            cancels_per_sec,
+            max_open_orders
+        }
+    }
+}
+
+// Why a request was rejected by the rate limiter.
+#[derive(Debug, Copy, Clone)]
+pub enum Throttled {
+    OrderRate(f64),     // The account's orders per second limit
+    CancelRate(f64),    // The account's cancels per second limit
+    OpenOrders(usize)   // The account's max open orders
+}
+
+impl Throttled {
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::RateLimited
+    }
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Throttled::OrderRate(limit) => write!(f, "Throttled! This account may only place {} order(s) per second, please slow down.", limit),
+            Throttled::CancelRate(limit) => write!(f, "Throttled! This account may only cancel {} order(s) per second, please slow down.", limit),
+            Throttled::OpenOrders(limit) => write!(f, "Throttled! This account already has the maximum of {} open order(s).", limit)
+        }
+    }
+}
+
+// The rate limiter's state for a single account.
+#[derive(Debug, Clone)]
+struct AccountUsage {
+    limits: RateLimits,         // The limits these buckets were built with
+    orders: TokenBucket,
+    cancels: TokenBucket,
+    rejected: u64               // Requests rejected since the exchange started
+}
+
+impl AccountUsage {
+    fn new(limits: RateLimits) -> Self {
+        AccountUsage {
+            limits,
+            orders: TokenBucket::new(limits.orders_per_sec),
+            cancels: TokenBucket::new(limits.cancels_per_sec),
+            rejected: 0
+        }
+    }
+}
+
+/* Limits how fast each account can place and cancel orders, and how many orders it can have open.
+ *
+ * Market makers quote both sides of many markets, so they get their own (higher) limits.
+ * The defaults come from the config, and the admin can also give a single account its own limits,
+ * which are saved in the store (see AccountStore::write_update_rate_limits).
+ **/
+#[derive(Debug)]
+pub struct RateLimiter {
+    pub default: RateLimits,
+    pub market_maker: RateLimits,
+    overrides: HashMap<String, RateLimits>, // Username -> limits
+    usage: HashMap<String, AccountUsage>    // Username -> usage
+}
+
+impl RateLimiter {
+    pub fn new(default: RateLimits, market_maker: RateLimits) -> Self {
+        RateLimiter {
+            default,
+            market_maker,
+            overrides: HashMap::new(),
+            usage: HashMap::new()
+        }
+    }
+
+    /* The limits of an account: its own limits if it has them,
+     * otherwise the limits of its role.
+     **/
+    pub fn limits_for(&self, account: &UserAccount) -> RateLimits {
+        if let Some(limits) = self.overrides.get(&account.username) {
+            return *limits;
+        }
+        if account.roles.contains(&Role::MarketMaker) {
+            return self.market_maker;
+        }
+        self.default
+    }
+
+    /* Get the usage of an account, starting over if its limits have changed. */
+    fn usage(&mut self, account: &UserAccount) -> &mut AccountUsage {
+        let limits = self.limits_for(account);
+        let usage = self.usage.entry(account.username.clone()).or_insert_with(|| AccountUsage::new(limits));
+        if usage.limits.orders_per_sec != limits.orders_per_sec || usage.limits.cancels_per_sec != limits.cancels_per_sec {
+            let rejected = usage.rejected;
+            *usage = AccountUsage::new(limits);
+            usage.rejected = rejected;
+        }
+        usage.limits = limits;
+        usage
+    }
+
+    /* Check that the account may place another order, taking a token if it can.
+     * The account must have a complete view of its pending orders.
+     **/
+    pub fn check_order(&mut self, account: &UserAccount) -> Result<(), Throttled> {
+        let open_orders = account.pending_orders.count();
+        let usage = self.usage(account);
+
+        let result = if usage.limits.max_open_orders <= open_orders {
+            Err(Throttled::OpenOrders(usage.limits.max_open_orders))
+        } else if !usage.orders.try_take() {
+            Err(Throttled::OrderRate(usage.limits.orders_per_sec))
+        } else {
+            Ok(())
+        };
+
+        if result.is_err() {
+            usage.rejected += 1;
+        }
+        result
+    }
+
+    /* Check that the account may cancel another order, taking a token if it can. */
+    pub fn check_cancel(&mut self, account: &UserAccount) -> Result<(), Throttled> {
+        let usage = self.usage(account);
+        if usage.cancels.try_take() {
+            return Ok(());
+        }
+        usage.rejected += 1;
+        Err(Throttled::CancelRate(usage.limits.cancels_per_sec))
+    }
+
+    /* Give an account its own limits, or None to go back to the limits of its role. */
+    pub fn set_account_limits(&mut self, username: &str, limits: Option<RateLimits>) {
+        match limits {
+            Some(limits) => self.overrides.insert(username.to_string(), limits),
+            None => self.overrides.remove(username)
+        };
+    }
+
+    /* Keep an account's limits and usage when its username changes. */
+    pub fn rename_account(&mut self, username: &str, new_username: &str) {
+        if let Some(limits) = self.overrides.remove(username) {
+            self.overrides.insert(new_username.to_string(), limits);
+        }
+        if let Some(usage) = self.usage.remove(username) {
+            self.usage.insert(new_username.to_string(), usage);
+        }
+    }
+
+    /* Show an account's limits and how much of them it is using. */
+    pub fn show_usage(&mut self, account: &UserAccount) -> String {
+        let open_orders = account.pending_orders.count();
+        let custom = self.overrides.contains_key(&account.username);
+        let usage = self.usage(account);
+
+        let mut lines: Vec<String> = Vec::new();
+        lines.push(format!["\nRate limits for user: {}{}", account.username, if custom { " (account specific)" } else { "" }]);
+        lines.push("\t\t| Limit       | Allowed | Available |".to_string());
+        lines.push("\t\t-------------------------------------".to_string());
+        lines.push(format!["\t\t| Orders/sec  | {:>7} | {:>9.1} |", usage.limits.orders_per_sec, usage.orders.available()]);
+        lines.push(format!["\t\t| Cancels/sec | {:>7} | {:>9.1} |", usage.limits.cancels_per_sec, usage.cancels.available()]);
+        lines.push(format!["\t\t| Open orders | {:>7} | {:>9} |", usage.limits.max_open_orders, usage.limits.max_open_orders.saturating_sub(open_orders)]);
+        lines.push("\t\t-------------------------------------".to_string());
+        lines.push(format!["\t\tRejected requests: {}", usage.rejected]);
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::exchange::Order;
+
+    #[test]
+    fn buckets_refill_at_their_rate() {
+        let mut bucket = TokenBucket::new(10.0);
+        for _ in 0..10 {
+            assert!(bucket.try_take());
+        }
+        assert!(!bucket.try_take());
+
+        // Half a second later, half the tokens are back.
+        bucket.last_refill -= Duration::from_millis(500);
+        let available = bucket.available();
+        assert!(4.9 < available && available < 5.5, "{} tokens", available);
+
+        // But the bucket never holds more than a second's worth.
+        bucket.last_refill -= Duration::from_secs(10);
+        assert_eq!(bucket.available(), 10.0);
+    }
+
+    #[test]
+    fn requests_over_the_limits_are_rate_limited() {
+        let mut limiter = RateLimiter::new(RateLimits::new(2.0, 1.0, 10), RateLimits::new(100.0, 100.0, 100));
+        let mut bob = UserAccount::from("bob", "password");
+
+        assert!(limiter.check_order(&bob).is_ok() && limiter.check_order(&bob).is_ok());
+        match limiter.check_order(&bob) {
+            Err(throttled @ Throttled::OrderRate(_)) => assert_eq!(throttled.code(), ErrorCode::RateLimited),
+            other => panic!("Expected the order rate to be exceeded, got {:?}", other)
+        }
+
+        assert!(limiter.check_cancel(&bob).is_ok());
+        assert!(matches!(limiter.check_cancel(&bob), Err(Throttled::CancelRate(_))));
+
+        // An account's own limits apply from then on.
+        limiter.set_account_limits("bob", Some(RateLimits::new(5.0, 5.0, 1)));
+        bob.pending_orders.insert_order(Order::direct("BUY", "GME", 10, 100.0, 1, 1));
+        assert!(matches!(limiter.check_order(&bob), Err(Throttled::OpenOrders(1))));
+        assert!(limiter.show_usage(&bob).contains("Rejected requests: 3"));
+    }
+}
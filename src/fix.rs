@@ -0,0 +1,794 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::database::{Store, Cache, CacheBackend};
+
+use crate::exchange::{Exchange, Order, OrderStatus, Request, CancelOrder, AmendOrder, BufferCollection, ErrorCode};
+use crate::exchange::requests;
+use crate::exchange::filled::Trade;
+use crate::account::{Users, Credentials};
+use crate::parser::{self, Response, Status};
+use crate::server::Incoming;
+
+pub mod tags;
+pub mod message;
+pub mod session;
+pub use crate::fix::message::FixMessage;
+
+// Our CompID, clients send their messages to TargetCompID=RUSTX.
+pub const EXCHANGE_COMP_ID: &str = "RUSTX";
+
+/* Sent to the main thread by the FIX sessions. Sessions are named by the client's SenderCompID. */
+pub enum FixCommand {
+    Logon { session: String, username: String, password: String, events: mpsc::Sender<FixEvent> },
+    Disconnect(String),
+    NewOrder { session: String, cl_ord_id: String, symbol: String, side: String, quantity: i32, price: f64, poss_dup: bool },
+    Cancel { session: String, cl_ord_id: String, orig_cl_ord_id: String, symbol: String },
+    Replace { session: String, cl_ord_id: String, orig_cl_ord_id: String, symbol: String, quantity: i32, price: f64 }
+}
+
+// Sent to a FIX session by the main thread.
+pub enum FixEvent {
+    LoggedOn,
+    Rejected(String),       // The Logon was rejected, with this reason
+    Message(FixMessage)     // An application message, the session adds the header
+}
+
+impl FixCommand {
+    /* Translate a NewOrderSingle (D), OrderCancelRequest (F) or OrderCancelReplaceRequest (G).
+     * Returns the tag that was missing or invalid, and why, if we can't.
+     **/
+    pub fn from_message(message: &FixMessage, session: &str) -> Result<FixCommand, (u32, String)> {
+        let required = |tag: u32| -> Result<String, (u32, String)> {
+            match message.get(tag) {
+                Some(value) if !value.is_empty() => Ok(value.to_string()),
+                _ => Err((tag, format!["Required tag {} missing", tag]))
+            }
+        };
+        let session = session.to_string();
+        let cl_ord_id = required(tags::CL_ORD_ID)?;
+        let symbol = required(tags::SYMBOL)?.to_uppercase();
+
+        let side = match required(tags::SIDE)?.as_str() {
+            "1" => "BUY".to_string(),
+            "2" => "SELL".to_string(),
+            _ => return Err((tags::SIDE, "Only Side 1 (Buy) and 2 (Sell) are supported".to_string()))
+        };
+        // Only the new order and the replacement carry a quantity and price.
+        let quantity_price = || -> Result<(i32, f64), (u32, String)> {
+            if required(tags::ORD_TYPE)? != "2" {
+                return Err((tags::ORD_TYPE, "Only OrdType 2 (Limit) is supported".to_string()));
+            }
+            let quantity = match required(tags::ORDER_QTY)?.parse::<f64>() {
+                Ok(quantity) if quantity > 0.0 && quantity.fract() == 0.0 && quantity <= i32::MAX as f64 => quantity as i32,
+                _ => return Err((tags::ORDER_QTY, "OrderQty must be a whole number of shares greater than 0".to_string()))
+            };
+            let price = match required(tags::PRICE)?.parse::<f64>() {
+                Ok(price) if price > 0.0 => price,
+                _ => return Err((tags::PRICE, "Price must be greater than 0".to_string()))
+            };
+            Ok((quantity, price))
+        };
+
+        match message.msg_type() {
+            "D" => {
+                let (quantity, price) = quantity_price()?;
+                // The client may be resending an order it isn't sure we received.
+                let poss_dup = message.get(tags::POSS_DUP_FLAG) == Some("Y");
+                Ok(FixCommand::NewOrder { session, cl_ord_id, symbol, side, quantity, price, poss_dup })
+            },
+            "F" => {
+                let orig_cl_ord_id = required(tags::ORIG_CL_ORD_ID)?;
+                Ok(FixCommand::Cancel { session, cl_ord_id, orig_cl_ord_id, symbol })
+            },
+            _ => {
+                let orig_cl_ord_id = required(tags::ORIG_CL_ORD_ID)?;
+                let (quantity, price) = quantity_price()?;
+                Ok(FixCommand::Replace { session, cl_ord_id, orig_cl_ord_id, symbol, quantity, price })
+            }
+        }
+    }
+}
+
+// A logged on session, as the main thread sees it.
+struct FixSession {
+    username: String,
+    password: String,           // To start a new exchange session if the last one expired
+    credentials: Credentials,   // The exchange session the orders are sent with
+    events: mpsc::Sender<FixEvent>
+}
+
+// An order placed over FIX, so we can report its fills.
+struct FixOrder {
+    session: String,
+    cl_ord_id: String,      // As the client sent it
+    symbol: String,
+    side: String,
+    quantity: i32,
+    price: f64,
+    cum_qty: i32,
+    notional: f64           // Sum of the price * quantity of each fill, for AvgPx
+}
+
+impl FixOrder {
+    fn leaves_qty(&self) -> i32 {
+        self.quantity - self.cum_qty
+    }
+
+    fn avg_px(&self) -> f64 {
+        if self.cum_qty == 0 { 0.0 } else { self.notional / self.cum_qty as f64 }
+    }
+
+    // OrdStatus: New (0), Partially filled (1) or Filled (2)
+    fn ord_status(&self) -> &'static str {
+        if self.cum_qty == 0 {
+            "0"
+        } else if self.cum_qty < self.quantity {
+            "1"
+        } else {
+            "2"
+        }
+    }
+
+    fn execution_report(&self, order_id: i64, exec_id: u64, exec_type: &str, fill: Option<&Trade>) -> FixMessage {
+        let side = if self.side == "BUY" { "1" } else { "2" };
+        let mut report = FixMessage::new("8")
+            .with(tags::ORDER_ID, order_id)
+            .with(tags::CL_ORD_ID, &self.cl_ord_id)
+            .with(tags::EXEC_ID, exec_id)
+            .with(tags::EXEC_TYPE, exec_type)
+            .with(tags::ORD_STATUS, self.ord_status())
+            .with(tags::SYMBOL, &self.symbol)
+            .with(tags::SIDE, side)
+            .with(tags::ORD_TYPE, "2")
+            .with(tags::ORDER_QTY, self.quantity)
+            .with(tags::PRICE, format!["{:.2}", self.price]);
+        if let Some(trade) = fill {
+            report = report.with(tags::LAST_QTY, trade.exchanged).with(tags::LAST_PX, format!["{:.2}", trade.price]);
+        }
+        report.with(tags::LEAVES_QTY, self.leaves_qty().max(0))
+              .with(tags::CUM_QTY, self.cum_qty)
+              .with(tags::AVG_PX, format!["{:.4}", self.avg_px()])
+              .with(tags::TRANSACT_TIME, message::timestamp())
+    }
+}
+
+/* Turns FIX commands into Requests, and the results into ExecutionReports.
+ * Lives on the main thread, next to the exchange.
+ **/
+pub struct FixGateway {
+    sessions: HashMap<String, FixSession>,
+    orders: HashMap<i64, FixOrder>,                 // order id -> order
+    client_orders: HashMap<(String, String), i64>,  // (session, lowercased ClOrdID) -> order id
+    next_exec_id: u64
+}
+
+impl Default for FixGateway {
+    fn default() -> Self {
+        FixGateway::new()
+    }
+}
+
+impl FixGateway {
+    pub fn new() -> Self {
+        FixGateway {
+            sessions: HashMap::new(),
+            orders: HashMap::new(),
+            client_orders: HashMap::new(),
+            // Start from the time, so ExecIDs stay unique across restarts.
+            next_exec_id: chrono::Utc::now().timestamp_millis() as u64 * 1000
+        }
+    }
+
+    pub fn service(&mut self, command: FixCommand, exchange: &mut Exchange, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) {
+        match command {
+            FixCommand::Logon { session, username, password, events } => {
+                if self.sessions.contains_key(&session) {
+                    events.send(FixEvent::Rejected(format!["{} is already logged on", session])).ok();
+                    return;
+                }
+                // Usernames and passwords are lowercase, like everything the console reads.
+                let (username, password) = (username.to_lowercase(), password.to_lowercase());
+                match users.login(&username, &password, store) {
+                    Ok(token) => {
+                        events.send(FixEvent::LoggedOn).ok();
+                        let credentials = Credentials::Session(token);
+                        self.sessions.insert(session, FixSession { username, password, credentials, events });
+                    },
+                    Err(e) => {
+                        events.send(FixEvent::Rejected(e.to_string())).ok();
+                    }
+                }
+            },
+            FixCommand::Disconnect(session) => {
+                self.sessions.remove(&session);
+            },
+            FixCommand::NewOrder { session, cl_ord_id, symbol, side, quantity, price, poss_dup } => {
+                let mut fix_order = FixOrder { session: session.clone(), cl_ord_id, symbol, side, quantity, price, cum_qty: 0, notional: 0.0 };
+                let credentials = match self.credentials(&session, users, store) {
+                    Ok(credentials) => credentials,
+                    Err(text) => return self.reject_order(&fix_order, 99, &text)
+                };
+                // Client order ids are case insensitive, as they are on the console.
+                let client_id = fix_order.cl_ord_id.to_lowercase();
+                if !requests::is_valid_client_id(&client_id) {
+                    let text = format!["ClOrdID must be 1 to {} letters, numbers, dashes or underscores", requests::MAX_CLIENT_ID_LEN];
+                    return self.reject_order(&fix_order, 99, &text);
+                }
+
+                let mut order = Order::from(fix_order.side.clone(), fix_order.symbol.clone(), quantity, price, OrderStatus::PENDING, None);
+                order.client_id = Some(client_id.clone());
+                let response = parser::service_request(Request::OrderReq(order, credentials), exchange, users, buffers, store, cache);
+                let placed = match response.order.clone() {
+                    Some(placed) if response.is_ok() => placed,
+                    _ => return self.reject_order(&fix_order, ord_rej_reason(&response), &response.message)
+                };
+                // The exchange answers a ClOrdID it has already seen with that order, rather than placing another.
+                let duplicate = response.reports.is_empty();
+                if self.orders.contains_key(&placed.order_id) {
+                    if !poss_dup {
+                        return self.reject_order(&fix_order, 6, "Duplicate ClOrdID");
+                    }
+                    // A resend of an order we've acknowledged, tell the client where it stands.
+                    let exec_id = self.exec_id();
+                    let report = self.orders[&placed.order_id].execution_report(placed.order_id, exec_id, "I", None);
+                    return self.send(&session, report);
+                }
+
+                // Fills from this request are reported once we've acknowledged the order.
+                // An order placed before we restarted may have been filled already.
+                fix_order.price = placed.price;
+                fix_order.cum_qty = placed.filled - filled_by(&exchange.trade_prints, placed.order_id);
+                fix_order.notional = fix_order.cum_qty as f64 * placed.price;
+                if duplicate && poss_dup {
+                    // We don't know this order, ex. it was placed before we restarted, so send its status from the exchange.
+                    let mut report = fix_order.execution_report(placed.order_id, self.exec_id(), "I", None);
+                    if matches!(placed.status, OrderStatus::CANCELLED) {
+                        set_field(&mut report, tags::ORD_STATUS, "4");
+                        set_field(&mut report, tags::LEAVES_QTY, "0");
+                        return self.send(&session, report);
+                    }
+                    self.send(&session, report);
+                } else {
+                    let report = fix_order.execution_report(placed.order_id, self.exec_id(), "0", None);
+                    self.send(&session, report);
+                }
+                self.track(placed.order_id, client_id, fix_order);
+            },
+            FixCommand::Cancel { session, cl_ord_id, orig_cl_ord_id, symbol } => {
+                let credentials = match self.credentials(&session, users, store) {
+                    Ok(credentials) => credentials,
+                    Err(text) => return self.reject_cancel(&session, &cl_ord_id, &orig_cl_ord_id, "1", &Response::error(Status::Unauthorized, &text))
+                };
+                let orig_client_id = orig_cl_ord_id.to_lowercase();
+                let cancel = CancelOrder { symbol, order_id: 0, username: String::new(), client_id: Some(orig_client_id.clone()) };
+                let response = parser::service_request(Request::CancelReq(cancel, credentials), exchange, users, buffers, store, cache);
+                if !response.is_ok() {
+                    return self.reject_cancel(&session, &cl_ord_id, &orig_cl_ord_id, "1", &response);
+                }
+
+                if let Some(order_id) = self.client_orders.remove(&(session.clone(), orig_client_id)) {
+                    if let Some(mut fix_order) = self.orders.remove(&order_id) {
+                        fix_order.cl_ord_id = cl_ord_id;
+                        let mut report = fix_order.execution_report(order_id, self.exec_id(), "4", None)
+                            .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id);
+                        set_field(&mut report, tags::ORD_STATUS, "4");
+                        set_field(&mut report, tags::LEAVES_QTY, "0");
+                        self.send(&session, report);
+                    }
+                }
+            },
+            FixCommand::Replace { session, cl_ord_id, orig_cl_ord_id, symbol, quantity, price } => {
+                let credentials = match self.credentials(&session, users, store) {
+                    Ok(credentials) => credentials,
+                    Err(text) => return self.reject_cancel(&session, &cl_ord_id, &orig_cl_ord_id, "2", &Response::error(Status::Unauthorized, &text))
+                };
+                let (client_id, orig_client_id) = (cl_ord_id.to_lowercase(), orig_cl_ord_id.to_lowercase());
+                if !requests::is_valid_client_id(&client_id) {
+                    let response = Response::error(Status::BadRequest, "ClOrdID is not a valid client order id");
+                    return self.reject_cancel(&session, &cl_ord_id, &orig_cl_ord_id, "2", &response);
+                }
+                let cancel = CancelOrder { symbol: symbol.clone(), order_id: 0, username: String::new(), client_id: Some(orig_client_id.clone()) };
+                let amend = AmendOrder { cancel, quantity, price, client_id: Some(client_id.clone()) };
+                let response = parser::service_request(Request::AmendReq(amend, credentials), exchange, users, buffers, store, cache);
+                let placed = match response.order.clone() {
+                    Some(placed) if response.is_ok() => placed,
+                    _ => return self.reject_cancel(&session, &cl_ord_id, &orig_cl_ord_id, "2", &response)
+                };
+
+                // The replacement is a new order for the new quantity, at the new price.
+                if let Some(order_id) = self.client_orders.remove(&(session.clone(), orig_client_id)) {
+                    self.orders.remove(&order_id);
+                }
+                let mut fix_order = FixOrder { session: session.clone(), cl_ord_id, symbol, side: placed.action.clone(), quantity, price: placed.price, cum_qty: 0, notional: 0.0 };
+                fix_order.cum_qty = placed.filled - filled_by(&exchange.trade_prints, placed.order_id);
+                fix_order.notional = fix_order.cum_qty as f64 * placed.price;
+                let report = fix_order.execution_report(placed.order_id, self.exec_id(), "5", None)
+                    .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id);
+                self.send(&session, report);
+                self.track(placed.order_id, client_id, fix_order);
+            }
+        }
+    }
+
+    /* Report the fills of orders placed over FIX, whether they were the resting order or the one that crossed. */
+    pub fn report_trades(&mut self, trades: &[Trade]) {
+        if self.orders.is_empty() {
+            return;
+        }
+        for trade in trades.iter() {
+            for order_id in [trade.filled_oid, trade.filler_oid].iter() {
+                let fix_order = match self.orders.get_mut(order_id) {
+                    Some(fix_order) => fix_order,
+                    None => continue
+                };
+                fix_order.cum_qty += trade.exchanged;
+                fix_order.notional += trade.exchanged as f64 * trade.price;
+                let (session, done) = (fix_order.session.clone(), fix_order.leaves_qty() <= 0);
+
+                let exec_id = self.exec_id();
+                let report = self.orders[order_id].execution_report(*order_id, exec_id, "F", Some(trade));
+                self.send(&session, report);
+                if done {
+                    if let Some(fix_order) = self.orders.remove(order_id) {
+                        self.client_orders.remove(&(fix_order.session, fix_order.cl_ord_id.to_lowercase()));
+                    }
+                }
+            }
+        }
+    }
+
+    fn track(&mut self, order_id: i64, client_id: String, fix_order: FixOrder) {
+        if fix_order.leaves_qty() > 0 {
+            self.client_orders.insert((fix_order.session.clone(), client_id), order_id);
+            self.orders.insert(order_id, fix_order);
+        }
+    }
+
+    /* The credentials to send this session's orders with.
+     * Exchange sessions expire when they aren't used, so we log in again if we have to.
+     **/
+    fn credentials(&mut self, session: &str, users: &mut Users, store: &mut dyn Store) -> Result<Credentials, String> {
+        let fix_session = match self.sessions.get_mut(session) {
+            Some(fix_session) => fix_session,
+            None => return Err("Not logged on.".to_string())
+        };
+        if let Credentials::Session(token) = &fix_session.credentials {
+            if users.authenticate_session(token, store).is_ok() {
+                return Ok(fix_session.credentials.clone());
+            }
+        }
+        match users.login(&fix_session.username, &fix_session.password, store) {
+            Ok(token) => {
+                fix_session.credentials = Credentials::Session(token);
+                Ok(fix_session.credentials.clone())
+            },
+            Err(e) => Err(Response::from(e).message)
+        }
+    }
+
+    // ExecIDs must be unique for the life of the exchange.
+    fn exec_id(&mut self) -> u64 {
+        self.next_exec_id += 1;
+        self.next_exec_id
+    }
+
+    fn reject_order(&mut self, fix_order: &FixOrder, reason: u32, text: &str) {
+        let mut report = fix_order.execution_report(0, self.exec_id(), "8", None)
+            .with(tags::ORD_REJ_REASON, reason)
+            .with(tags::TEXT, single_line(text));
+        set_field(&mut report, tags::ORDER_ID, "NONE");
+        set_field(&mut report, tags::ORD_STATUS, "8");
+        set_field(&mut report, tags::LEAVES_QTY, "0");
+        self.send(&fix_order.session, report);
+    }
+
+    /* OrderCancelReject, in response to a cancel (1) or a cancel/replace (2). */
+    fn reject_cancel(&mut self, session: &str, cl_ord_id: &str, orig_cl_ord_id: &str, response_to: &str, response: &Response) {
+        let order_id = self.client_orders.get(&(session.to_string(), orig_cl_ord_id.to_string())).copied();
+        let (order_id, ord_status) = match order_id.and_then(|id| self.orders.get(&id).map(|order| (id, order.ord_status()))) {
+            Some((id, status)) => (id.to_string(), status),
+            None => ("NONE".to_string(), "8")
+        };
+        // Unknown order (1), or other (99).
+        let reason = match response.code {
+            Some(ErrorCode::UnknownOrder) | Some(ErrorCode::NotOwner) => 1,
+            _ if response.status == Status::NotFound => 1,
+            _ => 99
+        };
+        let reject = FixMessage::new("9")
+            .with(tags::ORDER_ID, order_id)
+            .with(tags::CL_ORD_ID, cl_ord_id)
+            .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(tags::ORD_STATUS, ord_status)
+            .with(tags::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tags::CXL_REJ_REASON, reason)
+            .with(tags::TEXT, single_line(&response.message));
+        self.send(session, reject);
+    }
+
+    fn send(&self, session: &str, message: FixMessage) {
+        if let Some(fix_session) = self.sessions.get(session) {
+            fix_session.events.send(FixEvent::Message(message)).ok();
+        }
+    }
+}
+
+// The OrdRejReason of an order the exchange rejected.
+fn ord_rej_reason(response: &Response) -> u32 {
+    match response.code {
+        Some(ErrorCode::UnknownSymbol) => 1,
+        Some(ErrorCode::MarketHalted) => 2,         // Exchange closed
+        Some(ErrorCode::InsufficientFunds) | Some(ErrorCode::ShortSaleRejected) => 3, // Order exceeds limit
+        Some(ErrorCode::InvalidQuantity) => 13,     // Incorrect quantity
+        _ => 99
+    }
+}
+
+// The shares of an order that were filled by these trades.
+fn filled_by(trades: &[Trade], order_id: i64) -> i32 {
+    trades.iter()
+          .filter(|trade| trade.filled_oid == order_id || trade.filler_oid == order_id)
+          .map(|trade| trade.exchanged)
+          .sum()
+}
+
+fn set_field(message: &mut FixMessage, tag: u32, value: &str) {
+    if let Some(field) = message.fields.iter_mut().find(|(field, _)| *field == tag) {
+        field.1 = value.to_string();
+    }
+}
+
+// Text fields can't hold the delimiter, or span lines.
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/* Accept FIX 4.4 sessions on the given address, one thread per connection.
+ *
+ * Each session keeps its sequence numbers in the given cache.
+ * Returns the handle of the accepting thread, or an error if we can't listen on the address.
+ **/
+pub fn listen(address: &str, requests: mpsc::Sender<Incoming>, cache: CacheBackend) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(address)?;
+    dark_green!("Accepting FIX sessions on {} as {}\n", listener.local_addr()?, EXCHANGE_COMP_ID);
+
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let requests = requests.clone();
+                    let cache = cache.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = session::run(stream, &requests, &cache) {
+                            eprintln!("[FIX]: Session ended: {}", e);
+                        }
+                    });
+                },
+                Err(e) => eprintln!("Failed to accept a connection: {}", e)
+            }
+        }
+    });
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::{Duration, Instant};
+
+    use crate::database::{MarketStore, MemoryStore, MemoryCache, StoreBackend};
+    use crate::fix::message::FixReader;
+    use crate::runtime::Runtime;
+    use crate::server::ClientRequest;
+
+    // How long we wait for the exchange to answer. Logons hash the password,
+    // which is slow in debug builds while the other tests are running.
+    const WAIT: Duration = Duration::from_secs(30);
+
+    /* An in-memory exchange with a GME market and the accounts admin, alice and bob (who may go short),
+     * accepting FIX sessions on the returned address. Stopped by EXIT when dropped.
+     **/
+    struct TestExchange {
+        address: SocketAddr,
+        requests: mpsc::Sender<Incoming>,
+        runtime: Option<thread::JoinHandle<()>>
+    }
+
+    impl TestExchange {
+        fn start() -> Self {
+            let mut store = MemoryStore::new();
+            store.upgrade_db(&mut "add,GME,GameStop\n".as_bytes(), "rustx");
+            let cache = CacheBackend::Memory(MemoryCache::new());
+            let (requests, incoming) = mpsc::channel();
+
+            let session_cache = cache.clone();
+            let runtime = thread::spawn(move || {
+                let mut runtime = Runtime::with_backends(StoreBackend::Memory(store), session_cache);
+                for line in ["account create admin password", "account create alice password", "account create bob password",
+                             "short enable bob 1000 100000 admin password"].iter() {
+                    let response = runtime.service(parser::tokenize_input(line.to_string()).unwrap());
+                    assert!(response.is_ok(), "{}: {}", line, response.message);
+                }
+                runtime.serve(incoming);
+                runtime.shutdown();
+            });
+
+            // The same as listen, on a port of our choosing.
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let session_requests = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let (requests, cache) = (session_requests.clone(), cache.clone());
+                    thread::spawn(move || session::run(stream.unwrap(), &requests, &cache));
+                }
+            });
+            TestExchange { address, requests, runtime: Some(runtime) }
+        }
+    }
+
+    impl Drop for TestExchange {
+        fn drop(&mut self) {
+            let (reply, _replies) = mpsc::channel();
+            self.requests.send(Incoming::Line(ClientRequest { line: "exit".to_string(), console: true, reply })).ok();
+            if let Some(runtime) = self.runtime.take() {
+                // Don't hide the failure of a test behind the runtime's.
+                if runtime.join().is_err() && !thread::panicking() {
+                    panic!("The runtime panicked.");
+                }
+            }
+        }
+    }
+
+    // The client's side of a session, it numbers what it sends itself.
+    struct TestClient {
+        comp_id: String,
+        writer: TcpStream,
+        reader: FixReader,
+        next_out: u64
+    }
+
+    impl TestClient {
+        fn connect(exchange: &TestExchange, comp_id: &str, next_out: u64) -> Self {
+            let stream = TcpStream::connect(exchange.address).unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            TestClient { comp_id: comp_id.to_string(), writer: stream.try_clone().unwrap(), reader: FixReader::new(stream), next_out }
+        }
+
+        fn send(&mut self, message: FixMessage) {
+            let seq = self.next_out;
+            self.send_as(message, seq);
+            self.next_out += 1;
+        }
+
+        // Send with this sequence number, whatever the next one is.
+        fn send_as(&mut self, message: FixMessage, seq: u64) {
+            let mut framed = FixMessage::new(message.msg_type())
+                .with(tags::SENDER_COMP_ID, &self.comp_id)
+                .with(tags::TARGET_COMP_ID, EXCHANGE_COMP_ID)
+                .with(tags::MSG_SEQ_NUM, seq)
+                .with(tags::SENDING_TIME, message::timestamp());
+            framed.fields.extend(message.fields.into_iter().skip(1));
+            self.writer.write_all(&framed.encode()).unwrap();
+        }
+
+        fn receive(&mut self) -> FixMessage {
+            let start = Instant::now();
+            while start.elapsed() < WAIT {
+                if let Some(message) = self.reader.read_message().unwrap() {
+                    return message;
+                }
+            }
+            panic!("{} received nothing.", self.comp_id);
+        }
+
+        // Receive the next message, which must be of this type.
+        fn expect(&mut self, msg_type: &str) -> FixMessage {
+            let message = self.receive();
+            assert_eq!(message.msg_type(), msg_type, "{:?}", message);
+            message
+        }
+
+        fn logon(&mut self, username: &str) -> FixMessage {
+            let logon = FixMessage::new("A")
+                .with(tags::ENCRYPT_METHOD, 0)
+                .with(tags::HEART_BT_INT, 30)
+                .with(tags::USERNAME, username)
+                .with(tags::PASSWORD, "password");
+            self.send(logon);
+            self.receive()
+        }
+
+        // Wait for the exchange to end the session, so the comp id may log on again.
+        fn closed(mut self) {
+            let start = Instant::now();
+            let mut byte = [0; 1];
+            self.writer.set_read_timeout(Some(WAIT)).unwrap();
+            while start.elapsed() < WAIT {
+                match self.writer.read(&mut byte) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => ()
+                }
+            }
+            panic!("{} is still connected.", self.comp_id);
+        }
+
+        fn logout(mut self) {
+            self.send(FixMessage::new("5"));
+            self.expect("5");
+            self.closed();
+        }
+    }
+
+    fn limit_order(msg_type: &str, cl_ord_id: &str, side: &str, quantity: i32, price: f64) -> FixMessage {
+        FixMessage::new(msg_type)
+            .with(tags::CL_ORD_ID, cl_ord_id)
+            .with(tags::SYMBOL, "GME")
+            .with(tags::SIDE, side)
+            .with(tags::ORD_TYPE, "2")
+            .with(tags::ORDER_QTY, quantity)
+            .with(tags::PRICE, price)
+    }
+
+    fn cancel(cl_ord_id: &str, orig_cl_ord_id: &str) -> FixMessage {
+        FixMessage::new("F")
+            .with(tags::CL_ORD_ID, cl_ord_id)
+            .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(tags::SYMBOL, "GME")
+            .with(tags::SIDE, "1")
+    }
+
+    fn assert_fields(message: &FixMessage, expected: &[(u32, &str)]) {
+        for (tag, value) in expected.iter() {
+            assert_eq!(message.get(*tag), Some(*value), "tag {} of {:?}", tag, message);
+        }
+    }
+
+    #[test]
+    fn logon() {
+        let exchange = TestExchange::start();
+
+        let mut client = TestClient::connect(&exchange, "WRONG", 1);
+        client.send(FixMessage::new("A").with(tags::HEART_BT_INT, 30).with(tags::USERNAME, "alice").with(tags::PASSWORD, "guess"));
+        client.expect("5");
+        client.closed();
+
+        let mut client = TestClient::connect(&exchange, "ALICE", 1);
+        let reply = client.logon("alice");
+        assert_fields(&reply, &[(tags::MSG_TYPE, "A"), (tags::MSG_SEQ_NUM, "1"), (tags::TARGET_COMP_ID, "ALICE"), (tags::HEART_BT_INT, "30")]);
+
+        // One session per comp id.
+        let mut twin = TestClient::connect(&exchange, "ALICE", 1);
+        let reply = twin.logon("alice");
+        assert_fields(&reply, &[(tags::MSG_TYPE, "5"), (tags::TEXT, "ALICE is already logged on")]);
+        twin.closed();
+
+        client.send(FixMessage::new("1").with(tags::TEST_REQ_ID, "ping"));
+        assert_fields(&client.expect("0"), &[(tags::TEST_REQ_ID, "ping"), (tags::MSG_SEQ_NUM, "2")]);
+        client.logout();
+    }
+
+    #[test]
+    fn sequence_numbers_survive_reconnects() {
+        let exchange = TestExchange::start();
+
+        let mut client = TestClient::connect(&exchange, "ALICE", 1);
+        client.logon("alice");
+        client.send(FixMessage::new("1").with(tags::TEST_REQ_ID, "ping"));
+        client.expect("0");
+        client.logout();
+
+        // We sent 3 and received 3, so both sides carry on from 4.
+        let mut client = TestClient::connect(&exchange, "ALICE", 4);
+        assert_fields(&client.logon("alice"), &[(tags::MSG_TYPE, "A"), (tags::MSG_SEQ_NUM, "4")]);
+        client.logout();
+
+        // Starting over without ResetSeqNumFlag is refused.
+        let mut client = TestClient::connect(&exchange, "ALICE", 1);
+        let reply = client.logon("alice");
+        assert_fields(&reply, &[(tags::MSG_TYPE, "5"), (tags::TEXT, "MsgSeqNum too low, expecting 6 but received 1")]);
+        client.closed();
+
+        // With it, both sides start over at 1.
+        let mut client = TestClient::connect(&exchange, "ALICE", 1);
+        let logon = FixMessage::new("A").with(tags::HEART_BT_INT, 30).with(tags::RESET_SEQ_NUM_FLAG, "Y")
+            .with(tags::USERNAME, "alice").with(tags::PASSWORD, "password");
+        client.send(logon);
+        assert_fields(&client.expect("A"), &[(tags::MSG_SEQ_NUM, "1"), (tags::RESET_SEQ_NUM_FLAG, "Y")]);
+        client.logout();
+    }
+
+    #[test]
+    fn resend_request_and_gap_fill() {
+        let exchange = TestExchange::start();
+        let mut client = TestClient::connect(&exchange, "ALICE", 1);
+        client.logon("alice");
+        client.send(limit_order("D", "a1", "1", 10, 100.0));
+        let ack = client.expect("8");
+        client.send(FixMessage::new("1").with(tags::TEST_REQ_ID, "ping"));
+        client.expect("0");
+
+        // The Logon and the Heartbeat are gap filled, the ExecutionReport is sent again as it was.
+        client.send(FixMessage::new("2").with(tags::BEGIN_SEQ_NO, 1).with(tags::END_SEQ_NO, 0));
+        assert_fields(&client.expect("4"), &[(tags::MSG_SEQ_NUM, "1"), (tags::GAP_FILL_FLAG, "Y"), (tags::NEW_SEQ_NO, "2")]);
+        let resent = client.expect("8");
+        assert_fields(&resent, &[(tags::MSG_SEQ_NUM, "2"), (tags::POSS_DUP_FLAG, "Y"), (tags::CL_ORD_ID, "a1"), (tags::EXEC_ID, ack.get(tags::EXEC_ID).unwrap())]);
+        assert_eq!(resent.get(tags::ORIG_SENDING_TIME), ack.get(tags::SENDING_TIME));
+        assert_fields(&client.expect("4"), &[(tags::MSG_SEQ_NUM, "3"), (tags::GAP_FILL_FLAG, "Y"), (tags::NEW_SEQ_NO, "4")]);
+
+        // Skip 5, the exchange asks for it and ignores what comes after the gap.
+        client.send_as(limit_order("D", "a2", "1", 10, 100.0), 6);
+        assert_fields(&client.expect("2"), &[(tags::BEGIN_SEQ_NO, "5"), (tags::END_SEQ_NO, "0")]);
+
+        // 5 was a Heartbeat, so we fill it, then resend the order.
+        client.send_as(FixMessage::new("4").with(tags::GAP_FILL_FLAG, "Y").with(tags::NEW_SEQ_NO, 6), 5);
+        client.send_as(limit_order("D", "a2", "1", 10, 100.0).with(tags::POSS_DUP_FLAG, "Y").with(tags::ORIG_SENDING_TIME, message::timestamp()), 6);
+        client.next_out = 7;
+        assert_fields(&client.expect("8"), &[(tags::CL_ORD_ID, "a2"), (tags::EXEC_TYPE, "0")]);
+
+        // A message we already have is dropped, unless it's a possible duplicate the session ends.
+        client.send_as(FixMessage::new("1").with(tags::TEST_REQ_ID, "old").with(tags::POSS_DUP_FLAG, "Y"), 2);
+        client.send(FixMessage::new("1").with(tags::TEST_REQ_ID, "new"));
+        assert_fields(&client.expect("0"), &[(tags::TEST_REQ_ID, "new")]);
+        client.send_as(FixMessage::new("1").with(tags::TEST_REQ_ID, "old"), 2);
+        assert_fields(&client.expect("5"), &[(tags::TEXT, "MsgSeqNum too low, expecting 8 but received 2")]);
+        client.closed();
+    }
+
+    #[test]
+    fn orders_are_reported() {
+        let exchange = TestExchange::start();
+        let mut alice = TestClient::connect(&exchange, "ALICE", 1);
+        alice.logon("alice");
+        let mut bob = TestClient::connect(&exchange, "BOB", 1);
+        bob.logon("bob");
+
+        // ClOrdIDs are echoed as they were sent.
+        alice.send(limit_order("D", "Buy-1", "1", 10, 100.0));
+        let ack = alice.expect("8");
+        assert_fields(&ack, &[(tags::CL_ORD_ID, "Buy-1"), (tags::EXEC_TYPE, "0"), (tags::ORD_STATUS, "0"), (tags::LEAVES_QTY, "10"), (tags::CUM_QTY, "0")]);
+        let order_id = ack.get(tags::ORDER_ID).unwrap().to_string();
+
+        // Sending it again is a mistake, unless it's flagged as a possible duplicate.
+        alice.send(limit_order("D", "Buy-1", "1", 10, 100.0));
+        assert_fields(&alice.expect("8"), &[(tags::EXEC_TYPE, "8"), (tags::ORD_STATUS, "8"), (tags::ORD_REJ_REASON, "6")]);
+        alice.send(limit_order("D", "Buy-1", "1", 10, 100.0).with(tags::POSS_DUP_FLAG, "Y").with(tags::ORIG_SENDING_TIME, message::timestamp()));
+        assert_fields(&alice.expect("8"), &[(tags::EXEC_TYPE, "I"), (tags::ORD_STATUS, "0"), (tags::ORDER_ID, &order_id), (tags::LEAVES_QTY, "10")]);
+
+        // Unsupported orders are rejected before they reach the exchange.
+        let mut market_order = limit_order("D", "Buy-2", "1", 10, 100.0);
+        set_field(&mut market_order, tags::ORD_TYPE, "1");
+        alice.send(market_order);
+        assert_fields(&alice.expect("3"), &[(tags::REF_TAG_ID, "40"), (tags::SESSION_REJECT_REASON, "5")]);
+
+        // The replacement is a new order.
+        alice.send(limit_order("G", "Buy-3", "1", 20, 101.0).with(tags::ORIG_CL_ORD_ID, "Buy-1"));
+        let replaced = alice.expect("8");
+        assert_fields(&replaced, &[(tags::EXEC_TYPE, "5"), (tags::CL_ORD_ID, "Buy-3"), (tags::ORIG_CL_ORD_ID, "Buy-1"), (tags::ORDER_QTY, "20"), (tags::PRICE, "101.00"), (tags::LEAVES_QTY, "20")]);
+        assert_ne!(replaced.get(tags::ORDER_ID), Some(order_id.as_str()));
+
+        // Bob's sell crosses, both sides hear about the fill.
+        bob.send(limit_order("D", "Sell-1", "2", 5, 100.0));
+        assert_fields(&bob.expect("8"), &[(tags::EXEC_TYPE, "0"), (tags::CL_ORD_ID, "Sell-1")]);
+        assert_fields(&bob.expect("8"), &[(tags::EXEC_TYPE, "F"), (tags::ORD_STATUS, "2"), (tags::LAST_QTY, "5"), (tags::LAST_PX, "101.00"), (tags::LEAVES_QTY, "0")]);
+        assert_fields(&alice.expect("8"), &[(tags::EXEC_TYPE, "F"), (tags::CL_ORD_ID, "Buy-3"), (tags::ORD_STATUS, "1"), (tags::CUM_QTY, "5"), (tags::LEAVES_QTY, "15"), (tags::AVG_PX, "101.0000")]);
+
+        alice.send(cancel("Cancel-1", "Buy-3"));
+        assert_fields(&alice.expect("8"), &[(tags::EXEC_TYPE, "4"), (tags::ORD_STATUS, "4"), (tags::CL_ORD_ID, "Cancel-1"), (tags::ORIG_CL_ORD_ID, "Buy-3"), (tags::CUM_QTY, "5"), (tags::LEAVES_QTY, "0")]);
+        alice.send(cancel("Cancel-2", "Buy-3"));
+        assert_fields(&alice.expect("9"), &[(tags::CL_ORD_ID, "Cancel-2"), (tags::CXL_REJ_RESPONSE_TO, "1"), (tags::CXL_REJ_REASON, "1")]);
+
+        // A possible duplicate of an order that's no longer live reports its final state.
+        alice.send(limit_order("D", "Buy-1", "1", 10, 100.0).with(tags::POSS_DUP_FLAG, "Y").with(tags::ORIG_SENDING_TIME, message::timestamp()));
+        assert_fields(&alice.expect("8"), &[(tags::EXEC_TYPE, "I"), (tags::ORD_STATUS, "4"), (tags::ORDER_ID, &order_id), (tags::LEAVES_QTY, "0")]);
+
+        alice.logout();
+        bob.logout();
+    }
+}
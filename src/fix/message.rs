@@ -0,0 +1,242 @@
+use std::io::{self, Read};
+use std::net::TcpStream;
+
+use chrono::Utc;
+
+use crate::fix::tags;
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+
+// The most we'll buffer while waiting for the rest of a message, and the largest BodyLength we accept.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/* A FIX message, as the tag=value fields in the order they were sent.
+ * BeginString, BodyLength and CheckSum are only added when encoding.
+ **/
+#[derive(Debug, Clone)]
+pub struct FixMessage {
+    pub fields: Vec<(u32, String)>
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        FixMessage {
+            fields: vec![(tags::MSG_TYPE, msg_type.to_string())]
+        }
+    }
+
+    pub fn with<T: ToString>(mut self, tag: u32, value: T) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(field, _)| *field == tag).map(|(_, value)| value.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tags::MSG_TYPE).unwrap_or("")
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(tags::MSG_SEQ_NUM).and_then(|seq| seq.parse::<u64>().ok())
+    }
+
+    pub fn is_poss_dup(&self) -> bool {
+        self.get(tags::POSS_DUP_FLAG) == Some("Y")
+    }
+
+    /* Session messages are never resent, they are gap filled instead. */
+    pub fn is_admin(&self) -> bool {
+        matches!(self.msg_type(), "0" | "1" | "2" | "3" | "4" | "5" | "A")
+    }
+
+    /* Parse a complete message, checking its BeginString, BodyLength and CheckSum. */
+    pub fn parse(raw: &[u8]) -> Result<FixMessage, String> {
+        let mut fields = Vec::new();
+        for field in raw.split(|byte| *byte == SOH).filter(|field| !field.is_empty()) {
+            let field = String::from_utf8_lossy(field);
+            let (tag, value) = match field.split_once('=') {
+                Some((tag, value)) => (tag, value),
+                None => return Err(format!["Field \"{}\" has no tag.", field])
+            };
+            match tag.parse::<u32>() {
+                Ok(tag) => fields.push((tag, value.to_string())),
+                Err(_) => return Err(format!["\"{}\" is not a tag.", tag])
+            }
+        }
+
+        match fields.first() {
+            Some((tags::BEGIN_STRING, begin)) if begin == BEGIN_STRING => (),
+            _ => return Err(format!["Messages must start with 8={}.", BEGIN_STRING])
+        }
+        // Everything after BodyLength, up to CheckSum.
+        let body_start = match raw.windows(3).position(|window| window == b"\x019=") {
+            Some(index) => index + 1 + raw[index + 1..].iter().position(|byte| *byte == SOH).unwrap_or(0) + 1,
+            None => return Err("The message has no BodyLength.".to_string())
+        };
+        let trailer_start = match raw.windows(4).rposition(|window| window == b"\x0110=") {
+            Some(index) => index + 1,
+            None => return Err("The message has no CheckSum.".to_string())
+        };
+        let body_length = fields.get(1).filter(|(tag, _)| *tag == tags::BODY_LENGTH).and_then(|(_, length)| length.parse::<usize>().ok());
+        if body_length != Some(trailer_start.saturating_sub(body_start)) {
+            return Err("The BodyLength is wrong.".to_string());
+        }
+        let expected = format!["{:03}", checksum(&raw[..trailer_start])];
+        if fields.last().map(|(_, sum)| sum.as_str()) != Some(expected.as_str()) {
+            return Err(format!["The CheckSum is wrong, expected {}.", expected]);
+        }
+
+        // Drop the BeginString, BodyLength and CheckSum, they're added back when encoding.
+        fields.retain(|(tag, _)| *tag != tags::BEGIN_STRING && *tag != tags::BODY_LENGTH && *tag != tags::CHECKSUM);
+        Ok(FixMessage { fields })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in self.fields.iter() {
+            body.extend_from_slice(format!["{}={}", tag, value].as_bytes());
+            body.push(SOH);
+        }
+        let mut message = format!["8={}\x019={}\x01", BEGIN_STRING, body.len()].into_bytes();
+        message.append(&mut body);
+        let sum = checksum(&message);
+        message.extend_from_slice(format!["10={:03}\x01", sum].as_bytes());
+        message
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().map(|byte| *byte as u32).sum::<u32>() % 256
+}
+
+// The time format of SendingTime and TransactTime, ex. 20210614-17:45:03.123
+pub fn timestamp() -> String {
+    Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+/* Splits the bytes read from a connection into messages. */
+pub struct FixReader {
+    stream: TcpStream,
+    buffer: Vec<u8>
+}
+
+impl FixReader {
+    pub fn new(stream: TcpStream) -> Self {
+        FixReader {
+            stream,
+            buffer: Vec::new()
+        }
+    }
+
+    /* Returns the next message, or None if the read timed out first.
+     * Garbled messages are dropped, like the spec asks, and we carry on with the next one.
+     **/
+    pub fn read_message(&mut self) -> io::Result<Option<FixMessage>> {
+        loop {
+            if let Some(raw) = self.next_frame() {
+                match FixMessage::parse(&raw?) {
+                    Ok(message) => return Ok(Some(message)),
+                    Err(e) => {
+                        eprintln!("[FIX]: Dropped a garbled message: {}", e);
+                        continue;
+                    }
+                }
+            }
+
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the client disconnected")),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(e)
+            }
+            if self.buffer.len() > MAX_MESSAGE_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+            }
+        }
+    }
+
+    /* Take the bytes of the next complete message out of the buffer, if we have them all.
+     * Errors if the message says its body is longer than we'll read.
+     **/
+    fn next_frame(&mut self) -> Option<io::Result<Vec<u8>>> {
+        // Skip anything before the start of a message.
+        let start = self.buffer.windows(2).position(|window| window == b"8=")?;
+        self.buffer.drain(..start);
+
+        // 8=FIX.4.4|9=LEN| then LEN bytes of body, then 10=XXX|
+        let length_start = self.buffer.windows(3).position(|window| window == b"\x019=")? + 3;
+        let length_end = length_start + self.buffer[length_start..].iter().position(|byte| *byte == SOH)?;
+        let body_length = match std::str::from_utf8(&self.buffer[length_start..length_end]).ok().and_then(|length| length.parse::<usize>().ok()) {
+            Some(length) => length,
+            None => {
+                // Not a message after all, look for the next one.
+                self.buffer.drain(..2);
+                return self.next_frame();
+            }
+        };
+        let trailer_start = match (length_end + 1).checked_add(body_length) {
+            Some(trailer_start) if body_length <= MAX_MESSAGE_LEN => trailer_start,
+            _ => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, "BodyLength is over the maximum message length")))
+        };
+        let end = trailer_start + self.buffer.get(trailer_start..)?.iter().position(|byte| *byte == SOH)? + 1;
+        Some(Ok(self.buffer.drain(..end).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    // A reader of whatever the returned stream writes.
+    fn connected_reader() -> (FixReader, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (FixReader::new(server), client)
+    }
+
+    #[test]
+    fn encoded_messages_parse() {
+        let message = FixMessage::new("D").with(tags::CL_ORD_ID, "abc-1").with(tags::SYMBOL, "GME");
+        let parsed = FixMessage::parse(&message.encode()).unwrap();
+        assert_eq!(parsed.fields, message.fields);
+    }
+
+    #[test]
+    fn wrong_checksum_is_rejected() {
+        let mut raw = FixMessage::new("0").encode();
+        let len = raw.len();
+        raw[len - 2] = if raw[len - 2] == b'0' { b'1' } else { b'0' };
+        assert!(FixMessage::parse(&raw).is_err());
+    }
+
+    #[test]
+    fn reads_messages_split_across_reads() {
+        let (mut reader, mut client) = connected_reader();
+        let raw = FixMessage::new("1").with(tags::TEST_REQ_ID, "t").encode();
+        let (first, second) = raw.split_at(raw.len() / 2);
+        client.write_all(b"junk").unwrap();
+        client.write_all(first).unwrap();
+        client.flush().unwrap();
+        reader.stream.set_read_timeout(Some(std::time::Duration::from_millis(50))).unwrap();
+        assert!(reader.read_message().unwrap().is_none());
+        client.write_all(second).unwrap();
+        assert_eq!(reader.read_message().unwrap().unwrap().get(tags::TEST_REQ_ID), Some("t"));
+    }
+
+    #[test]
+    fn huge_body_length_is_rejected() {
+        for length in [usize::MAX.to_string(), (MAX_MESSAGE_LEN + 1).to_string()].iter() {
+            let (mut reader, mut client) = connected_reader();
+            client.write_all(format!["8=FIX.4.4\x019={}\x0135=0\x01", length].as_bytes()).unwrap();
+            let error = reader.read_message().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
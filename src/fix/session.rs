@@ -0,0 +1,369 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::fix::{FixCommand, FixEvent, EXCHANGE_COMP_ID};
+use crate::fix::message::{self, FixMessage, FixReader};
+use crate::fix::tags;
+use crate::server::Incoming;
+use crate::database::{Cache, CacheBackend};
+
+// How often we wake up to send reports and check the heartbeat timers.
+const TICK: Duration = Duration::from_millis(50);
+// How long a client has to send its Logon after connecting.
+const LOGON_TIMEOUT: Duration = Duration::from_secs(30);
+
+/* One FIX session, from the exchange's side.
+ *
+ * Sequence numbers are stored in the cache, so a client can log back on where it left off:
+ *      fix:seqs:{comp_id}  => hash of the next incoming (in) and outgoing (out) sequence numbers
+ *      fix:sent:{comp_id}  => hash of outgoing sequence number -> message, for ResendRequests
+ * A Logon with ResetSeqNumFlag=Y starts both over at 1.
+ **/
+struct Session {
+    writer: TcpStream,
+    comp_id: String,            // The client's SenderCompID, our TargetCompID
+    next_in: u64,
+    next_out: u64,
+    heartbeat: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request_sent: bool,    // We're waiting for the client to answer a TestRequest
+    resend_requested: bool,     // We asked the client to fill a gap, and it hasn't yet
+    logged_out: bool,
+    cache: Box<dyn Cache>
+}
+
+/* Run a session on a new connection, until the client logs out or disconnects. */
+pub fn run(stream: TcpStream, requests: &mpsc::Sender<Incoming>, cache: &CacheBackend) -> Result<(), String> {
+    stream.set_read_timeout(Some(TICK)).map_err(|e| e.to_string())?;
+    let writer = stream.try_clone().map_err(|e| e.to_string())?;
+    let mut reader = FixReader::new(stream);
+
+    // The first message has to be a Logon.
+    let connected = Instant::now();
+    let logon = loop {
+        if let Some(message) = reader.read_message().map_err(|e| e.to_string())? {
+            break message;
+        }
+        if connected.elapsed() > LOGON_TIMEOUT {
+            return Err("no Logon received".to_string());
+        }
+    };
+    if logon.msg_type() != "A" {
+        return Err(format!["expected a Logon, got MsgType {}", logon.msg_type()]);
+    }
+    let comp_id = match logon.get(tags::SENDER_COMP_ID) {
+        Some(comp_id) if !comp_id.is_empty() => comp_id.to_string(),
+        _ => return Err("the Logon has no SenderCompID".to_string())
+    };
+    if logon.get(tags::TARGET_COMP_ID) != Some(EXCHANGE_COMP_ID) {
+        return Err(format!["{} sent a Logon to TargetCompID {:?}, we are {}", comp_id, logon.get(tags::TARGET_COMP_ID), EXCHANGE_COMP_ID]);
+    }
+    let heartbeat = logon.get(tags::HEART_BT_INT).and_then(|interval| interval.parse::<u64>().ok()).unwrap_or(30);
+
+    let mut session = Session {
+        writer,
+        comp_id: comp_id.clone(),
+        next_in: 1,
+        next_out: 1,
+        heartbeat: Duration::from_secs(heartbeat.max(1)),
+        last_sent: Instant::now(),
+        last_received: Instant::now(),
+        test_request_sent: false,
+        resend_requested: false,
+        logged_out: false,
+        cache: cache.connect()
+    };
+    session.load_seq_nums();
+
+    // The main thread checks the credentials, and sends us our reports from now on.
+    let (events_tx, events_rx) = mpsc::channel();
+    let username = logon.get(tags::USERNAME).unwrap_or("").to_string();
+    let password = logon.get(tags::PASSWORD).unwrap_or("").to_string();
+    let command = FixCommand::Logon { session: comp_id.clone(), username, password, events: events_tx };
+    if requests.send(Incoming::Fix(command)).is_err() {
+        return Ok(());
+    }
+    match events_rx.recv() {
+        Ok(FixEvent::LoggedOn) => (),
+        Ok(FixEvent::Rejected(text)) => {
+            // The comp id may be logged on elsewhere, so leave its sequence numbers alone.
+            let logout = session.with_header(FixMessage::new("5").with(tags::TEXT, &text), session.next_out, None);
+            session.writer.write_all(&logout.encode()).ok();
+            return Err(format!["rejected the Logon of {}: {}", comp_id, text]);
+        },
+        _ => return Ok(())
+    }
+    let reset = logon.get(tags::RESET_SEQ_NUM_FLAG) == Some("Y");
+    if reset {
+        session.reset_seq_nums();
+    }
+
+    let seq = logon.seq_num().unwrap_or(0);
+    if seq < session.next_in && !reset {
+        let text = format!["MsgSeqNum too low, expecting {} but received {}", session.next_in, seq];
+        session.send(FixMessage::new("5").with(tags::TEXT, &text)).ok();
+        requests.send(Incoming::Fix(FixCommand::Disconnect(comp_id))).ok();
+        return Err(text);
+    }
+    let mut reply = FixMessage::new("A").with(tags::ENCRYPT_METHOD, 0).with(tags::HEART_BT_INT, heartbeat);
+    if reset {
+        reply = reply.with(tags::RESET_SEQ_NUM_FLAG, "Y");
+    }
+    let result = session.send(reply).map_err(|e| e.to_string()).and_then(|_| {
+        if seq > session.next_in {
+            session.request_resend().map_err(|e| e.to_string())?;
+        } else {
+            session.next_in = seq + 1;
+            session.save_seq_nums();
+        }
+        dark_green!("[FIX]: {} logged on.\n", comp_id);
+        session.serve(&mut reader, &events_rx, requests).map_err(|e| e.to_string())
+    });
+
+    requests.send(Incoming::Fix(FixCommand::Disconnect(comp_id.clone()))).ok();
+    dark_green!("[FIX]: {} disconnected.\n", comp_id);
+    result
+}
+
+impl Session {
+    fn serve(&mut self, reader: &mut FixReader, events: &mpsc::Receiver<FixEvent>, requests: &mpsc::Sender<Incoming>) -> io::Result<()> {
+        while !self.logged_out {
+            if let Some(message) = reader.read_message()? {
+                self.last_received = Instant::now();
+                self.test_request_sent = false;
+                self.receive(message, requests)?;
+            }
+
+            // Reports from the main thread.
+            for event in events.try_iter() {
+                if let FixEvent::Message(message) = event {
+                    self.send(message)?;
+                }
+            }
+
+            // Keep the connection alive, and find out if the client went away.
+            if self.last_sent.elapsed() >= self.heartbeat {
+                self.send(FixMessage::new("0"))?;
+            }
+            let silence = self.last_received.elapsed();
+            if silence >= self.heartbeat * 2 && self.test_request_sent {
+                self.send(FixMessage::new("5").with(tags::TEXT, "No response to TestRequest"))?;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the client stopped responding"));
+            }
+            if silence >= self.heartbeat + self.heartbeat / 5 && !self.test_request_sent {
+                self.send(FixMessage::new("1").with(tags::TEST_REQ_ID, message::timestamp()))?;
+                self.test_request_sent = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, message: FixMessage, requests: &mpsc::Sender<Incoming>) -> io::Result<()> {
+        let seq = match message.seq_num() {
+            Some(seq) => seq,
+            None => {
+                self.send(FixMessage::new("5").with(tags::TEXT, "MsgSeqNum missing"))?;
+                self.logged_out = true;
+                return Ok(());
+            }
+        };
+
+        // A SequenceReset moves the next incoming sequence number, whatever its own number is.
+        if message.msg_type() == "4" {
+            return self.sequence_reset(&message, seq);
+        }
+        if seq > self.next_in {
+            // We missed something. Ask for it, and drop anything after the gap until it's filled.
+            if !self.resend_requested {
+                self.request_resend()?;
+            }
+            if message.msg_type() == "5" {
+                self.send(FixMessage::new("5"))?;
+                self.logged_out = true;
+            }
+            return Ok(());
+        }
+        if seq < self.next_in {
+            if !message.is_poss_dup() {
+                let text = format!["MsgSeqNum too low, expecting {} but received {}", self.next_in, seq];
+                self.send(FixMessage::new("5").with(tags::TEXT, text))?;
+                self.logged_out = true;
+            }
+            return Ok(());
+        }
+
+        self.next_in += 1;
+        self.resend_requested = false;
+        self.save_seq_nums();
+
+        match message.msg_type() {
+            "0" | "3" => Ok(()),
+            "1" => {
+                let test_req_id = message.get(tags::TEST_REQ_ID).unwrap_or("").to_string();
+                self.send(FixMessage::new("0").with(tags::TEST_REQ_ID, test_req_id))
+            },
+            "2" => {
+                let begin = message.get(tags::BEGIN_SEQ_NO).and_then(|seq| seq.parse::<u64>().ok()).unwrap_or(1);
+                let end = message.get(tags::END_SEQ_NO).and_then(|seq| seq.parse::<u64>().ok()).unwrap_or(0);
+                self.resend(begin, end)
+            },
+            "5" => {
+                self.send(FixMessage::new("5"))?;
+                self.logged_out = true;
+                Ok(())
+            },
+            "A" => self.reject(&message, seq, 0, "Already logged on"),
+            "D" | "F" | "G" => {
+                match FixCommand::from_message(&message, &self.comp_id) {
+                    Ok(command) => {
+                        if requests.send(Incoming::Fix(command)).is_err() {
+                            self.logged_out = true;
+                        }
+                        Ok(())
+                    },
+                    Err((tag, text)) => self.reject(&message, seq, tag, &text)
+                }
+            },
+            msg_type => {
+                let reject = FixMessage::new("j")
+                    .with(tags::REF_SEQ_NUM, seq)
+                    .with(tags::REF_MSG_TYPE, msg_type)
+                    .with(tags::BUSINESS_REJECT_REASON, 3) // Unsupported message type
+                    .with(tags::TEXT, format!["MsgType {} is not supported", msg_type]);
+                self.send(reject)
+            }
+        }
+    }
+
+    fn sequence_reset(&mut self, message: &FixMessage, seq: u64) -> io::Result<()> {
+        let new_seq = match message.get(tags::NEW_SEQ_NO).and_then(|seq| seq.parse::<u64>().ok()) {
+            Some(new_seq) => new_seq,
+            None => return self.reject(message, seq, tags::NEW_SEQ_NO, "NewSeqNo missing")
+        };
+        let gap_fill = message.get(tags::GAP_FILL_FLAG) == Some("Y");
+        if gap_fill && seq > self.next_in {
+            if !self.resend_requested {
+                self.request_resend()?;
+            }
+            return Ok(());
+        }
+        if gap_fill && seq < self.next_in {
+            return Ok(());
+        }
+        if new_seq < self.next_in {
+            return self.reject(message, seq, tags::NEW_SEQ_NO, "NewSeqNo is lower than the expected MsgSeqNum");
+        }
+        self.next_in = new_seq;
+        self.resend_requested = false;
+        self.save_seq_nums();
+        Ok(())
+    }
+
+    fn request_resend(&mut self) -> io::Result<()> {
+        let request = FixMessage::new("2").with(tags::BEGIN_SEQ_NO, self.next_in).with(tags::END_SEQ_NO, 0);
+        self.resend_requested = true;
+        self.send(request)
+    }
+
+    /* Session level Reject of a message we couldn't accept. */
+    fn reject(&mut self, message: &FixMessage, seq: u64, tag: u32, text: &str) -> io::Result<()> {
+        let mut reject = FixMessage::new("3")
+            .with(tags::REF_SEQ_NUM, seq)
+            .with(tags::REF_MSG_TYPE, message.msg_type());
+        if tag != 0 {
+            // Missing tag (1), or incorrect value (5).
+            let reason = if message.get(tag).is_none() { 1 } else { 5 };
+            reject = reject.with(tags::REF_TAG_ID, tag).with(tags::SESSION_REJECT_REASON, reason);
+        }
+        self.send(reject.with(tags::TEXT, text))
+    }
+
+    /* Send a message with the next sequence number, and keep it in case the client asks for it again. */
+    fn send(&mut self, message: FixMessage) -> io::Result<()> {
+        let seq = self.next_out;
+        let message = self.with_header(message, seq, None);
+        let raw = message.encode();
+        self.writer.write_all(&raw)?;
+        self.last_sent = Instant::now();
+
+        self.next_out += 1;
+        self.cache.write_fix_sent(&self.comp_id, seq, &raw);
+        self.save_seq_nums();
+        Ok(())
+    }
+
+    /* Resend the messages from begin to end (0 means everything we've sent).
+     * Session messages aren't resent, the gaps they leave are filled with SequenceResets.
+     **/
+    fn resend(&mut self, begin: u64, end: u64) -> io::Result<()> {
+        let last = if end == 0 || end >= self.next_out { self.next_out - 1 } else { end };
+        let mut gap_start: Option<u64> = None;
+
+        for seq in begin.max(1)..=last {
+            let stored = self.cache.read_fix_sent(&self.comp_id, seq);
+            let original = stored.and_then(|raw| FixMessage::parse(&raw).ok());
+            match original {
+                Some(original) if !original.is_admin() => {
+                    if let Some(start) = gap_start.take() {
+                        self.gap_fill(start, seq)?;
+                    }
+                    let sent_at = original.get(tags::SENDING_TIME).map(|time| time.to_string());
+                    let resent = self.with_header(original, seq, sent_at);
+                    self.writer.write_all(&resent.encode())?;
+                },
+                _ => {
+                    gap_start.get_or_insert(seq);
+                }
+            }
+        }
+        if let Some(start) = gap_start {
+            self.gap_fill(start, last + 1)?;
+        }
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn gap_fill(&mut self, seq: u64, new_seq: u64) -> io::Result<()> {
+        let gap_fill = FixMessage::new("4").with(tags::GAP_FILL_FLAG, "Y").with(tags::NEW_SEQ_NO, new_seq);
+        let gap_fill = self.with_header(gap_fill, seq, Some(message::timestamp()));
+        self.writer.write_all(&gap_fill.encode())
+    }
+
+    /* Put the header fields after the MsgType, replacing any the message already had.
+     * Resent messages (orig_sending_time is Some) are flagged as possible duplicates.
+     **/
+    fn with_header(&self, message: FixMessage, seq: u64, orig_sending_time: Option<String>) -> FixMessage {
+        let header = [tags::MSG_TYPE, tags::SENDER_COMP_ID, tags::TARGET_COMP_ID, tags::MSG_SEQ_NUM, tags::SENDING_TIME, tags::POSS_DUP_FLAG, tags::ORIG_SENDING_TIME];
+        let mut framed = FixMessage::new(message.msg_type())
+            .with(tags::SENDER_COMP_ID, EXCHANGE_COMP_ID)
+            .with(tags::TARGET_COMP_ID, &self.comp_id)
+            .with(tags::MSG_SEQ_NUM, seq);
+        if let Some(orig_sending_time) = orig_sending_time {
+            framed = framed.with(tags::POSS_DUP_FLAG, "Y").with(tags::ORIG_SENDING_TIME, orig_sending_time);
+        }
+        framed = framed.with(tags::SENDING_TIME, message::timestamp());
+        framed.fields.extend(message.fields.into_iter().filter(|(tag, _)| !header.contains(tag)));
+        framed
+    }
+
+    fn load_seq_nums(&mut self) {
+        if let Some((next_in, next_out)) = self.cache.read_fix_seq_nums(&self.comp_id) {
+            self.next_in = next_in;
+            self.next_out = next_out;
+        }
+    }
+
+    fn save_seq_nums(&mut self) {
+        self.cache.write_fix_seq_nums(&self.comp_id, self.next_in, self.next_out);
+    }
+
+    fn reset_seq_nums(&mut self) {
+        self.next_in = 1;
+        self.next_out = 1;
+        self.cache.delete_fix_sent(&self.comp_id);
+        self.save_seq_nums();
+    }
+}
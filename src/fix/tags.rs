@@ -0,0 +1,53 @@
+// The FIX 4.4 tags we read or write.
+
+// Header and trailer
+pub const BEGIN_STRING: u32         = 8;
+pub const BODY_LENGTH: u32          = 9;
+pub const CHECKSUM: u32             = 10;
+pub const MSG_TYPE: u32             = 35;
+pub const SENDER_COMP_ID: u32       = 49;
+pub const TARGET_COMP_ID: u32       = 56;
+pub const MSG_SEQ_NUM: u32          = 34;
+pub const SENDING_TIME: u32         = 52;
+pub const POSS_DUP_FLAG: u32        = 43;
+pub const ORIG_SENDING_TIME: u32    = 122;
+
+// Session messages
+pub const TEXT: u32                 = 58;
+pub const ENCRYPT_METHOD: u32       = 98;
+pub const HEART_BT_INT: u32         = 108;
+pub const TEST_REQ_ID: u32          = 112;
+pub const RESET_SEQ_NUM_FLAG: u32   = 141;
+pub const USERNAME: u32             = 553;
+pub const PASSWORD: u32             = 554;
+pub const BEGIN_SEQ_NO: u32         = 7;
+pub const END_SEQ_NO: u32           = 16;
+pub const NEW_SEQ_NO: u32           = 36;
+pub const GAP_FILL_FLAG: u32        = 123;
+pub const REF_SEQ_NUM: u32          = 45;
+pub const REF_TAG_ID: u32           = 371;
+pub const REF_MSG_TYPE: u32         = 372;
+pub const SESSION_REJECT_REASON: u32 = 373;
+pub const BUSINESS_REJECT_REASON: u32 = 380;
+
+// Application messages
+pub const AVG_PX: u32               = 6;
+pub const CL_ORD_ID: u32            = 11;
+pub const CUM_QTY: u32              = 14;
+pub const EXEC_ID: u32              = 17;
+pub const LAST_PX: u32              = 31;
+pub const LAST_QTY: u32             = 32;
+pub const ORDER_ID: u32             = 37;
+pub const ORDER_QTY: u32            = 38;
+pub const ORD_STATUS: u32           = 39;
+pub const ORD_TYPE: u32             = 40;
+pub const ORIG_CL_ORD_ID: u32       = 41;
+pub const PRICE: u32                = 44;
+pub const SIDE: u32                 = 54;
+pub const SYMBOL: u32               = 55;
+pub const TRANSACT_TIME: u32        = 60;
+pub const CXL_REJ_REASON: u32       = 102;
+pub const ORD_REJ_REASON: u32       = 103;
+pub const EXEC_TYPE: u32            = 150;
+pub const LEAVES_QTY: u32           = 151;
+pub const CXL_REJ_RESPONSE_TO: u32  = 434;
@@ -0,0 +1,59 @@
+//! RustX, a stock exchange: a price-time priority matching engine, accounts with roles, sessions,
+//! rate limits and short selling, batched writes to Postgres, and the interfaces built on them.
+//!
+//! The `exchange` and `exchange-server` binaries are thin wrappers around this library,
+//! which can also be embedded in other services:
+//!
+//! ```no_run
+//! use exchange::{Runtime, Request, parser};
+//!
+//! let mut runtime = Runtime::start();   // Reads rustx.toml, connects to the databases, and loads the markets
+//! let request = parser::tokenize_input("buy GME 10 167.34 example pass".to_string()).unwrap();
+//! let response = runtime.service(request);
+//! for report in response.reports.iter() {
+//!     println!("{} {} filled, {} left", report.exec_type.name(), report.cum_quantity, report.leaves_quantity);
+//! }
+//! runtime.service(Request::ExitReq);    // Flushes the buffers to the database
+//! runtime.shutdown();
+//! ```
+//!
+//! The modules:
+//!  - `exchange`: the markets and matching engine, orders, trades, execution reports and errors.
+//!  - `account`: accounts, their positions, margin, roles, sessions and the user cache.
+//!  - `buffer`: batches the exchange's changes, and the thread that writes them to the database.
+//!  - `database`: the store and cache traits, with Postgres, Redis and in-memory implementations.
+//!  - `parser`: turns request lines into `Request`s, and services them into `Response`s.
+//!  - `config`: the settings, read from a TOML file, the environment and the command line.
+//!  - `runtime`: starts an exchange, services requests one at a time, and shuts it down.
+//!  - `server`, `api`, `stream`, `fix`, `binary_feed` and `drop_copy`: the network interfaces.
+#[macro_use] extern crate random_number;
+extern crate chrono;
+extern crate ctrlc;
+extern crate redis;
+#[macro_use] extern crate colour;
+
+pub mod exchange;
+pub mod parser;
+pub mod account;
+pub mod buffer;
+pub mod database;
+pub mod runtime;
+pub mod server;
+pub mod api;
+pub mod stream;
+pub mod fix;
+pub mod binary_feed;
+pub mod drop_copy;
+pub mod config;
+
+pub use crate::exchange::{Exchange, Market, Request, Order, ExecutionReport, ErrorCode, OrderError};
+pub use crate::account::{Users, Permission};
+pub use crate::buffer::{BufferCollection, UpdateCategories};
+pub use crate::parser::{Response, Status, ParseError};
+pub use crate::runtime::Runtime;
+pub use crate::server::{ClientRequest, Incoming};
+pub use crate::stream::MarketFeed;
+pub use crate::fix::FixGateway;
+pub use crate::binary_feed::BinaryFeed;
+pub use crate::drop_copy::DropCopy;
+pub use crate::config::Config;
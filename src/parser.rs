@@ -1,9 +1,19 @@
-pub use crate::exchange::{self, Exchange, Market, Order, InfoRequest, Simulation, CancelOrder, Request, PriceError, OrderStatus, BufferCollection};
-pub use crate::print_instructions;
-use postgres::Client;
-use crate::database;
+pub use crate::exchange::{self, Exchange, Market, Order, InfoRequest, Simulation, CancelOrder, AmendOrder, CancelAll, Request, PriceError, OrderStatus, BufferCollection, MarginRequest, RoleRequest, LimitRequest, MarketRequest, AccountRequest, RateLimits, ErrorCode, OrderError};
+use crate::database::{Store, Cache};
+use crate::config::Config;
 
-use crate::account::{UserAccount, Users};
+use crate::account::{UserAccount, Users, CostBasis, MarginAccount, MarginCallAction, MarginPolicy, Credentials, Role, AccountStatus};
+use crate::account::{self, session};
+use crate::exchange::requests::{self, MAX_CLIENT_ID_LEN};
+
+pub mod response;
+pub use crate::parser::response::{Response, Status};
+
+pub mod errors;
+pub use crate::parser::errors::ParseError;
+
+pub mod instructions;
+pub use crate::parser::instructions::{instructions, print_instructions};
 
 // IO stuff
 use std::io::{self, BufReader};
@@ -12,57 +22,250 @@ use std::fs::File;
 
 pub struct Argument<R> {
     pub interactive: bool,                      // false means read from file, true means interactive mode
-    pub reader: Option<std::io::BufReader<R>>   // The buffer we read from
+    pub reader: Option<std::io::BufReader<R>>,  // The buffer we read from
+    pub config: Config                          // The config file, overridden by the environment and these arguments
+}
+
+impl<R> Argument<R> {
+    /* True if we were asked to serve clients on any interface. */
+    pub fn serves(&self) -> bool {
+        self.config.serves()
+    }
 }
 
 // Parses the command line arguments.
 // Returns an argument struct on success, or an error string.
-pub fn command_args(mut args: env::Args) -> Result<Argument<std::fs::File>, String> {
-    args.next(); // skip the first argument since it's the program name
+pub fn command_args(args: env::Args) -> Result<Argument<std::fs::File>, String> {
+    let args: Vec<String> = args.skip(1).collect(); // skip the first argument since it's the program name
+
+    // The config file has to be read first, since the other arguments override it.
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => Some(path.as_str()),
+            None => return Err("Please provide the path of the config file, ex. --config rustx.toml".to_string())
+        },
+        None => None
+    };
 
     // Default argument
     let mut argument = Argument {
         interactive: true,
-        reader: None
+        reader: None,
+        config: Config::load(config_path)?
     };
 
     // Modify the argument depending on user input.
-    match args.next() {
-        Some(filename) => {
-            let file = match File::open(filename) {
-                Ok(f) => f,
-                // TODO: pass the error up call stack?
-                Err(_) => return Err("Failed to open the file!".to_string())
-            };
-            argument.interactive = false;
-            argument.reader = Some(BufReader::new(file));
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            args.next();
+            continue;
         }
-        None => ()
+        if argument.config.read_flag(&arg, &mut args)? {
+            continue;
+        }
+        let file = match File::open(&arg) {
+            Ok(f) => f,
+            // TODO: pass the error up call stack?
+            Err(_) => return Err("Failed to open the file!".to_string())
+        };
+        argument.interactive = false;
+        argument.reader = Some(BufReader::new(file));
     }
-    return Ok(argument);
+    argument.config.validate()?;
+    Ok(argument)
 }
 
-/* Prints some helpful information to the console when input is malformed. */
-fn malformed_req(req: &str, req_type: &str) {
-    eprintln!("\nMalformed \"{}\" request!", req);
+/* A malformed request, with some helpful information on how it should look. */
+fn malformed(req: &str, req_type: &str) -> ParseError {
+    let mut hint = Vec::new();
     match req_type {
-       "account"    => eprintln!("Hint - format should be: {} create/show username password", req),
-       "order"      => eprintln!("Hint - format should be: {} symbol quantity price username password", req),
-       "cancel"     => eprintln!("Hint - format should be: {} symbol order_id username password", req),
-       "info"       => eprintln!("Hint - format should be: {} symbol", req),
-       "sim"        => eprintln!("Hint - format should be: {} trader_count market_count duration", req),
-       "upgrade_db" => eprintln!("Hint - format should be: {} file_path username password", req),
-       "exit"       => eprintln!("Hint - format should be: EXIT"),
+       "account"    => {
+           hint.push("Hint - format should be one of:".to_string());
+           hint.push(format!["\t{} create/show username password [fifo/average], or {} show session_token [fifo/average]", req, req]);
+           hint.push(format!["\t{} password/rename new_value username password", req]);
+           hint.push(format!["\t{} suspend username [cancel] operator password", req]);
+           hint.push(format!["\t{} reactivate/close username operator password", req]);
+           hint.push("Any username and password after the first two words can be replaced by a session token.".to_string());
+       },
+       "order"      => {
+           hint.push(format!["Hint - format should be: {} symbol quantity price [clid=client_order_id] username password", req]);
+           hint.push("The username and password can be replaced by a session token.".to_string());
+       },
+       "cancel"     => {
+           hint.push(format!["Hint - format should be: {} symbol order_id username password, or {} symbol order_id session_token", req, req]);
+           hint.push("The order_id can be the exchange's id, or clid=client_order_id.".to_string());
+       },
+       "cancel_all" => {
+           hint.push(format!["Hint - format should be: {} [symbol] [buy/sell] username password", req]);
+           hint.push(format!["Operators and the admin can cancel for another account with: {} [symbol] [buy/sell] user=username operator password", req]);
+           hint.push("The username and password can be replaced by a session token.".to_string());
+       },
+       "amend"      => {
+           hint.push(format!["Hint - format should be: {} symbol order_id quantity price [clid=new_client_order_id] username password", req]);
+           hint.push("The order_id can be the exchange's id, or clid=client_order_id. The username and password can be replaced by a session token.".to_string());
+       },
+       "login"      => hint.push(format!["Hint - format should be: {} username password", req]),
+       "logout"     => hint.push(format!["Hint - format should be: {} session_token", req]),
+       "info"       => hint.push(format!["Hint - format should be: {} symbol", req]),
+       "sim"        => hint.push(format!["Hint - format should be: {} trader_count market_count duration", req]),
+       "upgrade_db" => hint.push(format!["Hint - format should be: {} db_name username password, or {} db_name session_token", req, req]),
+       "short"      => {
+           hint.push("Hint - format should be one of:".to_string());
+           hint.push(format!["\t{} enable username borrow_limit collateral operator password", req]);
+           hint.push(format!["\t{} disable username operator password", req]);
+           hint.push(format!["\t{} policy initial_margin maintenance_margin flag/liquidate operator password", req]);
+           hint.push(format!["\t{} calls operator password", req]);
+           hint.push("The operator's username and password can be replaced by a session token.".to_string());
+       },
+       "role"       => {
+           hint.push("Hint - format should be one of:".to_string());
+           hint.push(format!["\t{} grant/revoke username role admin password", req]);
+           hint.push(format!["\t{} show username admin password", req]);
+           hint.push("Roles are read_only, trader, market_maker, operator and admin. The admin's username and password can be replaced by a session token.".to_string());
+       },
+       "limits"     => {
+           hint.push("Hint - format should be one of:".to_string());
+           hint.push(format!["\t{} show/reset username operator password", req]);
+           hint.push(format!["\t{} set username orders_per_sec cancels_per_sec max_open_orders operator password", req]);
+           hint.push(format!["\t{} default orders_per_sec cancels_per_sec max_open_orders operator password", req]);
+           hint.push("The operator's username and password can be replaced by a session token.".to_string());
+       },
+       "market"     => {
+           hint.push(format!["Hint - format should be: {} halt/resume symbol operator password", req]);
+           hint.push("The operator's username and password can be replaced by a session token.".to_string());
+       },
+       "cache"      => hint.push("Hint - format should be: cache".to_string()),
+       "exit"       => hint.push("Hint - format should be: EXIT".to_string()),
        _            => ()
     }
+    ParseError::Malformed { request: req.to_string(), hint }
+}
+
+/* The last words of a request are either a username and password, or a session token. */
+pub fn parse_credentials(words: &[String]) -> Option<Credentials> {
+    match words {
+        [token] if session::is_token(token) => Some(Credentials::Session(token.to_string())),
+        [username, password] => Some(Credentials::Password(username.to_string(), password.to_string())),
+        _ => None
+    }
+}
+
+/* A word of the form clid=abc123 is a client order id.
+ * Returns Ok(None) if the word isn't one, and Err if it is, but the id isn't valid.
+ **/
+fn parse_client_id(word: &str) -> Result<Option<String>, ParseError> {
+    match word.strip_prefix("clid=") {
+        Some(id) => {
+            if !requests::is_valid_client_id(id) {
+                return Err(ParseError::Invalid(format!["Client order ids must be 1 to {} letters, numbers, dashes or underscores!", MAX_CLIENT_ID_LEN]));
+            }
+            Ok(Some(id.to_string()))
+        },
+        None => Ok(None)
+    }
+}
+
+/* Orders are referred to by the exchange's id, or by the client order id, ex. 42 or clid=abc123.
+ * Returns the exchange's id (0 if we were given a client order id), and the client order id.
+ **/
+fn parse_order_ref(word: &str) -> Result<(i64, Option<String>), ParseError> {
+    if let Some(client_id) = parse_client_id(word)? {
+        return Ok((0, Some(client_id)));
+    }
+    match word.trim().parse::<i64>() {
+        Ok(id) => Ok((id, None)),
+        Err(e) => {
+            Err(ParseError::Invalid(format!["{}. Please enter an integer order_id, or clid=client_order_id", e]))
+        }
+    }
+}
+
+/* Parses a mass cancel, ex.
+ *      cancel_all [symbol] [buy/sell] [user=username] username password
+ * The credentials are the last word if it's a session token, otherwise the last two.
+ **/
+fn parse_cancel_all(words: &[String]) -> Result<Request, ParseError> {
+    let cred_len = match words.last() {
+        Some(word) if session::is_token(word) => 1,
+        _ => 2
+    };
+    let credentials = match words.len().checked_sub(cred_len).and_then(|start| words.get(start..)).and_then(parse_credentials) {
+        Some(credentials) if cred_len < words.len() => credentials,
+        _ => {
+            return Err(malformed(&words[0], &words[0]));
+        }
+    };
+
+    // The options must come in order: symbol, side, then user.
+    let mut req = CancelAll { symbol: None, action: None, username: None };
+    for word in words[1..words.len() - cred_len].iter() {
+        if let Some(username) = word.strip_prefix("user=") {
+            if req.username.is_some() || username.is_empty() {
+                return Err(malformed(&words[0], &words[0]));
+            }
+            req.username = Some(username.to_string());
+        } else if word == "buy" || word == "sell" {
+            if req.action.is_some() || req.username.is_some() {
+                return Err(malformed(&words[0], &words[0]));
+            }
+            req.action = Some(word.to_uppercase());
+        } else {
+            if req.symbol.is_some() || req.action.is_some() || req.username.is_some() {
+                return Err(malformed(&words[0], &words[0]));
+            }
+            req.symbol = Some(word.to_uppercase());
+        }
+    }
+    Ok(Request::CancelAllReq(req, credentials))
+}
+
+/* With a session token, we only learn the username once the token is checked. */
+fn credentials_username(credentials: &Credentials) -> String {
+    match credentials {
+        Credentials::Password(username, _) => username.to_string(),
+        Credentials::Session(_) => String::new()
+    }
+}
+
+/* Parses the account requests that change an account, ex.
+ *      account suspend username [cancel] admin password
+ *      account password new_password username password
+ **/
+fn parse_account_request(words: &[String]) -> Result<Request, ParseError> {
+    // `cancel` is optional, so only treat it as a keyword if what follows it are credentials.
+    let cancel = words[1] == "suspend" && words.get(3).map(|word| word.as_str()) == Some("cancel") && words.get(4..).and_then(parse_credentials).is_some();
+    let args = if cancel { 4 } else { 3 };
+
+    let credentials = match words.get(args..).and_then(parse_credentials) {
+        Some(credentials) => credentials,
+        None => {
+            return Err(malformed(&words[0], &words[0]));
+        }
+    };
+
+    let target = words[2].to_string();
+    let request = match words[1].as_str() {
+        "suspend" => AccountRequest::Suspend(target, cancel),
+        "reactivate" => AccountRequest::Reactivate(target),
+        "close" => AccountRequest::Close(target),
+        "password" => AccountRequest::ChangePassword(target),
+        _ => {
+            if 15 < target.len() || session::is_token(&target) {
+                return Err(ParseError::Invalid("Usernames can be at most 15 characters, and can't look like a session token!".to_string()));
+            }
+            AccountRequest::ChangeUsername(target)
+        }
+    };
+    Ok(Request::AccountReq(request, credentials))
 }
 
 /* Takes a string from stdin, and turns it into a Request Enum.
  *
  * If the request does not abide by the required formatting,
- * we return an empty Err, which the caller will handle.
+ * we return why, for the caller to show the sender.
  */
-pub fn tokenize_input(text: String) -> Result<Request, ()> {
+pub fn tokenize_input(text: String) -> Result<Request, ParseError> {
 
     // Split the words and reformat them.
     let parsed = text.split_whitespace();
@@ -73,105 +276,294 @@ pub fn tokenize_input(text: String) -> Result<Request, ()> {
     }
 
     // Exit early on empty input
-    if words.len() == 0 {
-        return Err(());
+    if words.is_empty() {
+        return Err(ParseError::Empty);
     }
 
     // The first entry should be the action type.
     match &(words[0])[..] {
         // Create a new user
         "account" => {
-            if let 4 = words.len() {
-                let action = words[1].to_string().clone();
-                let user = UserAccount::from(&words[2], &words[3]);
-                return Ok(Request::UserReq(user, action));
-            } else {
-                malformed_req(&words[0], &words[0]);
-                return Err(());
+            if let Some("suspend") | Some("reactivate") | Some("close") | Some("password") | Some("rename") = words.get(1).map(|word| word.as_str()) {
+                return parse_account_request(&words);
             }
+
+            let len = words.len();
+            // Accounts can be shown with a session token, but only created with a password.
+            let (credentials, basis) = if (len == 3 || len == 4) && words[1] == "show" && session::is_token(&words[2]) {
+                (Credentials::Session(words[2].to_string()), words.get(3))
+            } else if len == 4 || (len == 5 && words[1] == "show") {
+                (Credentials::Password(words[2].to_string(), words[3].to_string()), words.get(4))
+            } else {
+                return Err(malformed(&words[0], &words[0]));
+            };
+
+            // The optional last word picks how the P&L is calculated.
+            let basis = match basis {
+                Some(word) => match CostBasis::from(word) {
+                    Some(basis) => Some(basis),
+                    None => {
+                        return Err(ParseError::Invalid(format!["Unknown cost basis \'{}\', please use fifo or average.", word]));
+                    }
+                },
+                None => None
+            };
+            let action = words[1].to_string().clone();
+            Ok(Request::UserReq(credentials, action, basis))
         }
         // Order
         "buy" | "sell" => {
-            if let 6 = words.len() {
+            // The client order id is optional, and comes right before the credentials.
+            let client_id = match words.get(4) {
+                Some(word) => parse_client_id(word)?,
+                None => None
+            };
+            let args = if client_id.is_some() { 5 } else { 4 };
+            if let Some(credentials) = words.get(args..).and_then(parse_credentials) {
                 let quantity = match words[2].to_string().trim().parse::<i32>() {
                     Ok(quant) => quant,
                     Err(e) => {
-                        eprintln!("{}", e);
-                        eprintln!("Please enter an integer number of shares!");
-                        return Err(());
+                        return Err(ParseError::InvalidQuantity(format!["{}. Please enter an integer number of shares!", e]));
                     }
                 };
 
                 let price = match words[3].to_string().trim().parse::<f64>() {
                     Ok(price) => price,
                     Err(e) => {
-                        eprintln!("{}", e);
-                        eprintln!("Please enter a floating point price!");
-                        return Err(());
+                        return Err(ParseError::InvalidPrice(format!["{}. Please enter a floating point price!", e]));
                     }
                 };
                 // Note that we do not provide an order ID (arg is None).
                 // This value actually gets set later.
-                let order = Order::from( words[0].to_string().to_uppercase(),
-                                         words[1].to_string().to_uppercase(),
-                                         quantity,
-                                         price,
-                                         OrderStatus::PENDING,
-                                         None
-                                       );
-                if order.quantity <= 0 || order.price <= 0.0 {
-                    eprintln!("Malformed \"{}\" request!", words[0]);
-                    eprintln!("Make sure the quantity and price are greater than 0!");
-                    return Err(());
+                let mut order = Order::from( words[0].to_string().to_uppercase(),
+                                             words[1].to_string().to_uppercase(),
+                                             quantity,
+                                             price,
+                                             OrderStatus::PENDING,
+                                             None
+                                           );
+                order.client_id = client_id;
+                if order.quantity <= 0 {
+                    return Err(ParseError::InvalidQuantity("Make sure the quantity is greater than 0!".to_string()));
                 }
-                return Ok(Request::OrderReq(order, words[4].to_string(), words[5].to_string()));
+                if order.price <= 0.0 {
+                    return Err(ParseError::InvalidPrice("Make sure the price is greater than 0!".to_string()));
+                }
+                Ok(Request::OrderReq(order, credentials))
             } else {
-                malformed_req(&words[0], "order");
-                return Err(());
+                Err(malformed(&words[0], "order"))
             }
         },
         "cancel" => {
-            if let 5 = words.len() {
-                let order_id = match words[2].to_string().trim().parse::<i32>() {
-                    Ok(id) => id,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        eprintln!("Please enter an integer order_id");
-                        return Err(());
-                    }
-                };
+            if let Some(credentials) = words.get(3..).and_then(parse_credentials) {
+                let (order_id, client_id) = parse_order_ref(&words[2])?;
                 let req = CancelOrder {
                     symbol: words[1].to_string().to_uppercase(),
-                    order_id: order_id,
-                    username: words[3].to_string()
+                    order_id,
+                    username: credentials_username(&credentials),
+                    client_id
                 };
 
-                return Ok(Request::CancelReq(req, words[4].to_string()));
+                Ok(Request::CancelReq(req, credentials))
             } else {
-                malformed_req(&words[0], &words[0]);
-                return Err(());
+                Err(malformed(&words[0], &words[0]))
             }
         }
+        // Cancel many orders at once, ex. a market maker pulling all of its quotes.
+        "cancel_all" => parse_cancel_all(&words),
+        // Cancel what remains of an order, and replace it at a new quantity and price.
+        "amend" => {
+            let client_id = match words.get(5) {
+                Some(word) => parse_client_id(word)?,
+                None => None
+            };
+            let args = if client_id.is_some() { 6 } else { 5 };
+            if let Some(credentials) = words.get(args..).and_then(parse_credentials) {
+                let (order_id, old_client_id) = parse_order_ref(&words[2])?;
+                let quantity = words[3].trim().parse::<i32>();
+                let price = words[4].trim().parse::<f64>();
+                let quantity = match quantity {
+                    Ok(quantity) if 0 < quantity => quantity,
+                    _ => return Err(ParseError::InvalidQuantity("Make sure the quantity is an integer greater than 0!".to_string()))
+                };
+                let price = match price {
+                    Ok(price) if 0.0 < price => price,
+                    _ => return Err(ParseError::InvalidPrice("Make sure the price is greater than 0!".to_string()))
+                };
+                let cancel = CancelOrder {
+                    symbol: words[1].to_string().to_uppercase(),
+                    order_id,
+                    username: credentials_username(&credentials),
+                    client_id: old_client_id
+                };
+                Ok(Request::AmendReq(AmendOrder { cancel, quantity, price, client_id }, credentials))
+            } else {
+                Err(malformed(&words[0], &words[0]))
+            }
+        },
         // request price info, current market info, or past market info
         "price" | "show" | "history" =>  {
             if let 2 = words.len() {
                 let req: InfoRequest = InfoRequest::new(words[0].to_string(), words[1].to_string().to_uppercase());
-                return Ok(Request::InfoReq(req));
+                Ok(Request::InfoReq(req))
             } else {
-                malformed_req(&words[0], "info");
-                return Err(());
+                Err(malformed(&words[0], "info"))
             }
         },
         // Upgrade the database, only the admin can do this.
         "upgrade_db" => {
-            if let 4 = words.len() {
+            if let Some(credentials) = words.get(2..).and_then(parse_credentials) {
                 let db_name   = words[1].to_string();
-                let username  = words[2].to_string();
-                let password  = words[3].to_string();
-                return Ok(Request::UpgradeDbReq(db_name, username, password));
+                Ok(Request::UpgradeDbReq(db_name, credentials))
             } else {
-                malformed_req(&words[0], &words[0]);
-                return Err(());
+                Err(malformed(&words[0], &words[0]))
+            }
+        },
+        // Change short selling settings, only operators and the admin can do this.
+        "short" => {
+            // The number of words before the credentials.
+            let args = match words.get(1).map(|word| word.as_str()) {
+                Some("enable") | Some("policy") => 5,
+                Some("disable") => 3,
+                Some("calls") => 2,
+                _ => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+            let credentials = match words.get(args..).and_then(parse_credentials) {
+                Some(credentials) => credentials,
+                None => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+
+            let request = match words[1].as_str() {
+                "enable" => {
+                    let borrow_limit = match words[3].trim().parse::<i32>() {
+                        Ok(limit) if 0 <= limit => limit,
+                        _ => {
+                            return Err(ParseError::Invalid("Please enter a non-negative integer borrow limit!".to_string()));
+                        }
+                    };
+                    let collateral = match words[4].trim().parse::<f64>() {
+                        Ok(collateral) if 0.0 <= collateral => collateral,
+                        _ => {
+                            return Err(ParseError::Invalid("Please enter a non-negative floating point collateral!".to_string()));
+                        }
+                    };
+                    MarginRequest::Account(words[2].to_string(), MarginAccount::direct(true, borrow_limit, collateral))
+                },
+                "disable" => MarginRequest::Account(words[2].to_string(), MarginAccount::default()),
+                "policy" => {
+                    let initial = words[2].trim().parse::<f64>();
+                    let maintenance = words[3].trim().parse::<f64>();
+                    let (initial, maintenance) = match (initial, maintenance) {
+                        (Ok(initial), Ok(maintenance)) if 0.0 <= maintenance && maintenance <= initial => (initial, maintenance),
+                        _ => {
+                            return Err(ParseError::Invalid("Margins are fractions of short market value (ex. 0.5), and maintenance can't be above initial!".to_string()));
+                        }
+                    };
+                    let action = match MarginCallAction::parse(&words[4]) {
+                        Some(action) => action,
+                        None => {
+                            return Err(malformed(&words[0], &words[0]));
+                        }
+                    };
+                    MarginRequest::Policy(MarginPolicy::new(initial, maintenance, action))
+                },
+                _ => MarginRequest::Calls
+            };
+            Ok(Request::MarginReq(request, credentials))
+        },
+        // Grant, revoke or show an account's roles, only the admin can do this.
+        "role" => {
+            let args = match words.get(1).map(|word| word.as_str()) {
+                Some("grant") | Some("revoke") => 4,
+                Some("show") => 3,
+                _ => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+            let credentials = match words.get(args..).and_then(parse_credentials) {
+                Some(credentials) => credentials,
+                None => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+
+            let username = words[2].to_string();
+            let request = match words[1].as_str() {
+                "show" => RoleRequest::Show(username),
+                action => {
+                    let role = match Role::from(&words[3]) {
+                        Some(role) => role,
+                        None => {
+                            return Err(ParseError::Invalid(format!["Unknown role \'{}\', please use one of: read_only, trader, market_maker, operator, admin.", words[3]]));
+                        }
+                    };
+                    if action == "grant" {
+                        RoleRequest::Grant(username, role)
+                    } else {
+                        RoleRequest::Revoke(username, role)
+                    }
+                }
+            };
+            Ok(Request::RoleReq(request, credentials))
+        },
+        // Change or show rate limits, only operators and the admin can do this.
+        "limits" => {
+            let args = match words.get(1).map(|word| word.as_str()) {
+                Some("show") | Some("reset") => 3,
+                Some("set") => 6,
+                Some("default") => 5,
+                _ => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+            let credentials = match words.get(args..).and_then(parse_credentials) {
+                Some(credentials) => credentials,
+                None => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+
+            // The limits are the last 3 words before the credentials.
+            let limits = if words[1] == "set" || words[1] == "default" {
+                let orders = words[args - 3].trim().parse::<f64>();
+                let cancels = words[args - 2].trim().parse::<f64>();
+                let open = words[args - 1].trim().parse::<usize>();
+                match (orders, cancels, open) {
+                    // Like the limits in the config, all of these must be positive.
+                    (Ok(orders), Ok(cancels), Ok(open)) if 0.0 < orders && 0.0 < cancels && 0 < open => Some(RateLimits::new(orders, cancels, open)),
+                    _ => {
+                        return Err(ParseError::Invalid("Rates must be positive numbers, and max open orders a positive integer!".to_string()));
+                    }
+                }
+            } else {
+                None
+            };
+
+            let request = match (words[1].as_str(), limits) {
+                ("show", _) => LimitRequest::Show(words[2].to_string()),
+                ("reset", _) => LimitRequest::Reset(words[2].to_string()),
+                ("set", Some(limits)) => LimitRequest::Set(words[2].to_string(), limits),
+                (_, Some(limits)) => LimitRequest::Default(limits),
+                _ => {
+                    return Err(malformed(&words[0], &words[0]));
+                }
+            };
+            Ok(Request::LimitReq(request, credentials))
+        },
+        // Halt or resume trading in a market, only operators and the admin can do this.
+        "market" => {
+            let request = match (words.get(1).map(|word| word.as_str()), words.get(2)) {
+                (Some("halt"), Some(symbol)) => MarketRequest::Halt(symbol.to_uppercase()),
+                (Some("resume"), Some(symbol)) => MarketRequest::Resume(symbol.to_uppercase()),
+                _ => return Err(malformed(&words[0], &words[0]))
+            };
+            match words.get(3..).and_then(parse_credentials) {
+                Some(credentials) => Ok(Request::MarketReq(request, credentials)),
+                None => Err(malformed(&words[0], &words[0]))
             }
         },
         // Simulate a market for n time steps
@@ -180,107 +572,227 @@ pub fn tokenize_input(text: String) -> Result<Request, ()> {
                 let trader_count = match words[1].to_string().trim().parse::<u32>() {
                     Ok(count) => count,
                     Err(e) => {
-                        eprintln!("{}", e);
-                        eprintln!("Please enter an integer number of traders!");
-                        return Err(());
+                        return Err(ParseError::Invalid(format!["{}. Please enter an integer number of traders!", e]));
                     }
                 };
 
                 let market_count = match words[2].to_string().trim().parse::<u32>() {
                     Ok(count) => count,
                     Err(e) => {
-                        eprintln!("{}", e);
-                        eprintln!("Please enter an integer number of markets!");
-                        return Err(());
+                        return Err(ParseError::Invalid(format!["{}. Please enter an integer number of markets!", e]));
                     }
                 };
 
                 let time_step_count = match words[3].to_string().trim().parse::<u32>() {
                     Ok(count) => count,
                     Err(e) => {
-                        eprintln!("{}", e);
-                        eprintln!("Please enter an integer number of time steps!");
-                        return Err(());
+                        return Err(ParseError::Invalid(format!["{}. Please enter an integer number of time steps!", e]));
                     }
                 };
 
                 let req: Simulation = Simulation::from( words[0].to_string(), trader_count, market_count, time_step_count);
-                return Ok(Request::SimReq(req));
+                Ok(Request::SimReq(req))
 
             } else {
-                malformed_req(&words[0], "sim");
-                return Err(());
+                Err(malformed(&words[0], "sim"))
+            }
+        },
+        // Start a session, so the user doesn't need to send their password with every request.
+        "login" => {
+            if let 3 = words.len() {
+                return Ok(Request::LoginReq(words[1].to_string(), words[2].to_string()));
             }
+            Err(malformed(&words[0], &words[0]))
+        },
+        "logout" => {
+            if words.len() == 2 && session::is_token(&words[1]) {
+                return Ok(Request::LogoutReq(words[1].to_string()));
+            }
+            Err(malformed(&words[0], &words[0]))
+        },
+        "cache" => {
+            if words.len() == 1 {
+                return Ok(Request::CacheReq);
+            }
+            Err(malformed(&words[0], &words[0]))
         },
         "exit" => {
             if words.len() == 1 {
                 return Ok(Request::ExitReq)
             }
-            malformed_req(&words[0], &words[0]);
-            return Err(());
+            Err(malformed(&words[0], &words[0]))
         }
         // request instructions
         "help" => {
-            print_instructions();
-            return Err(()); // We return an empty error only because there's no more work to do.
+            if words.len() == 1 {
+                return Ok(Request::HelpReq);
+            }
+            Err(malformed(&words[0], &words[0]))
         },
         // Unknown input
         _ => {
-            eprintln!("I don't understand the action type \'{}\'.", words[0]);
-            return Err(());
+            Err(ParseError::Unknown(words[0].to_string()))
         }
     }
 }
 
-/* Given a valid Request format, try to execute the Request. */
-pub fn service_request(request: Request, exchange: &mut Exchange, users: &mut Users, buffers: &mut BufferCollection, conn: &mut Client, redis_conn: &mut redis::Connection) {
+/* Given a valid Request format, try to execute the Request.
+ * Returns what happened, for the console to print or a server to send back to its client.
+ **/
+pub fn service_request(request: Request, exchange: &mut Exchange, users: &mut Users, buffers: &mut BufferCollection, store: &mut dyn Store, cache: &mut dyn Cache) -> Response {
+    // Requests sent by an account are only serviced if one of the account's roles grants this.
+    let permission = request.permission();
     match request {
-        Request::OrderReq(mut order, username, password) => {
+        Request::OrderReq(mut order, credentials) => {
             match &order.action[..] {
                 "BUY" | "SELL" => {
                     // Try to get the account
-                    match users.authenticate(&username, &password, conn) {
-                        Ok(mut account) => {
-                            // Set the order's user id now that we have an account
-                            order.user_id = account.id;
-
-                            // If we don't have the full picture of this users pending orders,
-                            // get it. This is so we can ensure they don't fill their own order,
-                            // and accurately represent their account state.
-                            if !account.pending_orders.is_complete {
-                                exchange.fetch_account_pending_orders(&mut account, redis_conn);
-                            }
+                    let account = match users.authorize(&credentials, permission, store) {
+                        Ok(account) => account,
+                        Err(e) => return Response::from(e)
+                    };
+                    let username = account.username.clone();
 
-                            if let Some(obstruction) = account.validate_order(&order) {
-                                eprintln!("\
+                    // Set the order's user id now that we have an account
+                    order.user_id = account.id;
+
+                    // If we don't have the full picture of this users pending orders,
+                    // get it. This is so we can ensure they don't fill their own order,
+                    // and accurately represent their account state.
+                    if !account.pending_orders.is_complete {
+                        exchange.fetch_account_pending_orders(account, cache);
+                    }
+
+                    // A client order id we've seen before means the client is retrying,
+                    // so we tell them what happened to the original instead of placing it twice.
+                    if let Some(client_id) = &order.client_id {
+                        if let Some(original) = account.find_client_order(client_id, buffers, store) {
+                            return Response::ok(&format!["Duplicate client order id, this order was already placed. {}", original.describe()]).with_order(original);
+                        }
+                    }
+
+                    if let Err(throttled) = exchange.rate_limiter.check_order(account) {
+                        return Response::from(throttled).rejecting(&order);
+                    }
+                    if let Some(obstruction) = account.validate_order(&order) {
+                        return Response::error(Status::BadRequest, &format!["\
 The order could not be placed. You have a pending order in ${} that could potentially be filled by the order you just requested.
-Please change the price of your order so that it cannot fill the following pending order:\n\t{:?}", obstruction.symbol, obstruction);
-                            } else {
-                                if let Err(e) =  &exchange.submit_order_to_market(users, buffers, order.clone(), &username, true, conn) {
-                                    eprintln!("{}", e);
-                                } else {
-                                    &exchange.show_market(&order.symbol);
-                                }
-                            }
+Please change the price of your order so that it cannot fill the following pending order:\n\t{}", obstruction.symbol, obstruction.describe()]).rejecting(&order);
+                    }
+
+                    let rejected = order.clone();
+                    match exchange.submit_order_to_market(users, buffers, order, &username, true, store) {
+                        Ok((placed, reports)) => {
+                            exchange.monitor_margin(users, buffers, store, cache);
+                            Response::ok("").with_reports(reports).with_order(placed)
                         },
-                        Err(e) => Users::print_auth_error(e)
+                        Err(e) => Response::from(e).rejecting(&rejected)
                     }
                 },
                 // Handle unknown action!
-                _ => eprintln!("Sorry, I do not know how to perform {:?}", order)
+                _ => Response::error(Status::BadRequest, &format!["Sorry, I do not know how to perform {:?}", order]).with_code(ErrorCode::Malformed)
             }
         },
-        Request::CancelReq(order_to_cancel, password) => {
-            match users.authenticate(&(order_to_cancel.username), &password, conn) {
-                Ok(_) => {
-                    match exchange.cancel_order(&order_to_cancel, users, buffers, conn, redis_conn) {
-                        Ok(_) => println!("Order successfully cancelled."),
-                        Err(e) => eprintln!("{}", e)
-                    }
-                },
-                Err(e) => Users::print_auth_error(e)
+        Request::CancelReq(mut order_to_cancel, credentials) => {
+            let account = match users.authorize(&credentials, permission, store) {
+                Ok(account) => account,
+                Err(e) => return Response::from(e)
+            };
+            order_to_cancel.username = account.username.clone();
+            if let Err(throttled) = exchange.rate_limiter.check_cancel(account) {
+                return Response::from(throttled);
+            }
+            if !account.pending_orders.is_complete {
+                exchange.fetch_account_pending_orders(account, cache);
+            }
+            if let Err(e) = account.resolve_client_id(&mut order_to_cancel, buffers, store) {
+                return Response::failed(ErrorCode::UnknownOrder, &e);
+            }
+            match exchange.cancel_order(&order_to_cancel, users, buffers, store, cache) {
+                Ok(report) => Response::ok("").with_reports(vec![report]),
+                Err(e) => Response::from(e)
+            }
+        },
+        Request::CancelAllReq(req, credentials) => {
+            let sender = match users.authorize(&credentials, permission, store) {
+                Ok(account) => account.username.clone(),
+                Err(e) => return Response::from(e)
+            };
+            // This is a kill switch, so it isn't rate limited.
+            let target = req.username.clone().unwrap_or(sender);
+            if !store.read_account_exists(&target) {
+                return Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target]);
+            }
+            let cancelled = exchange.cancel_matching_orders(&target, &req, users, buffers, store, cache);
+            Response::ok(&format!["Cancelled {} resting order(s) for {}.", cancelled, target])
+        },
+        Request::AmendReq(mut amend, credentials) => {
+            let account = match users.authorize(&credentials, permission, store) {
+                Ok(account) => account,
+                Err(e) => return Response::from(e)
+            };
+            let username = account.username.clone();
+            amend.cancel.username = username.clone();
+            if !account.pending_orders.is_complete {
+                exchange.fetch_account_pending_orders(account, cache);
             }
+            if let Err(e) = account.resolve_client_id(&mut amend.cancel, buffers, store) {
+                return Response::failed(ErrorCode::UnknownOrder, &e);
+            }
+            if let Some(client_id) = &amend.client_id {
+                if let Some(original) = account.find_client_order(client_id, buffers, store) {
+                    return Response::ok(&format!["Duplicate client order id, this order was already placed. {}", original.describe()]).with_order(original);
+                }
+            }
+
+            let original = match account.pending_orders.get_order_in_market(&amend.cancel.symbol, amend.cancel.order_id) {
+                Some(order) => order.clone(),
+                None => return Response::failed(ErrorCode::UnknownOrder, "The order requested to be amended was not found in your pending orders!")
+            };
+            let mut replacement = Order::from(original.action.clone(), original.symbol.clone(), amend.quantity, amend.price, OrderStatus::PENDING, account.id);
+            replacement.client_id = amend.client_id;
 
+            // Check everything before cancelling, so we never cancel an order we can't replace.
+            // The order being replaced is on the same side, so it can't obstruct its replacement.
+            if exchange.halted.contains(&replacement.symbol) {
+                return Response::from(OrderError::MarketHalted(replacement.symbol.clone())).rejecting(&replacement);
+            }
+            if let Err(throttled) = exchange.rate_limiter.check_cancel(account).and_then(|_| exchange.rate_limiter.check_order(account)) {
+                return Response::from(throttled).rejecting(&replacement);
+            }
+            if let Some(obstruction) = account.validate_order(&replacement) {
+                return Response::error(Status::BadRequest, &format!["\
+The order could not be amended. You have a pending order in ${} that could potentially be filled by the new order.
+Please change the price of your order so that it cannot fill the following pending order:\n\t{}", obstruction.symbol, obstruction.describe()]).rejecting(&replacement);
+            }
+            if replacement.action == "SELL" {
+                users.load_positions(&username);
+            }
+            if let Ok(account) = users.get_mut(&username, true) {
+                if let Err(e) = account.validate_short_sale(&replacement, Some(&original), &exchange.statistics, &exchange.margin_policy) {
+                    return Response::from(e).rejecting(&replacement);
+                }
+            }
+
+            let cancelled = match exchange.cancel_order(&amend.cancel, users, buffers, store, cache) {
+                Ok(report) => report,
+                Err(e) => return Response::from(e).rejecting(&replacement)
+            };
+            let rejected = replacement.clone();
+            match exchange.submit_order_to_market(users, buffers, replacement, &username, true, store) {
+                Ok((placed, mut reports)) => {
+                    exchange.monitor_margin(users, buffers, store, cache);
+                    reports[0].replace_ack(original.order_id);
+                    reports.insert(0, cancelled);
+                    Response::ok("").with_reports(reports).with_order(placed)
+                },
+                Err(e) => {
+                    let reason = format!["Order {} was cancelled, but its replacement could not be placed: {}", original.order_id, e];
+                    let mut response = Response::failed(e.code(), &reason).rejecting(&rejected);
+                    response.reports.insert(0, cancelled);
+                    response
+                }
+            }
         },
         Request::InfoReq(req) => {
             match &req.action[..] {
@@ -288,15 +800,13 @@ Please change the price of your order so that it cannot fill the following pendi
                 "price" => {
                     let price = exchange.get_price(&req.symbol);
                     match price {
-                        Ok(price) => {
-                            println!("Last trading price of ${} is ${}", req.symbol, price);
-                        },
+                        Ok(price) => Response::ok(&format!["Last trading price of ${} is ${}", req.symbol, price]),
                         Err(e) => match e {
                             PriceError::NoMarket => {
-                                println!("There is no market for ${}, so no price information exists.", req.symbol);
+                                Response::error(Status::NotFound, &format!["There is no market for ${}, so no price information exists.", req.symbol]).with_code(ErrorCode::UnknownSymbol)
                             },
                             PriceError::NoTrades => {
-                                println!("This market has not had any trades yet, so there is no price!");
+                                Response::error(Status::NotFound, "This market has not had any trades yet, so there is no price!")
                             }
                         }
                     }
@@ -304,93 +814,296 @@ Please change the price of your order so that it cannot fill the following pendi
                 // Show the current market.
                 "show" => {
                     if exchange.statistics.contains_key(&req.symbol) {
-                        exchange.show_market(&req.symbol);
+                        Response::ok(&exchange.show_market(&req.symbol))
                     } else {
-                        println!("${} is not a market!", req.symbol);
+                        Response::error(Status::NotFound, &format!["${} is not a market!", req.symbol]).with_code(ErrorCode::UnknownSymbol)
                     }
                 },
                 // Show the past orders of this market.
                 "history" => {
                     match exchange.has_trades.get(&req.symbol) {
                         Some(has_trades) => {
-                            if *has_trades {
-                                exchange.show_market_history(&req.symbol, conn);
-                            } else {
-                                println!("The market that was requested has no past trades!");
+                            match exchange.show_market_history(&req.symbol, store) {
+                                Some(history) if *has_trades => Response::ok(&history),
+                                _ => Response::error(Status::NotFound, "The market that was requested has no past trades!")
                             }
                         },
-                        None => println!("The symbol that was requested does not exist.")
+                        None => Response::error(Status::NotFound, "The symbol that was requested does not exist.").with_code(ErrorCode::UnknownSymbol)
                     }
                 },
-                _ => {
-                    eprintln!("I don't know how to handle this information request.");
+                _ => Response::error(Status::BadRequest, "I don't know how to handle this information request.")
+            }
+        },
+        Request::UpgradeDbReq(db_name, credentials) => {
+            if let Err(e) = users.authorize(&credentials, permission, store) {
+                return Response::from(e);
+            }
+            println!("Please enter the file path to the configuration:");
+            let mut file_path = String::new();
+            io::stdin()
+                .read_line(&mut file_path)
+                    .expect("Failed to read line");
+            file_path = file_path.split_whitespace().next().expect("Please be sure to enter text!").to_string();
+            match File::open(file_path) {
+                Ok(f) => {
+                    store.upgrade_db(&mut BufReader::new(f), &db_name);
+
+                    // Markets added to the store we're running on can be traded right away.
+                    let mut upgraded = Exchange::new(&Config::default());
+                    store.populate_market_statistics(&mut upgraded);
+                    store.populate_has_trades(&mut upgraded);
+                    for (_symbol, stats) in upgraded.statistics.into_iter() {
+                        exchange.open_market(stats);
+                    }
+                    for (symbol, has_trades) in upgraded.has_trades.into_iter() {
+                        exchange.has_trades.entry(symbol).or_insert(has_trades);
+                    }
+                    Response::ok("")
+                },
+                Err(e) => Response::error(Status::BadRequest, &e.to_string())
+            }
+        },
+        Request::MarginReq(req, credentials) => {
+            if let Err(e) = users.authorize(&credentials, permission, store) {
+                return Response::from(e);
+            }
+            match req {
+                MarginRequest::Account(target, margin) => {
+                    let enabled = margin.short_enabled;
+                    if users.set_margin_account(&target, margin, store) {
+                        if enabled {
+                            Response::ok(&format!["Short selling enabled for {}.", target])
+                        } else {
+                            Response::ok(&format!["Short selling disabled for {}.", target])
+                        }
+                    } else {
+                        Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target])
+                    }
+                },
+                MarginRequest::Policy(policy) => {
+                    let message = format!["Margin policy updated: {:?}", policy];
+                    exchange.margin_policy = policy;
+                    Response::ok(&message)
+                },
+                MarginRequest::Calls => {
+                    if exchange.margin_calls.is_empty() {
+                        return Response::ok("No accounts are below maintenance margin.");
+                    }
+                    let calls: Vec<String> = exchange.margin_calls.values().map(|call| {
+                        format!["{} (${}): equity ${:.2}, requirement ${:.2}, short value ${:.2}",
+                                call.username, call.symbol, call.equity, call.requirement, call.short_value]
+                    }).collect();
+                    Response::ok(&calls.join("; "))
                 }
             }
         },
-        Request::UpgradeDbReq(db_name, username, password) => {
-            // First, lets authenticate to make sure we're the admin.
-            if username.as_str() == "admin" {
-                match users.authenticate(&username, &password, conn) {
-                    Ok(_) => {
-                        println!("Please enter the file path to the configuration:");
-                        let mut file_path = String::new();
-                        io::stdin()
-                            .read_line(&mut file_path)
-                                .expect("Failed to read line");
-                        file_path = file_path.split_whitespace().next().expect("Please be sure to enter text!").to_string();
-                        match File::open(file_path) {
-                            Ok(f) => {
-                                database::upgrade_db(BufReader::new(f), &db_name);
-                            },
-                            Err(e) => {
-                                eprintln!("{}", e);
+        Request::RoleReq(req, credentials) => {
+            let admin = match users.authorize(&credentials, permission, store) {
+                Ok(account) => account.username.clone(),
+                Err(e) => return Response::from(e)
+            };
+            let (target, updated) = match req {
+                RoleRequest::Grant(target, role) => {
+                    let updated = users.set_role(&target, role, true, store);
+                    (target, updated)
+                },
+                RoleRequest::Revoke(target, role) => {
+                    // Don't let the admin lock themselves out.
+                    if let (Role::Admin, true) = (role, target == admin) {
+                        return Response::error(Status::Forbidden, "You can't revoke your own admin role, have another admin do it.");
+                    }
+                    let updated = users.set_role(&target, role, false, store);
+                    (target, updated)
+                },
+                RoleRequest::Show(target) => {
+                    let updated = if store.read_account_exists(&target) {
+                        Some(users.get_mut_unauthenticated(&target, store).roles.clone())
+                    } else {
+                        None
+                    };
+                    (target, updated)
+                }
+            };
+            match updated {
+                Some(roles) => Response::ok(&format!["{} has role(s): {}", target, account::roles::to_names(&roles).join(", ")]),
+                None => Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target])
+            }
+        },
+        Request::MarketReq(req, credentials) => {
+            if let Err(e) = users.authorize(&credentials, permission, store) {
+                return Response::from(e);
+            }
+            let (symbol, halt) = match req {
+                MarketRequest::Halt(symbol) => (symbol, true),
+                MarketRequest::Resume(symbol) => (symbol, false)
+            };
+            if !exchange.statistics.contains_key(&symbol) && !store.read_market_exists(&symbol) {
+                return Response::error(Status::NotFound, &format!["${} is not a market!", symbol]).with_code(ErrorCode::UnknownSymbol);
+            }
+            match (exchange.set_halted(&symbol, halt), halt) {
+                (true, true) => Response::ok(&format!["Trading in ${} is halted, new orders will be rejected until it's resumed.", symbol]),
+                (true, false) => Response::ok(&format!["Trading in ${} has resumed.", symbol]),
+                (false, true) => Response::ok(&format!["${} was already halted.", symbol]),
+                (false, false) => Response::ok(&format!["${} wasn't halted.", symbol])
+            }
+        },
+        Request::LimitReq(req, credentials) => {
+            if let Err(e) = users.authorize(&credentials, permission, store) {
+                return Response::from(e);
+            }
+            match req {
+                LimitRequest::Show(target) => {
+                    if !store.read_account_exists(&target) {
+                        return Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target]);
+                    }
+                    let account = users.get_mut_unauthenticated(&target, store);
+                    if !account.pending_orders.is_complete {
+                        exchange.fetch_account_pending_orders(account, cache);
+                    }
+                    Response::ok(&exchange.rate_limiter.show_usage(account))
+                },
+                LimitRequest::Set(target, limits) => {
+                    if !store.read_account_exists(&target) {
+                        return Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target]);
+                    }
+                    store.write_update_rate_limits(&target, Some(&limits));
+                    exchange.rate_limiter.set_account_limits(&target, Some(limits));
+                    Response::ok(&format!["Rate limits for {} set to: {:?}", target, limits])
+                },
+                LimitRequest::Reset(target) => {
+                    if !store.read_account_exists(&target) {
+                        return Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target]);
+                    }
+                    store.write_update_rate_limits(&target, None);
+                    exchange.rate_limiter.set_account_limits(&target, None);
+                    Response::ok(&format!["{} is back on the rate limits of its role.", target])
+                },
+                LimitRequest::Default(limits) => {
+                    exchange.rate_limiter.default = limits;
+                    Response::ok(&format!["Default rate limits set to: {:?}", limits])
+                }
+            }
+        },
+        Request::AccountReq(req, credentials) => {
+            let sender = match users.authorize(&credentials, permission, store) {
+                Ok(account) => account.username.clone(),
+                Err(e) => return Response::from(e)
+            };
+            match req {
+                AccountRequest::Suspend(target, cancel) => {
+                    if !users.set_status(&target, AccountStatus::Suspended, store) {
+                        return Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target]);
+                    }
+                    if cancel {
+                        let cancelled = exchange.cancel_account_orders(&target, users, buffers, store, cache);
+                        return Response::ok(&format!["Suspended {}, and cancelled {} resting order(s).", target, cancelled]);
+                    }
+                    Response::ok(&format!["Suspended {}.", target])
+                },
+                AccountRequest::Reactivate(target) => {
+                    if users.set_status(&target, AccountStatus::Active, store) {
+                        Response::ok(&format!["Reactivated {}.", target])
+                    } else {
+                        Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target])
+                    }
+                },
+                AccountRequest::Close(target) => {
+                    if target == sender {
+                        return Response::error(Status::Forbidden, "You can't close your own account, have another operator do it.");
+                    }
+                    if !users.set_status(&target, AccountStatus::Closed, store) {
+                        return Response::error(Status::NotFound, &format!["Sorry, no account has the username {}.", target]);
+                    }
+                    // A closed account can't cancel its own orders, so we do it for them.
+                    let cancelled = exchange.cancel_account_orders(&target, users, buffers, store, cache);
+                    exchange.margin_calls.remove(&target);
+                    Response::ok(&format!["Closed {}, and cancelled {} resting order(s).", target, cancelled])
+                },
+                AccountRequest::ChangePassword(new_password) => {
+                    users.change_password(&sender, &new_password, store);
+                    Response::ok("Password changed. All of your sessions have ended, please login again.")
+                },
+                AccountRequest::ChangeUsername(new_username) => {
+                    match users.change_username(&sender, &new_username, store) {
+                        Ok(()) => {
+                            // The margin monitor and rate limiter track accounts by username.
+                            if let Some(mut call) = exchange.margin_calls.remove(&sender) {
+                                call.username = new_username.clone();
+                                exchange.margin_calls.insert(new_username.clone(), call);
                             }
-                        };
-                    },
-                    Err(e) => Users::print_auth_error(e)
+                            exchange.rate_limiter.rename_account(&sender, &new_username);
+                            Response::ok(&format!["Your username is now {}. All of your sessions have ended, please login again.", new_username])
+                        },
+                        Err(e) => Response::error(Status::Conflict, &e)
+                    }
                 }
-            } else {
-                eprintln!("Only the administrator can upgrade the database!");
             }
         },
         Request::SimReq(req) => {
             match &req.action[..] {
                 "simulate" => {
                     println!("Simulating {} order(s) in {} market(s) among {} account(s)!", req.duration, req.market_count, req.trader_count);
-                    &exchange.simulate_market(&req, users, buffers, conn, redis_conn);
+                    // Nothing is published outside a Runtime, see Runtime::simulate.
+                    exchange.simulate_market(&req, users, buffers, store, cache, &mut |_, _| ());
+                    Response::ok("")
                 },
-                _ => {
-                    eprintln!("I don't know how to handle this Simulation request.");
-                }
+                _ => Response::error(Status::BadRequest, "I don't know how to handle this Simulation request.")
             }
         },
-        Request::UserReq(account, action) => {
+        Request::UserReq(credentials, action, basis) => {
             match &action[..] {
                 "create" => {
-                   match users.new_account(account, conn) {
-                       Some(id) => println!("Successfully created new account with id {}.", id),
-                       None => println!("Sorry, that username is already taken!")
-                   }
+                    if let Credentials::Password(username, password) = &credentials {
+                        return match users.new_account(UserAccount::from(username, password), store) {
+                            Some(id) => Response::ok(&format!["Successfully created new account with id {}.", id]),
+                            None => Response::error(Status::Conflict, "Sorry, that username is already taken!")
+                        };
+                    }
+                    Response::error(Status::BadRequest, "Accounts can only be created with a username and password.")
                 },
                 "show" => {
-                    match users.authenticate(&account.username, &account.password, conn) {
+                    match users.authorize(&credentials, permission, store) {
                         Ok(acc) => {
                             if !acc.pending_orders.is_complete {
-                                exchange.fetch_account_pending_orders(acc, redis_conn);
+                                exchange.fetch_account_pending_orders(acc, cache);
                             }
-                            &acc.print_user();
+                            Response::ok(&acc.show_user(&exchange.statistics, basis.unwrap_or(CostBasis::AverageCost), cache))
                         },
-                        Err(e) => Users::print_auth_error(e)
+                        Err(e) => Response::from(e)
                     }
                 },
-                _ => println!("Sorry I do not know how to handle that account request.")
+                _ => Response::error(Status::BadRequest, "Sorry I do not know how to handle that account request.")
+            }
+        },
+        Request::LoginReq(username, password) => {
+            match users.login(&username, &password, store) {
+                Ok(token) => {
+                    // Keep the token last, so clients of the line protocol can read it.
+                    Response::ok(&format!["Logged in as {}. Use your session token in place of your username and password, \
+it expires after {} minutes of inactivity. Your session token is: {}", username, session::SESSION_TTL / 60, token])
+                },
+                Err(e) => Response::from(e)
             }
         },
+        Request::LogoutReq(token) => {
+            if users.logout(&token) {
+                Response::ok("Logged out.")
+            } else {
+                Response::error(Status::NotFound, "That session does not exist, or has already expired.")
+            }
+        },
+        Request::CacheReq => {
+            Response::ok(&users.show_cache_stats())
+        },
+        Request::HelpReq => {
+            Response::ok(&instructions())
+        },
         Request::ExitReq => {
             println!("Initiating graceful shutdown...");
             buffers.flush_on_shutdown(exchange);
             users.flush_user_cache(); // Send all cached users recent trades to redis.
             buffers.tx.as_ref().unwrap().send(None).unwrap();
+            Response::ok("")
         }
     }
 }
@@ -0,0 +1,44 @@
+use std::fmt;
+
+use crate::exchange::ErrorCode;
+
+/* Why a line couldn't be turned into a Request. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Empty,                  // Nothing was entered
+    Unknown(String),        // The first word isn't a request we know
+    Malformed {             // The words don't fit the request
+        request: String,
+        hint: Vec<String>   // How the request should look
+    },
+    InvalidQuantity(String),// Why the quantity is invalid
+    InvalidPrice(String),   // Why the price is invalid
+    Invalid(String)         // Why another argument is invalid
+}
+
+impl ParseError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ParseError::InvalidQuantity(_) => ErrorCode::InvalidQuantity,
+            ParseError::InvalidPrice(_) => ErrorCode::InvalidPrice,
+            _ => ErrorCode::Malformed
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Empty request."),
+            ParseError::Unknown(action) => write!(f, "I don't understand the action type \'{}\'.", action),
+            ParseError::Malformed { request, hint } => {
+                write!(f, "Malformed \"{}\" request!", request)?;
+                for line in hint.iter() {
+                    write!(f, "\n{}", line)?;
+                }
+                Ok(())
+            },
+            ParseError::InvalidQuantity(message) | ParseError::InvalidPrice(message) | ParseError::Invalid(message) => write!(f, "{}", message)
+        }
+    }
+}
@@ -0,0 +1,75 @@
+/* The console's instructions, also sent to whoever types help. */
+pub fn instructions() -> String {
+    let buy_price = 167.34;
+    let buy_amount = 24;
+    let sell_price = 999.85;
+    let sell_amount = 12;
+    let user = "example";
+    let pass = "pass";
+    let mut lines: Vec<String> = Vec::new();
+
+    lines.push("Usage:".to_string());
+    lines.push("\tOrders: ACTION(buy/sell) SYMBOL(ticker) QUANTITY PRICE [clid=CLIENT_ORDER_ID] USERNAME PASSWORD".to_string());
+    lines.push(format!["\t\tEx: buy GME {} {} {} {}\t<---- Sends a buy order for {} shares of GME at ${} a share. Order is placed by {} with password {}.", buy_amount, buy_price, user, pass, buy_amount, buy_price, user, pass]);
+    lines.push(format!["\t\tEx: sell GME {} {} {} {}\t<---- Sends a sell order for {} shares of GME at ${} a share. Order is placed by {} with password {}.", sell_amount, sell_price, user, pass, sell_amount, sell_price, user, pass]);
+
+    lines.push(format!["\t\tEx: buy GME {} {} clid=gme-1 {} {}\t<---- Same as the buy above, but resending it won't place a second order.\n", buy_amount, buy_price, user, pass]);
+
+    lines.push("\tCancel Request: cancel SYMBOL ORDER_ID USERNAME PASSWORD".to_string());
+    lines.push("\t\tEx: cancel AAPL 4 admin pass\t\t<---- Cancels the order with ID 4 in the AAPL market, provided user (admin) placed it.".to_string());
+    lines.push("\t\tEx: cancel GME clid=gme-1 admin pass\t<---- Cancels the order admin placed with client order id gme-1.\n".to_string());
+
+    lines.push("\tMass Cancel: cancel_all [SYMBOL] [buy/sell] USERNAME PASSWORD".to_string());
+    lines.push("\t\tEx: cancel_all GME buy admin pass\t<---- Cancels all of admin's buy orders in the GME market.".to_string());
+    lines.push("\t\tEx: cancel_all user=bob admin pass\t<---- Cancels all of bob's orders, for operators and the admin.\n".to_string());
+
+    lines.push("\tAmend Request: amend SYMBOL ORDER_ID QUANTITY PRICE [clid=CLIENT_ORDER_ID] USERNAME PASSWORD".to_string());
+    lines.push("\t\tEx: amend AAPL 4 10 150.25 admin pass\t<---- Cancels what remains of order 4, and places a new order for 10 shares at $150.25.\n".to_string());
+
+    lines.push("\tInfo Requests: ACTION SYMBOL(ticker)".to_string());
+    lines.push("\t\tEx: price GME\t\t<---- gives latest price an order was filled at.".to_string());
+    lines.push("\t\tEx: show GME\t\t<---- shows statistics for the GME market.".to_string());
+    lines.push("\t\tEx: history GME\t\t<---- shows past orders that were filled in the GME market.\n".to_string());
+
+    lines.push("\tSimulation Requests: simulate NUM_USERS NUM_MARKETS NUM_ORDERS".to_string());
+    lines.push("\t\tEx: simulate 300 500 10000\t<---- Simulates 10000 random buy/sell orders in 500 markets, with 300 random users.\n".to_string());
+
+    lines.push("\tSessions: login USERNAME PASSWORD / logout TOKEN".to_string());
+    lines.push(format!["\t\tEx: login {} {}\t\t<---- Prints a session token. Use it in place of USERNAME PASSWORD in orders, cancels and account show.", user, pass]);
+    lines.push(format!["\t\tEx: buy GME {} {} sess_0123456789abcdef0123456789abcdef", buy_amount, buy_price]);
+    lines.push("\t\tEx: logout sess_0123456789abcdef0123456789abcdef\n".to_string());
+
+    lines.push("\tAccount Requests: account create/show USERNAME PASSWORD".to_string());
+    lines.push("\t\tEx: account create bigMoney notHashed".to_string());
+    lines.push("\t\tEx: account show bigMoney notHashed fifo\t<---- Shows the account's orders, trades and P&L. The last word is optional (fifo/average).".to_string());
+    lines.push("\t\tEx: account password newPassword bigMoney notHashed\t<---- Changes bigMoney's password (account rename newName ... changes the username).".to_string());
+    lines.push("\t\tEx: account suspend bigMoney cancel admin pass\t<---- Operators/admin only. Blocks new orders, and cancels resting orders if `cancel` is given.".to_string());
+    lines.push("\t\tEx: account reactivate bigMoney admin pass\t<---- Operators/admin only. account close bigMoney admin pass closes the account for good.\n\n".to_string());
+    lines.push("\tShort Selling Requests (operator/admin only): short enable/disable/policy/calls ...".to_string());
+    lines.push("\t\tEx: short enable bigMoney 500 25000 admin pass\t<---- Lets bigMoney be short up to 500 shares per market, with $25000 of collateral.".to_string());
+    lines.push("\t\tEx: short disable bigMoney admin pass".to_string());
+    lines.push("\t\tEx: short policy 0.5 0.3 liquidate admin pass\t<---- 50% initial margin, 30% maintenance margin, buy back shorts that fall below it.".to_string());
+    lines.push("\t\tEx: short calls admin pass\t\t<---- Shows the accounts that are below maintenance margin.\n".to_string());
+    lines.push("\tRate Limit Requests (operator/admin only): limits show/reset USERNAME, limits set USERNAME ORDERS CANCELS OPEN, or limits default ORDERS CANCELS OPEN".to_string());
+    lines.push("\t\tEx: limits set bigMoney 5 10 100 admin pass\t<---- bigMoney may place 5 orders and cancel 10 orders per second, with at most 100 open orders.".to_string());
+    lines.push("\t\tEx: limits show bigMoney admin pass\t\t<---- Shows bigMoney's limits, how much of them are available, and how many requests were rejected.\n".to_string());
+    lines.push("\tMarket Requests (operator/admin only): market halt/resume SYMBOL, followed by the operator's credentials".to_string());
+    lines.push("\t\tEx: market halt GME admin pass\t\t<---- New orders in GME are rejected until it's resumed, resting orders can still be cancelled.\n".to_string());
+    lines.push("\tRole Requests (admin only): role grant/revoke USERNAME ROLE, or role show USERNAME, followed by the admin's credentials".to_string());
+    lines.push("\t\tEx: role grant bigMoney operator admin pass\t<---- Roles are read_only, trader, market_maker, operator and admin.".to_string());
+    lines.push("\t\tEx: role show bigMoney admin pass\n".to_string());
+    lines.push("\tTo serve clients over TCP, start the exchange with --serve ADDRESS (ex. 127.0.0.1:7878). Clients send the requests above, one per line.".to_string());
+    lines.push("\tTo serve the JSON HTTP API, start the exchange with --http ADDRESS (ex. 127.0.0.1:8080). See the README for its endpoints.".to_string());
+    lines.push("\tTo stream trades and book updates over WebSockets, start the exchange with --ws ADDRESS (ex. 127.0.0.1:9001).".to_string());
+    lines.push("\tTo publish the binary (ITCH style) feed, start the exchange with --itch-udp ADDRESS and/or --itch-file PATH. Rebuild the book with itch_book.".to_string());
+    lines.push("\tTo send operators a drop copy of every execution, start the exchange with --drop-copy ADDRESS (ex. 127.0.0.1:9879).".to_string());
+    lines.push("\tTo accept FIX 4.4 sessions, start the exchange with --fix ADDRESS (ex. 127.0.0.1:9878). Our TargetCompID is RUSTX.".to_string());
+    lines.push("\tTo see the user cache's size, hit rate and evictions, type cache.".to_string());
+    lines.push("\tTo perform a graceful shutdown and update the database, type EXIT.\n".to_string());
+    lines.push("\tYou can see these instructions at any point by typing help.".to_string());
+    lines.join("\n")
+}
+
+pub fn print_instructions() {
+    println!("{}", instructions());
+}
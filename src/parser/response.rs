@@ -0,0 +1,199 @@
+use std::fmt;
+
+use crate::account::AuthError;
+use crate::exchange::{Order, ExecutionReport, ErrorCode, OrderError, Throttled};
+use crate::parser::ParseError;
+
+/* The outcome of a request. The codes borrow their meaning from HTTP,
+ * so clients can tell what happened without reading the message.
+ **/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+    Ok,             // 200
+    BadRequest,     // 400 - malformed, or asks for something invalid (ex. a market that doesn't exist)
+    Unauthorized,   // 401 - unknown user, wrong password, or expired session
+    Forbidden,      // 403 - authenticated, but not allowed to do this
+    NotFound,       // 404 - the order or account the request refers to doesn't exist
+    Conflict,       // 409 - ex. a duplicate client order id, or a taken username
+    TooLarge,       // 413 - the body of an HTTP request is over MAX_BODY (see api.rs), or a line is over MAX_LINE (see server.rs)
+    Throttled,      // 429 - over a rate limit
+    Error           // 500 - something went wrong on our end
+}
+
+impl Status {
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Ok           => 200,
+            Status::BadRequest   => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden    => 403,
+            Status::NotFound     => 404,
+            Status::Conflict     => 409,
+            Status::TooLarge     => 413,
+            Status::Throttled    => 429,
+            Status::Error        => 500
+        }
+    }
+
+    /* The status of a request that failed for this reason. */
+    pub fn of(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Unauthorized                             => Status::Unauthorized,
+            ErrorCode::Forbidden | ErrorCode::NotOwner          => Status::Forbidden,
+            ErrorCode::UnknownOrder                             => Status::NotFound,
+            ErrorCode::MarketHalted                             => Status::Conflict,
+            ErrorCode::RateLimited                              => Status::Throttled,
+            _                                                   => Status::BadRequest
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Ok           => "OK",
+            Status::BadRequest   => "BAD_REQUEST",
+            Status::Unauthorized => "UNAUTHORIZED",
+            Status::Forbidden    => "FORBIDDEN",
+            Status::NotFound     => "NOT_FOUND",
+            Status::Conflict     => "CONFLICT",
+            Status::TooLarge     => "TOO_LARGE",
+            Status::Throttled    => "THROTTLED",
+            Status::Error        => "ERROR"
+        }
+    }
+}
+
+/* What service_request tells the sender of a request.
+ *
+ * The console prints the message, network clients get it as a single line,
+ * with the lines of tables like show_market and account show separated by "; ":
+ *      200 OK Order 42: BUY 10 $GME at $167.34, 0 filled, PENDING
+ * Errors with a code have it in brackets after the status:
+ *      400 BAD_REQUEST [UNKNOWN_SYMBOL] The market $XYZ was not found in the database. User error!
+ * Requests that place, cancel or replace orders respond with their execution reports instead,
+ * one per line on the console, separated by "; " for network clients:
+ *      200 OK NEW order 42: BUY 10 $GME at $167.34, cum 0 leaves 10 avg $0.00; PARTIAL_FILL order 42: ...
+ **/
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: Status,
+    pub message: String,
+    pub order: Option<Order>,           // The order a request placed, or the original of a duplicate client order id.
+    pub reports: Vec<ExecutionReport>,  // What happened to the orders of the request, in order
+    pub code: Option<ErrorCode>         // Why the request failed, if we know
+}
+
+impl Response {
+    pub fn ok(message: &str) -> Self {
+        Response {
+            status: Status::Ok,
+            message: message.to_string(),
+            order: None,
+            reports: Vec::new(),
+            code: None
+        }
+    }
+
+    pub fn error(status: Status, message: &str) -> Self {
+        Response {
+            status,
+            message: message.to_string(),
+            order: None,
+            reports: Vec::new(),
+            code: None
+        }
+    }
+
+    /* A request that failed for this reason, its status follows from the code. */
+    pub fn failed(code: ErrorCode, message: &str) -> Self {
+        Response::error(Status::of(code), message).with_code(code)
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_order(mut self, order: Order) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /* Respond with these reports, they replace the message. */
+    pub fn with_reports(mut self, reports: Vec<ExecutionReport>) -> Self {
+        self.message = reports.iter().map(|report| report.to_string()).collect::<Vec<String>>().join("\n");
+        self.reports = reports;
+        self
+    }
+
+    /* The order couldn't be placed, report it as rejected for the reason in the message. */
+    pub fn rejecting(mut self, order: &Order) -> Self {
+        self.reports.push(ExecutionReport::rejected(order, &self.message));
+        self
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+
+    /* Print the response to the console, errors go to stderr. */
+    pub fn print(&self) {
+        let lines: Vec<String> = if self.reports.is_empty() {
+            vec![self.message.clone()]
+        } else {
+            self.reports.iter().map(|report| report.to_string()).collect()
+        };
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            if self.is_ok() {
+                println!("{}", line);
+            } else {
+                eprintln!("{}", line);
+            }
+        }
+    }
+}
+
+impl From<AuthError<'_>> for Response {
+    fn from(err: AuthError) -> Self {
+        Response::failed(err.code(), &err.to_string())
+    }
+}
+
+impl From<OrderError> for Response {
+    fn from(err: OrderError) -> Self {
+        Response::failed(err.code(), &err.to_string())
+    }
+}
+
+impl From<Throttled> for Response {
+    fn from(err: Throttled) -> Self {
+        Response::failed(err.code(), &err.to_string())
+    }
+}
+
+impl From<ParseError> for Response {
+    fn from(err: ParseError) -> Self {
+        Response::failed(err.code(), &err.to_string())
+    }
+}
+
+// The single line sent to network clients. Messages never span lines.
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = if self.reports.is_empty() {
+            self.message.lines()
+                .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<String>>()
+                .join("; ")
+        } else {
+            self.reports.iter()
+                .map(|report| report.to_string().split_whitespace().collect::<Vec<&str>>().join(" "))
+                .collect::<Vec<String>>()
+                .join("; ")
+        };
+        match self.code {
+            Some(code) => write!(f, "{} {} [{}] {}", self.status.code(), self.status.name(), code.as_str(), message),
+            None => write!(f, "{} {} {}", self.status.code(), self.status.name(), message)
+        }
+    }
+}
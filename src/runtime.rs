@@ -0,0 +1,607 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use crate::exchange::{Exchange, Request, Simulation};
+use crate::account::{Users, Permission};
+use crate::buffer::BufferCollection;
+use crate::parser::{self, Argument, Response, Status, ParseError};
+use crate::server::{ClientRequest, Incoming};
+use crate::stream::MarketFeed;
+use crate::fix::FixGateway;
+use crate::binary_feed::BinaryFeed;
+use crate::drop_copy::DropCopy;
+use crate::database::{Store, Cache, StoreBackend, CacheBackend, MemoryStore, MemoryCache};
+use crate::config::{self, Config, LogLevel};
+use crate::api;
+
+/* A running exchange: the matching engine, the accounts, the database buffers and everything that
+ * publishes what happens. The binaries start one, service requests on it, then shut it down:
+ *
+ *      let mut runtime = Runtime::start();
+ *      let response = runtime.service(Request::OrderReq(order, credentials));
+ *      runtime.service(Request::ExitReq);
+ *      runtime.shutdown();
+ *
+ * Requests must be serviced one at a time, servers send theirs to `serve` over a channel.
+ **/
+pub struct Runtime {
+    pub exchange: Exchange,             // Our central exchange, everything happens here.
+    pub users: Users,                   // All our users are stored here.
+    pub buffers: BufferCollection,      // In-memory buffers that will batch write to DB.
+    pub store: Box<dyn Store>,          // Where accounts, orders, trades and markets are kept
+    pub cache: Box<dyn Cache>,          // Accounts, sessions and trades shared outside the user cache
+    pub cache_backend: CacheBackend,    // How to connect to the cache, ex. for each FIX session
+    pub feed: MarketFeed,               // Streams trades and book changes to WebSocket subscribers, if there are any.
+    pub binary_feed: BinaryFeed,        // Publishes the book order by order, if we were asked to.
+    pub gateway: FixGateway,            // Reports the fills of orders placed over FIX.
+    pub drop_copy: DropCopy,            // Journals every execution, and copies them to risk and back office subscribers.
+    writer: thread::JoinHandle<()>      // The thread that writes the buffers to the database
+}
+
+impl Runtime {
+    /* Start as configured by rustx.toml and the environment (Postgres and Redis by default),
+     * load the markets, and start the database writer.
+     **/
+    pub fn start() -> Self {
+        let config = match Config::load(None).and_then(|config| config.validate().map(|_| config)) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                panic!("The configuration is invalid.");
+            }
+        };
+        Runtime::from_config(&config)
+    }
+
+    /* Start on the backends the command line asked for, Postgres and Redis unless told otherwise. */
+    pub fn from_arguments<R>(argument: &Argument<R>) -> Self {
+        Runtime::from_config(&argument.config)
+    }
+
+    /* Start on the backends and with the settings of a validated config. */
+    pub fn from_config(config: &Config) -> Self {
+        if config.in_memory {
+            return Runtime::with_config(StoreBackend::Memory(MemoryStore::new()), CacheBackend::Memory(MemoryCache::new()), config);
+        }
+        let store_backend = match &config.sqlite {
+            Some(path) => StoreBackend::Sqlite(path.clone()),
+            None => StoreBackend::Postgres(config.postgres.clone())
+        };
+        let cache_backend = match &config.cache_file {
+            Some(path) => match MemoryCache::open(path) {
+                Ok(cache) => CacheBackend::Memory(cache),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    panic!("Failed to open the cache file.");
+                }
+            },
+            None => CacheBackend::Redis(config.redis.clone())
+        };
+        Runtime::with_config(store_backend, cache_backend, config)
+    }
+
+    /* Run without any external services, everything is lost on exit.
+     * The store starts out empty, markets are added with upgrade_db.
+     **/
+    pub fn in_memory() -> Self {
+        Runtime::with_backends(StoreBackend::Memory(MemoryStore::new()), CacheBackend::Memory(MemoryCache::new()))
+    }
+
+    /* Connect to the given store and cache, load the markets, and start the database writer. */
+    pub fn with_backends(store_backend: StoreBackend, cache_backend: CacheBackend) -> Self {
+        Runtime::with_config(store_backend, cache_backend, &Config::default())
+    }
+
+    /* Like with_backends, with the buffer, cache and logging settings of the config. */
+    pub fn with_config(store_backend: StoreBackend, cache_backend: CacheBackend, config: &Config) -> Self {
+        config::set_log_level(config.log_level);
+        let info = config::logs(LogLevel::Info);
+
+        let mut exchange = Exchange::new(config);
+        let users = Users::new(config.user_cache_capacity, cache_backend.connect());
+        let mut buffers = BufferCollection::new(config.order_buffer_capacity, config.trade_buffer_capacity);
+
+        let mut store = store_backend.connect();
+        let cache = cache_backend.connect();
+
+        if info {
+            dark_green!("Connected to database ({}) and cache ({}).\n", store_backend.name(), cache_backend.name());
+        }
+
+        let start = Instant::now();
+
+        // Accounts from before we hashed passwords are migrated here.
+        let password_time = Instant::now();
+        let hashed = store.write_hash_plaintext_passwords();
+        if hashed > 0 && info {
+            dark_green!("\tHashed {} plaintext password(s) in {} ms\n", hashed, password_time.elapsed().as_millis());
+        }
+
+        /* TODO: Should we store the top N buys and sells in each market, rather than all?
+         *       This would decrease the amount of RAM, and increases the computation speed.
+         *       I think this needs to wait for a move to Redis, as we currently read users
+         *       pending orders into their accounts by pulling this data
+         *          - (see fetch_account_pending_orders).
+         **/
+        if info {
+            println!("Initializing exchange...");
+        }
+        let market_time = Instant::now();
+        store.populate_exchange_markets(&mut exchange);                 // Fill the pending orders of the markets
+        let market_time = market_time.elapsed().as_millis();
+
+        let stats_time = Instant::now();
+        store.populate_market_statistics(&mut exchange);                // Fill the statistics for each market
+        let stats_time = stats_time.elapsed().as_millis();
+
+        let x_stats_time = Instant::now();
+        store.populate_exchange_statistics(&mut exchange);              // Fill the statistics for the exchange
+        let x_stats_time = x_stats_time.elapsed().as_millis();
+
+        let has_trades_time = Instant::now();
+        store.populate_has_trades(&mut exchange);                       // Fill the has_trades map for the exchange
+        let has_trades_time = has_trades_time.elapsed().as_millis();
+
+        store.populate_rate_limits(&mut exchange);                      // Give accounts the rate limits they were set
+
+        let end = start.elapsed().as_millis();
+        if info {
+            dark_green!("\tTime elapsed to populate markets: {} ms\n", market_time);
+            dark_green!("\tTime elapsed to populate market stats: {} ms\n", stats_time);
+            dark_green!("\tTime elapsed to populate exchange stats: {} ms\n", x_stats_time);
+            dark_green!("\tTime elapsed to populate has_trades: {} ms\n", has_trades_time);
+            dark_green!("\nTotal Setup Time elapsed : {} ms\n", end);
+        }
+
+        let writer = buffers.start_writer(&store_backend, config.writer_threads);
+        Runtime {
+            exchange,
+            users,
+            buffers,
+            store,
+            cache,
+            feed: MarketFeed::new(),
+            binary_feed: BinaryFeed::new(),
+            gateway: FixGateway::new(),
+            drop_copy: DropCopy::new(cache_backend.connect()),
+            cache_backend,
+            writer
+        }
+    }
+
+    /* Publish the binary feed wherever the command line asked, then start it with the book. */
+    pub fn start_binary_feed<R>(&mut self, argument: &Argument<R>) -> Result<(), String> {
+        if let Some(address) = &argument.config.itch_udp {
+            if let Err(e) = self.binary_feed.add_udp(address) {
+                return Err(format!["Failed to publish the binary feed to {}: {}", address, e]);
+            }
+        }
+        if let Some(path) = &argument.config.itch_file {
+            if let Err(e) = self.binary_feed.add_file(path) {
+                return Err(format!["Failed to record the binary feed to {}: {}", path, e]);
+            }
+        }
+        self.binary_feed.start(&self.exchange);
+        Ok(())
+    }
+
+    /* Service a request, then publish what it did.
+     * Nothing is published after EXIT, as the database writer has been told to stop.
+     **/
+    pub fn service(&mut self, request: Request) -> Response {
+        let exit = matches!(request, Request::ExitReq);
+        let response = match &request {
+            Request::SimReq(sim) if sim.action == "simulate" => self.simulate(sim),
+            _ => parser::service_request(request, &mut self.exchange, &mut self.users, &mut self.buffers, &mut *self.store, &mut *self.cache)
+        };
+        if !exit {
+            self.after_request();
+        }
+        response
+    }
+
+    /* Service the requests of every server and the console, one at a time, in the order they arrived.
+     * Returns once EXIT was serviced, or everyone sending requests is gone.
+     **/
+    pub fn serve(&mut self, requests: mpsc::Receiver<Incoming>) {
+        for incoming in requests.iter() {
+            let ClientRequest { line, console, reply } = match incoming {
+                Incoming::Line(request) => request,
+                Incoming::Api(request) => {
+                    let response = api::service_call(request.call, &mut self.exchange, &mut self.users, &mut self.buffers, &mut *self.store, &mut *self.cache);
+                    request.reply.send(response).ok();
+                    self.after_request();
+                    continue;
+                },
+                Incoming::Feed(command) => {
+                    self.feed.command(command, &self.exchange);
+                    continue;
+                },
+                Incoming::DropCopy(command) => {
+                    self.drop_copy.command(command, &mut self.users, &mut *self.store);
+                    continue;
+                },
+                Incoming::Fix(command) => {
+                    self.gateway.service(command, &mut self.exchange, &mut self.users, &mut self.buffers, &mut *self.store, &mut *self.cache);
+                    self.after_request();
+                    continue;
+                }
+            };
+            let request: Request = match parser::tokenize_input(line) {
+                Ok(req) => req,
+                Err(e)  => {
+                    // The console doesn't need to be told it entered nothing.
+                    let response = match e {
+                        ParseError::Empty if console => Response::ok(""),
+                        e => Response::from(e)
+                    };
+                    reply.send(response).ok();
+                    continue;
+                }
+            };
+
+            // Simulations and EXIT can only come from the console, and upgrade_db prompts on it.
+            let console_only = match request {
+                Request::UpgradeDbReq(_, _) => true,
+                _ => request.permission() == Permission::Console
+            };
+            if console_only && !console {
+                reply.send(Response::error(Status::Forbidden, "This request can only be made from the exchange's console.")).ok();
+                continue;
+            }
+
+            let exit = matches!(request, Request::ExitReq);
+            let response = self.service(request);
+            // The client may have disconnected while we were working on its request.
+            reply.send(response).ok();
+            if exit {
+                break;
+            }
+        }
+    }
+
+    /* Called after every request. Reports fills to FIX sessions and the drop copy, publishes the request's market data,
+     * and sends the buffers to the database thread once they fill up.
+     **/
+    pub fn after_request(&mut self) {
+        Runtime::publish(&mut self.gateway, &mut self.drop_copy, &mut self.binary_feed, &mut self.feed, &mut self.exchange, &mut self.users);
+
+        // Make sure our buffer states are accurate.
+        self.buffers.update_buffer_states();
+        // If order buffer was drained, we can reset our cached values modified field.
+        self.users.confirm_writes(self.buffers.batches_written());
+        if self.buffers.transmit_buffer_data(&self.exchange) {
+            self.users.reset_users_modified(self.buffers.batches_sent());
+
+            // Set all market stats modified to false
+            for (_key, entry) in self.exchange.statistics.iter_mut() {
+                entry.modified = false;
+            }
+
+            // Save the cache file along with the flush, so a crash loses no more than the buffers would.
+            if let Err(e) = self.cache_backend.persist() {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    // Publish what happened since we last did, clearing it from the exchange.
+    fn publish(gateway: &mut FixGateway, drop_copy: &mut DropCopy, binary_feed: &mut BinaryFeed, feed: &mut MarketFeed, exchange: &mut Exchange, users: &mut Users) {
+        gateway.report_trades(&exchange.trade_prints);
+        drop_copy.publish(users);
+        binary_feed.publish(exchange);
+        feed.publish(exchange);
+    }
+
+    /* Run a simulation, publishing as it goes rather than all at once when it's done. */
+    fn simulate(&mut self, sim: &Simulation) -> Response {
+        println!("Simulating {} order(s) in {} market(s) among {} account(s)!", sim.duration, sim.market_count, sim.trader_count);
+        let (gateway, drop_copy, binary_feed, feed) = (&mut self.gateway, &mut self.drop_copy, &mut self.binary_feed, &mut self.feed);
+        self.exchange.simulate_market(sim, &mut self.users, &mut self.buffers, &mut *self.store, &mut *self.cache, &mut |exchange, users| {
+            Runtime::publish(gateway, drop_copy, binary_feed, feed, exchange, users);
+        });
+        Response::ok("")
+    }
+
+    /* End the binary feed, and wait for the database writer to finish. Call after servicing EXIT. */
+    pub fn shutdown(mut self) {
+        self.binary_feed.stop();
+        self.writer.join().unwrap();
+        if let Err(e) = self.cache_backend.persist() {
+            eprintln!("{}", e);
+        }
+        println!("\nShutdown sequence complete. Goodbye!");
+    }
+}
+
+/* Set sigINT/sigTERM handlers
+ * TODO: If we want the sigINT handler thread to be capable of flushing the buffers, we'll need
+ * to share the buffers with it. To do this, we will have to wrap the buffers inside a mutex
+ * and wrap the mutex in an Arc.
+ *
+ * This might not be too technically difficult, but I'm not sure I like the behaviour:
+ *  -  It implies that we can shut the exchange while an order is being processed, potentially
+ *     resulting in inconsistent state.
+ *  -  To solve this, we would have to have some other shared var that says the state is
+ *     consistent, and since we're shutting down no more orders can be placed.
+ **/
+pub fn ignore_interrupts() {
+    ctrlc::set_handler(|| {
+        println!("Please use the EXIT command, still figuring out how to do a controlled shutdown...");
+    }).expect("Error setting Ctrl-C handler");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::CostBasis;
+    use crate::exchange::{ExecType, ErrorCode, PriceLevel, RateLimits, RateLimiter, SIM_PUBLISH_INTERVAL};
+
+    // An in-memory exchange with a GME market, and the accounts admin, alice and bob (who may be short up to 10 shares).
+    fn runtime() -> Runtime {
+        let mut runtime = Runtime::in_memory();
+        runtime.store.upgrade_db(&mut "add,GME,GameStop\n".as_bytes(), "rustx");
+        runtime.store.populate_market_statistics(&mut runtime.exchange);
+        runtime.store.populate_has_trades(&mut runtime.exchange);
+        for line in ["account create admin password", "account create alice password", "account create bob password",
+                     "short enable bob 10 100000 admin password"].iter() {
+            assert!(request(&mut runtime, line).is_ok(), "{}", line);
+        }
+        runtime
+    }
+
+    fn request(runtime: &mut Runtime, line: &str) -> Response {
+        runtime.service(parser::tokenize_input(line.to_string()).unwrap())
+    }
+
+    fn book(runtime: &Runtime) -> (Vec<PriceLevel>, Vec<PriceLevel>) {
+        runtime.exchange.live_orders.get("GME").map(|market| market.depth(10)).unwrap_or_default()
+    }
+
+    // The (action, quantity, filled, price) of the account's pending orders in GME.
+    fn pending(runtime: &mut Runtime, username: &str) -> Vec<(String, i32, i32, f64)> {
+        let account = match runtime.users.get(username, true) {
+            Ok(account) => account,
+            Err(_) => panic!("{} isn't cached.", username)
+        };
+        let mut orders: Vec<(String, i32, i32, f64)> = account.pending_orders.view_market("GME")
+            .map(|orders| orders.values().map(|order| (order.action.clone(), order.quantity, order.filled, order.price)).collect())
+            .unwrap_or_default();
+        orders.sort_by(|a, b| a.3.partial_cmp(&b.3).unwrap());
+        orders
+    }
+
+    // The shares held and the realised P&L.
+    fn position(runtime: &mut Runtime, username: &str) -> (i32, f64) {
+        let Runtime { users, exchange, cache, .. } = runtime;
+        let account = match users.get(username, true) {
+            Ok(account) => account,
+            Err(_) => panic!("{} isn't cached.", username)
+        };
+        let portfolio = account.portfolio(&exchange.statistics, CostBasis::Fifo, &mut **cache);
+        portfolio.get("GME").map(|position| (position.quantity, position.realised)).unwrap_or((0, 0.0))
+    }
+
+    fn exec_types(response: &Response) -> Vec<ExecType> {
+        response.reports.iter().map(|report| report.exec_type).collect()
+    }
+
+    #[test]
+    fn placed_orders_rest_on_the_book() {
+        let mut runtime = runtime();
+        let response = request(&mut runtime, "buy GME 10 100 alice password");
+        assert!(response.is_ok(), "{}", response.message);
+        assert_eq!(exec_types(&response), vec![ExecType::New]);
+        request(&mut runtime, "sell GME 5 110 bob password");
+
+        assert_eq!(book(&runtime), (vec![(100.0, 10)], vec![(110.0, 5)]));
+        assert_eq!(pending(&mut runtime, "alice"), vec![("BUY".to_string(), 10, 0, 100.0)]);
+        assert_eq!(pending(&mut runtime, "bob"), vec![("SELL".to_string(), 5, 0, 110.0)]);
+        assert_eq!(position(&mut runtime, "alice"), (0, 0.0));
+    }
+
+    #[test]
+    fn crossing_orders_trade_at_the_resting_price() {
+        let mut runtime = runtime();
+        request(&mut runtime, "buy GME 10 100 alice password");
+        let response = request(&mut runtime, "sell GME 4 99 bob password");
+        assert_eq!(exec_types(&response), vec![ExecType::New, ExecType::Fill]);
+        assert_eq!(response.reports[1].last_price, 100.0);
+
+        assert_eq!(book(&runtime), (vec![(100.0, 6)], vec![]));
+        assert_eq!(pending(&mut runtime, "alice"), vec![("BUY".to_string(), 10, 4, 100.0)]);
+        assert!(pending(&mut runtime, "bob").is_empty());
+        assert_eq!(position(&mut runtime, "alice"), (4, 0.0));
+        assert_eq!(position(&mut runtime, "bob"), (-4, 0.0));
+        assert_eq!(runtime.exchange.statistics["GME"].last_price, Some(100.0));
+
+        // Bob buys back what he sold, at a loss of 2 a share.
+        let response = request(&mut runtime, "buy GME 4 102 bob password");
+        request(&mut runtime, "sell GME 4 102 alice password");
+        assert_eq!(exec_types(&response), vec![ExecType::New]);
+        assert_eq!(position(&mut runtime, "alice"), (0, 8.0));
+        assert_eq!(position(&mut runtime, "bob"), (0, -8.0));
+        assert_eq!(book(&runtime), (vec![(100.0, 6)], vec![]));
+    }
+
+    #[test]
+    fn cancelled_orders_leave_the_book_and_the_account() {
+        let mut runtime = runtime();
+        let placed = request(&mut runtime, "buy GME 10 100 alice password").order.unwrap();
+        request(&mut runtime, "sell GME 4 100 bob password");
+
+        // Bob can't cancel alice's order.
+        let response = request(&mut runtime, &format!["cancel GME {} bob password", placed.order_id]);
+        assert!(!response.is_ok());
+        assert_eq!(book(&runtime), (vec![(100.0, 6)], vec![]));
+
+        let response = request(&mut runtime, &format!["cancel GME {} alice password", placed.order_id]);
+        assert!(response.is_ok(), "{}", response.message);
+        assert_eq!(exec_types(&response), vec![ExecType::Cancelled]);
+        assert_eq!(response.reports[0].cum_quantity, 4);
+        assert_eq!(book(&runtime), (vec![], vec![]));
+        assert!(pending(&mut runtime, "alice").is_empty());
+        // The fill stands.
+        assert_eq!(position(&mut runtime, "alice"), (4, 0.0));
+
+        let response = request(&mut runtime, &format!["cancel GME {} alice password", placed.order_id]);
+        assert!(!response.is_ok());
+    }
+
+    #[test]
+    fn amended_orders_are_replaced() {
+        let mut runtime = runtime();
+        request(&mut runtime, "buy GME 10 100 clid=a1 alice password");
+        request(&mut runtime, "sell GME 4 100 bob password");
+
+        // The replacement is a new order for 20 more shares, and it can cross.
+        request(&mut runtime, "sell GME 5 101 bob password");
+        let response = request(&mut runtime, "amend GME clid=a1 20 101 clid=a2 alice password");
+        assert!(response.is_ok(), "{}", response.message);
+        assert_eq!(exec_types(&response), vec![ExecType::Cancelled, ExecType::Replaced, ExecType::PartialFill]);
+        assert_eq!(book(&runtime), (vec![(101.0, 15)], vec![]));
+        assert_eq!(pending(&mut runtime, "alice"), vec![("BUY".to_string(), 20, 5, 101.0)]);
+        assert_eq!(position(&mut runtime, "alice"), (9, 0.0));
+
+        // The original is gone.
+        let response = request(&mut runtime, "amend GME clid=a1 10 100 alice password");
+        assert!(matches!(response.code, Some(ErrorCode::UnknownOrder)), "{}", response.message);
+    }
+
+    #[test]
+    fn amends_that_fail_their_checks_leave_the_original() {
+        let mut runtime = runtime();
+        request(&mut runtime, "sell GME 10 110 clid=s1 bob password");
+
+        // Bob is already 10 short if s1 fills, so its replacement may be up to 10 shares, not 11.
+        let response = request(&mut runtime, "amend GME clid=s1 11 111 bob password");
+        assert!(matches!(response.code, Some(ErrorCode::ShortSaleRejected)), "{}", response.message);
+        assert_eq!(book(&runtime), (vec![], vec![(110.0, 10)]));
+        assert_eq!(pending(&mut runtime, "bob"), vec![("SELL".to_string(), 10, 0, 110.0)]);
+
+        let response = request(&mut runtime, "amend GME clid=s1 10 111 bob password");
+        assert!(response.is_ok(), "{}", response.message);
+        assert_eq!(book(&runtime), (vec![], vec![(111.0, 10)]));
+        assert_eq!(pending(&mut runtime, "bob"), vec![("SELL".to_string(), 10, 0, 111.0)]);
+    }
+
+    #[test]
+    fn halted_markets_reject_orders_and_amends() {
+        let mut runtime = runtime();
+        request(&mut runtime, "buy GME 10 100 clid=a1 alice password");
+
+        // Only operators and the admin can halt a market.
+        let response = request(&mut runtime, "market halt GME alice password");
+        assert!(matches!(response.code, Some(ErrorCode::Forbidden)), "{}", response.message);
+        assert!(request(&mut runtime, "market halt GME admin password").is_ok());
+
+        let response = request(&mut runtime, "sell GME 5 100 bob password");
+        assert_eq!(response.status, Status::Conflict);
+        assert!(matches!(response.code, Some(ErrorCode::MarketHalted)), "{}", response.message);
+        assert_eq!(response.to_string().split_whitespace().nth(2), Some("[MARKET_HALTED]"));
+        assert_eq!(exec_types(&response), vec![ExecType::Rejected]);
+
+        // The amend is rejected before the original is cancelled, which is still allowed.
+        let response = request(&mut runtime, "amend GME clid=a1 5 101 alice password");
+        assert!(matches!(response.code, Some(ErrorCode::MarketHalted)), "{}", response.message);
+        assert_eq!(book(&runtime), (vec![(100.0, 10)], vec![]));
+        assert!(request(&mut runtime, "cancel GME clid=a1 alice password").is_ok());
+        assert_eq!(book(&runtime), (vec![], vec![]));
+
+        assert!(request(&mut runtime, "market resume GME admin password").is_ok());
+        assert!(request(&mut runtime, "sell GME 5 100 bob password").is_ok());
+        assert_eq!(book(&runtime), (vec![], vec![(100.0, 5)]));
+    }
+
+    #[test]
+    fn rejected_requests_say_why() {
+        let mut runtime = runtime();
+        let placed = request(&mut runtime, "sell GME 5 110 bob password").order.unwrap();
+        let codes = [
+            ("buy XYZ 10 100 alice password", ErrorCode::UnknownSymbol),
+            ("buy GME 10 100 alice wrong", ErrorCode::Unauthorized),
+            ("sell GME 1 100 alice password", ErrorCode::ShortSaleRejected),    // Short selling isn't enabled for alice.
+            ("sell GME 6 100 bob password", ErrorCode::ShortSaleRejected),      // 11 shares would be over bob's borrow limit of 10.
+            ("cancel GME 9999 alice password", ErrorCode::UnknownOrder),
+        ];
+        for (line, code) in codes.iter() {
+            let response = request(&mut runtime, line);
+            assert_eq!(response.code.as_ref(), Some(code), "{}: {}", line, response.message);
+        }
+        let response = request(&mut runtime, &format!["cancel GME {} alice password", placed.order_id]);
+        assert_eq!(response.code, Some(ErrorCode::NotOwner), "{}", response.message);
+
+        // Rejected orders are reported with the reason, and leave nothing behind.
+        let response = request(&mut runtime, "sell GME 6 100 bob password");
+        assert_eq!(exec_types(&response), vec![ExecType::Rejected]);
+        assert_eq!(response.reports[0].reason.as_deref(), Some(response.message.as_str()));
+        assert_eq!(response.reports[0].leaves_quantity, 0);
+        assert_eq!(book(&runtime), (vec![], vec![(110.0, 5)]));
+    }
+
+    #[test]
+    fn fills_report_the_running_totals() {
+        let mut runtime = runtime();
+        request(&mut runtime, "sell GME 4 100 bob password");
+        request(&mut runtime, "sell GME 4 102 bob password");
+        let response = request(&mut runtime, "buy GME 10 102 clid=b1 alice password");
+        assert_eq!(exec_types(&response), vec![ExecType::New, ExecType::PartialFill, ExecType::PartialFill]);
+        let totals: Vec<(i32, f64, i32, i32, f64)> = response.reports.iter()
+            .map(|report| (report.last_quantity, report.last_price, report.cum_quantity, report.leaves_quantity, report.avg_price))
+            .collect();
+        assert_eq!(totals, vec![(0, 0.0, 0, 10, 0.0), (4, 100.0, 4, 6, 100.0), (4, 102.0, 8, 2, 101.0)]);
+        assert!(response.reports.iter().all(|report| report.client_id.as_deref() == Some("b1")));
+
+        // The next sell fills the rest of alice's order, and all of its own.
+        let response = request(&mut runtime, "sell GME 2 101 bob password");
+        assert_eq!(exec_types(&response), vec![ExecType::New, ExecType::Fill]);
+        assert_eq!((response.reports[1].last_price, response.reports[1].cum_quantity, response.reports[1].leaves_quantity), (102.0, 2, 0));
+        assert!(pending(&mut runtime, "alice").is_empty());
+        assert_eq!(book(&runtime), (vec![], vec![]));
+    }
+
+    #[test]
+    fn requests_need_a_role_that_permits_them() {
+        let mut runtime = runtime();
+        // Only the admin grants roles, and can't revoke their own admin role.
+        assert_eq!(request(&mut runtime, "role grant bob operator alice password").status, Status::Forbidden);
+        assert_eq!(request(&mut runtime, "role revoke admin admin admin password").status, Status::Forbidden);
+
+        // A read only account can look at its account, but not trade.
+        assert!(request(&mut runtime, "role grant alice read_only admin password").is_ok());
+        assert!(request(&mut runtime, "role revoke alice trader admin password").is_ok());
+        assert!(request(&mut runtime, "account show alice password").is_ok());
+        let response = request(&mut runtime, "buy GME 10 100 alice password");
+        assert_eq!((response.status, response.code), (Status::Forbidden, Some(ErrorCode::Forbidden)));
+        assert_eq!(book(&runtime), (vec![], vec![]));
+    }
+
+    #[test]
+    fn help_is_sent_back() {
+        let mut runtime = Runtime::in_memory();
+        let response = request(&mut runtime, "help");
+        assert!(response.is_ok());
+        assert!(response.message.starts_with("Usage:"));
+        // Servers send it on one line.
+        assert_eq!(response.to_string().lines().count(), 1);
+    }
+
+    #[test]
+    fn simulations_publish_as_they_go() {
+        let mut runtime = runtime();
+        let limits = RateLimits::new(1_000_000.0, 1_000_000.0, 1_000_000);
+        runtime.exchange.rate_limiter = RateLimiter::new(limits, limits);
+
+        // Only the last order is placed after the simulation last published, though it may not have been
+        // placed at all, as simulated traders don't trade with themselves.
+        runtime.simulate(&Simulation::from("simulate".to_string(), 2, 1, 2 * SIM_PUBLISH_INTERVAL + 1));
+        let events = runtime.exchange.book_events.len();
+        assert!(events < 30, "{} book events weren't published", events);
+        assert!(runtime.exchange.trade_prints.len() < 14);
+
+        runtime.after_request();
+        assert!(runtime.exchange.book_events.is_empty() && runtime.exchange.trade_prints.is_empty());
+        assert!(runtime.users.executions.is_empty());
+    }
+}
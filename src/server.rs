@@ -0,0 +1,142 @@
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::parser::{Response, Status};
+use crate::api::ApiRequest;
+use crate::stream::FeedCommand;
+use crate::fix::FixCommand;
+use crate::drop_copy::DropCopyCommand;
+
+// The longest line we read from a client, requests are much shorter.
+// Clients that send a longer one are told so, and disconnected.
+pub const MAX_LINE: u64 = 4096;
+
+/* A line read from a client (or the console), and where to send its response.
+ *
+ * Every connection gets its own thread, but the exchange lives on the main thread,
+ * so all requests are sent there over one channel and serviced in the order they arrive.
+ **/
+pub struct ClientRequest {
+    pub line: String,
+    pub console: bool,                  // Console only requests (ex. simulate, EXIT) are rejected from the network.
+    pub reply: mpsc::Sender<Response>
+}
+
+// Everything the main thread services, from the line protocol, the HTTP API, the market data feed, FIX sessions and the drop copy.
+pub enum Incoming {
+    Line(ClientRequest),
+    Api(ApiRequest),
+    Feed(FeedCommand),
+    Fix(FixCommand),
+    DropCopy(DropCopyCommand)
+}
+
+/* Accept connections on the given address, each one speaks the same language as the console:
+ * one request per line, and one response line per request, ex.
+ *      > buy GME 10 167.34 sess_0123456789abcdef0123456789abcdef
+ *      < 200 OK Order 42: BUY 10 $GME at $167.34, 0 filled, PENDING
+ *
+ * Returns the handle of the accepting thread, or an error if we can't listen on the address.
+ **/
+pub fn listen(address: &str, requests: mpsc::Sender<Incoming>) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(address)?;
+    dark_green!("Listening for clients on {}\n", listener.local_addr()?);
+
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let requests = requests.clone();
+                    thread::spawn(move || {
+                        let peer = match stream.peer_addr() {
+                            Ok(addr) => addr.to_string(),
+                            Err(_) => "unknown".to_string()
+                        };
+                        if let Err(e) = handle_client(stream, requests) {
+                            eprintln!("Connection to {} closed: {}", peer, e);
+                        }
+                    });
+                },
+                Err(e) => eprintln!("Failed to accept a connection: {}", e)
+            }
+        }
+    });
+    Ok(handle)
+}
+
+/* Read requests from the client until it disconnects, or the exchange shuts down. */
+fn handle_client(stream: TcpStream, requests: mpsc::Sender<Incoming>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let (reply_tx, reply_rx) = mpsc::channel();
+
+    loop {
+        // Read at most MAX_LINE bytes and the line ending (\r\n), so we can tell a line that's too long.
+        let mut line = String::new();
+        let read = (&mut reader).take(MAX_LINE + 2).read_line(&mut line)? as u64;
+        if read == 0 {
+            break;
+        }
+        let content = line.trim_end_matches(['\r', '\n']);
+        if content.len() as u64 > MAX_LINE || (read == MAX_LINE + 2 && !line.ends_with('\n')) {
+            let response = Response::error(Status::TooLarge, &format!["Requests can be at most {} bytes long.", MAX_LINE]);
+            writer.write_all(format!["{}\n", response].as_bytes())?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the client sent a line that's too long"));
+        }
+        if content.trim().is_empty() {
+            continue;
+        }
+        let line = content.to_string();
+
+        let request = ClientRequest {
+            line,
+            console: false,
+            reply: reply_tx.clone()
+        };
+        // The exchange stopped taking requests, we're shutting down.
+        if requests.send(Incoming::Line(request)).is_err() {
+            break;
+        }
+        match reply_rx.recv() {
+            Ok(response) => writer.write_all(format!["{}\n", response].as_bytes())?,
+            Err(_) => break
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn over_long_lines_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || handle_client(stream, tx));
+
+        // Answer each line with the line itself.
+        thread::spawn(move || {
+            for incoming in rx.iter() {
+                if let Incoming::Line(request) = incoming {
+                    request.reply.send(Response::ok(&request.line)).ok();
+                }
+            }
+        });
+
+        let line = "a".repeat(MAX_LINE as usize);
+        client.write_all(format!["{}\r\n{}b\n", line, line].as_bytes()).unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+
+        let responses: Vec<&str> = responses.lines().collect();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0], format!["200 OK {}", line]);
+        assert!(responses[1].starts_with("413 TOO_LARGE"), "{}", responses[1]);
+        assert!(handle.join().unwrap().is_err());
+    }
+}
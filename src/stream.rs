@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+
+use crate::exchange::{Exchange, PriceLevel};
+use crate::exchange::filled::Trade;
+use crate::server::Incoming;
+
+// Hands out an id to each WebSocket connection, so we know whose subscription to remove.
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(1);
+
+// How long a connection waits for a message from its client before checking for updates to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/* Sent to the main thread by the WebSocket connections.
+ * Subscriptions are serviced in line with requests, so a snapshot is never torn by an order.
+ **/
+pub enum FeedCommand {
+    Subscribe(usize, String, mpsc::Sender<String>),  // connection id, symbol, where to send the updates
+    Unsubscribe(usize, String),
+    Disconnect(usize)
+}
+
+// What subscribers of one symbol have been told.
+struct SymbolFeed {
+    seq: u64,                               // The sequence number of the last update sent
+    bids: HashMap<i64, i32>,                // price in cents -> quantity, as of the last update
+    asks: HashMap<i64, i32>,
+    subscribers: HashMap<usize, mpsc::Sender<String>>
+}
+
+/* Streams trade prints and changes to each price level of the book, per symbol.
+ *
+ * A subscriber first gets a snapshot of the book, then every update with the next sequence number:
+ *      {"type": "snapshot", "symbol": "GME", "seq": 7, "bids": [{"price": 167.34, "quantity": 24}], "asks": [...]}
+ *      {"type": "trade", "symbol": "GME", "seq": 8, "price": 167.34, "quantity": 10, "aggressor": "SELL", ...}
+ *      {"type": "book", "symbol": "GME", "seq": 9, "side": "BUY", "action": "modify", "price": 167.34, "quantity": 14}
+ * Sequence numbers are per symbol, so a gap means an update was missed, and the client should resubscribe.
+ **/
+pub struct MarketFeed {
+    symbols: HashMap<String, SymbolFeed>    // Only symbols with subscribers are tracked.
+}
+
+impl Default for MarketFeed {
+    fn default() -> Self {
+        MarketFeed::new()
+    }
+}
+
+impl MarketFeed {
+    pub fn new() -> Self {
+        MarketFeed {
+            symbols: HashMap::new()
+        }
+    }
+
+    pub fn command(&mut self, command: FeedCommand, exchange: &Exchange) {
+        match command {
+            FeedCommand::Subscribe(connection, symbol, subscriber) => {
+                if !exchange.statistics.contains_key(&symbol) {
+                    let error = json!({ "type": "error", "symbol": symbol, "message": format!["${} is not a market.", symbol] });
+                    subscriber.send(error.to_string()).ok();
+                    return;
+                }
+                let feed = self.symbols.entry(symbol.clone()).or_insert_with(|| {
+                    let (bids, asks) = book_levels(exchange, &symbol);
+                    SymbolFeed { seq: 0, bids, asks, subscribers: HashMap::new() }
+                });
+                let snapshot = json!({
+                    "type": "snapshot",
+                    "symbol": symbol,
+                    "seq": feed.seq,
+                    "bids": levels_json(&feed.bids, true),
+                    "asks": levels_json(&feed.asks, false)
+                });
+                if subscriber.send(snapshot.to_string()).is_ok() {
+                    feed.subscribers.insert(connection, subscriber);
+                }
+            },
+            FeedCommand::Unsubscribe(connection, symbol) => {
+                if let Some(feed) = self.symbols.get_mut(&symbol) {
+                    feed.subscribers.remove(&connection);
+                }
+                self.symbols.retain(|_, feed| !feed.subscribers.is_empty());
+            },
+            FeedCommand::Disconnect(connection) => {
+                for feed in self.symbols.values_mut() {
+                    feed.subscribers.remove(&connection);
+                }
+                self.symbols.retain(|_, feed| !feed.subscribers.is_empty());
+            }
+        }
+    }
+
+    /* Send the trades and book changes of the last request to their subscribers.
+     * Called after every request, even with no subscribers, so the exchange's lists don't grow.
+     * Subscribers whose connection closed are removed when their connection sends Disconnect.
+     **/
+    pub fn publish(&mut self, exchange: &mut Exchange) {
+        let trades: Vec<Trade> = exchange.trade_prints.drain(..).collect();
+        let changed: Vec<String> = exchange.book_changes.drain().collect();
+        if self.symbols.is_empty() {
+            return;
+        }
+
+        for trade in trades.iter() {
+            if let Some(feed) = self.symbols.get_mut(&trade.symbol) {
+                // The trade's action is the side of the resting order.
+                let aggressor = if trade.action == "BUY" { "SELL" } else { "BUY" };
+                feed.seq += 1;
+                let print = json!({
+                    "type": "trade",
+                    "symbol": trade.symbol,
+                    "seq": feed.seq,
+                    "price": trade.price,
+                    "quantity": trade.exchanged,
+                    "aggressor": aggressor,
+                    "time": trade.execution_time.to_rfc3339()
+                });
+                feed.send(&print);
+            }
+        }
+
+        for symbol in changed.iter() {
+            if let Some(feed) = self.symbols.get_mut(symbol) {
+                let (bids, asks) = book_levels(exchange, symbol);
+                let mut deltas: Vec<(&str, i64, &str, i32)> = Vec::new();
+                for (price, action, quantity) in diff_levels(&feed.bids, &bids) {
+                    deltas.push(("BUY", price, action, quantity));
+                }
+                for (price, action, quantity) in diff_levels(&feed.asks, &asks) {
+                    deltas.push(("SELL", price, action, quantity));
+                }
+                for (side, price, action, quantity) in deltas {
+                    feed.seq += 1;
+                    let delta = json!({
+                        "type": "book",
+                        "symbol": symbol,
+                        "seq": feed.seq,
+                        "side": side,
+                        "action": action,
+                        "price": price as f64 / 100.0,
+                        "quantity": quantity
+                    });
+                    feed.send(&delta);
+                }
+                feed.bids = bids;
+                feed.asks = asks;
+            }
+        }
+    }
+}
+
+impl SymbolFeed {
+    fn send(&self, update: &Value) {
+        let update = update.to_string();
+        for subscriber in self.subscribers.values() {
+            subscriber.send(update.clone()).ok();
+        }
+    }
+}
+
+// Every price level on each side of the book, keyed by the price in cents.
+fn book_levels(exchange: &Exchange, symbol: &str) -> (HashMap<i64, i32>, HashMap<i64, i32>) {
+    let (bids, asks) = match exchange.live_orders.get(symbol) {
+        Some(market) => market.depth(usize::MAX),
+        None => (Vec::new(), Vec::new())
+    };
+    let to_map = |levels: Vec<PriceLevel>| levels.into_iter().map(|(price, quantity)| ((price * 100.0).round() as i64, quantity)).collect();
+    (to_map(bids), to_map(asks))
+}
+
+// The levels of one side of the book, best price first.
+fn levels_json(levels: &HashMap<i64, i32>, descending: bool) -> Vec<Value> {
+    let mut prices: Vec<&i64> = levels.keys().collect();
+    prices.sort();
+    if descending {
+        prices.reverse();
+    }
+    prices.into_iter().map(|price| json!({ "price": *price as f64 / 100.0, "quantity": levels[price] })).collect()
+}
+
+// The (price, add/modify/delete, new quantity) of each level that changed, in price order.
+fn diff_levels(old: &HashMap<i64, i32>, new: &HashMap<i64, i32>) -> Vec<(i64, &'static str, i32)> {
+    let mut changes = Vec::new();
+    for (price, quantity) in new.iter() {
+        match old.get(price) {
+            None => changes.push((*price, "add", *quantity)),
+            Some(old_quantity) if old_quantity != quantity => changes.push((*price, "modify", *quantity)),
+            _ => ()
+        }
+    }
+    for price in old.keys() {
+        if !new.contains_key(price) {
+            changes.push((*price, "delete", 0));
+        }
+    }
+    changes.sort_by_key(|change| change.0);
+    changes
+}
+
+/* Accept WebSocket connections on the given address. Clients send
+ *      {"subscribe": "GME"} or {"unsubscribe": "GME"}
+ * and receive the updates described on MarketFeed.
+ *
+ * Returns the handle of the accepting thread, or an error if we can't listen on the address.
+ **/
+pub fn listen(address: &str, requests: mpsc::Sender<Incoming>) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(address)?;
+    dark_green!("Streaming market data on ws://{}\n", listener.local_addr()?);
+
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let requests = requests.clone();
+                    thread::spawn(move || {
+                        let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+                        if let Err(e) = handle_subscriber(stream, connection, &requests) {
+                            eprintln!("WebSocket connection closed: {}", e);
+                        }
+                        requests.send(Incoming::Feed(FeedCommand::Disconnect(connection))).ok();
+                    });
+                },
+                Err(e) => eprintln!("Failed to accept a connection: {}", e)
+            }
+        }
+    });
+    Ok(handle)
+}
+
+/* Reads the client's subscriptions, and writes its updates, until either side goes away. */
+fn handle_subscriber(stream: TcpStream, connection: usize, requests: &mpsc::Sender<Incoming>) -> Result<(), Box<tungstenite::Error>> {
+    let mut socket: WebSocket<TcpStream> = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(e) => return Err(Box::new(tungstenite::Error::Io(io::Error::other(e.to_string()))))
+    };
+    // Don't block on the client, we also have updates to send.
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).map_err(tungstenite::Error::Io)?;
+    let (update_tx, update_rx) = mpsc::channel::<String>();
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let command = match serde_json::from_str::<Value>(&text) {
+                    Ok(message) => subscription(&message, connection, &update_tx),
+                    Err(_) => None
+                };
+                match command {
+                    Some(command) => {
+                        if requests.send(Incoming::Feed(command)).is_err() {
+                            return Ok(());
+                        }
+                    },
+                    None => {
+                        let error = json!({ "type": "error", "message": "Send {\"subscribe\": \"SYMBOL\"} or {\"unsubscribe\": \"SYMBOL\"}." });
+                        socket.send(Message::Text(error.to_string()))?;
+                    }
+                }
+            },
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => (),
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(Box::new(e))
+        }
+
+        for update in update_rx.try_iter() {
+            socket.send(Message::Text(update))?;
+        }
+    }
+}
+
+fn subscription(message: &Value, connection: usize, updates: &mpsc::Sender<String>) -> Option<FeedCommand> {
+    if let Some(symbol) = message.get("subscribe").and_then(Value::as_str) {
+        return Some(FeedCommand::Subscribe(connection, symbol.to_uppercase(), updates.clone()));
+    }
+    if let Some(symbol) = message.get("unsubscribe").and_then(Value::as_str) {
+        return Some(FeedCommand::Unsubscribe(connection, symbol.to_uppercase()));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::runtime::Runtime;
+    use crate::server::ClientRequest;
+
+    type Socket = WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>;
+
+    fn receive(socket: &mut Socket) -> Value {
+        match socket.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("Expected an update, got {:?}", message)
+        }
+    }
+
+    // Service a line on the main thread, like a client of the line protocol would.
+    fn request(requests: &mpsc::Sender<Incoming>, line: &str) {
+        let (reply, response) = mpsc::channel();
+        requests.send(Incoming::Line(ClientRequest { line: line.to_string(), console: false, reply })).unwrap();
+        let response = response.recv().unwrap();
+        assert!(response.is_ok(), "{}: {}", line, response.message);
+    }
+
+    #[test]
+    fn subscribers_get_a_snapshot_then_each_update() {
+        let mut runtime = Runtime::in_memory();
+        runtime.store.upgrade_db(&mut "add,GME,GameStop\n".as_bytes(), "rustx");
+        runtime.store.populate_market_statistics(&mut runtime.exchange);
+        runtime.store.populate_has_trades(&mut runtime.exchange);
+        for line in ["account create admin password", "account create alice password", "account create bob password",
+                     "short enable bob 10 100000 admin password", "buy GME 5 90 alice password"].iter() {
+            assert!(runtime.service(parser::tokenize_input(line.to_string()).unwrap()).is_ok(), "{}", line);
+        }
+
+        // Find a free port for the feed.
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        listen(&address, tx.clone()).unwrap();
+
+        // Exit once the client is done, even if it panicked, so serve returns.
+        let handle = thread::spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let (mut socket, _) = tungstenite::connect(format!["ws://{}", address]).unwrap();
+                socket.send(Message::Text(r#"{"subscribe": "XYZ"}"#.to_string())).unwrap();
+                assert_eq!(receive(&mut socket)["type"], "error");
+                socket.send(Message::Text("hello".to_string())).unwrap();
+                assert_eq!(receive(&mut socket)["type"], "error");
+
+                socket.send(Message::Text(r#"{"subscribe": "gme"}"#.to_string())).unwrap();
+                assert_eq!(receive(&mut socket), json!({
+                    "type": "snapshot", "symbol": "GME", "seq": 0, "bids": [{ "price": 90.0, "quantity": 5 }], "asks": []
+                }));
+
+                request(&tx, "buy GME 10 100 alice password");
+                assert_eq!(receive(&mut socket), json!({
+                    "type": "book", "symbol": "GME", "seq": 1, "side": "BUY", "action": "add", "price": 100.0, "quantity": 10
+                }));
+
+                request(&tx, "sell GME 4 99 bob password");
+                let trade = receive(&mut socket);
+                assert_eq!((&trade["type"], &trade["seq"], &trade["price"], &trade["quantity"], &trade["aggressor"]),
+                           (&json!("trade"), &json!(2), &json!(100.0), &json!(4), &json!("SELL")));
+                assert_eq!(receive(&mut socket), json!({
+                    "type": "book", "symbol": "GME", "seq": 3, "side": "BUY", "action": "modify", "price": 100.0, "quantity": 6
+                }));
+
+                // Nothing more is sent after unsubscribing, so the next message is the reply to a bad one.
+                socket.send(Message::Text(r#"{"unsubscribe": "GME"}"#.to_string())).unwrap();
+                thread::sleep(POLL_INTERVAL * 5);
+                request(&tx, "sell GME 6 100 bob password");
+                socket.send(Message::Text("hello".to_string())).unwrap();
+                assert_eq!(receive(&mut socket)["type"], "error");
+                socket.close(None).ok();
+            }));
+            let (reply, _) = mpsc::channel();
+            tx.send(Incoming::Line(ClientRequest { line: "exit".to_string(), console: true, reply })).unwrap();
+            if let Err(e) = result {
+                std::panic::resume_unwind(e);
+            }
+        });
+        runtime.serve(rx);
+        runtime.shutdown();
+        handle.join().unwrap();
+    }
+}